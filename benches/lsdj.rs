@@ -0,0 +1,106 @@
+//! Benchmarks of compressing, decompressing, and exporting songs, run with
+//! `cargo bench`. The songs are generated rather than read from real saves,
+//! but are built like LSDj's own: mostly runs of empty phrases and tables,
+//! default instruments and waves, and a scattering of note data.
+
+use std::io;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use lsdjtool::lsdj::{self, LsdjSave, LsdjSram};
+use lsdjtool::lsdj::layout::SRAM_SIZE;
+
+/// Songs put in the benchmarked save, about as many as fit in its blocks.
+const SONG_COUNT: u8 = 12;
+
+/// Returns the next number from a xorshift generator, so that the songs are
+/// the same on every run.
+fn next(state: &mut u32) -> u32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state
+}
+
+/// Generates the SRAM of a song, with `seed` choosing its note data.
+fn song_sram(seed: u32) -> LsdjSram {
+    let default_instrument = [0xa8, 0, 0, 0xff, 0, 0, 3, 0, 0, 0xd0, 0, 0, 0, 0xf3, 0, 0];
+    let default_wave = [0x8e, 0xcd, 0xcc, 0xbb, 0xaa, 0xa9, 0x99, 0x88, 0x87, 0x76, 0x66, 0x55, 0x54, 0x43, 0x32, 0x31];
+    let mut sram = LsdjSram::empty();
+    let mut state = seed | 1;
+    // phrases, chains, and tables full of notes, commands, and empty runs
+    for chunk in sram.data[..0x3080].chunks_mut(0x10) {
+        match next(&mut state) % 4 {
+            0 => chunk.fill(0xff),
+            1 => chunk.iter_mut().for_each(|b| *b = next(&mut state) as u8),
+            _ => (),
+        }
+    }
+    for instrument in sram.data[0x3080..0x3480].chunks_mut(0x10) {
+        instrument.copy_from_slice(&default_instrument);
+    }
+    for wave in sram.data[0x6000..0x7000].chunks_mut(0x10) {
+        wave.copy_from_slice(&default_wave);
+    }
+    for b in sram.data[0x7000..0x7ff0].iter_mut() {
+        *b = if next(&mut state).is_multiple_of(8) { next(&mut state) as u8 } else { 0xff };
+    }
+    sram.data[0x1e78..0x1e7a].copy_from_slice(b"rb");
+    sram.data[0x3e80..0x3e82].copy_from_slice(b"rb");
+    sram.data[0x7ff0..0x7ff2].copy_from_slice(b"rb");
+    sram
+}
+
+/// Builds a save holding `SONG_COUNT` generated songs.
+fn library_save() -> LsdjSave {
+    let mut save = LsdjSave::empty();
+    for i in 0..SONG_COUNT {
+        let title = lsdj::lsdjtitle_from(&format!("SONG{}", i)).unwrap();
+        save.import_decompressed_song(&song_sram(i as u32 + 1).data, title).unwrap();
+    }
+    save
+}
+
+fn compression(c: &mut Criterion) {
+    let sram = song_sram(1);
+    let blocks = sram.compress_into(1..).unwrap();
+    let mut group = c.benchmark_group("compression");
+    group.throughput(Throughput::Bytes(SRAM_SIZE as u64));
+    group.bench_function("compress", |b| b.iter(|| black_box(&sram).compress_into(1..).unwrap()));
+    group.bench_function("decompress", |b| b.iter(|| {
+        let mut sram = LsdjSram::empty();
+        lsdj::LsdjBlockExt::decompress_to(&blocks[..], &mut sram, 0).unwrap();
+        sram
+    }));
+    group.finish();
+}
+
+fn save(c: &mut Criterion) {
+    let save = library_save();
+    let exported: Vec<Vec<u8>> = (0..SONG_COUNT).map(|s| save.export_song(s)).collect();
+    let mut group = c.benchmark_group("save");
+    group.bench_function("decompress_song", |b| b.iter(|| save.decompress_song(black_box(0)).unwrap()));
+    group.bench_function("song_hash_all", |b| b.iter(|| {
+        (0..SONG_COUNT).map(|s| save.song_hash(s).unwrap()).fold(0, u64::wrapping_add)
+    }));
+    group.bench_function("export_all", |b| b.iter(|| (0..SONG_COUNT).map(|s| save.export_song(s)).collect::<Vec<_>>()));
+    group.bench_function("export_all_to", |b| b.iter(|| {
+        (0..SONG_COUNT).map(|s| save.export_song_to(s, &mut io::sink()).unwrap()).sum::<usize>()
+    }));
+    group.bench_function("import_all", |b| b.iter(|| {
+        let mut save = LsdjSave::empty();
+        for (i, song) in exported.iter().enumerate() {
+            save.import_song(song, lsdj::lsdjtitle_from(&format!("SONG{}", i)).unwrap()).unwrap();
+        }
+        save
+    }));
+    group.bench_function("bytes", |b| b.iter(|| save.bytes()));
+    group.bench_function("from_bytes", |b| {
+        let bytes = save.bytes();
+        b.iter(|| LsdjSave::from_bytes(black_box(&bytes)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, compression, save);
+criterion_main!(benches);
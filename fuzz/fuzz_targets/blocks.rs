@@ -0,0 +1,27 @@
+//! Decompresses arbitrary bytes as the blocks of an exported song, in both
+//! compatibility modes, and steps through each block's instructions.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use lsdjtool::lsdj::{self, Compat, CompressionStats, LsdjBlock};
+use lsdjtool::lsdj::layout::BLOCK_SIZE;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = lsdj::blocks_hash(data);
+    for &compat in [Compat::Lsdj, Compat::Legacy].iter() {
+        let _ = lsdj::song_from_blocks(data, compat);
+        let mut repaired = data.to_vec();
+        lsdj::repair_blocks(&mut repaired, compat);
+        let _ = lsdj::read_blocks(&repaired[..], &mut Vec::new(), true);
+        let mut stats = CompressionStats::default();
+        let mut position = 0;
+        for chunk in data.chunks_exact(BLOCK_SIZE) {
+            let mut block = LsdjBlock::empty();
+            block.data.copy_from_slice(chunk);
+            let _ = (block.is_terminated(), block.next_block(compat));
+            position = block.tally(position, compat, &mut stats);
+        }
+    }
+});
@@ -0,0 +1,21 @@
+//! Reads arbitrary bytes as a save file, then inspects and decompresses every
+//! song it claims to hold.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use lsdjtool::lsdj::LsdjSave;
+
+fuzz_target!(|data: &[u8]| {
+    let save = match LsdjSave::from_bytes(data) {
+        Ok(save) => save,
+        Err(_) => return,
+    };
+    let _ = (save.song_table(false), save.song_info(), save.audit_blocks(), save.check_sram());
+    let _ = (save.metadata.block_map(false), save.recover_songs(), save.deleted_songs());
+    for song in 0..=0xff {
+        let _ = (save.slot(song).map(|s| s.title()), save.export_song(song), save.song(song));
+        let _ = (save.compression_stats(song), save.round_trip(song), save.song_hash(song));
+    }
+});
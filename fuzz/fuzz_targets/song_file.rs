@@ -0,0 +1,16 @@
+//! Reads arbitrary bytes as the files songs are imported from: containers,
+//! armored text, and Goomba saves.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use lsdjtool::lsdj::{armor, container::Container, goomba};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Container::parse(data);
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = armor::dearmor(text);
+    }
+    let _ = goomba::extract(data, None);
+});
@@ -0,0 +1,70 @@
+/* C API for lsdjtool, built with the `ffi` feature:
+ *
+ *     cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * Functions which can fail return NULL or -1; lsdj_last_error() then says
+ * why. Buffers returned by the library must be freed with lsdj_buffer_free().
+ */
+#ifndef LSDJTOOL_H
+#define LSDJTOOL_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A parsed save file. */
+typedef struct LsdjSave LsdjSave;
+
+/* A buffer of bytes owned by the library. */
+typedef struct LsdjBuffer {
+    uint8_t *data;
+    size_t len;
+} LsdjBuffer;
+
+/* A song in a save file, as listed by lsdj_save_list(). */
+typedef struct LsdjSongInfo {
+    uint8_t index;
+    char title[9];   /* null-terminated */
+    uint8_t version; /* counts how many times the song has been saved */
+    size_t blocks;   /* number of blocks used */
+} LsdjSongInfo;
+
+/* Returns why the last call on this thread failed, or NULL if none has. The
+ * string is valid until the next failing call. */
+const char *lsdj_last_error(void);
+
+/* Parses a save file of any supported size, returning NULL on failure. */
+LsdjSave *lsdj_save_open(const uint8_t *bytes, size_t len);
+
+/* Frees a save; does nothing if save is NULL. */
+void lsdj_save_free(LsdjSave *save);
+
+/* Writes up to capacity songs to songs, in order of index, and returns how
+ * many songs there are (at most 32). */
+size_t lsdj_save_list(const LsdjSave *save, LsdjSongInfo *songs, size_t capacity);
+
+/* Returns the compressed blocks of a song, as in an .lsdsng file, or a buffer
+ * with NULL data if there's no such song. */
+LsdjBuffer lsdj_save_export_song(const LsdjSave *save, uint8_t song);
+
+/* Imports compressed song blocks into the first free slot, returning the
+ * slot's index or -1. */
+int lsdj_save_import_song(LsdjSave *save, const uint8_t *bytes, size_t len, const char *title);
+
+/* Deletes a song, freeing its blocks. Returns 0 or -1. */
+int lsdj_save_delete_song(LsdjSave *save, uint8_t song);
+
+/* Returns the bytes of the save file, ready to be written out. */
+LsdjBuffer lsdj_save_serialize(const LsdjSave *save);
+
+/* Frees a buffer returned by the library; does nothing if its data is NULL. */
+void lsdj_buffer_free(LsdjBuffer buffer);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* LSDJTOOL_H */
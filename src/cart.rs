@@ -0,0 +1,126 @@
+//! Reading and writing the SRAM of a flash cart over USB, with the protocol of
+//! EMS 64M USB carts (as spoken by ems-flasher). Needs the `usb` feature;
+//! without it, every transfer fails with `ErrorKind::Unsupported`.
+
+use std::io;
+
+/// Bytes of SRAM on the cart, which holds one 128KB save.
+pub const SRAM_SIZE: usize = 0x20000;
+
+#[cfg(feature = "usb")]
+mod ems {
+    use std::io;
+    use std::time::Duration;
+
+    use rusb::{Context, DeviceHandle, UsbContext};
+
+    const VENDOR_ID: u16 = 0x4670;
+    const PRODUCT_ID: u16 = 0x9394;
+    const ENDPOINT_OUT: u8 = 0x02;
+    const ENDPOINT_IN: u8 = 0x81;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    const READ_SRAM: u8 = 0x6d;
+    const WRITE_SRAM: u8 = 0x4d;
+    /// Bytes read from SRAM by each command.
+    const READ_CHUNK: usize = 0x1000;
+    /// Bytes written to SRAM by each command.
+    const WRITE_CHUNK: usize = 0x40;
+
+    /// Returns the header of a command: the command byte, then the SRAM
+    /// address and byte count (both big-endian).
+    pub(super) fn command(command: u8, address: u32, count: u32) -> [u8; 9] {
+        let mut bytes = [command, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes[1..5].copy_from_slice(&address.to_be_bytes());
+        bytes[5..].copy_from_slice(&count.to_be_bytes());
+        bytes
+    }
+
+    fn usb_error(e: rusb::Error) -> io::Error {
+        match e {
+            rusb::Error::NoDevice | rusb::Error::NotFound => io::Error::new(io::ErrorKind::NotFound, e),
+            rusb::Error::Access => io::Error::new(io::ErrorKind::PermissionDenied, e),
+            rusb::Error::Timeout => io::Error::new(io::ErrorKind::TimedOut, e),
+            e => io::Error::other(format!("USB transfer failed: {}", e)),
+        }
+    }
+
+    /// A connected EMS cart.
+    pub struct EmsCart {
+        handle: DeviceHandle<Context>,
+    }
+
+    impl EmsCart {
+        /// Opens the first EMS cart plugged in.
+        pub fn open() -> io::Result<EmsCart> {
+            let handle = Context::new().map_err(usb_error)?.open_device_with_vid_pid(VENDOR_ID, PRODUCT_ID)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no EMS cart found"))?;
+            handle.claim_interface(0).map_err(usb_error)?;
+            Ok(EmsCart { handle })
+        }
+
+        /// Reads `len` bytes of SRAM from the start.
+        pub fn read_sram(&self, len: usize) -> io::Result<Vec<u8>> {
+            let mut sram = vec![0; len];
+            for (i, chunk) in sram.chunks_mut(READ_CHUNK).enumerate() {
+                let header = command(READ_SRAM, (i * READ_CHUNK) as u32, chunk.len() as u32);
+                self.handle.write_bulk(ENDPOINT_OUT, &header, TIMEOUT).map_err(usb_error)?;
+                let read = self.handle.read_bulk(ENDPOINT_IN, chunk, TIMEOUT).map_err(usb_error)?;
+                if read != chunk.len() {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short read from cart"));
+                }
+            }
+            Ok(sram)
+        }
+
+        /// Writes `data` to SRAM from the start.
+        pub fn write_sram(&self, data: &[u8]) -> io::Result<()> {
+            for (i, chunk) in data.chunks(WRITE_CHUNK).enumerate() {
+                let mut message = command(WRITE_SRAM, (i * WRITE_CHUNK) as u32, chunk.len() as u32).to_vec();
+                message.extend_from_slice(chunk);
+                let written = self.handle.write_bulk(ENDPOINT_OUT, &message, TIMEOUT).map_err(usb_error)?;
+                if written != message.len() {
+                    return Err(io::Error::new(io::ErrorKind::WriteZero, "short write to cart"));
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Reads the save in the SRAM of the cart plugged in.
+#[cfg(feature = "usb")]
+pub fn pull() -> io::Result<Vec<u8>> {
+    ems::EmsCart::open()?.read_sram(SRAM_SIZE)
+}
+
+/// Writes `save` to the SRAM of the cart plugged in.
+#[cfg(feature = "usb")]
+pub fn push(save: &[u8]) -> io::Result<()> {
+    ems::EmsCart::open()?.write_sram(save)
+}
+
+#[cfg(not(feature = "usb"))]
+pub fn pull() -> io::Result<Vec<u8>> {
+    Err(unsupported())
+}
+
+#[cfg(not(feature = "usb"))]
+pub fn push(_save: &[u8]) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(feature = "usb"))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "lsdjtool was built without USB support (the usb feature)")
+}
+
+#[cfg(all(test, feature = "usb"))]
+mod tests {
+    use super::ems::command;
+
+    #[test]
+    fn test_command() {
+        assert_eq!(command(0x6d, 0x1000, 0x40), [0x6d, 0, 0, 0x10, 0, 0, 0, 0, 0x40]);
+    }
+}
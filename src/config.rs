@@ -0,0 +1,179 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::hooks::Hooks;
+
+const DEFAULT_EXPORT_TEMPLATE: &str = "{index}_{title}";
+
+/// When to color output meant for a terminal.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    /// Color output only when writing to a terminal.
+    Auto,
+    /// Color output even when it is redirected.
+    Always,
+    /// Never color output.
+    Never,
+}
+
+/// Defaults read from the config file, each of which is overridden by the
+/// matching command line flag.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Directory into which songs are exported when no output file is given.
+    pub output_dir: Option<PathBuf>,
+    /// Template for the names of exported songs, in which `{index}`,
+    /// `{title}`, and `{version}` are replaced (see `Config::export_name()`).
+    pub export_template: Option<String>,
+    /// Title given to imported songs.
+    pub default_title: Option<String>,
+    /// Backup policy, in the same format as `--backup`.
+    pub backup: Option<String>,
+    /// When to color output.
+    pub color: Option<ColorChoice>,
+    /// Commands run on events such as importing a song (see `Hooks`).
+    pub hooks: Hooks,
+}
+
+/// Returns the path of the config file: `$LSDJTOOL_CONFIG` if set, otherwise
+/// `lsdjtool/config.toml` inside `$XDG_CONFIG_HOME` (or `~/.config`).
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("LSDJTOOL_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("lsdjtool").join("config.toml"))
+}
+
+impl Config {
+    /// Parses the contents of a config file.
+    pub fn parse(s: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(s)
+    }
+
+    /// Reads the config file (see `config_path()`), returning the default
+    /// config if there is none.
+    pub fn load() -> io::Result<Config> {
+        let path = match config_path() {
+            Some(p) if p.is_file() => p,
+            _ => return Ok(Config::default()),
+        };
+        let contents = fs::read_to_string(&path)?;
+        Config::parse(&contents).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        })
+    }
+
+    /// Returns the name (without extension) under which the song at `index`
+    /// is exported, filled in from the export template.
+    ///
+    /// `{index}` and `{version}` are written in hex (the index padded to two
+    /// digits) unless given a format spec after a colon, as in Rust: a width,
+    /// padded with zeroes if it starts with 0, and `x` or `X` for hex (e.g.
+    /// `{index:02}` or `{version:02x}`). Characters in `{title}` other than
+    /// A-Z and 0-9, such as spaces and the lightning bolt, become `_`. A
+    /// trailing `.lsdsng` is dropped, as the extension is chosen by what is
+    /// exported.
+    pub fn export_name(&self, index: u8, title: &str, version: u8) -> String {
+        let template = self.export_template.as_deref().unwrap_or(DEFAULT_EXPORT_TEMPLATE);
+        let mut name = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            name.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => {
+                    rest = &rest[start..];
+                    break;
+                },
+            };
+            let field = &rest[(start + 1)..end];
+            let (key, spec) = match field.split_once(':') {
+                Some((key, spec)) => (key, Some(spec)),
+                None => (field, None),
+            };
+            match (key, spec) {
+                ("index", None) => name.push_str(&format!("{:02X}", index)),
+                ("version", None) => name.push_str(&format!("{:X}", version)),
+                ("index", Some(spec)) => name.push_str(&format_number(index, spec)),
+                ("version", Some(spec)) => name.push_str(&format_number(version, spec)),
+                ("title", _) => name.extend(title.chars().map(|c| match c {
+                    'A'..='Z' | '0'..='9' => c,
+                    _ => '_',
+                })),
+                _ => name.push_str(&rest[start..=end]), // not a field; left as is
+            }
+            rest = &rest[(end + 1)..];
+        }
+        name.push_str(rest);
+        match name.strip_suffix(".lsdsng") {
+            Some(stripped) => stripped.to_string(),
+            None => name,
+        }
+    }
+}
+
+/// Formats `n` according to a format spec from an export template (see
+/// `Config::export_name()`): an optional width, padded with zeroes if it
+/// starts with 0, followed by `x` or `X` for hex.
+fn format_number(n: u8, spec: &str) -> String {
+    let (width, radix) = spec.split_at(spec.find(|c: char| !c.is_ascii_digit()).unwrap_or(spec.len()));
+    let digits = match radix {
+        "x" => format!("{:x}", n),
+        "X" => format!("{:X}", n),
+        _ => n.to_string(),
+    };
+    let pad = if width.starts_with('0') { "0" } else { " " };
+    let width: usize = width.parse().unwrap_or(0);
+    format!("{}{}", pad.repeat(width.saturating_sub(digits.len())), digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        let config = Config::parse(r#"
+            output_dir = "songs"
+            export_template = "{title}.{version}"
+            default_title = "DEMO"
+            backup = "keep=3"
+            color = "never"
+
+            [hooks]
+            save_written = ["./upload.sh"]
+        "#).unwrap();
+        assert_eq!(config.output_dir, Some(PathBuf::from("songs")));
+        assert_eq!(config.default_title.as_deref(), Some("DEMO"));
+        assert_eq!(config.backup.as_deref(), Some("keep=3"));
+        assert_eq!(config.color, Some(ColorChoice::Never));
+        assert_eq!(config.hooks.save_written, ["./upload.sh"]);
+        assert!(Config::parse("colour = \"never\"").is_err());
+        assert!(Config::parse("color = \"sometimes\"").is_err());
+    }
+
+    #[test]
+    fn test_export_name() {
+        assert_eq!(Config::default().export_name(0x0b, "TEST", 3), "0B_TEST");
+        let config = Config { export_template: Some("{title}.{version}".to_string()), ..Config::default() };
+        assert_eq!(config.export_name(0x0b, "TEST", 0x1a), "TEST.1A");
+        let config = Config {
+            export_template: Some("{index:02}_{title}_{version:02x}.lsdsng".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(config.export_name(0x0b, "MY SONGx", 0x0a), "11_MY_SONG__0a");
+        let config = Config { export_template: Some("{index:3X}-{bpm}-{title".to_string()), ..Config::default() };
+        assert_eq!(config.export_name(0x0b, "TEST", 0), "  B-{bpm}-{title");
+    }
+}
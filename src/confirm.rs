@@ -0,0 +1,174 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+
+use crate::lsdj::LsdjSave;
+use crate::lsdj::layout::{SONG_SLOTS, SRAM_SIZE};
+
+/// One way in which writing a save file over another changes it.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// A song is written to an empty slot.
+    Add(u8, String),
+    /// The song in a slot is deleted.
+    Delete(u8, String),
+    /// The song in a slot is overwritten, by another song or a modified copy.
+    Replace(u8, String, String),
+    /// The working song is changed.
+    WorkingSong,
+    /// Bytes outside any song are changed (e.g. in freed blocks).
+    Other(usize),
+    /// The file being overwritten isn't a save file.
+    NotASave,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Add(s, new) => write!(f, "add      {:02X}: {}", s, new),
+            Change::Delete(s, old) => write!(f, "delete   {:02X}: {}", s, old),
+            Change::Replace(s, old, new) if old == new => write!(f, "change   {:02X}: {}", s, old),
+            Change::Replace(s, old, new) => write!(f, "replace  {:02X}: {} with {}", s, old, new),
+            Change::WorkingSong => write!(f, "change   the working song"),
+            Change::Other(n) => write!(f, "change   {} other byte(s)", n),
+            Change::NotASave => write!(f, "replace  a file which isn't a save file"),
+        }
+    }
+}
+
+/// Returns the title and version of `song`, as `dedupe` prints them.
+fn label(save: &LsdjSave, song: u8) -> String {
+    format!("{}.{:X}", save.metadata.song_title(song), save.metadata.version_table[song as usize])
+}
+
+/// Returns true if `song` holds the same song (title, version, and
+/// contents) in both saves.
+fn same_song(old: &LsdjSave, new: &LsdjSave, song: u8) -> bool {
+    label(old, song) == label(new, song) && (old.export_song(song) == new.export_song(song) ||
+        matches!((old.song_hash(song), new.song_hash(song)), (Ok(a), Ok(b)) if a == b))
+}
+
+/// Lists the changes made by writing the save file `new` over `old`, slot by
+/// slot, or returns `None` if either isn't a save file.
+pub fn changes(old: &[u8], new: &[u8]) -> Option<Vec<Change>> {
+    let (old_save, new_save) = (LsdjSave::from_bytes(old).ok()?, LsdjSave::from_bytes(new).ok()?);
+    let mut changes = Vec::new();
+    for s in 0..SONG_SLOTS as u8 {
+        match (old_save.slot(s).is_some(), new_save.slot(s).is_some()) {
+            (false, true) => changes.push(Change::Add(s, label(&new_save, s))),
+            (true, false) => changes.push(Change::Delete(s, label(&old_save, s))),
+            (true, true) if !same_song(&old_save, &new_save, s) =>
+                changes.push(Change::Replace(s, label(&old_save, s), label(&new_save, s))),
+            _ => (),
+        }
+    }
+    if old[..SRAM_SIZE] != new[..SRAM_SIZE] {
+        changes.push(Change::WorkingSong);
+    }
+    if changes.is_empty() && old != new {
+        let differing = old.iter().zip(new).filter(|(a, b)| a != b).count() + old.len().abs_diff(new.len());
+        changes.push(Change::Other(differing));
+    }
+    Some(changes)
+}
+
+/// Returns true if the user should be asked before anything is
+/// overwritten: only when stdin is a terminal, and `--yes` wasn't given.
+pub fn interactive(yes: bool) -> bool {
+    !yes && io::stdin().is_terminal()
+}
+
+/// Asks `question` on stderr, returning true if the answer read from stdin
+/// is yes. Always returns true if the user isn't to be asked.
+pub fn ask(question: &str, yes: bool) -> io::Result<bool> {
+    if !interactive(yes) {
+        return Ok(true);
+    }
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Lists what changes if the save file `new` is written over `target`, whose
+/// current contents are read by `old` (or are `None` if it doesn't exist
+/// yet), and asks whether to go ahead. `old` is only called if the user is to
+/// be asked, and no question is asked if nothing changes or `new` isn't a
+/// save file.
+pub fn confirm_changes<F>(target: &str, old: F, new: &[u8], yes: bool) -> io::Result<bool>
+    where F: FnOnce() -> io::Result<Option<Vec<u8>>> {
+    if !interactive(yes) || LsdjSave::from_bytes(new).is_err() {
+        return Ok(true);
+    }
+    let old = match old()? {
+        Some(old) => old,
+        None => return Ok(true),
+    };
+    let changes = changes(&old, new).unwrap_or_else(|| vec![Change::NotASave]);
+    if changes.is_empty() {
+        return Ok(true);
+    }
+    eprintln!("writing {} will:", target);
+    for change in changes.iter() {
+        eprintln!("    {}", change);
+    }
+    ask("go ahead?", yes)
+}
+
+/// Asks whether to write the save file `new` over the file at `path`, as
+/// `confirm_changes()` does.
+pub fn confirm_overwrite(path: &Path, new: &[u8], yes: bool) -> io::Result<bool> {
+    confirm_changes(&path.display().to_string(), || match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }, new, yes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::generate::{generate, test_save, GenOptions};
+
+    #[test]
+    fn test_changes() {
+        let old = generate(&GenOptions { songs: 3, blocks: Some(5), ..GenOptions::default() }).unwrap();
+        let old_bytes = old.bytes();
+        assert_eq!(changes(&old_bytes, &old_bytes), Some(vec![]));
+        assert_eq!(changes(&old_bytes, &[0; 0x100]), None);
+
+        let mut new = old.clone();
+        new.delete_song(1).unwrap();
+        new.metadata.version_table[2] = 1;
+        let song = test_save().decompress_song(0).unwrap();
+        new.import_decompressed_song(&song, *b"TEST\0\0\0\0").unwrap();
+        assert_eq!(changes(&old_bytes, &new.bytes()), Some(vec![
+            Change::Replace(1, "SONG01.0".to_string(), "TEST.0".to_string()),
+            Change::Replace(2, "SONG02.0".to_string(), "SONG02.1".to_string()),
+        ]));
+
+        let mut new = old.clone();
+        new.delete_song(0).unwrap();
+        let mut new_bytes = new.bytes();
+        new_bytes[0] ^= 1; // in the working song
+        let changed = changes(&old_bytes, &new_bytes).unwrap();
+        assert_eq!(changed, [Change::Delete(0, "SONG00.0".to_string()), Change::WorkingSong]);
+        assert_eq!(changed[0].to_string(), "delete   00: SONG00.0");
+        assert_eq!(Change::Replace(1, "A.1".to_string(), "A.1".to_string()).to_string(), "change   01: A.1");
+        assert_eq!(changes(&new.bytes(), &old_bytes).unwrap()[0], Change::Add(0, "SONG00.0".to_string()));
+
+        let mut new_bytes = old_bytes.clone();
+        *new_bytes.last_mut().unwrap() ^= 0xff; // in a free block
+        assert_eq!(changes(&old_bytes, &new_bytes), Some(vec![Change::Other(1)]));
+    }
+
+    #[test]
+    fn test_confirm_without_terminal() {
+        // cargo test doesn't give tests a terminal, and --yes never asks
+        let save = test_save().bytes();
+        assert!(confirm_changes("save", || panic!("read without asking"), &save, true).unwrap());
+        assert!(ask("go ahead?", true).unwrap());
+    }
+}
@@ -0,0 +1,76 @@
+use std::io;
+#[cfg(feature = "encrypt")]
+use std::sync::{Mutex, OnceLock};
+
+#[cfg(feature = "encrypt")]
+use lsdjtool::lsdj;
+
+/// Environment variable from which the passphrase is read instead of being
+/// prompted for.
+#[cfg(feature = "encrypt")]
+const PASSPHRASE_VAR: &str = "LSDJTOOL_PASSPHRASE";
+
+/// Passphrase given for this run, asked for at most once.
+#[cfg(feature = "encrypt")]
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+/// Held while the passphrase is asked for, so that songs encrypted in
+/// parallel don't each ask for it.
+#[cfg(feature = "encrypt")]
+static PROMPT: Mutex<()> = Mutex::new(());
+
+/// Returns the passphrase from `$LSDJTOOL_PASSPHRASE`, or otherwise prompts
+/// for it on the terminal (twice if `confirm` is true, as when encrypting).
+#[cfg(feature = "encrypt")]
+fn passphrase(confirm: bool) -> io::Result<&'static str> {
+    if let Some(p) = PASSPHRASE.get() {
+        return Ok(p);
+    }
+    let _prompt = PROMPT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(p) = PASSPHRASE.get() {
+        return Ok(p); // given while waiting for the lock
+    }
+    let passphrase = match std::env::var(PASSPHRASE_VAR) {
+        Ok(p) => p,
+        Err(_) => {
+            let p = rpassword::prompt_password("passphrase: ")?;
+            if confirm && rpassword::prompt_password("passphrase again: ")? != p {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "passphrases don't match"));
+            }
+            p
+        },
+    };
+    if passphrase.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "passphrase is empty"));
+    }
+    Ok(PASSPHRASE.get_or_init(|| passphrase))
+}
+
+/// Returns the error for encrypting or decrypting in a build without the
+/// encrypt feature.
+#[cfg(not(feature = "encrypt"))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "encrypted files can only be read or written when built with the encrypt feature")
+}
+
+/// Encrypts `bytes` with the passphrase (see `lsdj::io::encrypt()`).
+pub fn encrypt(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    #[cfg(feature = "encrypt")]
+    return lsdj::io::encrypt(bytes, passphrase(true)?);
+    #[cfg(not(feature = "encrypt"))]
+    {
+        let _ = bytes;
+        Err(unsupported())
+    }
+}
+
+/// Decrypts `bytes` with the passphrase (see `lsdj::io::decrypt()`).
+pub fn decrypt(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    #[cfg(feature = "encrypt")]
+    return lsdj::io::decrypt(bytes, passphrase(false)?);
+    #[cfg(not(feature = "encrypt"))]
+    {
+        let _ = bytes;
+        Err(unsupported())
+    }
+}
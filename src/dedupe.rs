@@ -0,0 +1,199 @@
+use std::io;
+use std::fs;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::lsdj::io::BackupPolicy;
+use crate::parallel;
+
+#[cfg(not(feature = "mmap"))]
+type Save = crate::lsdj::LsdjSave;
+#[cfg(feature = "mmap")]
+type Save = crate::lsdj::mapped::MappedSave; // avoids copying every save into memory up front
+
+/// Loads the save file at `path`.
+#[cfg(not(feature = "mmap"))]
+fn load(path: &Path) -> io::Result<Save> {
+    Ok(Save::from(&mut fs::File::open(path)?)?)
+}
+
+/// Loads the save file at `path`.
+#[cfg(feature = "mmap")]
+fn load(path: &Path) -> io::Result<Save> {
+    Ok(Save::open(path)?)
+}
+
+/// A song within one of the save files being deduplicated.
+struct SongRef {
+    save: usize, // index into the list of loaded saves
+    song: u8,
+    version: u8,
+}
+
+/// Returns true if `path` has one of `extensions` (ignoring case).
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    match path.extension() {
+        Some(ext) => extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)),
+        None => false,
+    }
+}
+
+/// Appends every save file in `path` to `out`, descending into directories.
+/// Files given directly are included regardless of their extension.
+pub fn find_saves(path: &Path, out: &mut Vec<PathBuf>) -> io::Result<()> {
+    find_files(path, &["sav"], out)
+}
+
+/// Appends every file in `path` with one of `extensions` to `out`, descending
+/// into directories. Files given directly are included regardless of their
+/// extension.
+pub fn find_files(path: &Path, extensions: &[&str], out: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        out.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort(); // read_dir order is platform-dependent
+    for entry in entries {
+        if entry.is_dir() {
+            find_files(&entry, extensions, out)?;
+        } else if has_extension(&entry, extensions) {
+            out.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Drops every path in `paths` which names the same file as one before it
+/// (e.g. a save given both on its own and within a directory given), so that
+/// no file is loaded twice and its songs taken as duplicates of themselves.
+fn unique_files(paths: Vec<PathBuf>) -> io::Result<Vec<PathBuf>> {
+    let mut seen = HashSet::new();
+    let mut unique = Vec::new();
+    for path in paths {
+        if seen.insert(fs::canonicalize(&path)?) {
+            unique.push(path);
+        }
+    }
+    Ok(unique)
+}
+
+/// Finds songs with identical contents across all save files in `paths`
+/// (searching directories recursively), and reports each group of duplicates,
+/// keeping the copy with the highest version byte. If `remove` is true, the
+/// other copies are deleted and the affected save files are rewritten (after
+/// being backed up according to `backup`), once the user agrees to it (which
+/// isn't asked if `yes` is true).
+pub fn dedupe(paths: &[PathBuf], remove: bool, backup: &BackupPolicy, yes: bool) -> io::Result<()> {
+    let mut savepaths = Vec::new();
+    for path in paths {
+        find_saves(path, &mut savepaths)?;
+    }
+    let savepaths = unique_files(savepaths)?;
+    let mut saves = parallel::map(&savepaths, |path| load(path)).into_iter()
+                                                                 .collect::<io::Result<Vec<Save>>>()?;
+
+    // decompressing songs is the slow part, so hash them all (in parallel, if
+    // enabled) before grouping them in order
+    let songs: Vec<(usize, u8)> = saves.iter().enumerate()
+                                       .flat_map(|(i, save)| save.metadata.songs().into_iter().map(move |s| (i, s)))
+                                       .collect();
+    let song_hashes = parallel::map(&songs, |&(i, song)| saves[i].song_hash(song));
+
+    let mut hashes = Vec::new(); // hashes in the order they were first found
+    let mut groups: HashMap<u64, Vec<SongRef>> = HashMap::new();
+    for (&(i, song), hash) in songs.iter().zip(song_hashes) {
+        let hash = match hash {
+            Ok(h) => h,
+            Err(e) => {
+                eprintln!("{} {:02X}: {}", savepaths[i].display(), song, e);
+                continue;
+            }
+        };
+        let version = saves[i].metadata.version_table[song as usize];
+        let group = groups.entry(hash).or_insert_with(|| { hashes.push(hash); Vec::new() });
+        if !group.iter().any(|r| r.save == i && r.song == song) { // never a duplicate of itself
+            group.push(SongRef { save: i, song, version });
+        }
+    }
+
+    let mut modified = vec![false; saves.len()];
+    for hash in hashes {
+        let mut group = groups.remove(&hash).unwrap_or_default();
+        if group.len() < 2 { continue; }
+        group.sort_by_key(|r| std::cmp::Reverse(r.version)); // stable, so the first copy found wins ties
+        println!("{:016x}:", hash);
+        for (n, r) in group.iter().enumerate() {
+            let action = if n == 0 { "keep" } else if remove { "removed" } else { "duplicate" };
+            println!("    {:9} {} {:02X}: {}.{:X}", action, savepaths[r.save].display(), r.song,
+                     saves[r.save].metadata.song_title(r.song), r.version);
+            if n > 0 && remove {
+                saves[r.save].delete_song(r.song).expect("song disappeared during deduplication");
+                modified[r.save] = true;
+            }
+        }
+    }
+
+    let rewrites: Vec<(usize, Vec<u8>)> = saves.iter().enumerate()
+                                              .filter(|&(i, _)| modified[i])
+                                              .map(|(i, save)| (i, save.bytes()))
+                                              .collect();
+    if !rewrites.is_empty() && !crate::confirm::ask(&format!("rewrite {} save file(s)?", rewrites.len()), yes)? {
+        eprintln!("aborted; no save files were rewritten");
+        std::process::exit(crate::exit::Status::Failed as i32);
+    }
+    drop(saves); // close (and unmap) save files before replacing them
+    for (i, bytes) in rewrites {
+        crate::lsdj::io::backup(&savepaths[i], backup)?;
+        crate::lsdj::io::write_atomic(&savepaths[i], &bytes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::generate::{generate, GenOptions};
+    use crate::lsdj::LsdjSave;
+
+    #[test]
+    fn test_dedupe_same_file_twice() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("lsdjtool-dedupe-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("x.sav");
+        let save = generate(&GenOptions { songs: 3, ..GenOptions::default() }).unwrap();
+        fs::write(&path, save.bytes())?;
+
+        // the save is found in the directory, given again directly, and again by another path
+        let paths = [dir.clone(), path.clone(), dir.join(".").join("x.sav")];
+        assert_eq!(unique_files(vec![path.clone(), dir.join("./x.sav")])?, vec![path.clone()]);
+        dedupe(&paths, true, &BackupPolicy::default(), true)?;
+        let deduped = LsdjSave::from(&mut fs::File::open(&path)?)?;
+        assert_eq!(deduped.metadata.songs(), [0, 1, 2]);
+        fs::remove_dir_all(&dir)
+    }
+
+    #[test]
+    fn test_dedupe_keeps_highest_version() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("lsdjtool-dedupe-versions-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let mut older = generate(&GenOptions { songs: 2, ..GenOptions::default() }).unwrap();
+        let mut newer = older.clone();
+        newer.delete_song(1).unwrap(); // only song 00 is shared
+        older.metadata.version_table[0] = 1;
+        newer.metadata.version_table[0] = 3;
+        fs::write(dir.join("a.sav"), older.bytes())?;
+        fs::write(dir.join("b.sav"), newer.bytes())?;
+
+        dedupe(std::slice::from_ref(&dir), true, &BackupPolicy::default(), true)?;
+        let older_after = LsdjSave::from(&mut fs::File::open(dir.join("a.sav"))?)?;
+        let newer_after = LsdjSave::from(&mut fs::File::open(dir.join("b.sav"))?)?;
+        assert_eq!(older_after.metadata.songs(), [1]); // the older copy was deleted
+        assert_eq!(older_after.export_song(1), older.export_song(1));
+        assert_eq!(newer_after.bytes(), newer.bytes()); // the newer copy was kept, untouched
+        fs::remove_dir_all(&dir)
+    }
+}
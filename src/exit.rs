@@ -0,0 +1,233 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::process;
+use std::str::FromStr;
+
+use structopt::clap;
+
+use crate::lsdj::{ErrorClass, LsdjError};
+
+/// Statuses the program exits with, so that scripts can tell failures apart
+/// (e.g. a save without enough free blocks from a corrupt one).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    /// A check (such as `verify`, `audit`, or `--check-fit`) failed, or the
+    /// user declined to go ahead.
+    Failed = 1,
+    /// The command line was malformed, or an argument was invalid.
+    Usage = 2,
+    /// A file couldn't be parsed, being corrupt or not of the kind expected.
+    Parse = 3,
+    /// There's no room (free blocks, song slots, or space within a song) for
+    /// what was to be added.
+    NoSpace = 4,
+    /// A song, or something else asked for, doesn't exist.
+    NotFound = 5,
+    /// Reading or writing a file or device failed.
+    Io = 6,
+}
+
+impl Status {
+    /// Returns the name of the status, as reported by `--error-format json`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Failed => "failed",
+            Status::Usage => "usage",
+            Status::Parse => "parse",
+            Status::NoSpace => "no_space",
+            Status::NotFound => "not_found",
+            Status::Io => "io",
+        }
+    }
+}
+
+impl From<ErrorClass> for Status {
+    fn from(class: ErrorClass) -> Status {
+        match class {
+            ErrorClass::Corrupt => Status::Parse,
+            ErrorClass::NoSpace => Status::NoSpace,
+            ErrorClass::NotFound => Status::NotFound,
+            ErrorClass::Argument => Status::Usage,
+            ErrorClass::Io => Status::Io,
+        }
+    }
+}
+
+/// How errors are written to stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ErrorFormat {
+    /// A line of the form `error: MESSAGE`.
+    #[default]
+    Text,
+    /// A line holding a JSON object: `{"error": {"status", "code", "message"}}`,
+    /// along with `offset` and `region` for corrupt data.
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<ErrorFormat, &'static str> {
+        match s {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err("error format must be one of text or json."),
+        }
+    }
+}
+
+impl ErrorFormat {
+    /// Returns the format given by `--error-format` in `args`, for reporting
+    /// errors in the arguments themselves.
+    pub fn from_args(args: &[OsString]) -> ErrorFormat {
+        let mut format = ErrorFormat::default();
+        for (i, arg) in args.iter().enumerate() {
+            let value = match arg.to_str() {
+                Some("--") => break,
+                Some("--error-format") => args.get(i + 1).and_then(|a| a.to_str()),
+                Some(arg) => arg.strip_prefix("--error-format="),
+                None => None,
+            };
+            if let Some(f) = value.and_then(|v| v.parse().ok()) {
+                format = f;
+            }
+        }
+        format
+    }
+}
+
+/// An error which ends the program with a particular status.
+#[derive(Debug)]
+pub struct Failure {
+    status: Status,
+    message: String,
+    cause: Option<LsdjError>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Returns an error which ends the program with `status`, reported as
+/// `message`.
+pub fn fail<M: fmt::Display>(status: Status, message: M) -> io::Error {
+    io::Error::other(Failure { status, message: message.to_string(), cause: None })
+}
+
+/// Returns an error reporting that `song` couldn't be read, ending the
+/// program with the status of `e`.
+pub fn song_failure(song: u8, e: LsdjError) -> io::Error {
+    io::Error::other(Failure { status: e.class().into(), message: format!("{:02X}: {}", song, e), cause: Some(e) })
+}
+
+/// Returns the library error which caused `e`, if any.
+fn lsdj_cause(e: &io::Error) -> Option<&LsdjError> {
+    let inner = e.get_ref()?;
+    match inner.downcast_ref::<Failure>() {
+        Some(failure) => failure.cause.as_ref(),
+        None => inner.downcast_ref::<LsdjError>(),
+    }
+}
+
+/// Returns the status the program exits with after `e`.
+pub fn status_of(e: &io::Error) -> Status {
+    if let Some(failure) = e.get_ref().and_then(|inner| inner.downcast_ref::<Failure>()) {
+        return failure.status;
+    }
+    if let Some(cause) = lsdj_cause(e) {
+        return cause.class().into();
+    }
+    match e.kind() {
+        io::ErrorKind::InvalidData => Status::Parse,
+        io::ErrorKind::InvalidInput => Status::Usage,
+        _ => Status::Io,
+    }
+}
+
+/// Returns `e` as written to stderr in `format`, without a trailing newline.
+pub fn render(e: &io::Error, format: ErrorFormat) -> String {
+    let status = status_of(e);
+    match format {
+        ErrorFormat::Text => format!("error: {}", e),
+        ErrorFormat::Json => {
+            let mut error = serde_json::json!({
+                "status": status.name(),
+                "code": status as i32,
+                "message": e.to_string(),
+            });
+            if let Some(LsdjError::Corrupt { offset, region, .. }) = lsdj_cause(e) {
+                error["offset"] = (*offset).into();
+                error["region"] = region.as_str().into();
+            }
+            serde_json::json!({ "error": error }).to_string()
+        },
+    }
+}
+
+/// Reports `e` on stderr in `format`, then exits with its status.
+pub fn report(e: &io::Error, format: ErrorFormat) -> ! {
+    eprintln!("{}", render(e, format));
+    process::exit(status_of(e) as i32);
+}
+
+/// Reports an error in the command-line arguments, exiting with
+/// `Status::Usage`; `--help` and `--version` are printed as usual.
+pub fn usage(e: clap::Error, format: ErrorFormat) -> ! {
+    match (e.kind, format) {
+        (clap::ErrorKind::HelpDisplayed, _) | (clap::ErrorKind::VersionDisplayed, _) => e.exit(),
+        (_, ErrorFormat::Text) => {
+            eprintln!("{}", e.message);
+            process::exit(Status::Usage as i32);
+        },
+        (_, ErrorFormat::Json) => {
+            let message = e.message.lines().next().unwrap_or_default().trim_start_matches("error: ");
+            report(&fail(Status::Usage, message), format)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_of() {
+        assert_eq!(status_of(&fail(Status::NotFound, "no such song")), Status::NotFound);
+        assert_eq!(status_of(&LsdjError::Invalid("not enough free blocks left!").into()), Status::NoSpace);
+        assert_eq!(status_of(&LsdjError::TruncatedSave { expected: 0x8400, got: 3 }.into()), Status::Parse);
+        assert_eq!(status_of(&song_failure(3, LsdjError::Invalid("no song exists at that index!"))), Status::NotFound);
+        assert_eq!(status_of(&io::Error::from(io::ErrorKind::PermissionDenied)), Status::Io);
+        assert_eq!(status_of(&io::Error::new(io::ErrorKind::InvalidData, "bad toml")), Status::Parse);
+    }
+
+    #[test]
+    fn test_render() {
+        let e = fail(Status::NoSpace, "not enough free blocks left!");
+        assert_eq!(render(&e, ErrorFormat::Text), "error: not enough free blocks left!");
+        let json: serde_json::Value = serde_json::from_str(&render(&e, ErrorFormat::Json)).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "error": { "status": "no_space", "code": 4, "message": "not enough free blocks left!" }
+        }));
+
+        let corrupt = LsdjError::Corrupt { reason: "LZO data is corrupt!", offset: 0x8204, region: "block 01".to_string(),
+                                           context: vec![0, 1], at: 1 };
+        let json: serde_json::Value = serde_json::from_str(&render(&song_failure(0, corrupt), ErrorFormat::Json)).unwrap();
+        assert_eq!(json["error"]["code"], 3);
+        assert_eq!(json["error"]["offset"], 0x8204);
+        assert_eq!(json["error"]["region"], "block 01");
+    }
+
+    #[test]
+    fn test_format_from_args() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(ErrorFormat::from_args(&args(&["lsdjtool", "-l", "a.sav"])), ErrorFormat::Text);
+        assert_eq!(ErrorFormat::from_args(&args(&["lsdjtool", "--error-format", "json", "-l"])), ErrorFormat::Json);
+        assert_eq!(ErrorFormat::from_args(&args(&["lsdjtool", "map", "--error-format=json"])), ErrorFormat::Json);
+        assert_eq!(ErrorFormat::from_args(&args(&["lsdjtool", "--", "--error-format=json"])), ErrorFormat::Text);
+    }
+}
@@ -0,0 +1,188 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Largest file downloaded, far bigger than any save file or song.
+#[cfg(feature = "http")]
+const MAX_DOWNLOAD: usize = 0x100000;
+
+/// Returns true if `path` is an http or https URL rather than a file path.
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+/// A file downloaded into a private directory of its own in the temp
+/// directory, both deleted when dropped.
+pub struct Download {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl Download {
+    /// Returns the path of the downloaded file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Downloads `path` (see `download()`) if it is a URL, returning `None` if it
+/// is a file path.
+pub fn resolve(path: &Path) -> io::Result<Option<Download>> {
+    if !is_url(path) {
+        return Ok(None);
+    }
+    #[cfg(feature = "http")]
+    return download(path.to_str().expect("URLs are UTF-8")).map(Some);
+    #[cfg(not(feature = "http"))]
+    Err(io::Error::new(io::ErrorKind::Unsupported,
+                       format!("{}: URLs can only be read when built with the http feature", path.display())))
+}
+
+/// Splits a `#sha256=HEX` fragment off `url`, returning the URL without it
+/// and the digest.
+#[cfg(feature = "http")]
+fn split_checksum(url: &str) -> io::Result<(&str, Option<[u8; 32]>)> {
+    let (url, hex) = match url.split_once("#sha256=") {
+        Some(split) => split,
+        None => return Ok((url, None)),
+    };
+    let bad_checksum = || io::Error::new(io::ErrorKind::InvalidInput, format!("{}: not a SHA-256 digest", hex));
+    if hex.len() != 64 {
+        return Err(bad_checksum());
+    }
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2).ok_or_else(bad_checksum)?, 16).map_err(|_| bad_checksum())?;
+    }
+    Ok((url, Some(digest)))
+}
+
+/// Creates a directory in the temp directory which only the current user can
+/// use, under a random name so that no one else can create (or link) it first.
+#[cfg(feature = "http")]
+fn private_temp_dir() -> io::Result<PathBuf> {
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    for _ in 0..16 {
+        let dir = std::env::temp_dir().join(format!("lsdjtool-{:016x}", RandomState::new().hash_one(std::process::id())));
+        match builder.create(&dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            r => return r.map(|_| dir),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "could not create a temporary directory"))
+}
+
+/// Downloads `url` into a new file in a private temp directory (see
+/// `private_temp_dir()`), named after the last segment of the URL so that its
+/// extension is kept.
+///
+/// If the URL ends in `#sha256=HEX`, the file's SHA-256 digest must match, as
+/// must its length match any Content-Length the server sends, or an `Err` is
+/// returned instead.
+#[cfg(feature = "http")]
+pub fn download(url: &str) -> io::Result<Download> {
+    use std::io::{Read, Write};
+    use sha2::{Digest, Sha256};
+
+    let (url, checksum) = split_checksum(url)?;
+    let response = ureq::get(url).call().map_err(|e| io::Error::other(e.to_string()))?;
+    let length = response.header("Content-Length").and_then(|l| l.parse::<usize>().ok());
+    let mut bytes = Vec::new();
+    response.into_reader().take(MAX_DOWNLOAD as u64 + 1).read_to_end(&mut bytes)?;
+    if bytes.len() > MAX_DOWNLOAD {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: larger than {} bytes", url, MAX_DOWNLOAD)));
+    }
+    if length.is_some_and(|l| l != bytes.len()) {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{}: download was cut short", url)));
+    }
+    if let Some(expected) = checksum {
+        if Sha256::digest(&bytes)[..] != expected[..] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: SHA-256 digest doesn't match", url)));
+        }
+    }
+    let name = url.rsplit('/').next().unwrap_or_default();
+    let name: String = name.split(['?', '#']).next().unwrap_or_default().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    let name = if name.trim_matches('.').is_empty() { "download".to_string() } else { name };
+    let dir = private_temp_dir()?;
+    let download = Download { path: dir.join(name), dir };
+    fs::OpenOptions::new().write(true).create_new(true).open(&download.path)?.write_all(&bytes)?;
+    Ok(download)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url(Path::new("https://example.com/lsdj.sav")));
+        assert!(is_url(Path::new("http://example.com/song.lsdsng")));
+        assert!(!is_url(Path::new("saves/test.sav")));
+        assert!(!is_url(Path::new("http.sav")));
+        assert!(resolve(Path::new("saves/test.sav")).unwrap().is_none());
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_split_checksum() {
+        let url = format!("https://example.com/a.sav#sha256={}ff", "00".repeat(31));
+        let (url, digest) = split_checksum(&url).unwrap();
+        assert_eq!(url, "https://example.com/a.sav");
+        assert_eq!(digest.unwrap()[31], 0xff);
+        assert_eq!(split_checksum("https://example.com/a.sav").unwrap(), ("https://example.com/a.sav", None));
+        assert!(split_checksum("https://example.com/a.sav#sha256=abc").is_err());
+        assert!(split_checksum(&format!("https://example.com/a.sav#sha256={}", "zz".repeat(32))).is_err());
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_download() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use sha2::{Digest, Sha256};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/songs/TEST.lsdsng", listener.local_addr().unwrap());
+        let body = b"not really a song".to_vec();
+        let served = body.clone();
+        let server = std::thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                stream.read_exact(&mut [0; 16]).unwrap(); // enough of the request
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", served.len()).unwrap();
+                stream.write_all(&served).unwrap();
+            }
+        });
+
+        let digest: String = Sha256::digest(&body).iter().map(|b| format!("{:02x}", b)).collect();
+        let download = download(&format!("{}#sha256={}", url, digest)).unwrap();
+        assert!(download.path().to_str().unwrap().ends_with("TEST.lsdsng"));
+        assert_eq!(fs::read(download.path()).unwrap(), body);
+        let dir = download.path().parent().unwrap().to_path_buf();
+        assert_ne!(dir, std::env::temp_dir());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+        }
+        drop(download);
+        assert!(!dir.exists());
+
+        let wrong = format!("{}#sha256={}", url, "00".repeat(32));
+        assert_eq!(super::download(&wrong).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+use std::io;
+use std::fs::File;
+use std::path::PathBuf;
+
+use crate::dedupe::find_saves;
+use crate::lsdj::LsdjSave;
+use crate::lsdj::song::{InstrumentType, INSTRUMENT_COUNT};
+
+/// Prints every instrument, in every song of the save files in `paths`
+/// (searching directories recursively), which plays `kit` and is of type
+/// `kind` (either of which matches anything if not given).
+///
+/// Each line names the save file, song, and instrument, along with the kits
+/// the instrument plays if it is a kit instrument.
+pub fn grep(paths: &[PathBuf], kit: Option<u8>, kind: Option<InstrumentType>) -> io::Result<()> {
+    let mut savepaths = Vec::new();
+    for path in paths {
+        find_saves(path, &mut savepaths)?;
+    }
+    for savepath in savepaths {
+        let save = LsdjSave::from(&mut File::open(&savepath)?)?;
+        for s in save.metadata.songs() {
+            let song = match save.song(s) {
+                Ok(song) => song,
+                Err(e) => {
+                    eprintln!("{}: {:02X}: {}", savepath.display(), s, e);
+                    continue;
+                },
+            };
+            for i in 0..INSTRUMENT_COUNT {
+                let instrument = match song.instrument(i) {
+                    Some(instrument) => instrument,
+                    None => continue,
+                };
+                let kits = instrument.kits();
+                if kind.is_some_and(|k| k != instrument.kind)
+                    || kit.is_some_and(|k| !kits.is_some_and(|kits| kits.contains(&k))) {
+                    continue;
+                }
+                print!("{}: {:02X} {:<8} I{:02X} {:<5} {}", savepath.display(), s,
+                       save.metadata.song_title(s), i, instrument.name, instrument.kind);
+                match kits {
+                    Some([kit_1, kit_2]) => println!(" {:02X} {:02X}", kit_1, kit_2),
+                    None => println!(),
+                }
+            }
+        }
+    }
+    Ok(())
+}
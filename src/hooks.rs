@@ -0,0 +1,176 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use lsdjtool::lsdj::LsdjSave;
+
+/// Commands run through the shell when songs are imported or exported or a
+/// save file is written, read from the `[hooks]` table of the config file.
+///
+/// Each command is given a JSON object describing the event on stdin, with
+/// the event's name under `event` (and in `$LSDJTOOL_EVENT`), the file written
+/// under `path` (null when writing to stdout), and the song(s) involved.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    /// Run after a song is imported into a save file and the save is written.
+    pub song_imported: Vec<String>,
+    /// Run after a song is exported.
+    pub song_exported: Vec<String>,
+    /// Run after a save file is written.
+    pub save_written: Vec<String>,
+}
+
+/// Something which hooks can be run on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    SongImported,
+    SongExported,
+    SaveWritten,
+}
+
+impl Event {
+    /// Returns the name of the event, as used in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::SongImported => "song_imported",
+            Event::SongExported => "song_exported",
+            Event::SaveWritten => "save_written",
+        }
+    }
+}
+
+/// A song as described to hooks.
+#[derive(Serialize)]
+struct Song {
+    index: u8,
+    title: String,
+    version: u8,
+    blocks: usize,
+}
+
+/// Describes `song` in `save` for a hook, or returns null if there's no song
+/// at that index.
+pub fn song(save: &LsdjSave, song: u8) -> Value {
+    let song = save.slot(song).map(|slot| Song {
+        index: song,
+        title: slot.title(),
+        version: slot.version(),
+        blocks: save.metadata.size_of(song),
+    });
+    serde_json::to_value(song).unwrap_or_default()
+}
+
+/// Describes every song in `save` for a hook.
+pub fn songs(save: &LsdjSave) -> Value {
+    Value::Array(save.metadata.songs().into_iter().map(|s| song(save, s)).collect())
+}
+
+impl Hooks {
+    /// Returns the commands run on `event`.
+    fn commands(&self, event: Event) -> &[String] {
+        match event {
+            Event::SongImported => &self.song_imported,
+            Event::SongExported => &self.song_exported,
+            Event::SaveWritten => &self.save_written,
+        }
+    }
+
+    /// Runs each command configured for `event` in turn, giving it `details`
+    /// (an object, to which the event's name is added) as JSON on stdin.
+    /// Whatever the command prints goes to stderr, so as not to mix with data
+    /// written to stdout. A command which fails is warned about, but doesn't
+    /// stop the rest.
+    pub fn run(&self, event: Event, mut details: Value) {
+        let commands = self.commands(event);
+        if commands.is_empty() {
+            return;
+        }
+        if let Value::Object(ref mut map) = details {
+            map.insert("event".to_string(), Value::from(event.name()));
+        }
+        let mut input = details.to_string();
+        input.push('\n');
+        for command in commands {
+            if let Err(e) = run_command(command, event, input.as_bytes()) {
+                eprintln!("warning: {} hook `{}` failed: {}", event.name(), command, e);
+            }
+        }
+    }
+}
+
+/// Runs `command` through the shell, writing `input` to its stdin.
+fn run_command(command: &str, event: Event, input: &[u8]) -> io::Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let mut child = shell.arg(command)
+        .env("LSDJTOOL_EVENT", event.name())
+        .stdin(Stdio::piped())
+        .stdout(io::stderr())
+        .spawn()?;
+    // a hook needn't read its input, so a broken pipe is no matter
+    let _ = child.stdin.take().expect("stdin is piped").write_all(input);
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("exited with {}", status)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let hooks: Hooks = toml::from_str(r#"
+            song_imported = ["notify-send imported"]
+            save_written = ["./upload.sh", "true"]
+        "#).unwrap();
+        assert_eq!(hooks.commands(Event::SongImported), ["notify-send imported"]);
+        assert!(hooks.commands(Event::SongExported).is_empty());
+        assert_eq!(hooks.commands(Event::SaveWritten).len(), 2);
+        assert!(toml::from_str::<Hooks>("song_deleted = []").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run() {
+        let save = lsdjtool::lsdj::generate::test_save();
+        let out = std::env::temp_dir().join(format!("lsdjtool-hook-{}.json", std::process::id()));
+        let hooks = Hooks {
+            song_exported: vec![
+                "exit 3".to_string(),
+                format!("(echo \"$LSDJTOOL_EVENT\"; cat) > '{}'", out.display()),
+            ],
+            ..Hooks::default()
+        };
+        hooks.run(Event::SongExported, json!({ "path": PathBuf::from("00_TEST.lsdsng"), "song": song(&save, 0) }));
+
+        let written = fs::read_to_string(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+        let (event, details) = written.split_once('\n').unwrap();
+        assert_eq!(event, "song_exported");
+        let details: Value = serde_json::from_str(details).unwrap();
+        assert_eq!(details, json!({
+            "event": "song_exported",
+            "path": "00_TEST.lsdsng",
+            "song": { "index": 0, "title": "TEST", "version": 0, "blocks": 6 },
+        }));
+        assert_eq!(song(&save, 1), Value::Null);
+    }
+}
@@ -0,0 +1,14 @@
+//! Reading, writing, compressing, and editing LittleSoundDj save files, as
+//! used by the `lsdjtool` command-line tool.
+//!
+//! Everything which needs an operating system (reading and writing files,
+//! gzip and zstd, memory-mapping, and rendering soft synths with
+//! floating-point math) is behind the `std` feature, enabled by default.
+//! Without it the crate is `no_std` and needs only `alloc`, so that saves can
+//! be parsed and songs compressed on devices such as microcontroller-based
+//! cart readers, working on byte slices (see `LsdjSave::from_bytes()`).
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod lsdj;
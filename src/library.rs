@@ -0,0 +1,502 @@
+use std::io;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+#[cfg(feature = "sqlite")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "sqlite")]
+use std::time::{Duration, UNIX_EPOCH};
+
+use regex::{Regex, RegexBuilder};
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::dedupe::find_files;
+use crate::lsdj::LsdjSave;
+use crate::parallel;
+use crate::sidecar::{sidecar_path, Sidecar};
+
+const TITLE_LENGTH: usize = 8;
+// Exported songs may start with the song's title and version byte, as written
+// by LSDj Patcher, before their blocks
+const LSDSNG_HEADER_LENGTH: usize = TITLE_LENGTH + 1;
+const BLOCK_SIZE: usize = 0x200;
+
+// Each file is recorded with the modification time and length it had when it
+// was scanned, so that unchanged files needn't be scanned again
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS files (
+        path     TEXT PRIMARY KEY,
+        modified INTEGER NOT NULL,
+        len      INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS songs (
+        path    TEXT NOT NULL REFERENCES files(path) ON DELETE CASCADE,
+        idx     INTEGER,
+        title   TEXT NOT NULL,
+        version INTEGER,
+        hash    INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS songs_path ON songs(path);
+    PRAGMA foreign_keys = ON;
+";
+
+/// A song found in a library of save files and exported songs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// File in which the song was found.
+    pub path: PathBuf,
+    /// Index of the song within its save file (`None` for exported songs).
+    pub index: Option<u8>,
+    pub title: String,
+    /// Version byte of the song (`None` for exported songs which don't record
+    /// it).
+    pub version: Option<u8>,
+    /// Fingerprint of the song's decompressed data (see `LsdjSave::song_hash()`).
+    pub hash: u64,
+    /// When the file was last modified.
+    pub modified: SystemTime,
+    /// Tags and other details from the song's sidecar file, if it has one
+    /// (see `sidecar::sidecar_path()`).
+    pub sidecar: Option<Sidecar>,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<8} ", self.title)?;
+        match self.version {
+            Some(v) => write!(f, "{:>2X} ", v)?,
+            None => write!(f, "-- ")?,
+        }
+        write!(f, "{:016x} {}", self.hash, self.path.display())?;
+        if let Some(i) = self.index {
+            write!(f, ":{:02X}", i)?;
+        }
+        match &self.sidecar {
+            Some(sidecar) => write!(f, "  {}", sidecar),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A pattern matched against song titles and tags, ignoring case.
+#[derive(Debug)]
+pub enum Pattern {
+    /// A glob matching the whole of a title, in which `*` matches any run of
+    /// characters and `?` any one character.
+    Glob(Vec<char>),
+    /// A regular expression matching any part of a title.
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Returns the glob `pattern`.
+    pub fn glob(pattern: &str) -> Pattern {
+        Pattern::Glob(pattern.to_uppercase().chars().collect())
+    }
+
+    /// Returns the regular expression `pattern`, or an error if it is
+    /// malformed.
+    pub fn regex(pattern: &str) -> Result<Pattern, regex::Error> {
+        Ok(Pattern::Regex(RegexBuilder::new(pattern).case_insensitive(true).build()?))
+    }
+
+    /// Returns true if `s` matches this pattern.
+    pub fn matches(&self, s: &str) -> bool {
+        match self {
+            Pattern::Glob(glob) => glob_matches(glob, &s.to_uppercase().chars().collect::<Vec<_>>()),
+            Pattern::Regex(regex) => regex.is_match(s),
+        }
+    }
+}
+
+/// Returns true if the whole of `s` matches `glob`.
+fn glob_matches(glob: &[char], s: &[char]) -> bool {
+    match glob.split_first() {
+        None => s.is_empty(),
+        Some(('*', rest)) => (0..=s.len()).any(|i| glob_matches(rest, &s[i..])),
+        Some(('?', rest)) => !s.is_empty() && glob_matches(rest, &s[1..]),
+        Some((c, rest)) => s.first() == Some(c) && glob_matches(rest, &s[1..]),
+    }
+}
+
+/// An index of every song in the save files (`.sav`) and exported songs
+/// (`.lsdsng`) under a directory.
+pub struct Library {
+    /// Songs in the order they were found: by path, then by index.
+    pub entries: Vec<Entry>,
+}
+
+impl Library {
+    /// Indexes every save file and exported song under `dir`, searching it
+    /// recursively. Files which can't be read are reported and skipped.
+    pub fn scan(dir: &Path) -> io::Result<Library> {
+        let mut paths = Vec::new();
+        find_files(dir, &["sav", "lsdsng"], &mut paths)?;
+        let mut entries = Vec::new();
+        for (path, found) in paths.iter().zip(parallel::map(&paths, |path| scan_file(path))) {
+            match found {
+                Ok(found) => entries.extend(found),
+                Err(e) => eprintln!("{}: {}", path.display(), e),
+            }
+        }
+        Ok(Library { entries })
+    }
+
+    /// Returns every song whose title contains `title` (ignoring case).
+    pub fn find(&self, title: &str) -> Vec<&Entry> {
+        let title = title.to_uppercase();
+        self.entries.iter().filter(|e| e.title.to_uppercase().contains(&title)).collect()
+    }
+
+    /// Returns the newest copy of each song, by title: the one with the
+    /// highest version byte, or if there are several, the one in the most
+    /// recently modified file. Songs are sorted by title.
+    pub fn latest(&self) -> Vec<&Entry> {
+        let mut latest: Vec<&Entry> = Vec::new();
+        for entry in self.entries.iter() {
+            match latest.iter_mut().find(|e| e.title == entry.title) {
+                Some(e) if (entry.version, entry.modified) > (e.version, e.modified) => *e = entry,
+                Some(_) => (),
+                None => latest.push(entry),
+            }
+        }
+        latest.sort_by(|a, b| a.title.cmp(&b.title));
+        latest
+    }
+
+    /// Returns each distinct version (by content hash) of the song titled
+    /// `title` (ignoring case), from oldest to newest, as the first copy of it
+    /// found (in the least recently modified file) along with the number of
+    /// copies found.
+    pub fn history(&self, title: &str) -> Vec<(&Entry, usize)> {
+        let mut copies: Vec<&Entry> = self.entries.iter().filter(|e| e.title.eq_ignore_ascii_case(title)).collect();
+        copies.sort_by_key(|e| e.modified); // stable, so ties keep the order the songs were found in
+        let mut history: Vec<(&Entry, usize)> = Vec::new();
+        for entry in copies {
+            match history.iter_mut().find(|(e, _)| e.hash == entry.hash) {
+                Some((_, count)) => *count += 1,
+                None => history.push((entry, 1)),
+            }
+        }
+        history
+    }
+}
+
+impl Entry {
+    /// Returns true if this song's sidecar file gives it the tag `tag`
+    /// (ignoring case).
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.sidecar.as_ref().is_some_and(|s| s.has_tag(tag))
+    }
+
+    /// Returns true if this song's title matches `pattern` or, if `tags` is
+    /// true, any of the tags in its sidecar file do.
+    pub fn matches(&self, pattern: &Pattern, tags: bool) -> bool {
+        pattern.matches(&self.title)
+            || tags && self.sidecar.as_ref().is_some_and(|s| s.tags.iter().any(|t| pattern.matches(t)))
+    }
+
+    /// Returns this entry with its sidecar file read (see `load_sidecar()`).
+    fn with_sidecar(mut self) -> Entry {
+        self.load_sidecar();
+        self
+    }
+
+    /// Reads the sidecar file of this song, if it has one. A sidecar which
+    /// can't be read is reported and ignored.
+    fn load_sidecar(&mut self) {
+        let path = match self.index {
+            Some(_) => sidecar_path(&self.path, Some(&self.title)),
+            None => sidecar_path(&self.path, None),
+        };
+        self.sidecar = Sidecar::load(&path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            None
+        });
+    }
+
+    /// Reads the blocks of compressed song data making up this song from the
+    /// file in which it was found.
+    pub fn export(&self) -> io::Result<Vec<u8>> {
+        match self.index {
+            Some(s) => Ok(LsdjSave::from(&mut File::open(&self.path)?)?.export_song(s)),
+            None => {
+                let bytes = fs::read(&self.path)?;
+                Ok(bytes[(bytes.len() % BLOCK_SIZE)..].to_vec()) // without any header
+            },
+        }
+    }
+}
+
+/// Formats `time` as a UTC date and time (`YYYY-MM-DD HH:MM:SS`).
+pub fn format_time(time: SystemTime) -> String {
+    let secs = time.duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // civil-from-days, counting in 400-year eras starting on March 1st
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Returns the path of the library index: `$LSDJTOOL_INDEX` if set, otherwise
+/// `lsdjtool/library.sqlite` inside `$XDG_CACHE_HOME` (or `~/.cache`).
+#[cfg(feature = "sqlite")]
+pub fn index_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("LSDJTOOL_INDEX") {
+        return Some(PathBuf::from(path));
+    }
+    let dir = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(dir.join("lsdjtool").join("library.sqlite"))
+}
+
+#[cfg(feature = "sqlite")]
+fn sql_err(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(feature = "sqlite")]
+impl Library {
+    /// Indexes every save file and exported song under `dir` like `scan()`,
+    /// but keeps the index in the SQLite database at `index`: only files which
+    /// have changed (or appeared) since they were last recorded there are
+    /// scanned, and files which have disappeared from `dir` are forgotten.
+    pub fn scan_indexed(dir: &Path, index: &Path) -> io::Result<Library> {
+        if let Some(parent) = index.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut db = Connection::open(index).map_err(sql_err)?;
+        db.execute_batch(SCHEMA).map_err(sql_err)?;
+        let tx = db.transaction().map_err(sql_err)?;
+
+        let mut paths = Vec::new();
+        find_files(dir, &["sav", "lsdsng"], &mut paths)?;
+        let mut keys = Vec::with_capacity(paths.len()); // canonical paths, under which files are recorded
+        let mut stale = Vec::new();
+        for path in paths.iter() {
+            let metadata = fs::metadata(path)?;
+            let stamp = (nanos(metadata.modified()?), metadata.len() as i64);
+            let key = fs::canonicalize(path)?.to_string_lossy().into_owned();
+            let recorded: Option<(i64, i64)> = tx.query_row("SELECT modified, len FROM files WHERE path = ?1",
+                                                            [&key], |r| Ok((r.get(0)?, r.get(1)?)))
+                                                 .optional().map_err(sql_err)?;
+            if recorded != Some(stamp) {
+                stale.push((path.clone(), key.clone(), stamp));
+            }
+            keys.push(key);
+        }
+
+        let scanned = parallel::map(&stale, |(path, _, _)| scan_file(path));
+        for ((path, key, (modified, len)), found) in stale.iter().zip(scanned) {
+            tx.execute("DELETE FROM files WHERE path = ?1", [key]).map_err(sql_err)?;
+            let found = match found {
+                Ok(found) => found,
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    continue;
+                },
+            };
+            tx.execute("INSERT INTO files (path, modified, len) VALUES (?1, ?2, ?3)",
+                       params![key, modified, len]).map_err(sql_err)?;
+            for entry in found {
+                tx.execute("INSERT INTO songs (path, idx, title, version, hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+                           params![key, entry.index, entry.title, entry.version, entry.hash as i64])
+                  .map_err(sql_err)?;
+            }
+        }
+
+        // forget files under dir which no longer exist
+        let prefix = fs::canonicalize(dir)?;
+        let found: HashSet<&String> = keys.iter().collect();
+        let recorded: Vec<String> = tx.prepare("SELECT path FROM files").map_err(sql_err)?
+                                      .query_map([], |r| r.get(0)).map_err(sql_err)?
+                                      .collect::<Result<_, _>>().map_err(sql_err)?;
+        for key in recorded.iter().filter(|k| Path::new(k).starts_with(&prefix) && !found.contains(k)) {
+            tx.execute("DELETE FROM files WHERE path = ?1", [key]).map_err(sql_err)?;
+        }
+
+        let mut entries = Vec::new();
+        let modified: HashMap<String, i64> = tx.prepare("SELECT path, modified FROM files").map_err(sql_err)?
+                                               .query_map([], |r| Ok((r.get(0)?, r.get(1)?))).map_err(sql_err)?
+                                               .collect::<Result<_, _>>().map_err(sql_err)?;
+        {
+            let mut query = tx.prepare("SELECT idx, title, version, hash FROM songs WHERE path = ?1 ORDER BY idx")
+                              .map_err(sql_err)?;
+            for (path, key) in paths.iter().zip(keys.iter()) {
+                let stamp = match modified.get(key) {
+                    Some(&m) => UNIX_EPOCH + Duration::from_nanos(m as u64),
+                    None => continue, // couldn't be scanned
+                };
+                let rows = query.query_map([key], |r| Ok(Entry {
+                    path: path.clone(),
+                    index: r.get(0)?,
+                    title: r.get(1)?,
+                    version: r.get(2)?,
+                    hash: r.get::<_, i64>(3)? as u64,
+                    modified: stamp,
+                    sidecar: None,
+                })).map_err(sql_err)?;
+                entries.extend(rows.collect::<Result<Vec<Entry>, _>>().map_err(sql_err)?);
+            }
+        }
+        tx.commit().map_err(sql_err)?;
+        // sidecars aren't indexed, since editing one doesn't touch the song's file
+        entries.iter_mut().for_each(Entry::load_sidecar);
+        Ok(Library { entries })
+    }
+}
+
+/// Returns `time` as a number of nanoseconds since the Unix epoch.
+#[cfg(feature = "sqlite")]
+fn nanos(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i64).unwrap_or(0)
+}
+
+/// Returns an entry for each song in the save file or exported song at `path`.
+pub fn scan_file(path: &Path) -> io::Result<Vec<Entry>> {
+    let modified = fs::metadata(path)?.modified()?;
+    let is_lsdsng = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("lsdsng"));
+    if !is_lsdsng {
+        let save = LsdjSave::from(&mut File::open(path)?)?;
+        return save.metadata.songs().into_iter().map(|s| Ok(Entry {
+            path: path.to_path_buf(),
+            index: Some(s),
+            title: save.metadata.song_title(s),
+            version: Some(save.metadata.version_table[s as usize]),
+            hash: save.song_hash(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            modified,
+            sidecar: None,
+        }.with_sidecar())).collect();
+    }
+    let bytes = fs::read(path)?;
+    let (title, version, blocks) = if bytes.len() % BLOCK_SIZE == LSDSNG_HEADER_LENGTH {
+        let title = &bytes[..TITLE_LENGTH];
+        let end = title.iter().position(|&c| c == 0).unwrap_or(TITLE_LENGTH);
+        (String::from_utf8_lossy(&title[..end]).into_owned(), Some(bytes[TITLE_LENGTH]), &bytes[LSDSNG_HEADER_LENGTH..])
+    } else {
+        (path.file_stem().unwrap_or_default().to_string_lossy().into_owned(), None, &bytes[..])
+    };
+    let hash = crate::lsdj::blocks_hash(blocks).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(vec![Entry { path: path.to_path_buf(), index: None, title, version, hash, modified, sidecar: None }.with_sidecar()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(std::time::UNIX_EPOCH), "1970-01-01 00:00:00");
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(951_782_400 + 3661);
+        assert_eq!(format_time(time), "2000-02-29 01:01:01");
+    }
+
+    #[test]
+    fn test_history() {
+        let entry = |hash: u64, secs: u64| Entry {
+            path: PathBuf::from(format!("{}.sav", secs)),
+            index: Some(0),
+            title: "TRACK".to_string(),
+            version: Some(secs as u8),
+            hash,
+            modified: std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs),
+            sidecar: None,
+        };
+        let mut other = entry(5, 0);
+        other.title = "OTHER".to_string();
+        let library = Library { entries: vec![entry(2, 20), entry(1, 10), entry(2, 30), entry(1, 40), other] };
+        let history: Vec<(u64, u64, usize)> = library.history("track").into_iter()
+            .map(|(e, n)| (e.hash, e.modified.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(), n))
+            .collect();
+        assert_eq!(history, vec![(1, 10, 2), (2, 20, 2)]);
+    }
+
+    #[test]
+    fn test_scan_sidecar() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("lsdjtool-sidecar-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let mut save = LsdjSave::empty();
+        save.import_decompressed_song(&[0; 0x8000], [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        save.import_decompressed_song(&[1; 0x8000], [b'B', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        fs::write(dir.join("a.sav"), save.bytes())?;
+        fs::write(dir.join("a.B.toml"), "tags = [\"live\"]\nbpm = 140\n")?;
+
+        let library = Library::scan(&dir)?;
+        assert_eq!(library.entries[0].sidecar, None);
+        assert_eq!(library.entries[1].sidecar.as_ref().and_then(|s| s.bpm), Some(140));
+        let tagged: Vec<&str> = library.entries.iter().filter(|e| e.has_tag("LIVE")).map(|e| e.title.as_str()).collect();
+        assert_eq!(tagged, vec!["B"]);
+        fs::remove_dir_all(&dir)
+    }
+
+    #[test]
+    fn test_pattern() {
+        let glob = Pattern::glob("old*j?m");
+        assert!(glob.matches("OLDJAM") && glob.matches("OLD_JIM") && glob.matches("oldjam"));
+        assert!(!glob.matches("OLDJAMS") && !glob.matches("MYOLDJAM"));
+        assert!(Pattern::glob("*").matches("") && !Pattern::glob("A").matches(""));
+        let regex = Pattern::regex("^(old|new)jam[0-9]").unwrap();
+        assert!(regex.matches("OLDJAM2X") && !regex.matches("OLDJAM"));
+        assert!(Pattern::regex("(").is_err());
+
+        let mut entry = Entry {
+            path: PathBuf::from("a.sav"),
+            index: Some(0),
+            title: "TRACK".to_string(),
+            version: Some(0),
+            hash: 0,
+            modified: std::time::UNIX_EPOCH,
+            sidecar: Some(Sidecar { tags: vec!["live".to_string()], ..Sidecar::default() }),
+        };
+        assert!(entry.matches(&Pattern::glob("tr*"), false));
+        assert!(!entry.matches(&Pattern::glob("LIVE"), false));
+        assert!(entry.matches(&Pattern::glob("LIVE"), true));
+        entry.sidecar = None;
+        assert!(!entry.matches(&Pattern::glob("LIVE"), true));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_scan_indexed() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("lsdjtool-library-{}", std::process::id()));
+        let saves = dir.join("saves");
+        fs::create_dir_all(&saves)?;
+        let index = dir.join("library.sqlite");
+        let mut save = LsdjSave::empty();
+        save.import_decompressed_song(&[0; 0x8000], [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        fs::write(saves.join("a.sav"), save.bytes())?;
+
+        let scanned = Library::scan(&saves)?.entries;
+        assert_eq!(Library::scan_indexed(&saves, &index)?.entries, scanned);
+
+        // unchanged files are read from the index rather than scanned again
+        Connection::open(&index).map_err(sql_err)?.execute("UPDATE songs SET hash = 1", []).map_err(sql_err)?;
+        assert_eq!(Library::scan_indexed(&saves, &index)?.entries[0].hash, 1);
+
+        save.import_decompressed_song(&[1; 0x8000], [b'B', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        fs::write(saves.join("b.sav"), save.bytes())?;
+        let entries = Library::scan_indexed(&saves, &index)?.entries;
+        assert_eq!(entries[1..], Library::scan(&saves)?.entries[1..]);
+        assert_eq!(entries.len(), 3);
+
+        fs::remove_file(saves.join("a.sav"))?;
+        assert_eq!(Library::scan_indexed(&saves, &index)?.entries.len(), 2);
+        let recorded: i64 = Connection::open(&index).map_err(sql_err)?
+                                       .query_row("SELECT COUNT(*) FROM songs", [], |r| r.get(0)).map_err(sql_err)?;
+        assert_eq!(recorded, 2);
+        fs::remove_dir_all(&dir)
+    }
+}
@@ -0,0 +1,98 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Environment variable naming the most detailed level of log messages to
+/// print: off (the default), error, warn, info, debug, or trace.
+const LOG_VAR: &str = "LSDJTOOL_LOG";
+
+/// Prints log messages from lsdjtool itself (not its dependencies) to stderr,
+/// prefixed with their level and the module they came from.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level() && metadata.target().starts_with("lsdjtool")
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{} {}: {}", record.level().as_str().to_lowercase(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Whether `-q` was given, silencing status messages (see `status!`).
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Returns true if status messages are silenced.
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Returns the level of log messages printed with `verbose` `-v` flags: info
+/// (files read and written) with one, debug (blocks given to songs) with two,
+/// and trace (every run compressed) with three or more.
+fn verbose_level(verbose: u8) -> LevelFilter {
+    match verbose {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Parses the level of log messages to print, as named in `$LSDJTOOL_LOG`.
+fn parse_level(level: &str) -> io::Result<LevelFilter> {
+    level.parse().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{}: unknown log level {:?}", LOG_VAR, level))
+    })
+}
+
+/// Prints log messages to stderr at the level named in `$LSDJTOOL_LOG`, or
+/// given by `verbose` `-v` flags if that's more detailed, so that compression
+/// and allocation decisions can be traced (e.g. with `LSDJTOOL_LOG=trace` or
+/// `-vvv`) when a song comes out corrupted. Status messages are silenced if
+/// `quiet` is true.
+pub fn init(quiet: bool, verbose: u8) -> io::Result<()> {
+    QUIET.store(quiet, Ordering::Relaxed);
+    let level = match std::env::var(LOG_VAR) {
+        Ok(level) => parse_level(&level)?,
+        Err(_) => LevelFilter::Off,
+    }.max(verbose_level(verbose));
+    log::set_logger(&LOGGER).map_err(|e| io::Error::other(e.to_string()))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("trace").unwrap(), LevelFilter::Trace);
+        assert_eq!(parse_level("DEBUG").unwrap(), LevelFilter::Debug);
+        assert_eq!(parse_level("off").unwrap(), LevelFilter::Off);
+        assert_eq!(parse_level("loud").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(verbose_level(0), LevelFilter::Off);
+        assert_eq!(verbose_level(2), LevelFilter::Debug);
+        assert_eq!(verbose_level(5), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_enabled() {
+        log::set_max_level(LevelFilter::Debug);
+        let metadata = |level, target| Metadata::builder().level(level).target(target).build();
+        assert!(LOGGER.enabled(&metadata(Level::Debug, "lsdjtool::lsdj::compression")));
+        assert!(!LOGGER.enabled(&metadata(Level::Trace, "lsdjtool::lsdj::compression")));
+        assert!(!LOGGER.enabled(&metadata(Level::Debug, "ureq::pool")));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::lsdj::prelude::*;
+use crate::lsdj::{err, lsdjtitle_from, read_blocks, LsdjError};
+use crate::lsdj::container::{crc32, Container};
+
+const BEGIN: &str = "-----BEGIN LSDJ SONG-----";
+const END: &str = "-----END LSDJ SONG-----";
+/// Starts the first line of each part made by `split()`.
+const PART: &str = "LSDJ PART";
+/// Characters of base64 on each line.
+const LINE_LENGTH: usize = 64;
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Writes a song as ASCII-armored text which can be pasted into forums and
+/// chats: header lines giving its title, versions, block count, and the CRC32
+/// of its blocks, then a blank line and the blocks in base64, between begin
+/// and end lines.
+pub fn armor(song: &Container) -> String {
+    let end = song.title.iter().position(|&c| c == 0).unwrap_or(song.title.len());
+    let mut text = format!("{}\nTitle: {}\nVersion: {:02X}\nFormat: {:02X}\nBlocks: {}\nCRC32: {:08x}\n\n", BEGIN,
+                           String::from_utf8_lossy(&song.title[..end]), song.version, song.format_version,
+                           song.blocks.len() / crate::lsdj::BLOCK_SIZE, crc32(&song.blocks));
+    let encoded = base64_encode(&song.blocks);
+    for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+        text.push_str(core::str::from_utf8(line).unwrap_or_default());
+        text.push('\n');
+    }
+    text.push_str(END);
+    text.push('\n');
+    text
+}
+
+/// Returns true if `bytes` contain an armored song (see `armor()`), which may
+/// be surrounded by other text.
+pub fn is_armored(bytes: &[u8]) -> bool {
+    bytes.windows(BEGIN.len()).any(|w| w == BEGIN.as_bytes())
+}
+
+/// Reads the first armored song in `text`, ignoring any text around it, and
+/// checks the checksum of its blocks.
+pub fn dearmor(text: &str) -> Result<Container, LsdjError> {
+    let start = text.find(BEGIN).ok_or(err::BAD_ARMOR)? + BEGIN.len();
+    let body = text[start..][..text[start..].find(END).ok_or(err::BAD_ARMOR)?].replace("\r\n", "\n");
+    let (header, data) = body.trim_start_matches('\n').split_once("\n\n").ok_or(err::BAD_ARMOR)?;
+    let field = |name: &str| -> Result<&str, &'static str> {
+        header.lines().find_map(|line| line.strip_prefix(name)?.strip_prefix(':').map(str::trim)).ok_or(err::BAD_ARMOR)
+    };
+    let byte = |name: &str| -> Result<u8, &'static str> {
+        u8::from_str_radix(field(name)?, 16).map_err(|_| err::BAD_ARMOR)
+    };
+
+    let encoded = base64_decode(data)?;
+    let expected = u32::from_str_radix(field("CRC32")?, 16).map_err(|_| err::BAD_ARMOR)?;
+    let got = crc32(&encoded);
+    if got != expected {
+        return Err(LsdjError::BadChecksum { expected, got });
+    }
+    let mut blocks = Vec::with_capacity(encoded.len());
+    let block_count = read_blocks(&encoded[..], &mut blocks, false)?;
+    if field("Blocks")?.parse() != Ok(block_count) {
+        return Err(LsdjError::Invalid(err::BAD_ARMOR));
+    }
+    Ok(Container {
+        title: lsdjtitle_from(field("Title")?)?,
+        version: byte("Version")?,
+        format_version: byte("Format")?,
+        blocks,
+    })
+}
+
+/// Splits armored text into numbered parts of whole lines, at most `length`
+/// characters of text each (unless a single line is longer), small enough to
+/// fit in a QR code (see `join()`). Each part starts with a line like
+/// `LSDJ PART 2/5`.
+pub fn split(text: &str, length: usize) -> Vec<String> {
+    let mut chunks: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match chunks.last_mut() {
+            Some(chunk) if chunk.len() + line.len() < length => chunk.push_str(line),
+            _ => chunks.push(line.to_string()),
+        }
+        chunks.last_mut().into_iter().for_each(|chunk| chunk.push('\n'));
+    }
+    let count = chunks.len();
+    chunks.iter().enumerate().map(|(i, chunk)| format!("{} {}/{}\n{}", PART, i + 1, count, chunk)).collect()
+}
+
+/// Joins parts made by `split()`, given in any order, back into armored text.
+/// Whitespace added around each part (as by some QR scanners) is ignored.
+/// Returns an `Err` if any part is missing, repeated, or has no part line.
+pub fn join<S: AsRef<str>>(parts: &[S]) -> Result<String, &'static str> {
+    let mut numbered = Vec::with_capacity(parts.len());
+    for part in parts {
+        let (line, chunk) = part.as_ref().trim().split_once('\n').ok_or(err::BAD_PARTS)?;
+        let (i, n) = line.trim_end().strip_prefix(PART).and_then(|p| p.trim().split_once('/')).ok_or(err::BAD_PARTS)?;
+        let (i, n): (usize, usize) = (i.parse().map_err(|_| err::BAD_PARTS)?, n.parse().map_err(|_| err::BAD_PARTS)?);
+        numbered.push((i, n, chunk));
+    }
+    numbered.sort_by_key(|&(i, _, _)| i);
+    if numbered.iter().enumerate().any(|(k, &(i, n, _))| i != k + 1 || n != numbered.len()) {
+        return Err(err::BAD_PARTS);
+    }
+    Ok(numbered.iter().map(|(_, _, chunk)| format!("{}\n", chunk)).collect())
+}
+
+/// Encodes `bytes` as base64 (with padding).
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            out.push(if i <= chunk.len() { BASE64[(n >> (18 - 6 * i)) as usize & 0x3f] as char } else { '=' });
+        }
+    }
+    out
+}
+
+/// Decodes base64 (with or without padding), ignoring whitespace.
+fn base64_decode(text: &str) -> Result<Vec<u8>, &'static str> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut n, mut bits) = (0u32, 0);
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()).take_while(|&c| c != b'=') {
+        let value = BASE64.iter().position(|&b| b == c).ok_or(err::BAD_ARMOR)?;
+        n = (n << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::BLOCK_SIZE;
+
+    #[test]
+    fn test_base64() {
+        for (bytes, text) in [(&b""[..], ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"), (b"foobar", "Zm9vYmFy")] {
+            assert_eq!(base64_encode(bytes), text);
+            assert_eq!(base64_decode(text).unwrap(), bytes);
+        }
+        assert_eq!(base64_decode("Zm9v\nYmFy"), Ok(b"foobar".to_vec()));
+        assert_eq!(base64_decode("Zm9v!"), Err(err::BAD_ARMOR));
+    }
+
+    #[test]
+    fn test_armor() {
+        let mut blocks = vec![0; BLOCK_SIZE];
+        blocks[..3].copy_from_slice(&[0xe0, 0xff, 0x00]); // end of file
+        let song = Container { title: *b"TEST\0\0\0\0", version: 3, format_version: 0x16, blocks };
+        let text = armor(&song);
+        assert!(text.starts_with("-----BEGIN LSDJ SONG-----\nTitle: TEST\nVersion: 03\nFormat: 16\nBlocks: 1\n"));
+        let pasted = format!("check this out:\n\n{}\nthanks!", text);
+        assert!(is_armored(pasted.as_bytes()));
+        assert_eq!(dearmor(&pasted).unwrap(), song);
+
+        let corrupted = text.replacen("4P8A", "4P8B", 1);
+        assert!(matches!(dearmor(&corrupted), Err(LsdjError::BadChecksum { .. })));
+        assert!(matches!(dearmor(&text[..40]), Err(LsdjError::Invalid(e)) if e == err::BAD_ARMOR));
+
+        let mut parts = split(&text, 200);
+        assert!(parts.len() >= text.len().div_ceil(200));
+        assert!(parts.iter().all(|p| p.len() <= 200 + "LSDJ PART 1/9\n".len()));
+        assert!(parts[0].starts_with(&format!("LSDJ PART 1/{}\n-----BEGIN", parts.len())));
+        parts.swap(0, 2);
+        parts[1].push_str("\r\n");
+        assert_eq!(join(&parts), Ok(text.clone()));
+        parts.pop();
+        assert_eq!(join(&parts), Err(err::BAD_PARTS));
+    }
+}
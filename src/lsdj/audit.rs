@@ -0,0 +1,206 @@
+use alloc::collections::BTreeSet;
+use core::fmt;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::{Compat, LsdjBlockTable};
+use crate::lsdj::metadata::{LsdjMetadata, SONG_SLOTS};
+
+/// A disagreement between the allocation table and the skip instructions
+/// which chain a song's blocks together, as found by `audit()`. Blocks are
+/// one-indexed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockProblem {
+    /// The chain of `song` comes back around to `block`.
+    Cycle { song: u8, block: usize },
+    /// The chain of `song` skips to `block`, which is allocated to `owner`
+    /// (or to no song, if `None`) rather than to `song`.
+    CrossesInto { song: u8, block: usize, owner: Option<u8> },
+    /// The chain of `song` breaks at `block`, which ends with neither an
+    /// end-of-file instruction nor a skip to a block in the save file.
+    Broken { song: u8, block: usize },
+    /// `block` is allocated to `song`, but the chain of `song` never reaches
+    /// it.
+    Unreachable { song: u8, block: usize },
+}
+
+impl BlockProblem {
+    /// Returns the song whose chain the problem was found in.
+    pub fn song(&self) -> u8 {
+        match *self {
+            BlockProblem::Cycle { song, .. } | BlockProblem::CrossesInto { song, .. } |
+            BlockProblem::Broken { song, .. } | BlockProblem::Unreachable { song, .. } => song,
+        }
+    }
+}
+
+impl fmt::Display for BlockProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockProblem::Cycle { song, block } =>
+                write!(f, "song {:02X}: chain loops back to block {:02X}", song, block),
+            BlockProblem::CrossesInto { song, block, owner: Some(owner) } =>
+                write!(f, "song {:02X}: chain crosses into block {:02X} of song {:02X}", song, block, owner),
+            BlockProblem::CrossesInto { song, block, owner: None } =>
+                write!(f, "song {:02X}: chain crosses into free block {:02X}", song, block),
+            BlockProblem::Broken { song, block } =>
+                write!(f, "song {:02X}: chain breaks at block {:02X} (no valid skip or end of file)", song, block),
+            BlockProblem::Unreachable { song, block } =>
+                write!(f, "song {:02X}: block {:02X} is allocated but never reached", song, block),
+        }
+    }
+}
+
+/// Follows the chain of skip instructions of every song from the first block
+/// allocated to it (where LSDj starts loading it), cross-checking each block
+/// against the allocation table. Returns the problems found, ordered by song.
+pub fn audit(metadata: &LsdjMetadata, blocks: &LsdjBlockTable, compat: Compat) -> Vec<BlockProblem> {
+    let mut problems = Vec::new();
+    for song in 0..SONG_SLOTS as u8 {
+        let first = match metadata.next_block_for(song, 0) {
+            Some(b) => b,
+            None => continue,
+        };
+        let mut reached = BTreeSet::new();
+        let mut current = first;
+        loop {
+            if reached.contains(&current) {
+                problems.push(BlockProblem::Cycle { song, block: current });
+                break;
+            }
+            let owner = metadata.alloc_table[current - 1];
+            if owner != song {
+                let owner = Some(owner).filter(|&o| o != 0xff);
+                problems.push(BlockProblem::CrossesInto { song, block: current, owner });
+                break;
+            }
+            reached.insert(current);
+            match blocks.get(current).and_then(|b| b.next_block(compat)) {
+                Some(0) => break, // end of file
+                Some(n) if blocks.get(n as usize).is_some() => current = n as usize,
+                _ => {
+                    problems.push(BlockProblem::Broken { song, block: current });
+                    break;
+                },
+            }
+        }
+        for block in (1..=blocks.len()).filter(|&b| metadata.alloc_table[b - 1] == song && !reached.contains(&b)) {
+            problems.push(BlockProblem::Unreachable { song, block });
+        }
+    }
+    problems
+}
+
+/// Rebuilds the chain of `song` from the order of the blocks allocated to it
+/// in the allocation table, rewriting the skip instruction at the end of
+/// each block to point to the next one (see `LsdjBlock::rechain()`). The
+/// chain ends with an end-of-file instruction in the last block, or in the
+/// block which fills SRAM, in which case the blocks allocated after it are
+/// freed. Returns the number of blocks freed.
+pub fn repair_chain(metadata: &mut LsdjMetadata, blocks: &mut LsdjBlockTable, song: u8, compat: Compat) -> usize {
+    let allocated: Vec<usize> = (1..=blocks.len()).filter(|&b| metadata.alloc_table[b - 1] == song).collect();
+    let mut position = 0;
+    for (i, &block) in allocated.iter().enumerate() {
+        let next = allocated.get(i + 1).map_or(0, |&b| b as u8);
+        let ended = match blocks.get_mut(block) {
+            Some(b) => {
+                let (reached, ended) = b.rechain(next, position, compat);
+                position = reached;
+                ended
+            },
+            None => true,
+        };
+        if ended {
+            for &freed in allocated[(i + 1)..].iter() {
+                metadata.alloc_table[freed - 1] = 0xff;
+            }
+            return allocated.len() - i - 1;
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::{err, LsdjBlock, LsdjError, LsdjSave, LsdjSram};
+
+    /// Returns a save holding two copies of a song which doesn't compress,
+    /// each spanning many blocks: song 0 first, then song 1.
+    fn two_song_save() -> LsdjSave {
+        let mut sram = LsdjSram::empty();
+        for (i, byte) in sram.data.iter_mut().enumerate() {
+            *byte = (i % 0x61) as u8;
+        }
+        let mut save = LsdjSave::empty();
+        let title = [b'T', b'E', b'S', b'T', 0, 0, 0, 0];
+        for _ in 0..2 {
+            save.import_decompressed_song(&sram.data[..], title).unwrap();
+        }
+        save
+    }
+
+    /// Points the skip instruction at the end of `block` to `to`.
+    fn skip(save: &mut LsdjSave, block: usize, to: usize) {
+        save.blocks_mut().get_mut(block).unwrap().skip_to_block(to, Compat::Lsdj).unwrap();
+    }
+
+    #[test]
+    fn test_audit() {
+        let save = two_song_save();
+        assert_eq!(save.metadata.size_of(0), save.metadata.size_of(1));
+        assert_eq!(save.audit_blocks(), vec![]);
+        let size = save.metadata.size_of(0);
+
+        let mut cycle = save.clone();
+        skip(&mut cycle, 2, 1);
+        assert_eq!(cycle.audit_blocks()[0], BlockProblem::Cycle { song: 0, block: 1 });
+        assert!(cycle.audit_blocks().contains(&BlockProblem::Unreachable { song: 0, block: 3 }));
+
+        let mut crossing = save.clone();
+        skip(&mut crossing, 1, size + 2);
+        assert_eq!(crossing.audit_blocks()[..2], [
+            BlockProblem::CrossesInto { song: 0, block: size + 2, owner: Some(1) },
+            BlockProblem::Unreachable { song: 0, block: 2 },
+        ]);
+        skip(&mut crossing, 1, 0xbe);
+        assert_eq!(crossing.audit_blocks()[0], BlockProblem::CrossesInto { song: 0, block: 0xbe, owner: None });
+
+        let mut broken = save.clone();
+        broken.blocks_mut().replace(2, LsdjBlock::empty()).unwrap();
+        assert_eq!(broken.audit_blocks()[0], BlockProblem::Broken { song: 0, block: 2 });
+        assert_eq!(broken.audit_blocks()[0].song(), 0);
+        assert!(broken.audit_blocks().iter().all(|p| p.song() == 0));
+    }
+
+    #[test]
+    fn test_repair_chain() {
+        let save = two_song_save();
+        let sram = save.decompress_song(0).unwrap();
+        let size = save.metadata.size_of(0);
+
+        let mut crossing = save.clone();
+        skip(&mut crossing, 1, size + 2);
+        assert_eq!(crossing.repair_chain(0).ok(), Some(0));
+        assert_eq!(crossing.audit_blocks(), vec![]);
+        assert_eq!(crossing, save);
+
+        // drop the end-of-file instruction from the last block
+        let mut unterminated = save.clone();
+        let last = unterminated.blocks_mut().get_mut(size).unwrap();
+        let end = last.data.windows(2).rposition(|w| w == [0xe0, 0xff]).unwrap();
+        last.data[end..end + 2].copy_from_slice(&[0, 0]);
+        assert!(unterminated.decompress_song(0).is_err());
+        assert_eq!(unterminated.repair_chain(0).ok(), Some(0));
+        assert_eq!(unterminated.audit_blocks(), vec![]);
+        assert_eq!(unterminated.decompress_song(0).unwrap(), sram);
+
+        // a block past the one filling SRAM is freed
+        let mut overlong = save.clone();
+        overlong.metadata.alloc_table[0xbe - 1] = 0;
+        assert_eq!(overlong.repair_chain(0).ok(), Some(1));
+        assert_eq!(overlong.metadata.alloc_table[0xbe - 1], 0xff);
+        assert_eq!(overlong.decompress_song(0).unwrap(), sram);
+
+        assert!(matches!(LsdjSave::empty().repair_chain(0), Err(LsdjError::Invalid(e)) if e == err::NO_SONG));
+    }
+}
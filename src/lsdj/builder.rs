@@ -0,0 +1,96 @@
+use crate::lsdj::{err, lsdjtitle_from, LsdjLayout, LsdjSave, SRAM_SIZE};
+
+/// Builds a save file from scratch, starting from an empty, initialized save
+/// and adding songs one at a time; blocks are allocated as each song is
+/// added.
+///
+/// ```ignore
+/// let save = LsdjSaveBuilder::new()
+///     .add_song(&blocks, "INTRO", 3)?
+///     .set_working(&sram)?
+///     .build();
+/// ```
+pub struct LsdjSaveBuilder {
+    save: LsdjSave,
+}
+
+impl LsdjSaveBuilder {
+    /// Starts building an empty 128KB save.
+    pub fn new() -> LsdjSaveBuilder {
+        LsdjSaveBuilder::with_layout(LsdjLayout::SAVE_128KB)
+    }
+
+    /// Starts building an empty save with the given layout.
+    pub fn with_layout(layout: LsdjLayout) -> LsdjSaveBuilder {
+        LsdjSaveBuilder { save: LsdjSave::empty_with_layout(layout) }
+    }
+
+    /// Adds a song from blocks of compressed song data (as exported by
+    /// `LsdjSave::export_song()`) in the first free song slot, with the given
+    /// title and version byte. Returns an `Err` under the same conditions as
+    /// `LsdjSave::import_song()`, or if `title` isn't a valid song title.
+    pub fn add_song(mut self, bytes: &[u8], title: &str, version: u8) -> Result<LsdjSaveBuilder, &'static str> {
+        let song = self.save.import_song(bytes, lsdjtitle_from(title)?)?;
+        self.save.metadata.version_table[song as usize] = version;
+        Ok(self)
+    }
+
+    /// Adds a song from a decompressed SRAM image ($8000 bytes), compressing
+    /// it first (see `add_song()`).
+    pub fn add_decompressed_song(mut self, sram: &[u8], title: &str, version: u8) -> Result<LsdjSaveBuilder, &'static str> {
+        let song = self.save.import_decompressed_song(sram, lsdjtitle_from(title)?)?;
+        self.save.metadata.version_table[song as usize] = version;
+        Ok(self)
+    }
+
+    /// Sets the working song: the decompressed SRAM image ($8000 bytes) which
+    /// LSDj loads on startup. Returns an `Err` if `sram` is the wrong size.
+    pub fn set_working(mut self, sram: &[u8]) -> Result<LsdjSaveBuilder, &'static str> {
+        if sram.len() != SRAM_SIZE {
+            return Err(err::BAD_FMT);
+        }
+        self.save.sram.data.copy_from_slice(sram);
+        Ok(self)
+    }
+
+    /// Returns the save built.
+    pub fn build(self) -> LsdjSave {
+        self.save
+    }
+}
+
+impl Default for LsdjSaveBuilder {
+    fn default() -> LsdjSaveBuilder {
+        LsdjSaveBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::{LsdjBlockExt, LsdjSram};
+
+    #[test]
+    fn test_builder() {
+        let mut sram = LsdjSram::empty();
+        for (i, byte) in sram.data.iter_mut().enumerate() {
+            *byte = (i % 0x61) as u8;
+        }
+        let blocks = sram.compress_into(1..).unwrap().bytes();
+        let save = LsdjSaveBuilder::new()
+            .add_song(&blocks, "INTRO", 3).unwrap()
+            .add_decompressed_song(&[0; SRAM_SIZE], "OUTRO", 7).unwrap()
+            .set_working(&sram.data).unwrap()
+            .build();
+        assert_eq!(save.metadata.song_title(0), "INTRO");
+        assert_eq!(save.metadata.version_table[..2], [3, 7]);
+        assert_eq!(save.export_song(0), blocks);
+        assert_eq!(save.decompress_song(1).unwrap(), [0; SRAM_SIZE]);
+        assert_eq!(save.bytes()[..SRAM_SIZE], sram.data[..]);
+
+        assert_eq!(LsdjSaveBuilder::new().add_song(&blocks, "too long!", 0).err(), Some(err::BAD_TITLE_FMT));
+        assert_eq!(LsdjSaveBuilder::new().set_working(&[0; 0x10]).err(), Some(err::BAD_FMT));
+        let full = LsdjSaveBuilder::with_layout(LsdjLayout::new(4).unwrap());
+        assert_eq!(full.add_song(&blocks, "INTRO", 0).err(), Some(err::NO_BLOCKS));
+    }
+}
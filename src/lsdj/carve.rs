@@ -0,0 +1,125 @@
+use core::fmt;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::{err, LsdjLayout, LsdjSave, BLOCK_ADDRESS, BLOCK_SIZE};
+use crate::lsdj::metadata::{SONG_SLOTS, SRAM_INIT_CHK_ADDRESS, SRAM_INIT_CHK_BYTES};
+
+/// Where in a dump `carve()` found a save.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Carving {
+    /// Offset of the save in the dump (after de-interleaving, if `interleaved`).
+    pub offset: usize,
+    /// Whether the save was found in every other byte of the dump, starting
+    /// from the first (`Some(0)`) or second (`Some(1)`).
+    pub interleaved: Option<usize>,
+    /// Number of blocks the dump holds after the metadata; any blocks past them
+    /// are carved as empty blocks.
+    pub blocks: usize,
+}
+
+impl fmt::Display for Carving {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "save found at offset ${:x}", self.offset)?;
+        if let Some(phase) = self.interleaved {
+            write!(f, " in every other byte (from byte {})", phase)?;
+        }
+        write!(f, ", holding {} block(s)", self.blocks)
+    }
+}
+
+/// Returns true if every entry of the allocation table of `save` is either
+/// free or a valid song index, which is unlikely of bytes which merely happen
+/// to follow a 'jk' elsewhere in a dump.
+fn plausible(save: &LsdjSave) -> bool {
+    save.metadata.alloc_table.iter().all(|&b| b == 0xff || (b as usize) < SONG_SLOTS)
+}
+
+/// Returns the number of songs in `save` which decompress, by which
+/// candidate saves are ranked.
+fn score(save: &LsdjSave) -> usize {
+    save.metadata.songs().into_iter().filter(|&s| save.decompress_song(s).is_ok()).count()
+}
+
+/// Looks for saves in `bytes` at every offset which puts a 'jk' at the SRAM
+/// initialization check, returning each plausible one found along with its
+/// offset and the number of blocks `bytes` holds after it.
+fn candidates(bytes: &[u8]) -> Vec<(LsdjSave, usize, usize)> {
+    let chk = SRAM_INIT_CHK_ADDRESS as usize;
+    let min_len = BLOCK_ADDRESS as usize + BLOCK_SIZE;
+    bytes.windows(2).enumerate()
+        .filter(|&(i, w)| i >= chk && w == SRAM_INIT_CHK_BYTES)
+        .map(|(i, _)| i - chk)
+        .filter(|&offset| bytes.len() - offset >= min_len)
+        .filter_map(|offset| {
+            let available = (bytes.len() - offset).min(LsdjLayout::SAVE_128KB.save_size());
+            let blocks = (available - BLOCK_ADDRESS as usize) / BLOCK_SIZE;
+            let len = BLOCK_ADDRESS as usize + blocks * BLOCK_SIZE;
+            let layout = LsdjLayout::new(blocks).ok()?; // however much of the save the dump holds
+            let mut save = LsdjSave::from_bytes_with_layout(&bytes[offset..offset + len], layout).ok()?;
+            save.set_layout(LsdjLayout::SAVE_128KB).ok()?;
+            Some((save, offset, blocks)).filter(|(s, ..)| plausible(s))
+        })
+        .collect()
+}
+
+/// Carves a 128KB save out of an arbitrary binary dump, such as those written
+/// by flashcart dumping tools: the save may be preceded by headers, followed
+/// by other data, cut short, or stored in every other byte (as dumped over a
+/// 16-bit bus).
+///
+/// Saves are located by their SRAM initialization check bytes ('jk') and a
+/// plausible allocation table after them; of those found, the one with the
+/// most songs which decompress is carved (the first found, on a tie). A dump
+/// which ends before the last block is carved with empty blocks in place of
+/// those missing. Returns an `Err` if no save is found.
+pub fn carve(dump: &[u8]) -> Result<(LsdjSave, Carving), &'static str> {
+    let mut found: Vec<(LsdjSave, Carving)> = candidates(dump).into_iter()
+        .map(|(save, offset, blocks)| (save, Carving { offset, interleaved: None, blocks }))
+        .collect();
+    for phase in 0..2 {
+        let bytes: Vec<u8> = dump.iter().skip(phase).step_by(2).copied().collect();
+        found.extend(candidates(&bytes).into_iter()
+            .map(|(save, offset, blocks)| (save, Carving { offset, interleaved: Some(phase), blocks })));
+    }
+    let best = found.iter().map(|(s, _)| score(s)).enumerate().rev().max_by_key(|&(_, score)| score);
+    match best {
+        Some((i, _)) => Ok(found.swap_remove(i)),
+        None => Err(err::NO_SAVE_FOUND),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_carve() {
+        let mut save = LsdjSave::empty();
+        save.import_decompressed_song(&[0x12; 0x8000], [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        let bytes = save.bytes();
+
+        let mut dump = b"DUMPER v1.0 jk\0\0".to_vec();
+        dump.extend_from_slice(&bytes);
+        dump.extend_from_slice(&[0xff; 0x1000]);
+        let (carved, carving) = carve(&dump).unwrap();
+        assert_eq!(carved, save);
+        assert_eq!(carving, Carving { offset: 0x10, interleaved: None, blocks: 0xbf });
+
+        let (carved, carving) = carve(&dump[..0x10 + 0x8200 + 0x200 * 4]).unwrap();
+        assert_eq!(carved.layout(), LsdjLayout::SAVE_128KB);
+        assert_eq!(carved.export_song(0), save.export_song(0));
+        assert_eq!(carving.blocks, 4);
+
+        let interleaved: Vec<u8> = bytes.iter().flat_map(|&b| [0xff, b]).collect();
+        let (carved, carving) = carve(&interleaved).unwrap();
+        assert_eq!(carved, save);
+        assert_eq!(carving.interleaved, Some(1));
+        let doubled: Vec<u8> = bytes.iter().flat_map(|&b| [b, b]).collect();
+        assert_eq!(carve(&doubled).unwrap().0, save);
+
+        assert_eq!(carve(&[0; 0x20000]).err(), Some(err::NO_SAVE_FOUND));
+        let mut implausible = bytes.clone();
+        implausible[0x8141] = 0x40;
+        assert_eq!(carve(&implausible).err(), Some(err::NO_SAVE_FOUND));
+    }
+}
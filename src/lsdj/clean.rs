@@ -0,0 +1,190 @@
+use alloc::collections::BTreeSet;
+use core::fmt;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::song::*;
+
+/// The chains, phrases, instruments, and tables which a song has allocated but
+/// never plays: nothing in the song arrangement leads to them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Unused {
+    pub chains: Vec<u8>,
+    pub phrases: Vec<u8>,
+    pub instruments: Vec<u8>,
+    pub tables: Vec<u8>,
+}
+
+impl Unused {
+    /// Returns true if nothing is unused.
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty() && self.phrases.is_empty() && self.instruments.is_empty() && self.tables.is_empty()
+    }
+}
+
+impl fmt::Display for Unused {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, items) in [("chains", &self.chains), ("phrases", &self.phrases),
+                              ("instruments", &self.instruments), ("tables", &self.tables)] {
+            if !items.is_empty() {
+                let items: Vec<String> = items.iter().map(|i| format!("{:02X}", i)).collect();
+                writeln!(f, "{:<12} {}", name, items.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds what `song` has allocated but never plays. Chains are played if a
+/// song row names them; phrases if a played chain does; instruments if a
+/// played phrase does; and tables if a played phrase or instrument runs them,
+/// or another played table does (with the table command).
+pub fn unused(song: &Song) -> Unused {
+    let mut chains = [false; CHAIN_COUNT];
+    for row in 0..ROW_COUNT {
+        for chain in song.row(row).iter().flatten().filter(|&&c| (c as usize) < CHAIN_COUNT) {
+            chains[*chain as usize] = true;
+        }
+    }
+    let mut phrases = [false; PHRASE_COUNT];
+    for chain in (0..CHAIN_COUNT).filter(|&c| chains[c]).filter_map(|c| song.chain(c)) {
+        for phrase in chain.iter().filter_map(|s| s.phrase).filter(|&p| (p as usize) < PHRASE_COUNT) {
+            phrases[phrase as usize] = true;
+        }
+    }
+    let mut instruments = [false; INSTRUMENT_COUNT];
+    let mut run = Vec::new();
+    for phrase in (0..PHRASE_COUNT).filter(|&p| phrases[p]).filter_map(|p| song.phrase(p)) {
+        for step in phrase.iter() {
+            if let Some(i) = step.instrument.filter(|&i| (i as usize) < INSTRUMENT_COUNT) {
+                instruments[i as usize] = true;
+            }
+            if step.command == TABLE_COMMAND {
+                run.push(step.value);
+            }
+        }
+    }
+    run.extend((0..INSTRUMENT_COUNT).filter(|&i| instruments[i]).filter_map(|i| song.instrument(i)?.table()));
+    let tables = tables_run(song, run);
+
+    Unused {
+        chains: (0..CHAIN_COUNT).filter(|&c| !chains[c] && song.chain(c).is_some()).map(|c| c as u8).collect(),
+        phrases: (0..PHRASE_COUNT).filter(|&p| !phrases[p] && song.phrase(p).is_some()).map(|p| p as u8).collect(),
+        instruments: (0..INSTRUMENT_COUNT).filter(|&i| !instruments[i] && song.instrument(i).is_some())
+                                          .map(|i| i as u8).collect(),
+        tables: (0..TABLE_COUNT as u8).filter(|t| !tables.contains(t) && song.table(*t as usize).is_some()).collect(),
+    }
+}
+
+/// Returns the tables in `tables` along with every table they run, directly
+/// or through other tables, with the table command.
+pub(super) fn tables_run(song: &Song, tables: impl IntoIterator<Item = u8>) -> BTreeSet<u8> {
+    let mut run = BTreeSet::new();
+    let mut queue: Vec<u8> = tables.into_iter().collect(); // tables whose own table commands are yet to be followed
+    while let Some(table) = queue.pop() {
+        if table as usize >= TABLE_COUNT || !run.insert(table) {
+            continue;
+        }
+        if let Some(columns) = song.table(table as usize) {
+            for (commands, values) in [(columns[2], columns[3]), (columns[4], columns[5])] {
+                queue.extend(commands.iter().zip(values.iter()).filter(|(&c, _)| c == TABLE_COMMAND).map(|(_, &v)| v));
+            }
+        }
+    }
+    run
+}
+
+/// Frees everything in `song` which it never plays (see `unused()`), clearing
+/// the freed data so that the song compresses better. Returns what was freed.
+pub fn clean(song: &mut Song) -> Unused {
+    let unused = unused(song);
+    unused.chains.iter().for_each(|&c| { song.clear_chain(c as usize); });
+    unused.phrases.iter().for_each(|&p| { song.clear_phrase(p as usize); });
+    unused.instruments.iter().for_each(|&i| { song.clear_instrument(i as usize); });
+    unused.tables.iter().for_each(|&t| { song.clear_table(t as usize); });
+    unused
+}
+
+/// Merges identical phrases in `song`: each chain step playing a phrase which
+/// is identical to an earlier one is pointed at the earlier one instead, and
+/// the later phrase is freed. Returns each phrase freed along with the phrase
+/// it was merged into.
+pub fn merge_phrases(song: &mut Song) -> Vec<(u8, u8)> {
+    let mut kept: Vec<(u8, [Step; STEP_COUNT])> = Vec::new();
+    let mut merged = Vec::new();
+    for p in 0..PHRASE_COUNT {
+        let steps = match song.phrase(p) {
+            Some(steps) => steps,
+            None => continue,
+        };
+        match kept.iter().find(|(_, s)| *s == steps) {
+            Some(&(original, _)) => merged.push((p as u8, original)),
+            None => kept.push((p as u8, steps)),
+        }
+    }
+    for chain in 0..CHAIN_COUNT {
+        let steps = match song.chain(chain) {
+            Some(steps) => steps,
+            None => continue,
+        };
+        for (i, step) in steps.iter().enumerate() {
+            if let Some(&(_, original)) = merged.iter().find(|(p, _)| Some(*p) == step.phrase) {
+                song.set_chain_phrase(chain, i, Some(original));
+            }
+        }
+    }
+    merged.iter().for_each(|&(p, _)| { song.clear_phrase(p as usize); });
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::song::tests::*;
+
+    #[test]
+    fn test_clean() {
+        let mut song = empty_song();
+        set_row(&mut song, 0, 0, 0x00);
+        set_chain(&mut song, 0x00, 0x00, 0);
+        set_chain(&mut song, 0x01, 0x01, 0); // not in the arrangement
+        set_phrase(&mut song, 0x00, 0x01, 0x02, TABLE_COMMAND, 0x04);
+        set_phrase(&mut song, 0x01, 0x01, 0x03, TABLE_COMMAND, 0x05);
+        set_instrument(&mut song, 0x02, "LEAD", [0, 0, 0, 0, 0, 0x21, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        set_instrument(&mut song, 0x03, "BASS", [0; 16]);
+        set_table(&mut song, 0x01, 0, 0);
+        set_table(&mut song, 0x04, TABLE_COMMAND, 0x06);
+        set_table(&mut song, 0x05, 0, 0);
+        set_table(&mut song, 0x06, 0, 0);
+        set_table(&mut song, 0x07, 0, 0);
+
+        let expected = Unused { chains: vec![0x01], phrases: vec![0x01], instruments: vec![0x03], tables: vec![0x05, 0x07] };
+        assert_eq!(unused(&song), expected);
+        assert_eq!(expected.to_string(), "chains       01\nphrases      01\ninstruments  03\ntables       05 07\n");
+        assert_eq!(clean(&mut song), expected);
+        assert_eq!(song.chain(0x01), None);
+        assert_eq!(song.phrase(0x01), None);
+        assert_eq!(song.instrument(0x03), None);
+        assert_eq!(song.table(0x05), None);
+        assert!(song.table(0x06).is_some());
+        assert!(unused(&song).is_empty());
+    }
+
+    #[test]
+    fn test_merge_phrases() {
+        let mut song = empty_song();
+        set_phrase(&mut song, 0x00, 0x01, 0x00, 0, 0);
+        set_phrase(&mut song, 0x01, 0x02, 0x00, 0, 0);
+        set_phrase(&mut song, 0x02, 0x01, 0x00, 0, 0);
+        set_phrase(&mut song, 0x03, 0x01, 0x00, 0, 0);
+        set_chain(&mut song, 0x00, 0x02, 0);
+        song.set_chain_phrase(0x00, 1, Some(0x01));
+        song.set_chain_phrase(0x00, 2, Some(0x03));
+
+        assert_eq!(merge_phrases(&mut song), vec![(0x02, 0x00), (0x03, 0x00)]);
+        let phrases: Vec<Option<u8>> = song.chain(0x00).unwrap().iter().take(4).map(|s| s.phrase).collect();
+        assert_eq!(phrases, vec![Some(0x00), Some(0x01), Some(0x00), None]);
+        assert_eq!(song.phrase(0x02), None);
+        assert_eq!(song.phrase(0x03), None);
+        assert!(merge_phrases(&mut song).is_empty());
+    }
+}
@@ -1,9 +1,11 @@
-use std::fmt;
-use std::convert::TryInto;
+use core::fmt;
+use core::str::FromStr;
 
-use crate::lsdj;
+use log::{debug, trace};
+
+use crate::lsdj::prelude::*;
 use crate::lsdj::err;
-use crate::lsdj::BLOCK_SIZE;
+use crate::lsdj::layout::{BLOCK_SIZE, MAX_BLOCK_COUNT, SRAM_SIZE};
 use crate::lsdj::LsdjSram;
 
 const RLE_BYTE     : u8 = 0xc0; // $c0 in a compressed block indicates the beginning of an RLE sequence
@@ -12,48 +14,270 @@ const DEF_INST_BYTE: u8 = 0xf1; // $f1 after $e0 indicates default instrument
 const DEF_WAVE_BYTE: u8 = 0xf0; // $f0 after $e0 indicates default wave
 const EOF_BYTE     : u8 = 0xff; // $ff after $f0 indicates end of compressed SRAM
 
-const DEF_INST_VALUES: [u8; DEF_INST_SIZE] = [0xa8, 0x00, 0x00, 0xff, 0x00, 0x00, 0x03, 0x00,
+pub(super) const DEF_INST_VALUES: [u8; DEF_INST_SIZE] = [0xa8, 0x00, 0x00, 0xff, 0x00, 0x00, 0x03, 0x00,
                                               0x00, 0xd0, 0x00, 0x00, 0x00, 0xf3, 0x00, 0x00];
-const DEF_WAVE_VALUES: [u8; DEF_WAVE_SIZE] = [0x8e, 0xcd, 0xcc, 0xbb, 0xaa, 0xa9, 0x99, 0x88,
+pub(super) const DEF_WAVE_VALUES: [u8; DEF_WAVE_SIZE] = [0x8e, 0xcd, 0xcc, 0xbb, 0xaa, 0xa9, 0x99, 0x88,
                                               0x87, 0x76, 0x66, 0x55, 0x54, 0x43, 0x32, 0x31];
 const DEF_INST_SIZE: usize = 0x10;
 const DEF_WAVE_SIZE: usize = 0x10;
 
+/// How default instruments and waves are written in compressed song data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compat {
+    /// `$e0 $f1 n` and `$e0 $f0 n` stand for `n` default instruments or waves
+    /// in a row, as LSDj and lsdpatch read and write them. Parsed from either
+    /// `lsdj` or `lsdpatch`.
+    #[default]
+    Lsdj,
+    /// `$e0 $f1` and `$e0 $f0` each stand for a single default instrument or
+    /// wave, with no count, as earlier versions of lsdjtool wrote them. Only
+    /// for reading (and rewriting) songs those versions wrote: LSDj misreads
+    /// them.
+    Legacy,
+}
+
+impl FromStr for Compat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Compat, &'static str> {
+        match s {
+            "lsdj" | "lsdpatch" => Ok(Compat::Lsdj),
+            "legacy" => Ok(Compat::Legacy),
+            _ => Err(err::BAD_COMPAT),
+        }
+    }
+}
+
 /// Returns true if the slice if `data` contains the bytes representing the
 /// LittleSoundDj default instrument.
 fn is_def_inst(data: &[u8]) -> bool {
-    let data_array: [u8; DEF_INST_SIZE] = match data.try_into() {
-        Ok(arr) => arr,
-        Err(_)  => return false // if slice is the wrong size
-    };
-
-    for (c, d) in data_array.iter().zip(DEF_INST_VALUES.iter()) {
-        if c != d {
-            return false; // return upon first non-matching byte
-        }
-    }
-    true
+    data == DEF_INST_VALUES // false if the slice is the wrong size
 }
 
 /// Returns true if the slice if `data` contains the bytes representing the
 /// LittleSoundDj default wave.
 fn is_def_wave(data: &[u8]) -> bool {
-    let data_array: [u8; DEF_WAVE_SIZE] = match data.try_into() {
-        Ok(arr) => arr,
-        Err(_)  => return false
-    };
-
-    for (c, d) in data_array.iter().zip(DEF_WAVE_VALUES.iter()) {
-        if c != d {
-            return false; // return upon first non-matching byte
+    data == DEF_WAVE_VALUES
+}
+
+/// Where decompressing blocks of compressed song data failed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DecompressError {
+    /// Index (zero-based) of the block, as stored, holding the instruction
+    /// which couldn't be decompressed.
+    pub block: usize,
+    /// Index in the block of that instruction.
+    pub index: usize,
+    /// What was wrong, as one of the messages in `lsdj::err`.
+    pub reason: &'static str,
+}
+
+/// Decompresses the block of compressed song data `data` into SRAM from
+/// `position` onwards, advancing `position` past the bytes written and
+/// returning the block to skip to next (or 0 at the end of SRAM). Default
+/// instruments and waves are read as `dest.compat` writes them.
+///
+/// Returns the index of the instruction which couldn't be decompressed (or
+/// of the end of the block, if it has no skip or end-of-file instruction)
+/// along with the `Err`.
+pub(super) fn decompress_block(data: &[u8], dest: &mut LsdjSram, position: &mut usize)
+    -> Result<u8, (usize, &'static str)> {
+    let mut start = 0;
+    decompress_instructions(data, dest, position, &mut start).map_err(|e| (start, e))
+}
+
+/// Decompresses `data` as `decompress_block()` does, keeping the index of the
+/// instruction being decompressed in `start`.
+fn decompress_instructions(data: &[u8], dest: &mut LsdjSram, position: &mut usize, start: &mut usize)
+    -> Result<u8, &'static str> {
+    let base = *position;
+    let mut offset = 0;
+    let mut bytes_iter = data.iter();
+
+    while let Some(&byte) = bytes_iter.next() {
+        *start = data.len() - bytes_iter.len() - 1;
+        match byte {
+            RLE_BYTE => {
+                let next_byte = match bytes_iter.next() {
+                    Some(&b) => b,
+                    None => return Err(err::BAD_FMT),
+                };
+                if next_byte == RLE_BYTE {
+                    *dest.data.get_mut(base + offset).ok_or(err::BAD_FMT)? = RLE_BYTE;
+                    offset += 1;
+                } else {
+                    let byte_value = next_byte;
+                    let byte_repeat = match bytes_iter.next() {
+                        Some(&b) => b,
+                        None => return Err(err::BAD_FMT),
+                    };
+                    trace!("${:04X}: run of {} ${:02X}", base + offset, byte_repeat, byte_value);
+                    let run = dest.data.get_mut((base + offset)..(base + offset + byte_repeat as usize));
+                    run.ok_or(err::BAD_FMT)?.fill(byte_value);
+                    offset += byte_repeat as usize;
+                }
+            },
+            SPECIAL_BYTE => {
+                let next_byte = match bytes_iter.next() {
+                    Some(&b) => b,
+                    None => return Err(err::BAD_FMT),
+                };
+                match next_byte {
+                    SPECIAL_BYTE => {
+                        *dest.data.get_mut(base + offset).ok_or(err::BAD_FMT)? = SPECIAL_BYTE;
+                        offset += 1;
+                    },
+                    DEF_INST_BYTE | DEF_WAVE_BYTE => {
+                        let values = if next_byte == DEF_INST_BYTE { &DEF_INST_VALUES } else { &DEF_WAVE_VALUES };
+                        let count = match dest.compat {
+                            Compat::Lsdj => *bytes_iter.next().ok_or(err::BAD_FMT)?,
+                            Compat::Legacy => 1,
+                        };
+                        trace!("${:04X}: {} default {}", base + offset, count,
+                               if next_byte == DEF_INST_BYTE { "instrument(s)" } else { "wave(s)" });
+                        for _ in 0..count {
+                            let slot = dest.data.get_mut((base + offset)..(base + offset + values.len()));
+                            slot.ok_or(err::BAD_FMT)?.copy_from_slice(values); // count runs past the end of SRAM
+                            offset += values.len();
+                        }
+                    },
+                    EOF_BYTE => {
+                        *position += offset;
+                        debug!("${:04X}: end of song data", position);
+                        return Ok(0);
+                    },
+                    switch_block => {
+                        *position += offset;
+                        debug!("${:04X}: skipping to block {}", position, switch_block);
+                        return Ok(switch_block);
+                    },
+                }
+            },
+            b => {
+                *dest.data.get_mut(base + offset).ok_or(err::BAD_FMT)? = b;
+                offset += 1;
+            },
         }
     }
-    true
+    *position += offset;
+    *start = data.len();
+    Err(err::BAD_FMT)
+}
+
+/// Decompresses a chain of blocks into `dest`, starting from the block at
+/// (zero-based) `start_index` and following skip instructions. `block` returns
+/// the data of the block at a given index, or `None` if there is no such
+/// block, which ends decompression. Returns the number of blocks decompressed,
+/// or an `Err` if a block skips back to one already decompressed (which would
+/// otherwise go round forever).
+pub fn decompress_chain<'a, F>(block: F, dest: &mut LsdjSram, start_index: usize) -> Result<usize, DecompressError>
+    where F: Fn(usize) -> Option<&'a [u8]> {
+    let mut visited = [false; 0x100]; // skip instructions can only reach blocks $01-$fe
+    let mut blocks_decompressed = 0;
+    let mut current_index = start_index;
+    let mut position = 0;
+
+    while let Some(data) = block(current_index) {
+        debug!("decompressing block {} at ${:04X}", current_index + 1, position);
+        if let Some(visited) = visited.get_mut(current_index) {
+            if *visited {
+                return Err(DecompressError { block: current_index, index: 0, reason: err::BLOCK_LOOP });
+            }
+            *visited = true;
+        }
+        let next_block = decompress_block(data, dest, &mut position)
+            .map_err(|(index, reason)| DecompressError { block: current_index, index, reason })?;
+        blocks_decompressed += 1;
+        match next_block {
+            0 => break, // return value of 0 indicates end of compressed SRAM
+            n => current_index = (n - 1) as usize // move to index of next block (subtracting 1 because blocks are 1-indexed)
+        }
+    }
+    Ok(blocks_decompressed)
+}
+
+/// Decompresses blocks stored one after another in `bytes` (as exported by
+/// `LsdjSave::export_song()`) into `dest`, in order, ignoring the block
+/// numbers in their skip instructions. Returns the number of blocks
+/// decompressed, or an `Err` if no block ends with an end-of-file instruction.
+pub fn decompress_sequence(bytes: &[u8], dest: &mut LsdjSram) -> Result<usize, DecompressError> {
+    let blocks = bytes.chunks(BLOCK_SIZE).count();
+    let mut position = 0;
+    for (i, data) in bytes.chunks(BLOCK_SIZE).enumerate() {
+        let next_block = decompress_block(data, dest, &mut position)
+            .map_err(|(index, reason)| DecompressError { block: i, index, reason })?;
+        if next_block == 0 {
+            return Ok(i + 1);
+        }
+    }
+    // the last block skips to another, which isn't there
+    let last = blocks.saturating_sub(1);
+    Err(DecompressError { block: last, index: bytes.len() - last * BLOCK_SIZE, reason: err::BAD_FMT })
+}
+
+/// The kinds of instruction which decompress to bytes of SRAM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Instruction {
+    /// A byte copied as is, or an escaped $c0 or $e0.
+    Literal,
+    /// A run of one byte ($c0 b n).
+    Run,
+    /// Default instruments or waves ($e0 $f1 or $e0 $f0).
+    Default,
+}
+
+/// How a song's blocks of compressed data decompress, broken down by kind of
+/// instruction (see `LsdjBlock::tally()`).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CompressionStats {
+    /// Number of blocks.
+    pub blocks: usize,
+    /// Bytes of compressed data, up to the end-of-file instruction.
+    pub compressed: usize,
+    /// Bytes written by literal instructions.
+    pub literal_encoded: usize,
+    /// Bytes of SRAM filled by literal instructions.
+    pub literal_bytes: usize,
+    /// Bytes written by run instructions.
+    pub run_encoded: usize,
+    /// Bytes of SRAM filled by run instructions.
+    pub run_bytes: usize,
+    /// Bytes written by default instrument and wave instructions.
+    pub default_encoded: usize,
+    /// Bytes of SRAM filled by default instrument and wave instructions.
+    pub default_bytes: usize,
+}
+
+impl CompressionStats {
+    /// Returns the number of bytes of SRAM decompressed.
+    pub fn decompressed(&self) -> usize {
+        self.literal_bytes + self.run_bytes + self.default_bytes
+    }
+
+    /// Returns the size of the compressed data as a fraction of the size of
+    /// the data decompressed.
+    pub fn ratio(&self) -> f64 {
+        self.compressed as f64 / self.decompressed().max(1) as f64
+    }
+
+    /// Returns the number of bytes saved by run instructions.
+    pub fn run_savings(&self) -> usize {
+        self.run_bytes.saturating_sub(self.run_encoded)
+    }
+
+    /// Returns the number of bytes saved by default instrument and wave
+    /// instructions.
+    pub fn default_savings(&self) -> usize {
+        self.default_bytes.saturating_sub(self.default_encoded)
+    }
 }
 
 /// Represents a block of compressed LSDj song data.
+///
+/// Blocks compare equal if their data is the same, wherever they are stored.
 #[derive(Clone, Copy)]
 pub struct LsdjBlock {
+    /// Number of the block (one-indexed) this block is to be stored at.
     pub position: usize,
     pub data: [u8; BLOCK_SIZE],
 }
@@ -64,246 +288,298 @@ impl LsdjBlock {
         LsdjBlock { position: 0, data: [0; BLOCK_SIZE] }
     }
 
-    /// Decompresses this block into a section of SRAM.
-    pub fn decompress(&self, dest: &mut LsdjSram) -> Result<u8, &'static str> {
-        let base = dest.position;
-        let mut offset = 0;
-        let mut bytes_iter = self.data.iter();
+    /// Decompresses this block into SRAM from `position` onwards, advancing
+    /// `position` past the bytes written (as for the next block of a song),
+    /// and returns the block to skip to next (or 0 at the end of SRAM).
+    pub fn decompress(&self, dest: &mut LsdjSram, position: &mut usize) -> Result<u8, &'static str> {
+        decompress_block(&self.data, dest, position).map_err(|(_, e)| e)
+    }
 
-        while let Some(&byte) = bytes_iter.next() {
-            match byte {
-                RLE_BYTE => {
-                    let next_byte = match bytes_iter.next() {
-                        Some(&b) => b,
-                        None => return Err(err::BAD_FMT),
-                    };
-                    if next_byte == RLE_BYTE {
-                        dest.data[base + offset] = RLE_BYTE;
-                        offset += 1;
-                    } else {
-                        let byte_value = next_byte;
-                        let byte_repeat = match bytes_iter.next() {
-                            Some(&b) => b,
-                            None => return Err(err::BAD_FMT),
-                        };
-                        for _j in 0..byte_repeat {
-                            dest.data[base + offset] = byte_value;
-                            offset += 1;
-                        }
-                    }
-                },
-                SPECIAL_BYTE => {
-                    let next_byte = match bytes_iter.next() {
-                        Some(&b) => b,
-                        None => return Err(err::BAD_FMT),
-                    };
-                    match next_byte {
-                        SPECIAL_BYTE => {
-                            dest.data[base + offset] = SPECIAL_BYTE;
-                            offset += 1;
-                        },
-                        DEF_INST_BYTE =>
-                            for j in 0..DEF_INST_SIZE {
-                                dest.data[base + offset] = DEF_INST_VALUES[j];
-                                offset += 1;
-                            },
-                        DEF_WAVE_BYTE =>
-                            for j in 0..DEF_WAVE_SIZE {
-                                dest.data[base + offset] = DEF_WAVE_VALUES[j];
-                                offset += 1;
-                            },
-                        EOF_BYTE => {
-                            dest.position += offset;
-                            return Ok(0);
-                        },
-                        switch_block => {
-                            dest.position += offset;
-                            return Ok(switch_block);
-                        },
-                    }
-                },
-                b => {
-                    dest.data[base + offset] = b;
-                    offset += 1;
+    /// Returns true if this block ends with a skip instruction ($e0, n) naming
+    /// a valid block or with an end-of-file instruction ($e0, $ff), found by
+    /// stepping through its instructions from the start as written with
+    /// either `Compat` mode.
+    pub fn is_terminated(&self) -> bool {
+        self.terminator(Compat::Lsdj).is_some() || self.terminator(Compat::Legacy).is_some()
+    }
+
+    /// Returns the index of the skip or end-of-file instruction ending this
+    /// block, stepping through its instructions as written with `compat`.
+    fn terminator(&self, compat: Compat) -> Option<usize> {
+        let default_len = if compat == Compat::Lsdj { 3 } else { 2 };
+        let mut i = 0;
+        while i < BLOCK_SIZE {
+            match self.data[i] {
+                RLE_BYTE => i += if self.data.get(i + 1) == Some(&RLE_BYTE) { 2 } else { 3 },
+                SPECIAL_BYTE => match self.data.get(i + 1) {
+                    Some(&SPECIAL_BYTE) => i += 2,
+                    Some(&DEF_INST_BYTE) | Some(&DEF_WAVE_BYTE) => i += default_len,
+                    Some(&EOF_BYTE) => return Some(i),
+                    Some(&n) if 1 <= n && n as usize <= MAX_BLOCK_COUNT => return Some(i),
+                    Some(_) | None => return None,
                 },
+                _ => i += 1,
             }
         }
-        dest.position += offset;
-        Err(err::BAD_FMT)
+        None
+    }
+
+    /// Returns the block this block skips to, as written with `compat`, or 0
+    /// if it ends with an end-of-file instruction. Returns `None` if it
+    /// doesn't end with either.
+    pub fn next_block(&self, compat: Compat) -> Option<u8> {
+        let i = self.terminator(compat)?;
+        Some(if self.data[i + 1] == EOF_BYTE { 0 } else { self.data[i + 1] })
+    }
+
+    /// Steps through the instructions in this block as written with `compat`,
+    /// given that `position` bytes of SRAM were decompressed before it, until
+    /// reaching a skip or end-of-file instruction or filling SRAM. Returns the
+    /// index reached in the block and the position reached in SRAM.
+    fn walk(&self, position: usize, compat: Compat) -> (usize, usize) {
+        self.walk_with(position, compat, |_, _, _| ())
+    }
+
+    /// Steps through the instructions in this block like `walk()`, calling
+    /// `f` with the kind of each instruction, its length in bytes, and the
+    /// number of bytes of SRAM it decompresses to.
+    fn walk_with<F>(&self, mut position: usize, compat: Compat, mut f: F) -> (usize, usize)
+        where F: FnMut(Instruction, usize, usize) {
+        let byte = |i: usize| self.data.get(i).copied();
+        let mut i = 0;
+        while i < BLOCK_SIZE && position < SRAM_SIZE {
+            let (kind, len, decompressed) = match (self.data[i], byte(i + 1)) {
+                (RLE_BYTE, Some(RLE_BYTE)) | (SPECIAL_BYTE, Some(SPECIAL_BYTE)) => (Instruction::Literal, 2, 1),
+                (RLE_BYTE, Some(_)) => (Instruction::Run, 3, byte(i + 2).unwrap_or(0) as usize),
+                (SPECIAL_BYTE, Some(DEF_INST_BYTE)) | (SPECIAL_BYTE, Some(DEF_WAVE_BYTE)) => match compat {
+                    Compat::Lsdj => (Instruction::Default, 3, byte(i + 2).unwrap_or(0) as usize * DEF_INST_SIZE),
+                    Compat::Legacy => (Instruction::Default, 2, DEF_INST_SIZE),
+                },
+                (RLE_BYTE, None) | (SPECIAL_BYTE, _) => break, // a skip or end-of-file instruction
+                _ => (Instruction::Literal, 1, 1),
+            };
+            f(kind, len, decompressed);
+            i += len;
+            position += decompressed;
+        }
+        (i, position)
+    }
+
+    /// Adds the instructions in this block, as written with `compat`, to
+    /// `stats`, given that `position` bytes of SRAM were decompressed before
+    /// it. Returns the position reached in SRAM.
+    pub fn tally(&self, position: usize, compat: Compat, stats: &mut CompressionStats) -> usize {
+        let (i, position) = self.walk_with(position, compat, |kind, len, decompressed| {
+            let (encoded, bytes) = match kind {
+                Instruction::Literal => (&mut stats.literal_encoded, &mut stats.literal_bytes),
+                Instruction::Run => (&mut stats.run_encoded, &mut stats.run_bytes),
+                Instruction::Default => (&mut stats.default_encoded, &mut stats.default_bytes),
+            };
+            *encoded += len;
+            *bytes += decompressed;
+        });
+        stats.blocks += 1;
+        stats.compressed += (i + 2).min(BLOCK_SIZE); // counting the skip or end-of-file instruction
+        position
+    }
+
+    /// Returns the number of bytes of SRAM this block decompresses to, as
+    /// written with `compat`, given that `position` bytes were decompressed
+    /// before it.
+    pub fn decompressed_len(&self, position: usize, compat: Compat) -> usize {
+        self.walk(position, compat).1 - position
+    }
+
+    /// Adds the end-of-file instruction ($e0, $ff) missing from this block,
+    /// right after the instruction which fills SRAM, given that `position`
+    /// bytes of SRAM were decompressed from the blocks before it. Returns false
+    /// (leaving the block unchanged) if the block doesn't fill SRAM exactly or
+    /// holds anything but zeroes after the instruction which does. Zeroes at
+    /// the end of the block are taken as padding, never as literal bytes.
+    pub fn add_terminator(&mut self, position: usize, compat: Compat) -> bool {
+        let (i, position) = self.walk(position, compat);
+        let end = self.data.iter().rposition(|&b| b != 0).map_or(0, |j| j + 1);
+        if position != SRAM_SIZE || i != end || i + 2 > BLOCK_SIZE {
+            return false;
+        }
+        self.data[i..(i + 2)].copy_from_slice(&[SPECIAL_BYTE, EOF_BYTE]);
+        true
+    }
+
+    /// Ends this block with a skip to block `next`, or with an end-of-file
+    /// instruction if `next` is 0, given that `position` bytes of SRAM were
+    /// decompressed from the blocks before it. If the block fills SRAM, it
+    /// ends with an end-of-file instruction right after the instruction which
+    /// does, whatever `next` is.
+    ///
+    /// The new instruction replaces any the block ended with, or follows its
+    /// last instruction, or, if there is no room for it, overwrites the last
+    /// two bytes of the block. Returns the position reached in SRAM and
+    /// whether the block now ends with an end-of-file instruction.
+    pub fn rechain(&mut self, next: u8, position: usize, compat: Compat) -> (usize, bool) {
+        let (i, position) = self.walk(position, compat);
+        let i = i.min(BLOCK_SIZE - 2);
+        let end = next == 0 || position >= SRAM_SIZE;
+        self.data[i..(i + 2)].copy_from_slice(&[SPECIAL_BYTE, if end { EOF_BYTE } else { next }]);
+        (position, end)
     }
 
     /// Changes the "skip to block `n`" instruction ($e0, n) at the end of the
-    /// block to point to the specified block.
-    pub fn skip_to_block(&mut self, block: usize) -> Result<(), &'static str> {
-        let mut bytes_iter = self.data.iter_mut();
-        while let Some(byte) = bytes_iter.next() {
-            if *byte == SPECIAL_BYTE {
-                match bytes_iter.next() {
-                    Some(n) if 1 <= *n && *n <= lsdj::BLOCK_COUNT as u8 || *n == b'x' => {
-                        *n = block as u8; // skip to block
-                        return Ok(());
-                    },
-                    Some(&mut DEF_INST_BYTE) | Some(&mut DEF_WAVE_BYTE) => (),
-                    Some(&mut EOF_BYTE) => return Err(err::NO_SKIP), // block doesn't contain a skip instruction
-                    Some(_) | None => return Err(err::BAD_FMT), // block contains a $c0 with no following byte
-                }
-            }
+    /// block, as written with `compat`, to point to the specified block.
+    pub fn skip_to_block(&mut self, block: usize, compat: Compat) -> Result<(), &'static str> {
+        let i = self.terminator(compat).ok_or(err::NO_SKIP)?;
+        match self.data[i + 1] {
+            EOF_BYTE => Err(err::NO_SKIP), // block doesn't contain a skip instruction
+            _ => {
+                self.data[i + 1] = block as u8; // skip to block
+                Ok(())
+            },
         }
-        Err(err::NO_SKIP)
     }
 }
 
 pub trait LsdjBlockExt<T> {
-    /// Decompresses all blocks stored in a `Vec<LsdjBlock>`, storing the
+    /// Decompresses all blocks stored in a slice of `LsdjBlock`s, storing the
     /// decompressed SRAM data in `dest`.
-    fn decompress_to(&self, dest: &mut LsdjSram, start_index: usize) -> Result<u8, &'static str>;
+    fn decompress_to(&self, dest: &mut LsdjSram, start_index: usize) -> Result<usize, &'static str>;
 
     /// Returns all bytes in all blocks as a `Vec<u8>`.
     fn bytes(&self) -> Vec<u8>;
 }
 
-impl LsdjBlockExt<LsdjBlock> for Vec<LsdjBlock> {
-    fn decompress_to(&self, mut dest: &mut LsdjSram, start_index: usize) -> Result<u8, &'static str> {
-        let mut blocks_decompressed = 0;
-        let mut current_index = start_index;
-
-        while current_index < self.len() {
-            let next_block = self[current_index].decompress(&mut dest)?;
-            blocks_decompressed += 1;
-            /*
-            match next_block {
-                Some(n) if n > 0 => current_index = (n - 1) as usize,
-                Some(0) => break,
-                None => return Err("error in decompression"),
-                _ => (),
-            }
-            */
-            match next_block {
-                0 => break, // return value of 0 indicates end of compressed SRAM
-                n => current_index = (n - 1) as usize // move to index of next block (subtracting 1 because blocks are 1-indexed)
-            }
-        }
-        Ok(blocks_decompressed)
+impl LsdjBlockExt<LsdjBlock> for [LsdjBlock] {
+    fn decompress_to(&self, dest: &mut LsdjSram, start_index: usize) -> Result<usize, &'static str> {
+        decompress_chain(|i| self.get(i).map(|b| &b.data[..]), dest, start_index).map_err(|e| e.reason)
     }
 
     fn bytes(&self) -> Vec<u8> {
-        let mut out = Vec::new();
+        let mut out = Vec::with_capacity(self.len() * BLOCK_SIZE);
         for block in self.iter() {
-            for byte in block.data.iter() {
-                out.push(*byte);
-            }
+            out.extend_from_slice(&block.data);
         }
         out
     }
 }
 
+/// Encodes the bytes at the start of `data` as a single compression
+/// instruction, storing it in `out`. Returns the length of the instruction and
+/// the number of bytes of `data` it represents.
+///
+/// Instructions are chosen in this order of preference: the default
+/// instrument and wave, escaped $c0 and $e0 bytes, runs of more than three
+/// bytes (split into runs of at most $ff), and finally literals. Consecutive
+/// default instruments or waves (at most $ff of them) are counted into one
+/// instruction, except with `Compat::Legacy`. Whether LSDj makes the same
+/// choices, and so writes the same bytes, isn't checked against its saves.
+fn encode(data: &[u8], out: &mut [u8; 3], compat: Compat) -> (usize, usize) {
+    for (byte, size, is_default) in [(DEF_INST_BYTE, DEF_INST_SIZE, is_def_inst as fn(&[u8]) -> bool),
+                                     (DEF_WAVE_BYTE, DEF_WAVE_SIZE, is_def_wave)] {
+        if data.len() >= size && is_default(&data[..size]) {
+            out[..2].copy_from_slice(&[SPECIAL_BYTE, byte]);
+            if compat == Compat::Legacy {
+                return (2, size);
+            }
+            let count = data.chunks_exact(size).take(0xff).take_while(|chunk| is_default(chunk)).count();
+            out[2] = count as u8;
+            return (3, count * size);
+        }
+    }
+    let byte = data[0];
+    if byte == RLE_BYTE || byte == SPECIAL_BYTE {
+        out[..2].copy_from_slice(&[byte, byte]);
+        return (2, 1);
+    }
+    let run = &data[..data.len().min(0xff)];
+    let repeat = run.iter().position(|&b| b != byte).unwrap_or(run.len());
+    if repeat > 3 {
+        out.copy_from_slice(&[RLE_BYTE, byte, repeat as u8]);
+        (3, repeat)
+    } else {
+        out[0] = byte;
+        (1, 1)
+    }
+}
+
 impl LsdjSram {
-    /// Compresses this SRAM data into block `dest`, stopping when the
+    /// Compresses this SRAM data from `position` onwards into block `dest`,
+    /// advancing `position` past the bytes compressed, and stopping when the
     /// destination block runs out of space or the SRAM hits its end.
-    fn compress(&mut self, dest: &mut LsdjBlock, block_num: u8) -> Result<u8, &'static str> {
-        let base = self.position;
-        let mut offset = 0;
+    ///
+    /// The last two bytes of every block are reserved for the instruction
+    /// which ends it, so that each block is filled as far as possible. If the
+    /// block runs out of space, it ends by skipping to `next_block`, which is
+    /// returned; if there is no next block, an `Err` is returned instead.
+    /// Returns `None` once the end of SRAM has been compressed.
+    fn compress(&self, position: &mut usize, dest: &mut LsdjBlock, next_block: Option<usize>)
+        -> Result<Option<usize>, &'static str> {
         let mut block_index = 0;
+        let mut instruction = [0; 3];
 
-        while base + offset < lsdj::SRAM_SIZE {
-            let mut repeat = 1;
-            match self.data[base + offset] {
-                RLE_BYTE => {
-                    dest.data[block_index] = RLE_BYTE;
-                    block_index += 1;
-                    dest.data[block_index] = RLE_BYTE;
-                    block_index += 1;
-                    offset += 1;
-                },
-                SPECIAL_BYTE => {
-                    dest.data[block_index] = SPECIAL_BYTE;
-                    block_index += 1;
-                    dest.data[block_index] = SPECIAL_BYTE;
-                    block_index += 1;
-                    offset += 1;
-                },
-                _ => {
-                    if block_index + 4 > lsdj::BLOCK_SIZE {
-                        dest.data[block_index] = SPECIAL_BYTE;
-                        block_index += 1;
-                        dest.data[block_index] = block_num + 1;
-                        self.position += offset;
-                        return Ok(block_num + 1);
-                    } else if base + offset + DEF_INST_SIZE <= lsdj::SRAM_SIZE &&
-                              is_def_inst(&self.data[(base + offset)..(base + offset + DEF_INST_SIZE)]) {
-                        dest.data[block_index] = SPECIAL_BYTE;
-                        block_index += 1;
-                        dest.data[block_index] = DEF_INST_BYTE;
-                        block_index += 1;
-                        offset += DEF_INST_SIZE;
-                    } else if base + offset + DEF_WAVE_SIZE <= lsdj::SRAM_SIZE &&
-                              is_def_wave(&self.data[(base + offset)..(base + offset + DEF_WAVE_SIZE)]) {
-                        dest.data[block_index] = SPECIAL_BYTE;
-                        block_index += 1;
-                        dest.data[block_index] = DEF_WAVE_BYTE;
-                        block_index += 1;
-                        offset += DEF_INST_SIZE;
-                    } else {
-                        let mut lookahead = 1;
-                        while base + offset + lookahead < lsdj::SRAM_SIZE && repeat < 0xff {
-                            let c = self.data[base + offset];
-                            let next = self.data[base + offset + lookahead];
-                            if c == next {
-                                repeat += 1;
-                            } else {
-                                break;
-                            }
-                            lookahead += 1;
-                        }
-                        if repeat <= 3 {
-                            for _i in 0..repeat {
-                                dest.data[block_index] = self.data[base + offset];
-                                block_index += 1;
-                                offset += 1;
-                            }
-                        } else {
-                            dest.data[block_index] = RLE_BYTE;
-                            block_index += 1;
-                            dest.data[block_index] = self.data[base + offset];
-                            block_index += 1;
-                            dest.data[block_index] = repeat;
-                            block_index += 1;
-                            offset += repeat as usize;
-                        }
-                    }
-                }
+        while *position < SRAM_SIZE {
+            let (len, consumed) = encode(&self.data[*position..], &mut instruction, self.compat);
+            if block_index + len > BLOCK_SIZE - 2 {
+                let next_block = next_block.ok_or(err::NO_BLOCKS)?;
+                debug!("${:04X}: block {} full after {} bytes, skipping to block {}",
+                       position, dest.position, block_index, next_block);
+                dest.data[block_index] = SPECIAL_BYTE;
+                dest.data[block_index + 1] = next_block as u8;
+                return Ok(Some(next_block));
             }
+            match instruction[..2] {
+                [RLE_BYTE, b] if len == 3 => trace!("${:04X}: run of {} ${:02X}", position, consumed, b),
+                [SPECIAL_BYTE, DEF_INST_BYTE] => trace!("${:04X}: {} default instrument(s)", position, consumed / DEF_INST_SIZE),
+                [SPECIAL_BYTE, DEF_WAVE_BYTE] => trace!("${:04X}: {} default wave(s)", position, consumed / DEF_WAVE_SIZE),
+                _ => (),
+            }
+            dest.data[block_index..(block_index + len)].copy_from_slice(&instruction[..len]);
+            block_index += len;
+            *position += consumed;
         }
+        debug!("${:04X}: end of song data in block {} after {} bytes", position, dest.position, block_index);
         dest.data[block_index] = SPECIAL_BYTE;
-        block_index += 1;
-        dest.data[block_index] = EOF_BYTE;
-        self.position += offset;
-        Ok(0)
-    }
-
-    /// Wrapper function for `compress()` that compresses an entire SRAM at
-    /// once and stores the compressed bytes into a `Vec<LsdjBlock>`.
-    pub fn compress_into(&mut self, blocks: &mut Vec<LsdjBlock>, first_block: usize) -> Result<u8, &'static str> {
-        let mut current_block = first_block;
-        let mut blocks_written = 0;
-        loop {
-            blocks.push(LsdjBlock::empty());
-            let next_block = self.compress(&mut blocks[current_block - 1], current_block as u8)?;
-            blocks_written += 1;
-            /*
-            match next_block {
-                Some(n) if n > 0 => current_block = n as usize,
-                Some(0) => break,
-                None => return Err("error in compression"),
-                _ => (),
+        dest.data[block_index + 1] = EOF_BYTE;
+        Ok(None)
+    }
+
+    /// Compresses this entire SRAM into blocks to be stored at the
+    /// (one-indexed) block numbers in `positions`, in order, so that each
+    /// block's skip instruction points to the next position. Default
+    /// instruments and waves are written as `self.compat` writes them.
+    ///
+    /// Returns the blocks actually produced, each with its `position` set to
+    /// its block number; positions left over once SRAM has been compressed
+    /// are unused. Returns an `Err` if `positions` runs out before the end of
+    /// SRAM, or contains a number which isn't a valid block number.
+    pub fn compress_into<I>(&self, positions: I) -> Result<Vec<LsdjBlock>, &'static str>
+        where I: IntoIterator<Item = usize> {
+        let mut positions = positions.into_iter();
+        let mut blocks = Vec::new();
+        let mut current = positions.next();
+        let mut sram_position = 0;
+        while let Some(position) = current {
+            if position == 0 || position > MAX_BLOCK_COUNT {
+                return Err(err::BAD_BLOCK);
             }
-            */
-            match next_block {
-                0 => break,
-                n => current_block = n as usize
+            let mut block = LsdjBlock { position, data: [0; BLOCK_SIZE] };
+            current = self.compress(&mut sram_position, &mut block, positions.next())?;
+            blocks.push(block);
+            if current.is_none() {
+                return Ok(blocks);
             }
         }
-        Ok(blocks_written)
+        Err(err::NO_BLOCKS)
+    }
+}
+
+impl PartialEq for LsdjBlock {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.data == rhs.data
+    }
+}
+
+impl Default for LsdjBlock {
+    fn default() -> LsdjBlock {
+        LsdjBlock::empty()
     }
 }
 
@@ -313,13 +589,13 @@ impl fmt::Debug for LsdjBlock {
         for i in 0..0x10 {
             write!(f, "{:X} | ", i)?;
         }
-        write!(f, "\n")?;
+        writeln!(f)?;
         for disp in 0..(BLOCK_SIZE / 0x10) {
             write!(f, "{:03X}  | ", disp * 0x10)?;
             for offset in 0..0x10 {
                 write!(f, "{:02X}| ", self.data[disp * 0x10 + offset])?;
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
         Ok(())
     }
@@ -327,9 +603,9 @@ impl fmt::Debug for LsdjBlock {
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
-    use std::fs::File;
+    use proptest::prelude::*;
 
+    use crate::lsdj::verify_roundtrip;
     use super::*;
 
     #[test]
@@ -361,9 +637,11 @@ mod tests {
         block.data[1] = 0x41;
         block.data[2] = 0x10;
         let mut sram = LsdjSram::empty();
-        match block.decompress(&mut sram) { Ok(_) | Err(_) => () } // ignore error raised by the lack of a "switch block" instruction at the end of the block
+        let mut position = 0;
+        match block.decompress(&mut sram, &mut position) { Ok(_) | Err(_) => () } // ignore error raised by the lack of a "switch block" instruction at the end of the block
         // SRAM should be 0x41, repeated 16 times
         assert_eq!(&sram.data[0..0x10], &[0x41; 0x10]);
+        assert_eq!(position, 0x10 + BLOCK_SIZE - 3); // followed by the rest of the block's zeros
     }
 
     #[test]
@@ -388,32 +666,269 @@ mod tests {
         sram.data[16] = 0x41;
         sram.data[17] = 0x41;
         let mut block = LsdjBlock::empty();
-        sram.compress(&mut block, 1).unwrap();
+        sram.compress(&mut 0, &mut block, Some(2)).unwrap();
         assert_eq!(&block.data[0..3], &[0xc0, 0x41, 18]);
     }
 
 
+    // The expected bytes are assembled by hand from the format LSDj reads;
+    // tests/golden.rs checks the compressor against saves LSDj wrote
+    #[test]
+    fn test_compression_format() {
+        let mut sram = LsdjSram::empty();
+        sram.data[0..18].copy_from_slice(&[0x41; 18]);
+        sram.data[18] = RLE_BYTE;
+        sram.data[19] = SPECIAL_BYTE;
+        sram.data[20..36].copy_from_slice(&DEF_INST_VALUES);
+        sram.data[36..52].copy_from_slice(&DEF_WAVE_VALUES);
+        sram.data[52..55].copy_from_slice(&[0x12; 3]);
+        let blocks = sram.compress_into(1..).unwrap();
+        assert_eq!(blocks.len(), 1);
+
+        let mut expected = vec![0xc0, 0x41, 18, 0xc0, 0xc0, 0xe0, 0xe0, 0xe0, 0xf1, 1, 0xe0, 0xf0, 1,
+                            0x12, 0x12, 0x12];
+        for _ in 0..((SRAM_SIZE - 55) / 0xff) {
+            expected.extend_from_slice(&[0xc0, 0x00, 0xff]); // runs longer than $ff are split
+        }
+        expected.extend_from_slice(&[0xc0, 0x00, ((SRAM_SIZE - 55) % 0xff) as u8, 0xe0, 0xff]);
+        expected.resize(BLOCK_SIZE, 0);
+        assert_eq!(&blocks[0].data[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_counted_defaults() {
+        let mut sram = LsdjSram::empty();
+        for i in 0..3 {
+            sram.data[(i * DEF_INST_SIZE)..][..DEF_INST_SIZE].copy_from_slice(&DEF_INST_VALUES);
+        }
+        for i in 0..0x101 {
+            sram.data[(0x100 + i * DEF_WAVE_SIZE)..][..DEF_WAVE_SIZE].copy_from_slice(&DEF_WAVE_VALUES);
+        }
+        let blocks = sram.compress_into(1..).unwrap();
+        assert_eq!(&blocks[0].data[..12], &[0xe0, 0xf1, 3, 0xc0, 0x00, 0xd0, 0xe0, 0xf0, 0xff, 0xe0, 0xf0, 2]);
+        assert!(blocks[0].is_terminated());
+
+        let mut decompressed = LsdjSram::empty();
+        blocks.decompress_to(&mut decompressed, 0).unwrap();
+        assert_eq!(sram, decompressed);
+
+        // songs written without counts are still read with Compat::Legacy
+        let mut legacy = LsdjSram::with_compat(Compat::Legacy);
+        legacy.data.copy_from_slice(&sram.data);
+        let legacy_blocks = legacy.compress_into(1..).unwrap();
+        assert_eq!(&legacy_blocks[0].data[..6], &[0xe0, 0xf1, 0xe0, 0xf1, 0xe0, 0xf1]);
+        let mut decompressed = LsdjSram::with_compat(Compat::Legacy);
+        legacy_blocks.decompress_to(&mut decompressed, 0).unwrap();
+        assert_eq!(sram, decompressed);
+        assert_eq!("lsdj".parse(), Ok(Compat::Lsdj));
+        assert_eq!("lsdpatch".parse(), Ok(Compat::Lsdj));
+        assert_eq!("legacy".parse(), Ok(Compat::Legacy));
+        assert_eq!("native".parse::<Compat>(), Err(err::BAD_COMPAT));
+    }
+
+    #[test]
+    fn test_tally() {
+        let mut sram = LsdjSram::empty();
+        sram.data[0x100..0x110].copy_from_slice(&DEF_INST_VALUES);
+        sram.data[0x200] = RLE_BYTE;
+        sram.data[0x201] = 0x12;
+        let blocks = sram.compress_into(1..).unwrap();
+        let mut stats = CompressionStats::default();
+        let mut position = 0;
+        for block in blocks.iter() {
+            position = block.tally(position, Compat::Lsdj, &mut stats);
+        }
+        assert_eq!(position, SRAM_SIZE);
+        assert_eq!(stats.decompressed(), SRAM_SIZE);
+        assert_eq!(stats.blocks, blocks.len());
+        assert_eq!(stats.literal_encoded, stats.literal_bytes + 1); // $c0 is escaped
+        assert_eq!((stats.default_encoded, stats.default_bytes), (3, DEF_INST_SIZE));
+        assert_eq!(stats.compressed, stats.literal_encoded + stats.run_encoded + stats.default_encoded + 2);
+        assert_eq!(stats.run_savings(), stats.run_bytes - stats.run_encoded);
+        assert_eq!(stats.default_savings(), DEF_INST_SIZE - 3);
+        assert!(stats.ratio() < 0.1);
+    }
+
+    #[test]
+    fn test_compression_fills_blocks() {
+        let mut sram = LsdjSram::empty();
+        for (i, byte) in sram.data.iter_mut().enumerate() {
+            *byte = (i % 0xc0) as u8; // no runs, $c0, or $e0
+        }
+        let blocks = sram.compress_into(1..).unwrap();
+        assert_eq!(blocks.len(), (SRAM_SIZE + BLOCK_SIZE - 3) / (BLOCK_SIZE - 2));
+        assert_eq!(&blocks[0].data[(BLOCK_SIZE - 2)..], &[SPECIAL_BYTE, 2]);
+        assert_eq!(&blocks[0].data[..(BLOCK_SIZE - 2)], &sram.data[..(BLOCK_SIZE - 2)]);
+
+        // two-byte escapes which don't fit at the end of a block move to the next one
+        let mut sram = LsdjSram::empty();
+        sram.data[0] = 0x01;
+        for byte in sram.data[1..0x400].iter_mut() {
+            *byte = RLE_BYTE;
+        }
+        let blocks = sram.compress_into(1..).unwrap();
+        assert_eq!(&blocks[0].data[(BLOCK_SIZE - 3)..], &[SPECIAL_BYTE, 2, 0]);
+        let mut decompressed = LsdjSram::empty();
+        blocks.decompress_to(&mut decompressed, 0).unwrap();
+        assert_eq!(sram, decompressed);
+
+        // the same blocks, stored elsewhere, decompress the same when read in order
+        let blocks = sram.compress_into((1..=blocks.len()).rev()).unwrap();
+        let mut decompressed = LsdjSram::empty();
+        assert_eq!(decompress_sequence(&blocks.bytes(), &mut decompressed), Ok(blocks.len()));
+        assert_eq!(sram, decompressed);
+        let unended = DecompressError { block: 0, index: BLOCK_SIZE, reason: err::BAD_FMT };
+        assert_eq!(decompress_sequence(&blocks.bytes()[..BLOCK_SIZE], &mut LsdjSram::empty()), Err(unended));
+    }
+
+    #[test]
+    fn test_compress_into_positions() {
+        let mut sram = LsdjSram::empty();
+        for (i, byte) in sram.data.iter_mut().enumerate() {
+            *byte = (i % 0xc0) as u8;
+        }
+        let positions = (1..=MAX_BLOCK_COUNT).rev();
+        let blocks = sram.compress_into(positions).unwrap();
+        assert_eq!(blocks[0].position, 0xbf);
+        assert_eq!(&blocks[0].data[(BLOCK_SIZE - 2)..], &[SPECIAL_BYTE, 0xbe]);
+        assert_eq!(blocks[1].position, 0xbe);
+
+        let mut stored = vec![LsdjBlock::empty(); MAX_BLOCK_COUNT];
+        for block in blocks.iter() {
+            stored[block.position - 1] = *block;
+        }
+        let mut decompressed = LsdjSram::empty();
+        stored.decompress_to(&mut decompressed, 0xbe).unwrap();
+        assert_eq!(sram, decompressed);
+
+        assert_eq!(sram.compress_into(1..4).err(), Some(err::NO_BLOCKS));
+        assert_eq!(sram.compress_into(vec![0, 1]).err(), Some(err::BAD_BLOCK));
+        assert_eq!(LsdjSram::empty().compress_into(vec![5]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_compression_round_trip() {
+        let mut sram = LsdjSram::empty();
+        let mut state = 1u32;
+        for byte in sram.data.iter_mut() {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            *byte = match (state >> 16) % 8 {
+                0 => RLE_BYTE,
+                1 => SPECIAL_BYTE,
+                2..=4 => 0x00,
+                _ => (state >> 24) as u8,
+            };
+        }
+        sram.data[0x100..0x110].copy_from_slice(&DEF_INST_VALUES);
+        sram.data[0x7ff0..].copy_from_slice(&DEF_WAVE_VALUES);
+        let blocks = sram.compress_into(1..).unwrap();
+        let mut decompressed = LsdjSram::empty();
+        blocks.decompress_to(&mut decompressed, 0).unwrap();
+        assert_eq!(sram, decompressed);
+    }
+
+    #[test]
+    fn test_special_runs_round_trip() {
+        // runs of the bytes the compressor escapes, as long as and just around
+        // the longest run one instruction holds, starting at each offset
+        // within a block's last few bytes
+        for &byte in [RLE_BYTE, SPECIAL_BYTE, DEF_INST_BYTE, EOF_BYTE, 0x00].iter() {
+            for &len in [1, 2, 3, 0xfe, 0xff, 0x100, 0x101, 0x1fe, 0x1ff].iter() {
+                for start in (BLOCK_SIZE - 4)..=BLOCK_SIZE {
+                    let mut sram = LsdjSram::empty();
+                    for (i, b) in sram.data[..start].iter_mut().enumerate() {
+                        *b = (i % 0xc0) as u8 | 1; // literals, to push the run to the end of a block
+                    }
+                    sram.data[start..(start + len)].fill(byte);
+                    assert_eq!(verify_roundtrip(&sram).map_err(|e| e.to_string()), Ok(()), "{} ${:02x} at {:#x}", len, byte, start);
+                }
+            }
+        }
+        // alternating escaped bytes, and long runs of defaults (counted, or not with Compat::Legacy)
+        for &compat in [Compat::Lsdj, Compat::Legacy].iter() {
+            let mut sram = LsdjSram::with_compat(compat);
+            for (i, b) in sram.data[..0x1000].iter_mut().enumerate() {
+                *b = if i % 2 == 0 { RLE_BYTE } else { SPECIAL_BYTE };
+            }
+            for wave in sram.data[0x1000..(0x1000 + 0x101 * DEF_WAVE_SIZE)].chunks_exact_mut(DEF_WAVE_SIZE) {
+                wave.copy_from_slice(&DEF_WAVE_VALUES);
+            }
+            assert_eq!(verify_roundtrip(&sram).map_err(|e| e.to_string()), Ok(()));
+        }
+    }
+
+    /// Builds SRAM out of the patterns the compressor special-cases: runs
+    /// (often of $c0, $e0, and the bytes which follow $e0), default
+    /// instruments and waves, and literal bytes, padded out with zeros.
+    fn sram_strategy() -> impl Strategy<Value = LsdjSram> {
+        let byte = prop_oneof![
+            Just(RLE_BYTE), Just(SPECIAL_BYTE), Just(DEF_INST_BYTE), Just(DEF_WAVE_BYTE), Just(EOF_BYTE), any::<u8>(),
+        ];
+        let piece = prop_oneof![
+            (byte.clone(), 1..0x200usize).prop_map(|(b, len)| vec![b; len]),
+            (1..0x20usize).prop_map(|n| DEF_INST_VALUES.repeat(n)),
+            (1..0x20usize).prop_map(|n| DEF_WAVE_VALUES.repeat(n)),
+            proptest::collection::vec(byte, 1..0x40),
+        ];
+        let compat = prop_oneof![Just(Compat::Lsdj), Just(Compat::Legacy)];
+        (proptest::collection::vec(piece, 0..0x100), compat).prop_map(|(pieces, compat)| {
+            let mut sram = LsdjSram::with_compat(compat);
+            let bytes = pieces.concat();
+            let len = bytes.len().min(SRAM_SIZE);
+            sram.data[..len].copy_from_slice(&bytes[..len]);
+            sram
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_patterns_round_trip(sram in sram_strategy()) {
+            prop_assert_eq!(verify_roundtrip(&sram).map_err(|e| e.to_string()), Ok(()));
+        }
+
+        #[test]
+        fn prop_arbitrary_round_trip(bytes in proptest::collection::vec(any::<u8>(), SRAM_SIZE)) {
+            let mut sram = LsdjSram::empty();
+            sram.data.copy_from_slice(&bytes);
+            prop_assert_eq!(verify_roundtrip(&sram).map_err(|e| e.to_string()), Ok(()));
+        }
+    }
+
     #[test]
     fn check_sram_compression() -> std::io::Result<()> {
-        let savepath = PathBuf::from("saves/test.sav");
-        let mut savefile = File::open(savepath)?;
-        let mut blocks: Vec<LsdjBlock> = Vec::new();
-        let mut sram = LsdjSram::from(&mut savefile)?;
-        sram.compress_into(&mut blocks, 1).unwrap();
+        let sram = crate::lsdj::generate::test_save().sram;
+        let blocks = sram.compress_into(1..).unwrap();
         let mut decompressed_sram = LsdjSram::empty();
         blocks.decompress_to(&mut decompressed_sram, 0).unwrap();
         assert_eq!(sram, decompressed_sram);
         Ok(())
     }
 
+    #[test]
+    fn test_is_terminated() {
+        let mut block = LsdjBlock::empty();
+        assert!(!block.is_terminated());
+        block.data[0..5].copy_from_slice(&[RLE_BYTE, SPECIAL_BYTE, 0x10, SPECIAL_BYTE, EOF_BYTE]);
+        assert!(block.is_terminated()); // the $e0 in the RLE sequence isn't an instruction
+        block.data[4] = 0x05;
+        assert!(block.is_terminated());
+        block.data[4] = 0xc5;
+        assert!(!block.is_terminated());
+        block.data[BLOCK_SIZE - 1] = SPECIAL_BYTE;
+        block.data[3..5].copy_from_slice(&[SPECIAL_BYTE, DEF_WAVE_BYTE]);
+        assert!(!block.is_terminated()); // $e0 with nothing after it
+    }
+
     #[test]
     fn test_skip_to_block() {
         let mut empty_block = LsdjBlock::empty();
-        assert_eq!(empty_block.skip_to_block(0xb), Err(err::NO_SKIP));
+        assert_eq!(empty_block.skip_to_block(0xb, Compat::Lsdj), Err(err::NO_SKIP));
         let mut real_block = LsdjBlock::empty();
         real_block.data[5] = SPECIAL_BYTE;
         real_block.data[6] = 4;
-        assert_eq!(real_block.skip_to_block(0xb), Ok(()));
+        assert_eq!(real_block.skip_to_block(0xb, Compat::Lsdj), Ok(()));
         assert_eq!(&real_block.data[5..7], &[SPECIAL_BYTE, 0xb]);
     }
 }
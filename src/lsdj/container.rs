@@ -0,0 +1,110 @@
+use crate::lsdj::prelude::*;
+use crate::lsdj::{err, read_blocks, LsdjError, BLOCK_SIZE};
+use crate::lsdj::metadata::{LsdjTitle, TITLE_LENGTH};
+
+/// Bytes every container starts with.
+const MAGIC: [u8; 4] = *b"LSNG";
+/// Version of the container layout, bumped whenever the header changes.
+const CONTAINER_VERSION: u8 = 1;
+/// Bytes in the header: magic, container version, title, song version, format
+/// version, block count (two bytes), and CRC32 (four bytes).
+const HEADER_LENGTH: usize = MAGIC.len() + 1 + TITLE_LENGTH + 2 + 2 + 4;
+
+/// Blocks of compressed song data (as exported by `LsdjSave::export_song()`),
+/// along with the title and versions of the song they hold and a CRC32 of the
+/// blocks, so that an exported song can be identified and checked for
+/// corruption before it's imported.
+///
+/// Integers in the header are little-endian.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Container {
+    pub title: LsdjTitle,
+    /// Version byte of the song, incremented by LSDj whenever it's saved.
+    pub version: u8,
+    pub format_version: u8,
+    pub blocks: Vec<u8>,
+}
+
+impl Container {
+    /// Returns true if `bytes` start like a container, rather than with raw
+    /// blocks.
+    pub fn is_container(bytes: &[u8]) -> bool {
+        bytes.starts_with(&MAGIC)
+    }
+
+    /// Reads a container, checking that its header is intact, that it holds
+    /// as many whole blocks as its header says, and that their checksum
+    /// matches.
+    pub fn parse(bytes: &[u8]) -> Result<Container, LsdjError> {
+        if bytes.len() < HEADER_LENGTH || !Container::is_container(bytes) || bytes[4] != CONTAINER_VERSION {
+            return Err(LsdjError::Invalid(err::BAD_CONTAINER));
+        }
+        let (header, data) = bytes.split_at(HEADER_LENGTH);
+        let mut title = [0; TITLE_LENGTH];
+        title.copy_from_slice(&header[5..][..TITLE_LENGTH]);
+        let fields = &header[(5 + TITLE_LENGTH)..];
+        let block_count = u16::from_le_bytes([fields[2], fields[3]]) as usize;
+        let expected = u32::from_le_bytes([fields[4], fields[5], fields[6], fields[7]]);
+        let got = crc32(data);
+        if got != expected {
+            return Err(LsdjError::BadChecksum { expected, got });
+        }
+        let mut blocks = Vec::with_capacity(data.len());
+        if read_blocks(data, &mut blocks, false)? != block_count {
+            return Err(LsdjError::Invalid(err::BAD_CONTAINER));
+        }
+        Ok(Container { title, version: fields[0], format_version: fields[1], blocks })
+    }
+
+    /// Returns the container as bytes: its header followed by its blocks.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + self.blocks.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(CONTAINER_VERSION);
+        bytes.extend_from_slice(&self.title);
+        bytes.extend_from_slice(&[self.version, self.format_version]);
+        bytes.extend_from_slice(&((self.blocks.len() / BLOCK_SIZE) as u16).to_le_bytes());
+        bytes.extend_from_slice(&crc32(&self.blocks).to_le_bytes());
+        bytes.extend_from_slice(&self.blocks);
+        bytes
+    }
+}
+
+/// Returns the CRC32 (as used by zip and PNG) of `bytes`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(&[]), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_container() {
+        let mut blocks = vec![0; BLOCK_SIZE];
+        blocks[..3].copy_from_slice(&[0xe0, 0xff, 0x00]); // end of file
+        let container = Container { title: *b"TEST\0\0\0\0", version: 3, format_version: 0x16, blocks };
+        let mut bytes = container.bytes();
+        assert_eq!(bytes.len(), HEADER_LENGTH + BLOCK_SIZE);
+        assert!(Container::is_container(&bytes));
+        assert_eq!(Container::parse(&bytes).unwrap(), container);
+
+        bytes[HEADER_LENGTH + 0x10] = 1;
+        assert!(matches!(Container::parse(&bytes), Err(LsdjError::BadChecksum { .. })));
+        assert!(matches!(Container::parse(&bytes[..10]), Err(LsdjError::Invalid(e)) if e == err::BAD_CONTAINER));
+        assert!(!Container::is_container(&container.blocks));
+    }
+}
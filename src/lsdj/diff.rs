@@ -0,0 +1,217 @@
+use core::fmt;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::song::*;
+
+const CHANNEL_NAMES: [&str; CHANNEL_COUNT] = ["PU1", "PU2", "WAV", "NOI"];
+const TABLE_COLUMN_NAMES: [&str; 6] = ["envelope", "transpose", "command 1", "value 1", "command 2", "value 2"];
+
+/// A part of a song which can differ between two songs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Item {
+    FormatVersion,
+    Tempo,
+    Row(u8),
+    Chain(u8),
+    Phrase(u8),
+    Instrument(u8),
+    Table(u8),
+    Groove(u8),
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Item::FormatVersion => write!(f, "format version"),
+            Item::Tempo => write!(f, "tempo"),
+            Item::Row(n) => write!(f, "row {:02X}", n),
+            Item::Chain(n) => write!(f, "chain {:02X}", n),
+            Item::Phrase(n) => write!(f, "phrase {:02X}", n),
+            Item::Instrument(n) => write!(f, "instrument {:02X}", n),
+            Item::Table(n) => write!(f, "table {:02X}", n),
+            Item::Groove(n) => write!(f, "groove {:02X}", n),
+        }
+    }
+}
+
+/// How an item differs between two songs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// The item is only allocated in the new song.
+    Added,
+    /// The item is only allocated in the old song.
+    Removed,
+    /// The item is allocated in both songs, but differs as described by each
+    /// line (e.g. `step 3: C-4 I01 --- -> D-4 I01 ---`).
+    Changed(Vec<String>),
+}
+
+/// One difference between two songs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference {
+    pub item: Item,
+    pub change: Change,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.change {
+            Change::Added => writeln!(f, "+ {}", self.item),
+            Change::Removed => writeln!(f, "- {}", self.item),
+            Change::Changed(lines) => {
+                writeln!(f, "~ {}", self.item)?;
+                for line in lines {
+                    writeln!(f, "    {}", line)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Compares two songs, returning the differences in their format version,
+/// tempo, song rows, chains, phrases, instruments, tables, and grooves (in
+/// that order).
+pub fn diff(old: &Song, new: &Song) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    let mut push = |item, change| differences.push(Difference { item, change });
+    if old.format_version() != new.format_version() {
+        push(Item::FormatVersion, Change::Changed(vec![
+            format!("{:02X} -> {:02X}", old.format_version(), new.format_version())]));
+    }
+    if old.tempo() != new.tempo() {
+        push(Item::Tempo, Change::Changed(vec![format!("{} -> {}", old.tempo(), new.tempo())]));
+    }
+    for row in 0..ROW_COUNT {
+        let lines: Vec<String> = old.row(row).iter().zip(new.row(row).iter()).enumerate()
+                                    .filter(|(_, (a, b))| a != b)
+                                    .map(|(channel, (a, b))| format!("{}: {} -> {}", CHANNEL_NAMES[channel], chain_name(*a), chain_name(*b)))
+                                    .collect();
+        if !lines.is_empty() {
+            push(Item::Row(row as u8), Change::Changed(lines));
+        }
+    }
+    for chain in 0..CHAIN_COUNT {
+        if let Some(change) = compare(old.chain(chain), new.chain(chain), |a, b| step_lines(&a, &b)) {
+            push(Item::Chain(chain as u8), change);
+        }
+    }
+    for phrase in 0..PHRASE_COUNT {
+        if let Some(change) = compare(old.phrase(phrase), new.phrase(phrase), |a, b| step_lines(&a, &b)) {
+            push(Item::Phrase(phrase as u8), change);
+        }
+    }
+    for instrument in 0..INSTRUMENT_COUNT {
+        if let Some(change) = compare(old.instrument(instrument), new.instrument(instrument), instrument_lines) {
+            push(Item::Instrument(instrument as u8), change);
+        }
+    }
+    for table in 0..TABLE_COUNT {
+        if let Some(change) = compare(old.table(table), new.table(table), |a, b| {
+            a.iter().zip(b.iter()).zip(TABLE_COLUMN_NAMES.iter())
+             .flat_map(|((a, b), name)| byte_lines(name, a, b))
+             .collect()
+        }) {
+            push(Item::Table(table as u8), change);
+        }
+    }
+    for groove in 0..GROOVE_COUNT {
+        let lines = byte_lines("step", &old.groove(groove), &new.groove(groove));
+        if !lines.is_empty() {
+            push(Item::Groove(groove as u8), Change::Changed(lines));
+        }
+    }
+    differences
+}
+
+/// Compares an item which may not be allocated in either song, describing the
+/// differences between two allocated items with `lines`. Returns `None` if the
+/// item doesn't differ.
+fn compare<T: PartialEq>(old: Option<T>, new: Option<T>, lines: impl Fn(T, T) -> Vec<String>) -> Option<Change> {
+    match (old, new) {
+        (None, None) => None,
+        (None, Some(_)) => Some(Change::Added),
+        (Some(_), None) => Some(Change::Removed),
+        (Some(a), Some(b)) if a == b => None,
+        (Some(a), Some(b)) => Some(Change::Changed(lines(a, b))),
+    }
+}
+
+/// Describes each differing step of a chain or phrase.
+fn step_lines<T: PartialEq + fmt::Display>(old: &[T], new: &[T]) -> Vec<String> {
+    old.iter().zip(new.iter()).enumerate()
+       .filter(|(_, (a, b))| a != b)
+       .map(|(i, (a, b))| format!("step {:X}: {} -> {}", i, a, b))
+       .collect()
+}
+
+/// Describes each differing byte, labelled `label`, of two lists of bytes.
+fn byte_lines(label: &str, old: &[u8], new: &[u8]) -> Vec<String> {
+    old.iter().zip(new.iter()).enumerate()
+       .filter(|(_, (a, b))| a != b)
+       .map(|(i, (a, b))| format!("{} {:X}: {:02X} -> {:02X}", label, i, a, b))
+       .collect()
+}
+
+/// Describes the differences between two instruments.
+fn instrument_lines(old: Instrument, new: Instrument) -> Vec<String> {
+    let mut lines = Vec::new();
+    if old.name != new.name {
+        lines.push(format!("name: {:?} -> {:?}", old.name, new.name));
+    }
+    if old.kind != new.kind {
+        lines.push(format!("type: {} -> {}", old.kind, new.kind));
+    }
+    let mut old_params = old.params;
+    old_params[0] = new.params[0]; // the type byte, already described above
+    lines.extend(byte_lines("param", &old_params, &new.params));
+    lines
+}
+
+/// Returns the name of a chain in a song row, or `--` for an empty row.
+fn chain_name(chain: Option<u8>) -> String {
+    match chain {
+        Some(c) => format!("{:02X}", c),
+        None => "--".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::song::tests::*;
+
+    #[test]
+    fn test_identical() {
+        let song = empty_song();
+        assert!(diff(&song, &song.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut old = empty_song();
+        set_phrase(&mut old, 0x00, 0x0d, 0x00, 0, 0);
+        set_phrase(&mut old, 0x01, 0x0d, 0x00, 0, 0);
+        set_instrument(&mut old, 0x00, "BASS", [0, 0xa8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut new = old.clone();
+        set_phrase(&mut new, 0x00, 0x0f, 0x00, 0x0c, 0x80);
+        set_chain(&mut new, 0x03, 0x00, 0x00);
+        set_row(&mut new, 0x10, 1, 0x03);
+        set_instrument(&mut new, 0x00, "BASS", [0, 0x68, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let differences = diff(&old, &new);
+        assert_eq!(differences, vec![
+            Difference { item: Item::Row(0x10), change: Change::Changed(vec!["PU2: -- -> 03".to_string()]) },
+            Difference { item: Item::Chain(0x03), change: Change::Added },
+            Difference { item: Item::Phrase(0x00), change: Change::Changed(vec![
+                "step 0: C-4 I00 --- -> D-4 I00 P80".to_string()]) },
+            Difference { item: Item::Instrument(0x00), change: Change::Changed(vec![
+                "param 1: A8 -> 68".to_string()]) },
+        ]);
+        assert_eq!(differences[1].to_string(), "+ chain 03\n");
+        assert_eq!(differences[2].to_string(), "~ phrase 00\n    step 0: C-4 I00 --- -> D-4 I00 P80\n");
+
+        let reversed = diff(&new, &old);
+        assert_eq!(reversed[1], Difference { item: Item::Chain(0x03), change: Change::Removed });
+    }
+}
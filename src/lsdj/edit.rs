@@ -0,0 +1,154 @@
+use crate::lsdj::prelude::*;
+use crate::lsdj::err;
+use crate::lsdj::song::{InstrumentType, Song, GROOVE_COUNT, HIGHEST_NOTE, MAX_TEMPO, MIN_TEMPO, PHRASE_COUNT,
+                        ROW_COUNT};
+
+/// Channel whose notes pick a noise shape rather than a pitch.
+const NOISE_CHANNEL: usize = 3;
+
+/// Returns, for each phrase, a bitfield of the channels (bit 0 for PU1) on
+/// which the song arrangement plays it.
+fn phrase_channels(song: &Song) -> [u8; PHRASE_COUNT] {
+    let mut channels = [0; PHRASE_COUNT];
+    for row in 0..ROW_COUNT {
+        for (channel, chain) in song.row(row).iter().enumerate() {
+            let steps = match chain.and_then(|c| song.chain(c as usize)) {
+                Some(steps) => steps,
+                None => continue,
+            };
+            for phrase in steps.iter().filter_map(|s| s.phrase).filter(|&p| (p as usize) < PHRASE_COUNT) {
+                channels[phrase as usize] |= 1 << channel;
+            }
+        }
+    }
+    channels
+}
+
+/// Transposes every note in the phrases of `song` by `semitones`, except for
+/// notes played on the noise channel and notes played by kit instruments,
+/// which pick a noise shape or sample rather than a pitch. A step without an
+/// instrument counts as played by the last instrument set earlier in its
+/// phrase.
+///
+/// A phrase played both on the noise channel and on another channel can't be
+/// transposed on one but not the other, so it is left alone; these phrases are
+/// returned. Returns an `Err` (leaving the song unchanged) if any note would be
+/// transposed out of LSDj's range.
+pub fn transpose(song: &mut Song, semitones: i8) -> Result<Vec<u8>, &'static str> {
+    let channels = phrase_channels(song);
+    let mut shared = Vec::new();
+    let mut notes = Vec::new(); // (phrase, step, transposed note)
+    for (phrase, &channels) in channels.iter().enumerate() {
+        if channels & (1 << NOISE_CHANNEL) != 0 {
+            if channels != 1 << NOISE_CHANNEL {
+                shared.push(phrase as u8);
+            }
+            continue;
+        }
+        let steps = match song.phrase(phrase) {
+            Some(steps) => steps,
+            None => continue,
+        };
+        let mut kit = false;
+        for (i, step) in steps.iter().enumerate() {
+            if let Some(instrument) = step.instrument {
+                kit = song.instrument(instrument as usize).is_some_and(|i| i.kind == InstrumentType::Kit);
+            }
+            if step.note == 0 || kit {
+                continue;
+            }
+            let note = step.note as i16 + semitones as i16;
+            if note < 1 || note > HIGHEST_NOTE as i16 {
+                return Err(err::NOTE_RANGE);
+            }
+            notes.push((phrase, i, note as u8));
+        }
+    }
+    for (phrase, step, note) in notes {
+        song.set_note(phrase, step, note);
+    }
+    Ok(shared)
+}
+
+/// Sets the tempo of `song` to `bpm`. If `rescale_grooves` is true, the step
+/// lengths of every groove are scaled by the same ratio as the tempo (rounded
+/// to a whole number of ticks, and at least one), so that the song plays at
+/// about the speed it did before. Returns an `Err` (leaving the song
+/// unchanged) if LSDj can't play at `bpm`.
+pub fn set_tempo(song: &mut Song, bpm: u16, rescale_grooves: bool) -> Result<(), &'static str> {
+    if !(MIN_TEMPO..=MAX_TEMPO).contains(&bpm) {
+        return Err(err::BAD_TEMPO);
+    }
+    if rescale_grooves {
+        let old = song.tempo() as u32;
+        for groove in 0..GROOVE_COUNT {
+            let mut steps = song.groove(groove);
+            for ticks in steps.iter_mut().filter(|t| **t != 0) { // 0 ends the groove
+                *ticks = ((*ticks as u32 * bpm as u32 + old / 2) / old).clamp(1, u8::MAX as u32) as u8;
+            }
+            song.set_groove(groove, steps);
+        }
+    }
+    song.set_tempo(bpm);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::song::tests::*;
+
+    #[test]
+    fn test_transpose() {
+        let mut song = empty_song();
+        set_instrument(&mut song, 0x00, "LEAD", [0; 16]);
+        set_instrument(&mut song, 0x01, "KICK", [2, 0, 0x03, 0, 0, 0, 0, 0, 0, 0x05, 0, 0, 0, 0, 0, 0]);
+        set_phrase(&mut song, 0x00, 0x10, 0x00, 0, 0); // played on PU1
+        set_phrase(&mut song, 0x01, 0x10, 0x01, 0, 0); // kit
+        set_phrase(&mut song, 0x02, 0x10, 0x00, 0, 0); // played on NOI
+        set_phrase(&mut song, 0x03, 0x10, 0x00, 0, 0); // played on PU2 and NOI
+        set_phrase(&mut song, 0x04, 0x10, 0xff, 0, 0); // not played
+        song.set_note(0x04, 1, 0x20);
+        set_chain(&mut song, 0x00, 0x00, 0);
+        set_chain(&mut song, 0x01, 0x02, 0);
+        set_chain(&mut song, 0x02, 0x03, 0);
+        set_row(&mut song, 0, 0, 0x00);
+        set_row(&mut song, 0, 1, 0x02);
+        set_row(&mut song, 0, 3, 0x01);
+        set_row(&mut song, 1, 3, 0x02);
+        let original = song.clone();
+
+        assert_eq!(transpose(&mut song, -2), Ok(vec![0x03]));
+        let notes: Vec<u8> = (0..5).map(|p| song.phrase(p).unwrap()[0].note).collect();
+        assert_eq!(notes, vec![0x0e, 0x10, 0x10, 0x10, 0x0e]);
+        assert_eq!(song.phrase(0x04).unwrap()[1].note, 0x1e);
+        assert_eq!(song.phrase(0x00).unwrap()[1].note, 0);
+
+        assert_eq!(transpose(&mut song, 0x60), Err(err::NOTE_RANGE));
+        assert_eq!(transpose(&mut song, -0x0e), Err(err::NOTE_RANGE));
+        assert_eq!(transpose(&mut song, 2), Ok(vec![0x03]));
+        assert_eq!(&song.data[..], &original.data[..]);
+    }
+
+    #[test]
+    fn test_set_tempo() {
+        let mut song = empty_song();
+        song.set_tempo(120);
+        song.set_groove(0, [6, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        song.set_groove(1, [1, 9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(set_tempo(&mut song, 39, true), Err(err::BAD_TEMPO));
+        assert_eq!(set_tempo(&mut song, 296, true), Err(err::BAD_TEMPO));
+        assert_eq!(song.tempo(), 120);
+
+        assert_eq!(set_tempo(&mut song, 140, false), Ok(()));
+        assert_eq!(song.tempo(), 140);
+        assert_eq!(song.groove(0)[..3], [6, 6, 0]);
+
+        assert_eq!(set_tempo(&mut song, 280, true), Ok(()));
+        assert_eq!(song.tempo(), 280);
+        assert_eq!(song.groove(0)[..3], [12, 12, 0]);
+        assert_eq!(song.groove(1)[..3], [2, 18, 0]);
+        assert_eq!(set_tempo(&mut song, 40, true), Ok(()));
+        assert_eq!(song.groove(1)[..3], [1, 3, 0]);
+    }
+}
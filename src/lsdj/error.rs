@@ -0,0 +1,165 @@
+use core::error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::err;
+
+/// Errors which carry details about where a problem was found, for cases where
+/// one of the messages in `lsdj::err` alone isn't enough to track it down.
+#[derive(Debug)]
+pub enum LsdjError {
+    /// A stream of blocks ended partway through block `block` (zero-indexed),
+    /// which was only `len` bytes long.
+    TruncatedBlock { block: usize, len: usize },
+    /// Block `block` (zero-indexed) doesn't end with a skip or end-of-file
+    /// instruction.
+    UnterminatedBlock { block: usize },
+    /// A save file was `got` bytes long, which is shorter than the `expected`
+    /// length of the smallest layout it could belong to.
+    TruncatedSave { expected: usize, got: usize },
+    /// The checksum of data read was `got`, rather than the `expected` one
+    /// stored with it.
+    BadChecksum { expected: u32, got: u32 },
+    /// Data was invalid, as described by `reason` (one of the messages in
+    /// `lsdj::err`), at `offset` bytes into the file it was read from, in
+    /// `region` (e.g. "block 05 (song 00, TEST)"). `context` holds the bytes
+    /// around it, with the byte at `offset` at index `at`.
+    Corrupt { reason: &'static str, offset: usize, region: String, context: Vec<u8>, at: usize },
+    /// Data was invalid, as described by one of the messages in `lsdj::err`.
+    Invalid(&'static str),
+    /// Reading or writing failed.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+}
+
+impl fmt::Display for LsdjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LsdjError::TruncatedBlock { block, len } =>
+                write!(f, "block {} is truncated ({:#x} of {:#x} bytes)", block, len, crate::lsdj::BLOCK_SIZE),
+            LsdjError::UnterminatedBlock { block } =>
+                write!(f, "block {} does not end with a skip or end-of-file instruction", block),
+            LsdjError::TruncatedSave { expected, got } =>
+                write!(f, "save file is truncated ({:#x} bytes, expected {:#x})", got, expected),
+            LsdjError::BadChecksum { expected, got } =>
+                write!(f, "checksum mismatch ({:08x}, expected {:08x}); the data is corrupt", got, expected),
+            LsdjError::Corrupt { reason, offset, region, context, at } => {
+                write!(f, "{} (at {:#x}, in {}:", reason, offset, region)?;
+                for (i, byte) in context.iter().enumerate() {
+                    if i == *at {
+                        write!(f, " [{:02x}]", byte)?;
+                    } else {
+                        write!(f, " {:02x}", byte)?;
+                    }
+                }
+                if *at >= context.len() {
+                    write!(f, " []")?; // the end of the data
+                }
+                write!(f, ")")
+            },
+            LsdjError::Invalid(e) => write!(f, "{}", e),
+            #[cfg(feature = "std")]
+            LsdjError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Broad classes of `LsdjError`, for callers which handle each differently
+/// (e.g. by exiting with a different status).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Data read is corrupt, or isn't of the kind expected (e.g. a file which
+    /// isn't a save file).
+    Corrupt,
+    /// There's no room left for what was to be added: no free blocks, song
+    /// slots, or chains, phrases, and the like within a song.
+    NoSpace,
+    /// Something asked for (such as a song, chain, or instrument) doesn't
+    /// exist.
+    NotFound,
+    /// An argument was malformed or out of range (such as a title, or a block
+    /// number).
+    Argument,
+    /// Reading or writing failed.
+    Io,
+}
+
+/// Number of bytes either side of a problem shown by `LsdjError::Corrupt`.
+const CONTEXT_LEN: usize = 8;
+
+impl LsdjError {
+    /// Returns a `Corrupt` error for the byte at index `at` of `data`, which
+    /// starts `base` bytes into the file it was read from, showing the bytes
+    /// of `data` around it.
+    pub(crate) fn corrupt(reason: &'static str, data: &[u8], base: usize, at: usize, region: String) -> LsdjError {
+        let start = at.saturating_sub(CONTEXT_LEN).min(data.len());
+        let end = (at + CONTEXT_LEN + 1).min(data.len());
+        LsdjError::Corrupt { reason, offset: base + at, region, context: data[start..end].to_vec(), at: at - start }
+    }
+
+    /// Returns the class of the error, telling e.g. a save without enough
+    /// free blocks from a corrupt one.
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            // a message not from `lsdj::err` was passed in by the caller, not
+            // found in anything read
+            LsdjError::Invalid(e) => err::class_of(e).unwrap_or(ErrorClass::Argument),
+            #[cfg(feature = "std")]
+            LsdjError::Io(_) => ErrorClass::Io,
+            _ => ErrorClass::Corrupt,
+        }
+    }
+}
+
+impl error::Error for LsdjError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            LsdjError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for LsdjError {
+    fn from(e: io::Error) -> LsdjError {
+        LsdjError::Io(e)
+    }
+}
+
+impl From<&'static str> for LsdjError {
+    fn from(e: &'static str) -> LsdjError {
+        LsdjError::Invalid(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<LsdjError> for io::Error {
+    fn from(e: LsdjError) -> io::Error {
+        match e {
+            LsdjError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_class() {
+        assert_eq!(LsdjError::Invalid(err::NO_BLOCKS).class(), ErrorClass::NoSpace);
+        assert_eq!(LsdjError::Invalid(err::NO_SONG).class(), ErrorClass::NotFound);
+        assert_eq!(LsdjError::Invalid(err::BAD_TITLE_FMT).class(), ErrorClass::Argument);
+        assert_eq!(LsdjError::Invalid(err::BAD_LZO).class(), ErrorClass::Corrupt);
+        assert_eq!(LsdjError::TruncatedSave { expected: 0x8400, got: 0x100 }.class(), ErrorClass::Corrupt);
+        assert_eq!(LsdjError::corrupt(err::BLOCK_LOOP, &[0; 4], 0x8200, 1, String::new()).class(), ErrorClass::Corrupt);
+        assert_eq!(LsdjError::from(io::Error::other("disk full")).class(), ErrorClass::Io);
+        assert_eq!(err::class_of(err::BAD_CODEC), Some(ErrorClass::Argument));
+        assert_eq!(err::class_of("disk on fire"), None);
+    }
+}
@@ -0,0 +1,249 @@
+//! A C API for embedding save file parsing and song compression in other
+//! programs, declared in `include/lsdjtool.h`.
+//!
+//! Saves are opaque `LsdjSave` handles opened from a buffer. Functions which
+//! can fail return a null pointer or a negative number, and the reason can
+//! then be had from `lsdj_last_error()`. Buffers returned by the library must
+//! be freed with `lsdj_buffer_free()`.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::slice;
+
+use crate::lsdj::err;
+use crate::lsdj::{lsdjtitle_from, LsdjSave};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Records `e` as the reason the last call on this thread failed.
+fn set_error<E: ToString>(e: E) {
+    let message = CString::new(e.to_string()).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// A buffer of bytes owned by the library.
+#[repr(C)]
+pub struct LsdjBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl LsdjBuffer {
+    fn from_vec(bytes: Vec<u8>) -> LsdjBuffer {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        LsdjBuffer { data, len }
+    }
+
+    fn null() -> LsdjBuffer {
+        LsdjBuffer { data: ptr::null_mut(), len: 0 }
+    }
+}
+
+/// A song in a save file, as listed by `lsdj_save_list()`.
+#[repr(C)]
+pub struct LsdjSongInfo {
+    pub index: u8,
+    /// Title of the song, null-terminated.
+    pub title: [c_char; 9],
+    /// Version byte, counting how many times the song has been saved.
+    pub version: u8,
+    /// Number of blocks used.
+    pub blocks: usize,
+}
+
+/// Returns a description of why the last call on this thread failed, or null
+/// if none has. The string is valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn lsdj_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Parses a save file of any supported size from `len` bytes at `bytes`,
+/// returning null if it couldn't be parsed.
+///
+/// # Safety
+///
+/// `bytes` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lsdj_save_open(bytes: *const u8, len: usize) -> *mut LsdjSave {
+    if bytes.is_null() {
+        set_error(err::BAD_SAVE_SIZE);
+        return ptr::null_mut();
+    }
+    match LsdjSave::from_bytes(slice::from_raw_parts(bytes, len)) {
+        Ok(save) => Box::into_raw(Box::new(save)),
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Frees a save opened with `lsdj_save_open()`. Does nothing if `save` is null.
+///
+/// # Safety
+///
+/// `save` must be null or have come from `lsdj_save_open()`, and mustn't be
+/// used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn lsdj_save_free(save: *mut LsdjSave) {
+    if !save.is_null() {
+        drop(Box::from_raw(save));
+    }
+}
+
+/// Writes up to `capacity` songs in the save to `songs`, in order of index,
+/// and returns how many songs there are (which may be more than `capacity`;
+/// there are never more than 32).
+///
+/// # Safety
+///
+/// `save` must be a valid save, and `songs` must point to room for
+/// `capacity` entries (it may be null if `capacity` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn lsdj_save_list(save: *const LsdjSave, songs: *mut LsdjSongInfo, capacity: usize) -> usize {
+    let save = &*save;
+    let indices = save.metadata.songs();
+    for (i, slot) in indices.iter().filter_map(|&s| save.slot(s)).take(capacity).enumerate() {
+        let mut title = [0; 9];
+        for (c, b) in title.iter_mut().take(8).zip(slot.title().bytes()) {
+            *c = b as c_char;
+        }
+        *songs.add(i) = LsdjSongInfo {
+            index: slot.index(),
+            title,
+            version: slot.version(),
+            blocks: slot.block_indices().len(),
+        };
+    }
+    indices.len()
+}
+
+/// Returns the blocks of compressed song data making up song `song`, as saved
+/// in an .lsdsng file, or a buffer with a null `data` if there's no such song.
+///
+/// # Safety
+///
+/// `save` must be a valid save.
+#[no_mangle]
+pub unsafe extern "C" fn lsdj_save_export_song(save: *const LsdjSave, song: u8) -> LsdjBuffer {
+    match (*save).slot(song) {
+        Some(slot) => LsdjBuffer::from_vec(slot.compressed_bytes()),
+        None => {
+            set_error(err::NO_SONG);
+            LsdjBuffer::null()
+        }
+    }
+}
+
+/// Imports `len` bytes of compressed song data at `bytes` into the first free
+/// song slot, titled `title`, returning the slot's index or -1 on failure.
+///
+/// # Safety
+///
+/// `save` must be a valid save, `bytes` must point to `len` readable bytes,
+/// and `title` must be a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn lsdj_save_import_song(save: *mut LsdjSave, bytes: *const u8, len: usize, title: *const c_char) -> c_int {
+    if bytes.is_null() || title.is_null() {
+        set_error(err::BAD_FMT);
+        return -1;
+    }
+    let title = match CStr::from_ptr(title).to_str().map_err(|_| err::BAD_TITLE_FMT).and_then(lsdjtitle_from) {
+        Ok(t) => t,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    match (*save).import_song(slice::from_raw_parts(bytes, len), title) {
+        Ok(song) => song as c_int,
+        Err(e) => {
+            set_error(e);
+            -1
+        }
+    }
+}
+
+/// Deletes song `song`, freeing its blocks. Returns 0, or -1 if there's no
+/// such song.
+///
+/// # Safety
+///
+/// `save` must be a valid save.
+#[no_mangle]
+pub unsafe extern "C" fn lsdj_save_delete_song(save: *mut LsdjSave, song: u8) -> c_int {
+    match (*save).delete_song(song) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(e);
+            -1
+        }
+    }
+}
+
+/// Returns the bytes of the save file, ready to be written out.
+///
+/// # Safety
+///
+/// `save` must be a valid save.
+#[no_mangle]
+pub unsafe extern "C" fn lsdj_save_serialize(save: *const LsdjSave) -> LsdjBuffer {
+    LsdjBuffer::from_vec((*save).bytes())
+}
+
+/// Frees a buffer returned by the library. Does nothing if its `data` is null.
+///
+/// # Safety
+///
+/// `buffer` must have come from the library, and mustn't be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn lsdj_buffer_free(buffer: LsdjBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        let bytes = crate::lsdj::generate::test_save().bytes();
+        unsafe {
+            assert!(lsdj_save_open(bytes.as_ptr(), 100).is_null());
+            assert_eq!(CStr::from_ptr(lsdj_last_error()).to_str(), Ok("save file is truncated (0x64 bytes, expected 0x10000)"));
+
+            let save = lsdj_save_open(bytes.as_ptr(), bytes.len());
+            let mut songs: [LsdjSongInfo; 2] = std::mem::zeroed();
+            assert_eq!(lsdj_save_list(save, songs.as_mut_ptr(), 2), 1);
+            assert_eq!(songs[0].index, 0);
+            assert_eq!(CStr::from_ptr(songs[0].title.as_ptr()).to_str(), Ok("TEST"));
+
+            let song = lsdj_save_export_song(save, 0);
+            assert_eq!(song.len, songs[0].blocks * 0x200);
+            assert!(lsdj_save_export_song(save, 1).data.is_null());
+            assert_eq!(lsdj_save_import_song(save, song.data, song.len, CString::new("COPY").unwrap().as_ptr()), 1);
+            assert_eq!(lsdj_save_import_song(save, song.data, song.len, CString::new("copy!").unwrap().as_ptr()), -1);
+            lsdj_buffer_free(song);
+            assert_eq!(lsdj_save_delete_song(save, 0), 0);
+            assert_eq!(lsdj_save_delete_song(save, 0), -1);
+
+            let out = lsdj_save_serialize(save);
+            assert_eq!(out.len, bytes.len());
+            let copy = lsdj_save_open(out.data, out.len);
+            assert_eq!(lsdj_save_list(copy, ptr::null_mut(), 0), 1);
+            assert_eq!((*copy).decompress_song(1).ok(), (*save).decompress_song(1).ok());
+            lsdj_buffer_free(out);
+            lsdj_save_free(copy);
+            lsdj_save_free(save);
+        }
+    }
+}
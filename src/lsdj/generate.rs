@@ -0,0 +1,293 @@
+use core::ops::Range;
+use core::str::FromStr;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::{err, blocks_from_sram, lsdjtitle_from, LsdjLayout, LsdjSave, Compat, SRAM_SIZE, BLOCK_SIZE};
+use crate::lsdj::compression::{DEF_INST_VALUES, DEF_WAVE_VALUES};
+use crate::lsdj::song::{Song, ChainStep, Step, CHANNEL_COUNT, CHAIN_COUNT, INSTRUMENT_COUNT, PHRASE_COUNT,
+                        STEP_COUNT, SYNTH_COUNT, PHRASE_NOTES_ADDRESS, CHAIN_PHRASES_ADDRESS,
+                        CHAIN_TRANSPOSES_ADDRESS, PHRASE_COMMANDS_ADDRESS, PHRASE_VALUES_ADDRESS, WAVES_ADDRESS,
+                        PHRASE_INSTRUMENTS_ADDRESS};
+
+/// Format version of the songs made by `generate()`.
+const FORMAT_VERSION: u8 = 0x16;
+
+/// Parts of a song which are filled with noise to make it compress to more
+/// blocks, in the order they are filled; none hold check bytes or the format
+/// version, so the song stays valid.
+const NOISE_REGIONS: [Range<usize>; 5] = [
+    PHRASE_COMMANDS_ADDRESS..(PHRASE_VALUES_ADDRESS + PHRASE_COUNT * STEP_COUNT),
+    PHRASE_NOTES_ADDRESS..(PHRASE_NOTES_ADDRESS + PHRASE_COUNT * STEP_COUNT),
+    CHAIN_PHRASES_ADDRESS..(CHAIN_TRANSPOSES_ADDRESS + CHAIN_COUNT * STEP_COUNT),
+    PHRASE_INSTRUMENTS_ADDRESS..(PHRASE_INSTRUMENTS_ADDRESS + PHRASE_COUNT * STEP_COUNT),
+    WAVES_ADDRESS..PHRASE_INSTRUMENTS_ADDRESS,
+];
+
+/// What the songs made by `generate()` are filled with.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Content {
+    /// Nothing but the check bytes and format version LSDj needs to load a
+    /// song, compressing to a single block.
+    Empty,
+    /// A few chains and phrases of notes, with default instruments and waves,
+    /// like a song just started in LSDj.
+    #[default]
+    Song,
+    /// Random bytes in every part of the song which can hold them, compressing
+    /// to as many blocks as a song can.
+    Noise,
+}
+
+impl FromStr for Content {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Content, &'static str> {
+        match s {
+            "empty" => Ok(Content::Empty),
+            "song"  => Ok(Content::Song),
+            "noise" => Ok(Content::Noise),
+            _ => Err(err::BAD_CONTENT),
+        }
+    }
+}
+
+/// Describes the save file made by `generate()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GenOptions {
+    /// Number of songs, titled `SONG00`, `SONG01`, and so on.
+    pub songs: usize,
+    /// Number of blocks each song compresses to, or `None` for however many
+    /// its content takes.
+    pub blocks: Option<usize>,
+    /// What the songs are filled with.
+    pub content: Content,
+    /// Seed from which the songs' random bytes are generated; the same
+    /// options always generate the same save.
+    pub seed: u64,
+    /// Layout of the save file.
+    pub layout: LsdjLayout,
+}
+
+impl Default for GenOptions {
+    fn default() -> GenOptions {
+        GenOptions { songs: 1, blocks: None, content: Content::Song, seed: 0, layout: LsdjLayout::SAVE_128KB }
+    }
+}
+
+/// A xorshift64* generator, so that generated songs don't depend on any
+/// source of randomness outside this module.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9e3779b97f4a7c15) | 1) // any nonzero state will do
+    }
+
+    fn next_u8(&mut self) -> u8 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545f4914f6cdd1d) >> 56) as u8
+    }
+}
+
+/// Returns the SRAM of a song filled with `content`, before any noise is
+/// added to it.
+fn base_sram(content: Content, rng: &mut Rng) -> [u8; SRAM_SIZE] {
+    let mut song = Song::empty();
+    if content == Content::Song {
+        // eight chains of eight phrases each, played two rows at a time
+        // on every channel, all with the default instrument
+        for row in 0..2 {
+            let mut chains = [None; CHANNEL_COUNT];
+            for (channel, chain) in chains.iter_mut().enumerate() {
+                *chain = Some((row * CHANNEL_COUNT + channel) as u8);
+            }
+            song.set_row(row, chains);
+        }
+        for chain in 0..(2 * CHANNEL_COUNT) {
+            let mut steps = [ChainStep { phrase: None, transpose: 0 }; STEP_COUNT];
+            for (i, step) in steps.iter_mut().take(8).enumerate() {
+                step.phrase = Some((chain * 8 + i) as u8);
+            }
+            song.set_chain(chain, &steps);
+        }
+        for phrase in 0..(2 * CHANNEL_COUNT * 8) {
+            let mut steps = [Step { note: 0, instrument: None, command: 0, value: 0 }; STEP_COUNT];
+            for step in steps.iter_mut() {
+                step.note = [0, 0, 0, 0x20, 0x24, 0x27, 0x2b][rng.next_u8() as usize % 7];
+                step.instrument = if step.note == 0 { None } else { Some(0) };
+            }
+            song.set_phrase(phrase, &steps);
+        }
+        for instrument in 1..INSTRUMENT_COUNT {
+            song.clear_instrument(instrument);
+        }
+        song.set_instrument(0, "", &DEF_INST_VALUES);
+        song.set_tempo(0x80);
+        for wave in 0..(SYNTH_COUNT * STEP_COUNT) {
+            song.set_wave(wave, &DEF_WAVE_VALUES);
+        }
+    }
+    song.set_format_version(FORMAT_VERSION);
+    song.data
+}
+
+/// Overwrites the first `len` bytes of `NOISE_REGIONS` in `sram` with the
+/// bytes of `noise`.
+fn add_noise(sram: &mut [u8; SRAM_SIZE], noise: &[u8], len: usize) {
+    let mut noise = noise[..len].iter();
+    for region in NOISE_REGIONS.iter() {
+        for (b, &n) in sram[region.clone()].iter_mut().zip(&mut noise) {
+            *b = n;
+        }
+    }
+}
+
+/// Returns the number of blocks `sram` compresses to.
+fn compressed_blocks(sram: &[u8; SRAM_SIZE]) -> usize {
+    blocks_from_sram(sram, Compat::Lsdj).map_or(usize::MAX, |b| b.len() / BLOCK_SIZE)
+}
+
+/// Generates the SRAM of a song filled with `content`, from the random
+/// bytes chosen by `seed`.
+///
+/// If `blocks` is given, enough noise is added to the song that it
+/// compresses to exactly that many blocks; an `Err` is returned if it can't
+/// be made that big (or is already bigger).
+pub fn song_sram(content: Content, blocks: Option<usize>, seed: u64) -> Result<[u8; SRAM_SIZE], &'static str> {
+    let mut rng = Rng::new(seed);
+    let base = base_sram(content, &mut rng);
+    let max_len: usize = NOISE_REGIONS.iter().map(|r| r.len()).sum();
+    let noise: Vec<u8> = (0..max_len).map(|_| rng.next_u8()).collect();
+    let with_noise = |len| {
+        let mut sram = base;
+        add_noise(&mut sram, &noise, len);
+        sram
+    };
+    let blocks = match (blocks, content) {
+        (Some(blocks), _) => blocks,
+        (None, Content::Noise) => return Ok(with_noise(max_len)),
+        (None, _) => return Ok(base),
+    };
+    // find the least noise which makes the song take `blocks` blocks; each
+    // byte of noise adds at most a few bytes to the compressed song, so the
+    // number of blocks it takes goes up one at a time
+    let (mut low, mut high) = (0, max_len);
+    while low < high {
+        let mid = (low + high) / 2;
+        if compressed_blocks(&with_noise(mid)) < blocks {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    let sram = with_noise(low);
+    if compressed_blocks(&sram) != blocks {
+        return Err(err::BAD_GEN_SIZE);
+    }
+    Ok(sram)
+}
+
+/// Generates a save file holding songs as described by `options`, with the
+/// first of them (or an empty song, if there are none) as the working song.
+///
+/// Every song is valid, so the save can be used to test anything which reads
+/// saves without depending on a real one. Returns an `Err` if the songs can't
+/// be made the size asked for, or don't fit in the save.
+pub fn generate(options: &GenOptions) -> Result<LsdjSave, &'static str> {
+    let mut save = LsdjSave::empty_with_layout(options.layout);
+    save.sram.data = base_sram(Content::Empty, &mut Rng::new(options.seed));
+    for i in 0..options.songs {
+        let seed = options.seed.wrapping_add(i as u64);
+        let sram = song_sram(options.content, options.blocks, seed)?;
+        let title = lsdjtitle_from(&format!("SONG{:02}", i))?;
+        save.import_decompressed_song(&sram, title)?;
+        if i == 0 {
+            save.sram.data = sram;
+        }
+    }
+    Ok(save)
+}
+
+/// Generates the save file the test suite reads: a single song, titled
+/// `TEST`, compressed into six blocks.
+pub fn test_save() -> LsdjSave {
+    let mut save = generate(&GenOptions { blocks: Some(6), ..GenOptions::default() }).expect("six blocks fit");
+    save.metadata.title(0, lsdjtitle_from("TEST").expect("valid title"));
+    save
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::song::Song;
+
+    #[test]
+    fn test_song_sram() {
+        for content in [Content::Empty, Content::Song, Content::Noise] {
+            let sram = song_sram(content, None, 1).unwrap();
+            assert!(Song::from(&sram).is_ok());
+            assert_eq!(song_sram(content, None, 1), Ok(sram)); // the same every time
+        }
+        assert_eq!(compressed_blocks(&song_sram(Content::Empty, None, 0).unwrap()), 1);
+        let noise = compressed_blocks(&song_sram(Content::Noise, None, 0).unwrap());
+        assert!(noise > 40);
+        for blocks in [5, 10, 40] {
+            let sram = song_sram(Content::Song, Some(blocks), 7).unwrap();
+            assert_eq!(compressed_blocks(&sram), blocks);
+            assert!(Song::from(&sram).is_ok());
+        }
+        assert_eq!(song_sram(Content::Noise, Some(noise), 0).map(|s| compressed_blocks(&s)), Ok(noise));
+        assert_eq!(song_sram(Content::Noise, Some(noise + 1), 0), Err(err::BAD_GEN_SIZE));
+        assert_eq!(song_sram(Content::Song, Some(1), 0), Err(err::BAD_GEN_SIZE)); // already bigger
+        assert_ne!(song_sram(Content::Noise, None, 1), song_sram(Content::Noise, None, 2));
+    }
+
+    #[test]
+    fn test_song_content() {
+        let song = Song::from(&song_sram(Content::Song, None, 0).unwrap()).unwrap();
+        assert_eq!(song.row(1), [Some(4), Some(5), Some(6), Some(7)]);
+        assert_eq!(song.length(), 2);
+        for chain in 0..8 {
+            let steps = song.chain(chain).unwrap();
+            assert_eq!(steps[7].phrase, Some(chain as u8 * 8 + 7));
+            assert_eq!(steps[8].phrase, None);
+            assert!(steps.iter().flat_map(|s| s.phrase).all(|p| song.phrase(p as usize).is_some()));
+        }
+        assert_eq!(song.chain(8), None);
+        assert_eq!(song.phrase(0x40), None);
+        assert_eq!(song.instrument(0).map(|i| i.params), Some(DEF_INST_VALUES));
+        assert_eq!(song.instrument(1), None);
+        assert_eq!(song.tempo(), 0x80);
+        assert_eq!(song.format_version(), FORMAT_VERSION);
+    }
+
+    #[test]
+    fn test_generate() {
+        let options = GenOptions { songs: 5, blocks: Some(8), content: Content::Noise, seed: 3, ..GenOptions::default() };
+        let save = generate(&options).unwrap();
+        assert_eq!(save.metadata.songs(), [0, 1, 2, 3, 4]);
+        assert_eq!(save.metadata.song_title(4), "SONG04");
+        for s in 0..5 {
+            assert_eq!(save.metadata.size_of(s), 8);
+            assert_eq!(save.round_trip(s).ok(), Some(8));
+        }
+        assert_eq!(save.check_sram(), Ok(()));
+        assert_eq!(generate(&options).unwrap(), save);
+
+        let too_many = GenOptions { songs: 8, layout: LsdjLayout::SAVE_64KB, ..options };
+        assert_eq!(generate(&too_many).err(), Some(err::NO_BLOCKS));
+        assert_eq!(generate(&GenOptions { songs: 0, ..options }).unwrap().check_sram(), Ok(()));
+    }
+
+    #[test]
+    fn test_test_save() {
+        let save = test_save();
+        assert_eq!(save.slot(0).map(|s| s.title()), Some("TEST".to_string()));
+        assert_eq!(save.metadata.size_of(0), 6);
+        assert_eq!(save.decompress_song(0).ok(), Some(save.sram.data));
+        assert_eq!("noise".parse(), Ok(Content::Noise));
+        assert_eq!("loud".parse::<Content>(), Err(err::BAD_CONTENT));
+    }
+}
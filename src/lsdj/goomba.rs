@@ -0,0 +1,191 @@
+use crate::lsdj::prelude::*;
+use crate::lsdj::err;
+use crate::lsdj::lzo;
+
+const STATE_ID     : u32   = 0x57a731d8; // first four bytes of every Goomba save
+const HEADER_SIZE  : usize = 0x30;
+const TITLE_OFFSET : usize = 0x10;
+const TITLE_LENGTH : usize = 0x20;
+
+/// Type of a Goomba save entry containing a game's compressed SRAM.
+pub const SRAM_SAVE  : u16 = 1;
+/// Type of a Goomba save entry containing the emulator's configuration.
+pub const CONFIG_SAVE: u16 = 2;
+
+/// Describes one entry in a Goomba save file. Each entry starts with a $30-byte
+/// header, followed by its (LZO-compressed) data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GoombaEntry {
+    /// Offset of the entry's header in the save file.
+    pub offset: usize,
+    /// Total length of the header and data.
+    pub size: usize,
+    /// Kind of entry (`SRAM_SAVE`, `CONFIG_SAVE`, or a savestate).
+    pub kind: u16,
+    /// Length of the entry's data once decompressed.
+    pub uncompressed_size: usize,
+    /// Title of the ROM the entry belongs to.
+    pub title: String,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+/// Returns every entry in the Goomba save file `save`, in the order they are
+/// stored. Returns an `Err` if `save` isn't a Goomba save file or if an
+/// entry runs past its end.
+pub fn entries(save: &[u8]) -> Result<Vec<GoombaEntry>, &'static str> {
+    if save.len() < 4 || read_u32(save, 0) != STATE_ID {
+        return Err(err::NOT_GOOMBA);
+    }
+    let mut entries = Vec::new();
+    let mut offset = 4;
+    while offset + HEADER_SIZE <= save.len() {
+        let size = read_u16(save, offset) as usize;
+        if size == 0 { break; } // end of entry list
+        if size < HEADER_SIZE || offset + size > save.len() {
+            return Err(err::NOT_GOOMBA);
+        }
+        let title = &save[(offset + TITLE_OFFSET)..(offset + TITLE_OFFSET + TITLE_LENGTH)];
+        let end = title.iter().position(|&c| c == 0).unwrap_or(TITLE_LENGTH);
+        entries.push(GoombaEntry {
+            offset,
+            size,
+            kind: read_u16(save, offset + 2),
+            uncompressed_size: read_u32(save, offset + 4) as usize,
+            title: String::from_utf8_lossy(&title[..end]).into_owned(),
+        });
+        offset += size;
+    }
+    Ok(entries)
+}
+
+/// Finds the SRAM entry to operate on: the first one whose ROM title contains
+/// `title`, or simply the first one if no title is given.
+fn find_sram(entries: &[GoombaEntry], title: Option<&str>) -> Result<GoombaEntry, &'static str> {
+    entries.iter()
+           .find(|e| e.kind == SRAM_SAVE && title.is_none_or(|t| e.title.contains(t)))
+           .cloned()
+           .ok_or(err::NO_GOOMBA_SRAM)
+}
+
+/// Returns true if Goomba was switched off while a game was running, leaving
+/// that game's newest SRAM uncompressed outside of its entry.
+fn is_unclean(save: &[u8], entries: &[GoombaEntry]) -> bool {
+    entries.iter().any(|e| e.kind == CONFIG_SAVE && read_u32(save, e.offset + 8) != 0)
+}
+
+/// Extracts and decompresses the SRAM of a game from the Goomba save file
+/// `save` (see `find_sram()` for the meaning of `title`).
+pub fn extract(save: &[u8], title: Option<&str>) -> Result<Vec<u8>, &'static str> {
+    let entries = entries(save)?;
+    if is_unclean(save, &entries) {
+        return Err(err::GOOMBA_UNCLEAN);
+    }
+    let entry = find_sram(&entries, title)?;
+    let data = &save[(entry.offset + HEADER_SIZE)..(entry.offset + entry.size)];
+    let sram = lzo::decompress(data, entry.uncompressed_size)?;
+    if sram.len() != entry.uncompressed_size {
+        return Err(err::BAD_LZO);
+    }
+    Ok(sram)
+}
+
+/// Replaces the SRAM of a game in the Goomba save file `save` with `sram`,
+/// returning the modified save file (see `find_sram()` for the meaning of
+/// `title`). Entries after the replaced one are moved to fit, and the save
+/// file keeps its original length.
+pub fn inject(save: &[u8], sram: &[u8], title: Option<&str>) -> Result<Vec<u8>, &'static str> {
+    let entries = entries(save)?;
+    if is_unclean(save, &entries) {
+        return Err(err::GOOMBA_UNCLEAN);
+    }
+    let entry = find_sram(&entries, title)?;
+    let data = lzo::compress(sram);
+    let size = (HEADER_SIZE + data.len() + 3) & !3; // entries are word-aligned
+    if size > u16::MAX as usize {
+        return Err(err::GOOMBA_FULL);
+    }
+
+    let mut out = Vec::with_capacity(save.len());
+    out.extend_from_slice(&save[..entry.offset]);
+    let mut header = save[entry.offset..(entry.offset + HEADER_SIZE)].to_vec();
+    header[0..2].copy_from_slice(&(size as u16).to_le_bytes());
+    header[4..8].copy_from_slice(&(sram.len() as u32).to_le_bytes());
+    out.extend_from_slice(&header);
+    out.extend_from_slice(&data);
+    out.resize(entry.offset + size, 0);
+    for e in entries.iter().filter(|e| e.offset > entry.offset) {
+        out.extend_from_slice(&save[e.offset..(e.offset + e.size)]);
+    }
+    if out.len() + 2 > save.len() {
+        return Err(err::GOOMBA_FULL); // no room left for the end of the entry list
+    }
+    out.resize(save.len(), 0);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a Goomba save file containing a config entry and one SRAM entry
+    /// per title, each holding `sram`.
+    fn goomba_save(titles: &[&str], sram: &[u8]) -> Vec<u8> {
+        let mut save = STATE_ID.to_le_bytes().to_vec();
+        let mut config = vec![0; HEADER_SIZE];
+        config[0..2].copy_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+        config[2..4].copy_from_slice(&CONFIG_SAVE.to_le_bytes());
+        save.extend_from_slice(&config);
+        for title in titles {
+            let data = lzo::compress(sram);
+            let size = (HEADER_SIZE + data.len() + 3) & !3;
+            let mut header = vec![0; HEADER_SIZE];
+            header[0..2].copy_from_slice(&(size as u16).to_le_bytes());
+            header[2..4].copy_from_slice(&SRAM_SAVE.to_le_bytes());
+            header[4..8].copy_from_slice(&(sram.len() as u32).to_le_bytes());
+            header[TITLE_OFFSET..(TITLE_OFFSET + title.len())].copy_from_slice(title.as_bytes());
+            save.extend_from_slice(&header);
+            save.extend_from_slice(&data);
+            save.resize((save.len() + 3) & !3, 0);
+        }
+        save.resize(0x10000, 0);
+        save
+    }
+
+    #[test]
+    fn test_entries() {
+        let save = goomba_save(&["LSDj", "TETRIS"], &[0; 0x2000]);
+        let entries = entries(&save).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].kind, CONFIG_SAVE);
+        assert_eq!(entries[1].title, "LSDj");
+        assert_eq!(entries[2].uncompressed_size, 0x2000);
+        assert_eq!(super::entries(&[0; 0x100]), Err(err::NOT_GOOMBA));
+    }
+
+    #[test]
+    fn test_extract_inject() {
+        let mut sram = vec![0; 0x20000];
+        sram[0x8000..0x8008].copy_from_slice(b"TESTSONG");
+        let save = goomba_save(&["TETRIS", "LSDj"], &[0x12; 0x2000]);
+        assert_eq!(extract(&save, Some("LSDj")).unwrap(), vec![0x12; 0x2000]);
+        let injected = inject(&save, &sram, Some("LSDj")).unwrap();
+        assert_eq!(injected.len(), save.len());
+        assert_eq!(extract(&injected, Some("LSDj")).unwrap(), sram);
+        assert_eq!(extract(&injected, None).unwrap(), vec![0x12; 0x2000]);
+        assert_eq!(extract(&injected, Some("ZELDA")), Err(err::NO_GOOMBA_SRAM));
+    }
+
+    #[test]
+    fn test_unclean() {
+        let mut save = goomba_save(&["LSDj"], &[0; 0x2000]);
+        save[4 + 8] = 0x34; // config entry records a checksum for SRAM in use
+        assert_eq!(extract(&save, None), Err(err::GOOMBA_UNCLEAN));
+    }
+}
@@ -0,0 +1,325 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use core::str::FromStr;
+
+use crate::lsdj::err;
+
+/// Describes how many backups to keep of a file before it is overwritten, and
+/// where to keep them.
+///
+/// Backups are numbered from newest to oldest (`save.sav.1`, `save.sav.2`,
+/// ...), and are kept next to the file unless a directory is given.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BackupPolicy {
+    /// Number of backups to keep (no backups are made if zero).
+    pub keep: usize,
+    /// Directory in which to keep backups.
+    pub dir: Option<PathBuf>,
+}
+
+impl FromStr for BackupPolicy {
+    type Err = &'static str;
+
+    /// Parses a comma-separated list of `keep=N` and `dir=PATH` settings. If a
+    /// directory is given without a number of backups, one backup is kept.
+    fn from_str(s: &str) -> Result<BackupPolicy, &'static str> {
+        let mut policy = BackupPolicy::default();
+        let mut keep = None;
+        for setting in s.split(',').filter(|s| !s.is_empty()) {
+            match setting.split_once('=') {
+                Some(("keep", n)) => keep = Some(n.parse().map_err(|_| err::BAD_BACKUP)?),
+                Some(("dir", path)) if !path.is_empty() => policy.dir = Some(PathBuf::from(path)),
+                _ => return Err(err::BAD_BACKUP),
+            }
+        }
+        policy.keep = keep.unwrap_or(if policy.dir.is_some() { 1 } else { 0 });
+        Ok(policy)
+    }
+}
+
+/// Returns the path of backup number `n` (starting from 1) of `path`.
+fn backup_path(path: &Path, policy: &BackupPolicy, n: usize) -> PathBuf {
+    let name = format!("{}.{}", path.file_name().unwrap_or_default().to_string_lossy(), n);
+    match &policy.dir {
+        Some(dir) => dir.join(name),
+        None => path.with_file_name(name),
+    }
+}
+
+/// Copies the file at `path` to a new backup according to `policy`, first
+/// renumbering existing backups and removing any beyond the number to keep.
+/// Returns the path of the new backup, or `None` if `path` doesn't exist or
+/// `policy` keeps no backups.
+pub fn backup(path: &Path, policy: &BackupPolicy) -> io::Result<Option<PathBuf>> {
+    if policy.keep == 0 || !path.is_file() {
+        return Ok(None);
+    }
+    if let Some(dir) = &policy.dir {
+        fs::create_dir_all(dir)?;
+    }
+    let oldest = backup_path(path, policy, policy.keep);
+    if oldest.exists() {
+        fs::remove_file(&oldest)?;
+    }
+    for n in (1..policy.keep).rev() {
+        let from = backup_path(path, policy, n);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, policy, n + 1))?;
+        }
+    }
+    let newest = backup_path(path, policy, 1);
+    fs::copy(path, &newest)?;
+    Ok(Some(newest))
+}
+
+/// Returns the path of the temporary file used while writing `path`, which is
+/// kept in the same directory so that it can be renamed over `path`.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+/// Writes `bytes` to `path` atomically: the bytes are written to a temporary
+/// file in the same directory, flushed to disk, and only then renamed over
+/// `path`. If writing fails partway (for example because the disk is full),
+/// `path` is left untouched and the temporary file is removed.
+///
+/// If `path` already exists, its permissions are kept. If it is a symbolic
+/// link, the file it points to is replaced rather than the link itself.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    write_atomic_with(path, |file| file.write_all(bytes))
+}
+
+/// Writes to `path` atomically, as `write_atomic()` does, with `write`
+/// writing the contents of the temporary file, so that they needn't be
+/// gathered into memory first.
+pub fn write_atomic_with<F>(path: &Path, write: F) -> io::Result<()>
+    where F: FnOnce(&mut File) -> io::Result<()> {
+    let path = match fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(_) => path.to_path_buf(), // doesn't exist yet
+    };
+    let temp = temp_path(&path);
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        write(&mut file)?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp, &path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// A general-purpose compressor which exports can be written through, making
+/// archives of many songs much smaller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    Gzip,
+    Zstd,
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Operating system recorded in gzip headers: "unknown", so that files don't
+/// differ by the platform they were written on.
+const GZIP_UNKNOWN_OS: u8 = 0xff;
+
+impl FromStr for Codec {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Codec, &'static str> {
+        match s {
+            "gzip" | "gz" => Ok(Codec::Gzip),
+            "zstd" | "zst" => Ok(Codec::Zstd),
+            _ => Err(err::BAD_CODEC),
+        }
+    }
+}
+
+impl Codec {
+    /// Returns the codec named by the extension of `path` (`.gz` or `.zst`),
+    /// if any.
+    pub fn from_path(path: &Path) -> Option<Codec> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    /// Returns the extension of files compressed with this codec.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gz",
+            Codec::Zstd => "zst",
+        }
+    }
+
+    /// Compresses `bytes` with this codec.
+    ///
+    /// The output depends only on `bytes`, so that compressing the same bytes
+    /// again gives a byte-identical file: gzip headers record neither a
+    /// modification time nor an operating system, and zstd frames have no
+    /// such fields. See `compress_stamped()` to record a time.
+    pub fn compress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        self.compress_stamped(bytes, 0)
+    }
+
+    /// Compresses `bytes` with this codec like `compress()`, but records
+    /// `mtime` (in seconds since the Unix epoch, or 0 for none) as the
+    /// modification time in gzip headers. zstd has nowhere to record it.
+    pub fn compress_stamped(self, bytes: &[u8], mtime: u32) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Gzip => {
+                let mut encoder = flate2::GzBuilder::new()
+                    .mtime(mtime)
+                    .operating_system(GZIP_UNKNOWN_OS)
+                    .write(Vec::new(), flate2::Compression::best());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            },
+            Codec::Zstd => zstd::encode_all(bytes, zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+}
+
+/// Decompresses `bytes` if they start like gzip or zstd data, and otherwise
+/// returns them unchanged, so that compressed and uncompressed files can be
+/// read alike.
+pub fn decompress(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    if bytes.starts_with(&GZIP_MAGIC) {
+        flate2::read::MultiGzDecoder::new(&bytes[..]).read_to_end(&mut out)?;
+    } else if bytes.starts_with(&ZSTD_MAGIC) {
+        out = zstd::decode_all(&bytes[..])?;
+    } else {
+        return Ok(bytes);
+    }
+    Ok(out)
+}
+
+/// Start of files encrypted with age.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+/// Extension of files encrypted with age.
+pub const ENCRYPTED_EXTENSION: &str = "age";
+
+/// Returns true if `bytes` start like a file encrypted with age.
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(AGE_MAGIC)
+}
+
+/// Encrypts `bytes` with `passphrase`, in age's format, so that they can be
+/// decrypted with the `age` tool as well as `decrypt()`.
+#[cfg(feature = "encrypt")]
+pub fn encrypt(bytes: &[u8], passphrase: &str) -> io::Result<Vec<u8>> {
+    let recipient = age::scrypt::Recipient::new(passphrase.to_string().into());
+    age::encrypt(&recipient, bytes).map_err(io::Error::other)
+}
+
+/// Decrypts `bytes` encrypted with `passphrase` (see `encrypt()`), returning
+/// an `Err` if the passphrase is wrong or they have been tampered with.
+#[cfg(feature = "encrypt")]
+pub fn decrypt(bytes: &[u8], passphrase: &str) -> io::Result<Vec<u8>> {
+    let identity = age::scrypt::Identity::new(passphrase.to_string().into());
+    age::decrypt(&identity, bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("lsdjtool-io-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("test.sav");
+        write_atomic(&path, b"first")?;
+        assert_eq!(fs::read(&path)?, b"first");
+        write_atomic(&path, b"second")?;
+        assert_eq!(fs::read(&path)?, b"second");
+        assert_eq!(fs::read_dir(&dir)?.count(), 1); // no temporary file left behind
+
+        assert!(write_atomic(&dir.join("missing").join("test.sav"), b"third").is_err());
+        assert_eq!(fs::read_dir(&dir)?.count(), 1);
+        fs::remove_dir_all(&dir)
+    }
+
+    #[test]
+    fn test_backup_policy() {
+        assert_eq!("".parse(), Ok(BackupPolicy::default()));
+        assert_eq!("keep=3".parse(), Ok(BackupPolicy { keep: 3, dir: None }));
+        assert_eq!("dir=bak".parse(), Ok(BackupPolicy { keep: 1, dir: Some(PathBuf::from("bak")) }));
+        assert_eq!("dir=bak,keep=0".parse(), Ok(BackupPolicy { keep: 0, dir: Some(PathBuf::from("bak")) }));
+        assert_eq!("keep=x".parse::<BackupPolicy>(), Err(err::BAD_BACKUP));
+        assert_eq!("count=2".parse::<BackupPolicy>(), Err(err::BAD_BACKUP));
+    }
+
+    #[test]
+    fn test_backup_rotation() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("lsdjtool-backup-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("test.sav");
+        let policy = BackupPolicy { keep: 2, dir: None };
+        assert_eq!(backup(&path, &policy)?, None); // nothing to back up yet
+        for contents in ["first", "second", "third", "fourth"].iter() {
+            backup(&path, &policy)?;
+            write_atomic(&path, contents.as_bytes())?;
+        }
+        assert_eq!(fs::read(dir.join("test.sav.1"))?, b"third");
+        assert_eq!(fs::read(dir.join("test.sav.2"))?, b"second");
+        assert!(!dir.join("test.sav.3").exists());
+
+        let policy = BackupPolicy { keep: 1, dir: Some(dir.join("backups")) };
+        assert_eq!(backup(&path, &policy)?, Some(dir.join("backups").join("test.sav.1")));
+        assert_eq!(fs::read(dir.join("backups").join("test.sav.1"))?, b"fourth");
+        assert_eq!(backup(&path, &BackupPolicy::default())?, None);
+        fs::remove_dir_all(&dir)
+    }
+
+    #[test]
+    fn test_codec() -> io::Result<()> {
+        let bytes = [0xc0u8; 0x2000];
+        for codec in [Codec::Gzip, Codec::Zstd] {
+            let compressed = codec.compress(&bytes)?;
+            assert!(compressed.len() < 0x100);
+            assert_eq!(decompress(compressed)?, bytes);
+            assert_eq!(Codec::from_path(Path::new(&format!("song.lsdsng.{}", codec.extension()))), Some(codec));
+        }
+        assert_eq!(decompress(bytes.to_vec())?, bytes);
+        assert_eq!(Codec::from_path(Path::new("song.lsdsng")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_codec_reproducible() -> io::Result<()> {
+        let bytes: Vec<u8> = (0..0x2000).map(|i| (i * 7 % 251) as u8).collect();
+        for codec in [Codec::Gzip, Codec::Zstd] {
+            assert_eq!(codec.compress(&bytes)?, codec.compress(&bytes)?);
+        }
+        let gzipped = Codec::Gzip.compress(&bytes)?;
+        assert_eq!(gzipped[4..8], [0, 0, 0, 0]); // no modification time
+        assert_eq!(gzipped[9], GZIP_UNKNOWN_OS);
+        let stamped = Codec::Gzip.compress_stamped(&bytes, 0x12345678)?;
+        assert_eq!(stamped[4..8], [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(decompress(stamped)?, bytes);
+        assert_eq!(Codec::Zstd.compress_stamped(&bytes, 0x12345678)?, Codec::Zstd.compress(&bytes)?);
+        Ok(())
+    }
+
+    #[cfg(feature = "encrypt")]
+    #[test]
+    fn test_encrypt() -> io::Result<()> {
+        let bytes = [0xc0u8; 0x200];
+        let encrypted = encrypt(&bytes, "hunter2")?;
+        assert!(is_encrypted(&encrypted));
+        assert!(!is_encrypted(&bytes));
+        assert_eq!(decrypt(&encrypted, "hunter2")?, bytes);
+        assert_eq!(decrypt(&encrypted, "hunter3").unwrap_err().kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+//! Sizes and addresses of the parts of an LSDj save file, and the layout
+//! (`LsdjLayout`) describing how many blocks a particular save file holds.
+//!
+//! Every save file starts with the working song's SRAM and the metadata, laid
+//! out identically whatever its size; only the block region after them
+//! varies. Only the standard 64KB and 128KB layouts are detected from a save's
+//! size; saves from hacked ROMs or other LSDj versions which keep some other
+//! number of blocks can be read by supplying a custom layout (see
+//! `LsdjLayout::new()` and `LsdjSave::from_bytes_with_layout()`).
+
+use crate::lsdj::err;
+
+/// Bytes in each block of compressed song data.
+pub const BLOCK_SIZE: usize = 0x200;
+/// Most blocks a save file can hold: one per entry in the allocation table.
+pub const MAX_BLOCK_COUNT: usize = ALLOC_TABLE_LENGTH;
+/// Bytes in each bank of SRAM.
+pub const BANK_SIZE: usize = 0x2000;
+/// Banks of SRAM holding the working song.
+pub const BANK_COUNT: usize = 4;
+/// Bytes of the working song, as decompressed.
+pub const SRAM_SIZE: usize = BANK_SIZE * BANK_COUNT;
+/// Address of the first block, just after the metadata.
+pub const BLOCK_ADDRESS: u64 = METADATA_ADDRESS + METADATA_LENGTH as u64;
+
+/// Address of the metadata, just after the working song.
+pub const METADATA_ADDRESS: u64 = SRAM_SIZE as u64;
+/// Bytes of metadata, from the title table to the end of the allocation table.
+pub const METADATA_LENGTH: usize = 0x200;
+/// Song slots in the title and version tables.
+pub const SONG_SLOTS: usize = 0x20;
+/// Bytes in each song title.
+pub const TITLE_LENGTH: usize = 8;
+
+pub const TITLE_TABLE_ADDRESS  : u64   = METADATA_ADDRESS;
+pub const TITLE_TABLE_LENGTH   : usize = TITLE_LENGTH * SONG_SLOTS;
+pub const VERSION_TABLE_ADDRESS: u64   = 0x8100;
+pub const VERSION_TABLE_LENGTH : usize = SONG_SLOTS;
+pub const EMPTY_BYTES_ADDRESS  : u64   = 0x8120;
+pub const EMPTY_BYTES_LENGTH   : usize = 0x1e;
+pub const SRAM_INIT_CHK_ADDRESS: u64   = 0x813e;
+pub const SRAM_INIT_CHK_LENGTH : usize = 2;
+pub const WORKING_SONG_ADDRESS : u64   = 0x8140;
+pub const ALLOC_TABLE_ADDRESS  : u64   = 0x8141;
+pub const ALLOC_TABLE_LENGTH   : usize = 0xbf;
+
+/// Describes the size of an LSDj save file and the number of blocks of
+/// compressed song data it holds.
+///
+/// SRAM and metadata are laid out identically in every save file; only the
+/// length of the block region after them (starting at `$8200`) differs. A
+/// layout can only be made by `new()` or `detect()` (or be one of the
+/// constants), so it always describes between 1 and `MAX_BLOCK_COUNT` blocks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LsdjLayout {
+    save_size: usize,
+    block_count: usize,
+}
+
+impl LsdjLayout {
+    /// Layout of a standard 128KB save file, holding $bf blocks.
+    pub const SAVE_128KB: LsdjLayout = LsdjLayout { save_size: 0x20000, block_count: 0xbf };
+    /// Layout of a 64KB save file, holding $3f blocks.
+    pub const SAVE_64KB: LsdjLayout = LsdjLayout { save_size: 0x10000, block_count: 0x3f };
+
+    /// Returns the layout of a save file holding `block_count` blocks, or an
+    /// `Err` if that's none, or more than the allocation table can describe.
+    pub fn new(block_count: usize) -> Result<LsdjLayout, &'static str> {
+        if block_count == 0 || block_count > MAX_BLOCK_COUNT {
+            return Err(err::BAD_BLOCK_COUNT);
+        }
+        Ok(LsdjLayout { save_size: BLOCK_ADDRESS as usize + block_count * BLOCK_SIZE, block_count })
+    }
+
+    /// Returns the layout of a save file which is `len` bytes long: one of the
+    /// standard 64KB and 128KB layouts, or an `Err` for any other length. A
+    /// save of some other length is more likely a truncated copy of a
+    /// standard one than a layout of its own, so other layouts must be asked
+    /// for explicitly.
+    pub fn detect(len: u64) -> Result<LsdjLayout, &'static str> {
+        [LsdjLayout::SAVE_64KB, LsdjLayout::SAVE_128KB].iter()
+            .find(|layout| layout.save_size as u64 == len)
+            .copied()
+            .ok_or(err::BAD_SAVE_SIZE)
+    }
+
+    /// Returns the total length of the save file in bytes.
+    pub const fn save_size(&self) -> usize {
+        self.save_size
+    }
+
+    /// Returns the number of $200-byte blocks in the save file.
+    pub const fn block_count(&self) -> usize {
+        self.block_count
+    }
+
+    /// Returns true if `block` (one-indexed) is present in save files with
+    /// this layout.
+    pub fn contains_block(&self, block: usize) -> bool {
+        (1..=self.block_count).contains(&block)
+    }
+
+    /// Returns the offset in the save file of `block` (one-indexed), or `None`
+    /// if there's no such block in this layout.
+    pub fn block_offset(&self, block: usize) -> Option<usize> {
+        Some(block).filter(|&b| self.contains_block(b)).map(|b| BLOCK_ADDRESS as usize + (b - 1) * BLOCK_SIZE)
+    }
+
+    /// Returns the one-indexed block holding the byte at `offset` in the save
+    /// file, or `None` if it's in SRAM, metadata, or past the last block.
+    pub fn block_at(&self, offset: usize) -> Option<usize> {
+        let block = offset.checked_sub(BLOCK_ADDRESS as usize)? / BLOCK_SIZE + 1;
+        Some(block).filter(|&b| self.contains_block(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_new() {
+        assert_eq!(LsdjLayout::new(0xbf), Ok(LsdjLayout::SAVE_128KB));
+        assert_eq!(LsdjLayout::new(0x3f), Ok(LsdjLayout::SAVE_64KB));
+        assert_eq!(LsdjLayout::new(0x10), Ok(LsdjLayout { save_size: 0xa200, block_count: 0x10 }));
+        assert_eq!(LsdjLayout::new(0), Err(err::BAD_BLOCK_COUNT));
+        assert_eq!(LsdjLayout::new(0xc0), Err(err::BAD_BLOCK_COUNT));
+    }
+
+    #[test]
+    fn test_layout_detect() {
+        assert_eq!(LsdjLayout::detect(0x20000), Ok(LsdjLayout::SAVE_128KB));
+        assert_eq!(LsdjLayout::detect(0x10000), Ok(LsdjLayout::SAVE_64KB));
+        assert_eq!(LsdjLayout::detect(0x8400), Err(err::BAD_SAVE_SIZE));
+        assert_eq!(LsdjLayout::detect(0x1fe00), Err(err::BAD_SAVE_SIZE)); // a truncated 128KB save
+        assert_eq!(LsdjLayout::detect(0x8200), Err(err::BAD_SAVE_SIZE));
+        assert_eq!(LsdjLayout::detect(0x20001), Err(err::BAD_SAVE_SIZE));
+        assert_eq!(LsdjLayout::detect(0x20200), Err(err::BAD_SAVE_SIZE));
+        assert_eq!(LsdjLayout::detect(0), Err(err::BAD_SAVE_SIZE));
+    }
+
+    #[test]
+    fn test_block_offsets() {
+        let layout = LsdjLayout::SAVE_64KB;
+        assert_eq!(layout.block_offset(1), Some(0x8200));
+        assert_eq!(layout.block_offset(0x3f), Some(0xfe00));
+        assert_eq!(layout.block_offset(0x40), None);
+        assert_eq!(layout.block_offset(0), None);
+        assert_eq!(layout.block_at(0x8200), Some(1));
+        assert_eq!(layout.block_at(0x85ff), Some(2));
+        assert_eq!(layout.block_at(0xffff), Some(0x3f));
+        assert_eq!(layout.block_at(0x10000), None);
+        assert_eq!(layout.block_at(0x81ff), None);
+    }
+}
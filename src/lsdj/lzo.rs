@@ -0,0 +1,248 @@
+use crate::lsdj::prelude::*;
+use crate::lsdj::err;
+
+const M2_MAX_OFFSET: usize = 0x0800;
+const M3_MAX_OFFSET: usize = 0x4000;
+const M4_BASE_OFFSET: usize = 0x4000;
+const MIN_MATCH: usize = 3;
+const HASH_BITS: usize = 14;
+const EOF_MARKER: [u8; 3] = [0x11, 0x00, 0x00]; // an M4 match with a distance of zero
+const MAX_SIZE_HINT: usize = 0x20000; // the most SRAM any Game Boy cartridge has
+
+/// Reads the extension bytes of a long literal run or match: each zero byte
+/// adds 255 to the length, and the first nonzero byte ends the sequence.
+fn read_extension(src: &[u8], ip: &mut usize) -> Result<usize, &'static str> {
+    let mut len = 0;
+    loop {
+        let byte = *src.get(*ip).ok_or(err::BAD_LZO)?;
+        *ip += 1;
+        if byte != 0 {
+            return Ok(len + byte as usize);
+        }
+        len += 255;
+    }
+}
+
+/// Copies `count` literal bytes from `src` at `ip` to the end of `out`.
+fn copy_literals(src: &[u8], ip: &mut usize, count: usize, out: &mut Vec<u8>) -> Result<(), &'static str> {
+    let literals = src.get(*ip..(*ip + count)).ok_or(err::BAD_LZO)?;
+    out.extend_from_slice(literals);
+    *ip += count;
+    Ok(())
+}
+
+/// Copies `len` bytes starting `dist` bytes before the end of `out` to the end
+/// of `out`, one at a time so that overlapping matches repeat correctly.
+fn copy_match(out: &mut Vec<u8>, dist: usize, len: usize) -> Result<(), &'static str> {
+    if dist == 0 || dist > out.len() {
+        return Err(err::BAD_LZO);
+    }
+    let start = out.len() - dist;
+    for i in 0..len {
+        let byte = out[start + i];
+        out.push(byte);
+    }
+    Ok(())
+}
+
+/// Decompresses an LZO1X stream, as produced by `lzo1x_1_compress()`.
+///
+/// `size_hint` is only used to preallocate the output, and is capped, as it
+/// usually comes from a header which may be corrupt. Any bytes after the
+/// end-of-stream marker (such as alignment padding) are ignored.
+pub fn decompress(src: &[u8], size_hint: usize) -> Result<Vec<u8>, &'static str> {
+    let mut out = Vec::with_capacity(size_hint.min(MAX_SIZE_HINT));
+    let mut ip = 0;
+    let mut state = 0; // literals copied by the previous instruction (4 meaning "4 or more")
+
+    let first = *src.first().ok_or(err::BAD_LZO)?;
+    if first > 17 {
+        ip += 1;
+        let count = (first - 17) as usize;
+        copy_literals(src, &mut ip, count, &mut out)?;
+        state = count.min(4);
+    }
+
+    loop {
+        let t = *src.get(ip).ok_or(err::BAD_LZO)?;
+        ip += 1;
+        let (dist, len, trailing) = if t < 16 {
+            match state {
+                0 => { // literal run
+                    let count = if t == 0 { 15 + read_extension(src, &mut ip)? } else { t as usize };
+                    copy_literals(src, &mut ip, count + 3, &mut out)?;
+                    state = 4;
+                    continue;
+                },
+                1..=3 => { // two-byte match following a short literal run
+                    let high = *src.get(ip).ok_or(err::BAD_LZO)? as usize;
+                    ip += 1;
+                    (1 + (t >> 2) as usize + (high << 2), 2, t & 3)
+                },
+                _ => { // three-byte match following a long literal run
+                    let high = *src.get(ip).ok_or(err::BAD_LZO)? as usize;
+                    ip += 1;
+                    (1 + M2_MAX_OFFSET + (t >> 2) as usize + (high << 2), 3, t & 3)
+                },
+            }
+        } else if t >= 64 { // M2: short match within $800 bytes
+            let high = *src.get(ip).ok_or(err::BAD_LZO)? as usize;
+            ip += 1;
+            (1 + ((t >> 2) & 7) as usize + (high << 3), (t >> 5) as usize + 1, t & 3)
+        } else if t >= 32 { // M3: match within $4000 bytes
+            let len = match t & 31 {
+                0 => 31 + read_extension(src, &mut ip)?,
+                l => l as usize,
+            };
+            let value = src.get(ip..(ip + 2)).ok_or(err::BAD_LZO)?;
+            let value = u16::from_le_bytes([value[0], value[1]]);
+            ip += 2;
+            (1 + (value >> 2) as usize, len + 2, value as u8 & 3)
+        } else { // M4: match within $bfff bytes, or the end of the stream
+            let len = match t & 7 {
+                0 => 7 + read_extension(src, &mut ip)?,
+                l => l as usize,
+            };
+            let value = src.get(ip..(ip + 2)).ok_or(err::BAD_LZO)?;
+            let value = u16::from_le_bytes([value[0], value[1]]);
+            ip += 2;
+            let dist = (((t & 8) as usize) << 11) + (value >> 2) as usize;
+            if dist == 0 {
+                return Ok(out); // end of stream
+            }
+            (dist + M4_BASE_OFFSET, len + 2, value as u8 & 3)
+        };
+        copy_match(&mut out, dist, len)?;
+        copy_literals(src, &mut ip, trailing as usize, &mut out)?;
+        state = trailing as usize;
+    }
+}
+
+/// Appends the extension bytes for a length which doesn't fit in an
+/// instruction (see `read_extension()`).
+fn write_extension(out: &mut Vec<u8>, mut len: usize) {
+    while len > 255 {
+        out.push(0);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// Appends a run of literals to `out`. `after_match` is the index in `out` of
+/// the low byte of the preceding match's distance, whose low two bits encode
+/// runs of one to three literals, or `None` at the start of the stream.
+fn write_literals(out: &mut Vec<u8>, literals: &[u8], after_match: Option<usize>) {
+    if literals.is_empty() {
+        return;
+    }
+    match after_match {
+        Some(i) if literals.len() <= 3 => out[i] |= literals.len() as u8,
+        None if literals.len() <= 238 => out.push(17 + literals.len() as u8),
+        _ => {
+            let count = literals.len() - 3;
+            if count <= 15 {
+                out.push(count as u8);
+            } else {
+                out.push(0);
+                write_extension(out, count - 15);
+            }
+        },
+    }
+    out.extend_from_slice(literals);
+}
+
+/// Hashes the three bytes at the start of `bytes` into a match table index.
+fn hash(bytes: &[u8]) -> usize {
+    let value = (bytes[0] as u32) | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16;
+    (value.wrapping_mul(0x9e3779b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses `src` into an LZO1X stream which can be decompressed by any
+/// LZO1X decompressor (including `decompress()`).
+///
+/// Only M3 matches are produced, which is less compact than `lzo1x_1_compress()`
+/// but still handles the long runs of repeated bytes found in SRAM well.
+pub fn compress(src: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(src.len() / 2);
+    let mut table = vec![usize::MAX; 1 << HASH_BITS]; // most recent position of each hash
+    let mut literal_start = 0;
+    let mut after_match = None;
+    let mut ip = 0;
+
+    while ip + MIN_MATCH <= src.len() {
+        let h = hash(&src[ip..]);
+        let candidate = table[h];
+        table[h] = ip;
+        if candidate == usize::MAX || ip - candidate > M3_MAX_OFFSET ||
+           src[candidate..(candidate + MIN_MATCH)] != src[ip..(ip + MIN_MATCH)] {
+            ip += 1;
+            continue;
+        }
+        let mut len = MIN_MATCH;
+        while ip + len < src.len() && src[candidate + len] == src[ip + len] {
+            len += 1;
+        }
+
+        write_literals(&mut out, &src[literal_start..ip], after_match);
+        if len - 2 <= 31 {
+            out.push(32 | (len - 2) as u8);
+        } else {
+            out.push(32);
+            write_extension(&mut out, len - 2 - 31);
+        }
+        let value = ((ip - candidate - 1) << 2) as u16;
+        after_match = Some(out.len());
+        out.extend_from_slice(&value.to_le_bytes());
+        ip += len;
+        literal_start = ip;
+    }
+    write_literals(&mut out, &src[literal_start..], after_match);
+    out.extend_from_slice(&EOF_MARKER);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[u8]) {
+        let compressed = compress(data);
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(&[]);
+        round_trip(&[1]);
+        round_trip(&[1, 2, 3]);
+        round_trip(&[0; 0x20000]);
+        round_trip(b"abcabcabcabcxyzxyzabcabcabc");
+        let mut data = Vec::new();
+        for i in 0..0x8000u32 {
+            data.push((i.wrapping_mul(2654435761) >> 13) as u8); // mostly incompressible
+            if i % 0x1000 == 0 {
+                data.extend_from_slice(&[0x42; 300]);
+            }
+        }
+        round_trip(&data);
+        round_trip(&data[..1000]);
+    }
+
+    #[test]
+    fn test_compress_runs() {
+        let compressed = compress(&[0; 0x20000]);
+        assert!(compressed.len() < 0x400);
+    }
+
+    #[test]
+    fn test_decompress_instructions() {
+        // 4 literals, an M2 match (distance 4, length 3) with one trailing
+        // literal, an M1 match (distance 1, length 2), then end of stream
+        let stream = [0x15, b'a', b'b', b'c', b'd', 0x4d, 0x00, b'e', 0x00, 0x00, 0x11, 0x00, 0x00];
+        assert_eq!(decompress(&stream, 0).unwrap(), b"abcdabceee");
+        assert_eq!(decompress(&stream[..10], 0), Err(err::BAD_LZO));
+        assert_eq!(decompress(&[0x11, 0x00, 0x00], 0).unwrap(), b"");
+        // one literal, then an M3 match reaching back past the start of the output
+        assert_eq!(decompress(&[0x12, b'a', 0x21, 0x10, 0x00, 0x11, 0x00, 0x00], 0), Err(err::BAD_LZO));
+    }
+}
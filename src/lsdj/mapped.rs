@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::lsdj::{err, fnv1a, BLOCK_ADDRESS, BLOCK_SIZE, SRAM_SIZE};
+use crate::lsdj::compression::{decompress_chain, LsdjBlock};
+use crate::lsdj::metadata::LsdjMetadata;
+use crate::lsdj::{LsdjError, LsdjLayout, LsdjSave, LsdjSram};
+
+/// An LSDj save file read through a memory map rather than copied into memory.
+///
+/// Only the metadata ($200 bytes) is copied when the save is opened; SRAM and
+/// blocks are read straight from the map. Modifying a block copies just that
+/// block, so unmodified blocks are never copied (see `block_mut()`).
+///
+/// As with any memory-mapped file, the save file must not be truncated by
+/// another process while it is open.
+pub struct MappedSave {
+    map: Mmap,
+    pub metadata: LsdjMetadata,
+    layout: LsdjLayout,
+    modified: HashMap<usize, LsdjBlock>, // copies of modified blocks, by (one-indexed) block number
+}
+
+impl MappedSave {
+    /// Opens the save file at `path`, detecting its layout from its length.
+    pub fn open(path: &Path) -> Result<MappedSave, LsdjError> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let layout = LsdjSave::layout_for_len(len)?;
+        // Safety: the map is only ever read, and save files aren't expected to
+        // be truncated while they are being worked on.
+        let map = unsafe { Mmap::map(&file)? };
+        let metadata = LsdjMetadata::from(&mut Cursor::new(&map[..]))?;
+        Ok(MappedSave { map, metadata, layout, modified: HashMap::new() })
+    }
+
+    /// Returns the layout of this save file.
+    pub fn layout(&self) -> LsdjLayout {
+        self.layout
+    }
+
+    /// Returns the SRAM stored in this save file.
+    pub fn sram(&self) -> &[u8] {
+        &self.map[..SRAM_SIZE]
+    }
+
+    /// Returns the contents of `block` (one-indexed), or `None` if there is no
+    /// such block.
+    pub fn block(&self, block: usize) -> Option<&[u8]> {
+        let start = self.layout.block_offset(block)?;
+        if let Some(copy) = self.modified.get(&block) {
+            return Some(&copy.data[..]);
+        }
+        Some(&self.map[start..(start + BLOCK_SIZE)])
+    }
+
+    /// Returns a mutable copy of `block` (one-indexed), which replaces the
+    /// block in the mapped file from then on. The block is copied the first
+    /// time this is called for it. Returns `None` if there is no such block.
+    pub fn block_mut(&mut self, block: usize) -> Option<&mut LsdjBlock> {
+        let start = self.layout.block_offset(block)?;
+        let map = &self.map;
+        Some(self.modified.entry(block).or_insert_with(|| {
+            let mut copy = LsdjBlock::empty();
+            copy.position = block;
+            copy.data.copy_from_slice(&map[start..(start + BLOCK_SIZE)]);
+            copy
+        }))
+    }
+
+    /// Extracts the blocks of the song at the given index (see
+    /// `LsdjSave::export_song()`).
+    pub fn export_song(&self, song: u8) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.metadata.size_of(song) * BLOCK_SIZE);
+        for i in 0..self.metadata.size_of(song) {
+            match self.metadata.next_block_for(song, i).and_then(|b| self.block(b)) {
+                Some(data) => bytes.extend_from_slice(data),
+                None => break,
+            }
+        }
+        bytes
+    }
+
+    /// Decompresses the song at the given index (see
+    /// `LsdjSave::decompress_song()`).
+    pub fn decompress_song(&self, song: u8) -> Result<[u8; SRAM_SIZE], LsdjError> {
+        let first_block = match self.metadata.next_block_for(song, 0) {
+            Some(b) => b,
+            None => return Err(err::NO_SONG.into()),
+        };
+        let mut sram = LsdjSram::empty();
+        decompress_chain(|i| self.block(i + 1), &mut sram, first_block - 1).map_err(|e| { // blocks are one-indexed
+            let base = BLOCK_ADDRESS as usize + e.block * BLOCK_SIZE;
+            let region = self.metadata.block_region(e.block + 1);
+            LsdjError::corrupt(e.reason, self.block(e.block + 1).unwrap_or_default(), base, e.index, region)
+        })?;
+        Ok(sram.data)
+    }
+
+    /// Returns a fingerprint of the song at the given index (see
+    /// `LsdjSave::song_hash()`).
+    pub fn song_hash(&self, song: u8) -> Result<u64, LsdjError> {
+        Ok(fnv1a(&self.decompress_song(song)?))
+    }
+
+    /// Deletes the song at the given index (see `LsdjSave::delete_song()`).
+    pub fn delete_song(&mut self, song: u8) -> Result<(), &'static str> {
+        if self.metadata.size_of(song) == 0 {
+            return Err(err::NO_SONG);
+        }
+        self.metadata.free(song);
+        Ok(())
+    }
+
+    /// Returns all bytes in this save file as a `Vec<u8>`, including any
+    /// changes made to it.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.layout.save_size());
+        out.extend_from_slice(&self.map[..SRAM_SIZE]);
+        out.extend_from_slice(&self.metadata.bytes());
+        for block in 1..=self.layout.block_count() {
+            out.extend_from_slice(self.block(block).unwrap_or(&[0; BLOCK_SIZE]));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::lsdj::LsdjSave;
+
+    #[test]
+    fn test_mapped_save() -> std::io::Result<()> {
+        let mut save = LsdjSave::empty();
+        let mut sram = [0; SRAM_SIZE];
+        for (i, byte) in sram.iter_mut().enumerate() {
+            *byte = (i % 0x61) as u8;
+        }
+        save.import_decompressed_song(&sram, [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        save.import_decompressed_song(&[0; SRAM_SIZE], [b'B', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        let path = std::env::temp_dir().join(format!("lsdjtool-mapped-{}.sav", std::process::id()));
+        fs::write(&path, save.bytes())?;
+
+        let mut mapped = MappedSave::open(&path)?;
+        assert_eq!(mapped.layout(), LsdjLayout::SAVE_128KB);
+        assert_eq!(mapped.bytes(), save.bytes());
+        assert_eq!(mapped.export_song(1), save.export_song(1));
+        assert_eq!(&mapped.decompress_song(0).unwrap()[..], &sram[..]);
+        assert_eq!(mapped.song_hash(1).ok(), save.song_hash(1).ok());
+        assert_eq!(mapped.block(0), None);
+        assert_eq!(mapped.block(0xc0), None);
+
+        mapped.block_mut(0xbf).unwrap().data[0] = 0x12;
+        mapped.block_mut(0xbf).unwrap().data[1] = 0x34; // edits the same copy
+        assert_eq!(mapped.block(0xbf).unwrap()[..2], [0x12, 0x34]);
+        assert_eq!(mapped.bytes()[0x1fe00], 0x12);
+        assert_eq!(fs::read(&path)?[0x1fe00], 0); // the file itself is untouched
+
+        mapped.delete_song(0).unwrap();
+        assert_eq!(mapped.metadata.songs(), vec![1]);
+        assert_eq!(mapped.delete_song(0), Err(err::NO_SONG));
+        fs::remove_file(&path)
+    }
+}
@@ -1,34 +1,85 @@
-use std::io;
-use std::io::Read;
-use std::io::Seek;
-use std::io::SeekFrom::Start;
-use std::fs::File;
-use std::fmt;
-use std::str::from_utf8;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Seek, SeekFrom::Start};
+use core::fmt;
+use core::str::FromStr;
+use core::str::from_utf8;
 
+use log::debug;
+
+use crate::lsdj::prelude::*;
 use crate::lsdj::err;
 
-const TITLE_TABLE_ADDRESS  : u64   = 0x8000;
-const TITLE_LENGTH         : usize = 8;
-const SONG_SLOTS           : usize = 0x20;
-const _TITLE_TABLE_LENGTH   : usize = TITLE_LENGTH * SONG_SLOTS;
-const _VERSION_TABLE_ADDRESS: u64   = 0x8100;
-const VERSION_TABLE_LENGTH : usize = 0x20;
-const _EMPTY_BYTES_ADDRESS  : u64   = 0x8120;
-const EMPTY_BYTES_LENGTH   : usize = 0x1e;
-const _SRAM_INIT_CHK_ADDRESS: u64   = 0x813e;
-const SRAM_INIT_CHK_LENGTH : usize = 2;
-const _WORKING_SONG_ADDRESS : u64   = 0x8140;
-const _ALLOC_TABLE_ADDRESS  : u64   = 0x8141;
-const ALLOC_TABLE_LENGTH   : usize = 0xbf;
-
-const SRAM_INIT_CHK_BYTES: [u8; 2] = [b'j', b'k'];
+pub(super) use crate::lsdj::layout::{SONG_SLOTS, SRAM_INIT_CHK_ADDRESS, TITLE_LENGTH};
+use crate::lsdj::layout::*;
+
+pub(super) const SRAM_INIT_CHK_BYTES: [u8; 2] = [b'j', b'k'];
+
+/// What to do when a song being imported has the same title as a song
+/// already in the save file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OnCollision {
+    /// Import the song under its title anyway.
+    #[default]
+    Force,
+    /// Import the song under a title made unique with a number (see
+    /// `LsdjMetadata::unique_title()`).
+    Rename,
+    /// Refuse to import the song.
+    Skip,
+}
+
+impl FromStr for OnCollision {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<OnCollision, &'static str> {
+        match s {
+            "force"  => Ok(OnCollision::Force),
+            "rename" => Ok(OnCollision::Rename),
+            "skip"   => Ok(OnCollision::Skip),
+            _ => Err(err::BAD_COLLISION),
+        }
+    }
+}
+
+// ANSI foreground colors cycled through to distinguish songs in `block_map()`
+const SONG_COLORS: [u8; 12] = [31, 32, 33, 34, 35, 36, 91, 92, 93, 94, 95, 96];
+
+/// Returns the ANSI foreground color used to distinguish `song` from other
+/// songs in colored output.
+pub fn song_color(song: u8) -> u8 {
+    SONG_COLORS[song as usize % SONG_COLORS.len()]
+}
 
 /// LSDj song titles consist of at most eight ASCII characters, padded with zeros.
 pub type LsdjTitle = [u8; TITLE_LENGTH];
 
+/// The order in which `LsdjMetadata::sort_songs()` arranges songs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+    /// Alphabetically by title.
+    Title,
+    /// By version byte.
+    Version,
+    /// By number of blocks used.
+    Size,
+}
+
+impl FromStr for SortKey {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<SortKey, &'static str> {
+        match s {
+            "title"   => Ok(SortKey::Title),
+            "version" => Ok(SortKey::Version),
+            "size"    => Ok(SortKey::Size),
+            _ => Err(err::BAD_SORT_KEY),
+        }
+    }
+}
+
 /// Contains a representation of all metadata in an LSDj save file (all data between
 /// addresses `$8000` and `$81ff`).
+#[derive(Clone, PartialEq)]
 pub struct LsdjMetadata {
     /// Contains the titles of all $20 songs on the save file.
     pub title_table  : [LsdjTitle; SONG_SLOTS],
@@ -40,8 +91,9 @@ pub struct LsdjMetadata {
     pub empty_bytes  : [u8; EMPTY_BYTES_LENGTH],
     /// LSDj sets to `[$6a, $6b]` (`['j', 'k']`) on init.
     pub sram_init_chk: [u8; SRAM_INIT_CHK_LENGTH],
-    /// Byte representing the index of the song currently loaded into SRAM.
-    pub working_song : [u8; 1],
+    /// Byte representing the index of the song currently loaded into SRAM (see
+    /// `working_song()`).
+    working_song : [u8; 1],
     /// Block allocation table, containing information about which blocks are being used.
     ///
     /// Each byte in the allocation table represents a $200-byte block of compressed song data
@@ -58,7 +110,7 @@ pub struct LsdjMetadata {
 /// all bytes after a null byte is found.
 /// 
 /// # Example
-/// ```
+/// ```ignore
 /// let title: LsdjTitle = [b'T', b'I', b'T', b'L', b'E', 0, b'C', b'R'];
 /// assert_eq!(strip_title(title), [b'T', b'I', b'T', b'L', b'E', 0, 0, 0]);
 /// ```
@@ -76,9 +128,57 @@ fn strip_title(src: LsdjTitle) -> LsdjTitle {
     out
 }
 
+/// Converts `title` to a `String`, ending it at its first zero byte (see
+/// `LsdjMetadata::song_title()`).
+pub fn title_string(title: LsdjTitle) -> String {
+    let stripped_title = strip_title(title);
+    let end = stripped_title.iter().position(|&c| c == 0).unwrap_or(TITLE_LENGTH);
+    from_utf8(&stripped_title[..end]).unwrap_or_default().to_string()
+}
+
+/// Returns true if `c` is in the character set LSDj allows in song titles.
+pub fn is_title_char(c: u8) -> bool {
+    matches!(c, b'A'..=b'Z' | b'0'..=b'9' | b'x' | b' ')
+}
+
+/// Something wrong with an entry in the title table, as found by
+/// `LsdjMetadata::check_titles()`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TitleProblem {
+    /// The title of `song` is followed by the nonzero `bytes` after its
+    /// terminating zero (see `strip_title()`).
+    Junk { song: u8, bytes: Vec<u8> },
+    /// The title of `song` contains `bytes`, which aren't in LSDj's character
+    /// set.
+    BadChars { song: u8, bytes: Vec<u8> },
+}
+
+impl TitleProblem {
+    /// Returns the song whose title the problem was found in.
+    pub fn song(&self) -> u8 {
+        match *self {
+            TitleProblem::Junk { song, .. } | TitleProblem::BadChars { song, .. } => song,
+        }
+    }
+}
+
+impl fmt::Display for TitleProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (song, bytes, what) = match self {
+            TitleProblem::Junk { song, bytes } => (song, bytes, "junk after the end of the title"),
+            TitleProblem::BadChars { song, bytes } => (song, bytes, "characters outside LSDj's character set"),
+        };
+        write!(f, "title {:02X}: {}:", song, what)?;
+        for b in bytes {
+            write!(f, " {:02X}", b)?;
+        }
+        Ok(())
+    }
+}
+
 /// Takes an `&str` and returns an `LsdjTitle` on success, or an error if String can't
 /// be converted to an LsdjTitle.
-pub fn lsdjtitle_from<'a>(from: &'a str) -> Result<LsdjTitle, &'static str> {
+pub fn lsdjtitle_from(from: &str) -> Result<LsdjTitle, &'static str> {
     let mut title = [0; TITLE_LENGTH];
 
     if from.len() > TITLE_LENGTH {
@@ -87,13 +187,13 @@ pub fn lsdjtitle_from<'a>(from: &'a str) -> Result<LsdjTitle, &'static str> {
     
     for (inc, outc) in from.bytes().zip(title.iter_mut()) {
         match inc {
-            b'A'..=b'Z' | b'0'..=b'9' | b'x' | b' ' => *outc = inc, // copy byte to output if valid title character
+            c if is_title_char(c) => *outc = c, // copy byte to output if valid title character
             _ => return Err(err::BAD_TITLE_FMT), // error otherwise
         }
     }
 
-    for i in from.len()..title.len() {
-        title[i] = 0; // fill rest of title with zeros
+    for c in title.iter_mut().skip(from.len()) {
+        *c = 0; // fill rest of title with zeros
     }
     Ok(title)
 }
@@ -113,25 +213,33 @@ impl LsdjMetadata {
         }
     }
 
-    /// Populates the struct with data from the given File.
-    fn fill(&mut self, savefile: &mut File) -> io::Result<()> {
-        savefile.seek(Start(TITLE_TABLE_ADDRESS))?; // seek to beginning of metadata ($8000)
-        for i in 0..SONG_SLOTS {
-            savefile.take(TITLE_LENGTH as u64).read(&mut self.title_table[i])?; // read titles
-        }
-        savefile.take(VERSION_TABLE_LENGTH as u64).read(&mut self.version_table)?; // read versions
-        savefile.take(EMPTY_BYTES_LENGTH as u64).read(&mut self.empty_bytes)?;
-        savefile.take(SRAM_INIT_CHK_LENGTH as u64).read(&mut self.empty_bytes)?;
-        savefile.take(1).read(&mut self.working_song)?;
-        savefile.take(ALLOC_TABLE_LENGTH as u64).read(&mut self.alloc_table)?;
-        Ok(())
+    /// Returns an instance of `LsdjMetadata` filled with the metadata in
+    /// `bytes`, which start at the beginning of the metadata ($8000).
+    pub fn from_bytes(bytes: &[u8; METADATA_LENGTH]) -> LsdjMetadata {
+        let mut metadata = LsdjMetadata::empty();
+        let mut position = 0;
+        let mut read = |field: &mut [u8]| {
+            field.copy_from_slice(&bytes[position..(position + field.len())]);
+            position += field.len();
+        };
+        for title in metadata.title_table.iter_mut() {
+            read(title); // read titles
+        }
+        read(&mut metadata.version_table); // read versions
+        read(&mut metadata.empty_bytes);
+        read(&mut metadata.sram_init_chk);
+        read(&mut metadata.working_song);
+        read(&mut metadata.alloc_table);
+        metadata
     }
 
-    /// Returns an instance of `LsdjMetadata` pre-filled with the metadata from the given File.
-    pub fn from(mut savefile: &mut File) -> io::Result<LsdjMetadata> {
-        let mut metadata = LsdjMetadata::empty();
-        metadata.fill(&mut savefile)?;
-        Ok(metadata)
+    /// Returns an instance of `LsdjMetadata` pre-filled with the metadata read from `savefile`.
+    #[cfg(feature = "std")]
+    pub fn from<R: Read + Seek>(savefile: &mut R) -> io::Result<LsdjMetadata> {
+        let mut bytes = [0; METADATA_LENGTH];
+        savefile.seek(Start(TITLE_TABLE_ADDRESS))?; // seek to beginning of metadata ($8000)
+        savefile.read_exact(&mut bytes)?;
+        Ok(LsdjMetadata::from_bytes(&bytes))
     }
 
     /// Checks whether the SRAM initialization check bytes are equal to 'jk' (the
@@ -153,10 +261,7 @@ impl LsdjMetadata {
     /// Note that blocks in LSDj are one-indexed (i.e., the first block of compressed
     /// song data is block 1).
     pub fn next_empty_block(&self) -> Option<usize> {
-        for block in 1..=self.alloc_table.len() {
-            if !self.is_allocated(block) { return Some(block); }
-        }
-        None
+        (1..=self.alloc_table.len()).find(|&block| !self.is_allocated(block))
     }
 
     /// Reserves `block` for song `song`.
@@ -166,6 +271,7 @@ impl LsdjMetadata {
         if self.alloc_table[block - 1] != 0xff {
             return Err(err::BLOCK_TAKEN);
         } else {
+            debug!("reserving block {} for song {:02X}", block, song);
             self.alloc_table[block - 1] = song;
         }
         Ok(())
@@ -179,6 +285,42 @@ impl LsdjMetadata {
         self.title_table[song as usize] = title;
     }
 
+    /// Frees all blocks allocated to `song` and clears its title and version.
+    ///
+    /// The blocks themselves are left untouched, as LSDj does when a song is
+    /// deleted.
+    pub fn free(&mut self, song: u8) {
+        for (i, belongs_to) in self.alloc_table.iter_mut().enumerate() {
+            if *belongs_to == song {
+                debug!("freeing block {} of song {:02X}", i + 1, song);
+                *belongs_to = 0xff;
+            }
+        }
+        if let Some(title) = self.title_table.get_mut(song as usize) {
+            *title = [0; TITLE_LENGTH];
+            self.version_table[song as usize] = 0;
+        }
+    }
+
+    /// Frees every block allocated to a song which cannot exist: either its index
+    /// is past the last song slot, or its slot has an empty title. Returns the
+    /// number of blocks freed.
+    pub fn prune_orphans(&mut self) -> usize {
+        let mut freed = 0;
+        for belongs_to in self.alloc_table.iter_mut() {
+            if *belongs_to == 0xff { continue; } // block is already free
+            let orphaned = match self.title_table.get(*belongs_to as usize) {
+                Some(title) => title[0] == 0,
+                None => true, // song index out of range
+            };
+            if orphaned {
+                *belongs_to = 0xff;
+                freed += 1;
+            }
+        }
+        freed
+    }
+
     /// Returns the index of the next block allocated to song `song`, starting
     /// at block `skip`.
     pub fn next_block_for(&self, song: u8, skip: usize) -> Option<usize> {
@@ -220,7 +362,7 @@ impl LsdjMetadata {
     /// Returns the next song index to which no blocks are allocated, or `None` if
     /// there are no remaining song slots.
     pub fn next_available_song(&self) -> Option<u8> {
-        if self.blocks_used() as usize == ALLOC_TABLE_LENGTH { return None; }
+        if self.blocks_used() == ALLOC_TABLE_LENGTH { return None; }
         let mut song = 0;
         for _i in 0..SONG_SLOTS {
             for belongs_to in self.alloc_table.iter() {
@@ -234,76 +376,258 @@ impl LsdjMetadata {
         }
     }
 
-    /// Returns a `std::String` containing a prettified representing all song
-    /// titles in the save file, along with their indices and version bytes.
-    pub fn list_songs(&self) -> String {
-        let mut out = String::new();
-        for (index, title) in self.title_table.iter().enumerate() {
-            if title[0] == 0 { break; } // end of title table
-            let stripped_title = &strip_title(*title);
-            out.push_str(format!("{:02X}: {}.{:X}\n", index, match from_utf8(stripped_title) {
-                Ok(t) => t,
-                Err(_) => ""
-            }, self.version_table[index]).as_str());
+    /// Returns the indices of all songs which have at least one block allocated
+    /// to them, in ascending order.
+    pub fn songs(&self) -> Vec<u8> {
+        (0..SONG_SLOTS as u8).filter(|&song| self.size_of(song) > 0).collect()
+    }
+
+    /// Returns the first song titled `title`, if any.
+    pub fn find_title(&self, title: LsdjTitle) -> Option<u8> {
+        self.songs().into_iter().find(|&s| strip_title(self.title_table[s as usize]) == strip_title(title))
+    }
+
+    /// Checks every entry in the title table (including those of empty
+    /// slots) for junk after the title's terminating zero and for characters
+    /// LSDj doesn't allow in titles, returning the problems found in order of
+    /// song.
+    pub fn check_titles(&self) -> Vec<TitleProblem> {
+        let mut problems = Vec::new();
+        for (song, title) in (0..).zip(self.title_table.iter()) {
+            let end = title.iter().position(|&c| c == 0).unwrap_or(TITLE_LENGTH);
+            let bad: Vec<u8> = title[..end].iter().copied().filter(|&c| !is_title_char(c)).collect();
+            if !bad.is_empty() {
+                problems.push(TitleProblem::BadChars { song, bytes: bad });
+            }
+            let junk = title.get(end + 1..).unwrap_or_default();
+            if junk.iter().any(|&c| c != 0) {
+                problems.push(TitleProblem::Junk { song, bytes: junk.to_vec() });
+            }
         }
-        out
+        problems
     }
 
-    /// Returns all bytes in this instance as a `Vec<u8>`.
-    pub fn bytes(&self) -> Vec<u8> {
-        let mut out = Vec::new();
-        for t in self.title_table.iter() {
-            for c in t.iter() {
-                out.push(*c);
+    /// Zero-fills every entry in the title table after its terminating zero
+    /// (see `strip_title()`), returning the songs whose titles changed.
+    /// Characters outside LSDj's character set are left as they are.
+    pub fn fix_titles(&mut self) -> Vec<u8> {
+        let mut fixed = Vec::new();
+        for (song, title) in (0..).zip(self.title_table.iter_mut()) {
+            let stripped = strip_title(*title);
+            if stripped != *title {
+                *title = stripped;
+                fixed.push(song);
+            }
+        }
+        fixed
+    }
+
+    /// Returns `title` with a number in place of any number it ends with,
+    /// counting up from 2 until no song has the resulting title: SONG becomes
+    /// SONG2, or SONG3 if SONG2 is taken too. The title is cut short to make
+    /// room for the number if need be.
+    pub fn unique_title(&self, title: LsdjTitle) -> LsdjTitle {
+        let title = strip_title(title);
+        let len = title.iter().position(|&c| c == 0).unwrap_or(TITLE_LENGTH);
+        let mut base = &title[..len];
+        if let Some(end) = base.iter().rposition(|c| !c.is_ascii_digit()) {
+            base = &base[..=end]; // drop the number, counting on from it
+        }
+        let mut n: u32 = 2;
+        loop {
+            let suffix = n.to_string();
+            let mut unique = [0; TITLE_LENGTH];
+            let kept = base.len().min(TITLE_LENGTH - suffix.len());
+            unique[..kept].copy_from_slice(&base[..kept]);
+            unique[kept..(kept + suffix.len())].copy_from_slice(suffix.as_bytes());
+            if self.find_title(unique).is_none() {
+                return unique;
             }
+            n += 1;
+        }
+    }
+
+    /// Reorders songs by `key` (in descending order if `reverse` is true),
+    /// moving them into consecutive slots starting at 0. The title table,
+    /// version table, allocation table, and working song are all updated to
+    /// match; songs which compare equal keep their relative order. A working
+    /// song whose slot is empty is given the first slot after the sorted songs.
+    pub fn sort_songs(&mut self, key: SortKey, reverse: bool) {
+        let mut songs = self.songs();
+        match key {
+            SortKey::Title   => songs.sort_by_key(|&s| self.song_title(s)),
+            SortKey::Version => songs.sort_by_key(|&s| self.version_table[s as usize]),
+            SortKey::Size    => songs.sort_by_key(|&s| self.size_of(s)),
         }
-        for b in self.version_table.iter() {
-            out.push(*b);
+        if reverse {
+            songs.reverse();
         }
-        for b in self.empty_bytes.iter() {
-            out.push(*b);
+
+        let mut new_slot = [None; SONG_SLOTS]; // maps old song indices to new ones
+        let mut title_table = [[0; TITLE_LENGTH]; SONG_SLOTS];
+        let mut version_table = [0; VERSION_TABLE_LENGTH];
+        for (new, &old) in songs.iter().enumerate() {
+            new_slot[old as usize] = Some(new as u8);
+            title_table[new] = self.title_table[old as usize];
+            version_table[new] = self.version_table[old as usize];
         }
-        for b in self.sram_init_chk.iter() {
-            out.push(*b);
+        for belongs_to in self.alloc_table.iter_mut() {
+            if let Some(&Some(new)) = new_slot.get(*belongs_to as usize) {
+                *belongs_to = new;
+            }
         }
-        for b in self.working_song.iter() {
-            out.push(*b);
+        if let Some(old) = self.working_song() {
+            // a working song in an empty slot moves past the sorted songs, or
+            // LSDj would save it over whichever song was moved into its slot
+            self.working_song[0] = new_slot[old as usize].unwrap_or(songs.len() as u8);
         }
-        for b in self.alloc_table.iter() {
-            out.push(*b);
+        self.title_table = title_table;
+        self.version_table = version_table;
+    }
+
+    /// Returns the index of the song currently loaded into SRAM, or `None` if
+    /// the working song byte doesn't name a valid song slot.
+    pub fn working_song(&self) -> Option<u8> {
+        Some(self.working_song[0]).filter(|&s| (s as usize) < SONG_SLOTS)
+    }
+
+    /// Marks `song` as the song currently loaded into SRAM. Returns an `Err` if
+    /// `song` is not a valid song slot.
+    pub fn set_working_song(&mut self, song: u8) -> Result<(), &'static str> {
+        if song as usize >= SONG_SLOTS {
+            return Err(err::BAD_SONG_INDEX);
         }
+        self.working_song[0] = song;
+        Ok(())
+    }
+
+    /// Returns the title of `song` as a `String`, with any bytes after its
+    /// terminating null byte removed, or an empty string if `song` is past
+    /// the last song slot (as corrupt allocation tables can claim).
+    pub fn song_title(&self, song: u8) -> String {
+        self.title_table.get(song as usize).map(|&t| title_string(t)).unwrap_or_default()
+    }
+
+    /// Returns a description of the metadata byte at `address` in the save file
+    /// (e.g. `title of song 03 (TEST)`), or `None` if `address` isn't in the
+    /// metadata.
+    pub fn region(&self, address: u64) -> Option<String> {
+        let song = |s: u64| match self.song_title(s as u8) {
+            title if self.size_of(s as u8) > 0 => format!("song {:02X} ({})", s, title),
+            _ => format!("song {:02X}", s),
+        };
+        Some(match address {
+            TITLE_TABLE_ADDRESS..VERSION_TABLE_ADDRESS =>
+                format!("title of {}", song((address - TITLE_TABLE_ADDRESS) / TITLE_LENGTH as u64)),
+            VERSION_TABLE_ADDRESS..EMPTY_BYTES_ADDRESS => format!("version of {}", song(address - VERSION_TABLE_ADDRESS)),
+            EMPTY_BYTES_ADDRESS..SRAM_INIT_CHK_ADDRESS => "unused metadata".to_string(),
+            SRAM_INIT_CHK_ADDRESS..WORKING_SONG_ADDRESS => "SRAM initialization check bytes ('jk')".to_string(),
+            WORKING_SONG_ADDRESS => "index of the working song".to_string(),
+            _ if (ALLOC_TABLE_ADDRESS..(ALLOC_TABLE_ADDRESS + ALLOC_TABLE_LENGTH as u64)).contains(&address) =>
+                format!("allocation table entry of block {:02X}", address - ALLOC_TABLE_ADDRESS + 1),
+            _ => return None,
+        })
+    }
+
+    /// Returns a description of `block` (one-indexed) and the song it is
+    /// allocated to (e.g. `block 05 (song 00, TEST)`).
+    pub fn block_region(&self, block: usize) -> String {
+        match self.alloc_table.get(block.wrapping_sub(1)) {
+            Some(&0xff) | None => format!("block {:02X} (free)", block),
+            Some(&song) => format!("block {:02X} (song {:02X}, {})", block, song, self.song_title(song)),
+        }
+    }
+
+    /// Returns a `std::String` containing the block allocation table laid out
+    /// as a grid, where each cell shows the index of the song which owns that
+    /// block (or `..` for an unallocated block), followed by a legend listing
+    /// each song's title and size. If `color` is true, each song's cells are
+    /// colored with ANSI escape codes.
+    pub fn block_map(&self, color: bool) -> String {
+        let paint = |song: u8, text: String| -> String {
+            if color {
+                format!("\x1b[{}m{}\x1b[0m", song_color(song), text)
+            } else {
+                text
+            }
+        };
+        let mut out = String::from("    |");
+        for i in 0..0x10 {
+            out.push_str(format!(" {:X} ", i).as_str());
+        }
+        out.pop(); // remove trailing space
+        out.push('\n');
+        for (row, entries) in self.alloc_table.chunks(0x10).enumerate() {
+            out.push_str(format!("{:02X}  |", row * 0x10).as_str());
+            for &belongs_to in entries.iter() {
+                out.push(' ');
+                if belongs_to == 0xff {
+                    out.push_str("..");
+                } else {
+                    out.push_str(paint(belongs_to, format!("{:02X}", belongs_to)).as_str());
+                }
+            }
+            out.push('\n');
+        }
+        out.push('\n');
+        for song in self.songs() {
+            let line = format!("{:02X}: {} ({} blocks)", song, self.song_title(song), self.size_of(song));
+            out.push_str(paint(song, line).as_str());
+            out.push('\n');
+        }
+        out.push_str(format!("free: {} blocks\n", ALLOC_TABLE_LENGTH - self.blocks_used()).as_str());
         out
     }
+
+    /// Returns all bytes in this instance as a `Vec<u8>`.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(METADATA_LENGTH);
+        for t in self.title_table.iter() {
+            out.extend_from_slice(t);
+        }
+        out.extend_from_slice(&self.version_table);
+        out.extend_from_slice(&self.empty_bytes);
+        out.extend_from_slice(&self.sram_init_chk);
+        out.extend_from_slice(&self.working_song);
+        out.extend_from_slice(&self.alloc_table);
+        out
+    }
+}
+
+impl Default for LsdjMetadata {
+    fn default() -> LsdjMetadata {
+        LsdjMetadata::empty()
+    }
 }
 
 impl fmt::Debug for LsdjMetadata {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "song list [index: title.version]:\n")?;
+        writeln!(f, "song list [index: title.version]:")?;
         for (i, title) in self.title_table.iter().enumerate() {
-            write!(f, "{:02X}: {:?}.{:X}\n", i, match from_utf8(&title[..]) {
-                Ok(t) => t,
-                Err(_) => ""
-            }, self.version_table[i])?;
-        }
-        write!(f, "sram init check: {:X?}\t{}\n", self.sram_init_chk,
-               if self.check_sram_init() { "[OK]" } else { "[FAIL]" })?;
-        write!(f, "working song: {:02X} {:?}\n", self.working_song[0],
-               match from_utf8(&self.title_table[self.working_song[0] as usize][0..]) {
-                   Ok(t) => t, Err(_) => ""})?;
-        write!(f, "block allocation table:\n")?;
+            writeln!(f, "{:02X}: {:?}.{:X}", i, from_utf8(&title[..]).unwrap_or_default(),
+                     self.version_table[i])?;
+        }
+        writeln!(f, "sram init check: {:X?}\t{}", self.sram_init_chk,
+                 if self.check_sram_init() { "[OK]" } else { "[FAIL]" })?;
+        match self.working_song() {
+            Some(song) => writeln!(f, "working song: {:02X} {:?}", song,
+                                   from_utf8(&self.title_table[song as usize][0..]).unwrap_or_default())?,
+            None => writeln!(f, "working song: {:02X} [INVALID]", self.working_song[0])?,
+        }
+        writeln!(f, "block allocation table:")?;
         for disp in 0..(self.alloc_table.len() / 0x10) {
             write!(f, "{:02X}  | ", disp * 0x10)?;
             for offset in 0..0x10 {
                 write!(f, "{:02X}| ", self.alloc_table[disp * 0x10 + offset])?;
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
         //FIXME: ugly!
         write!(f, "B0  | ")?;
         for offset in 0xb0..0xbf {
             write!(f, "{:02X}| ", self.alloc_table[offset])?;
         }
-        write!(f, "\n")?;
+        writeln!(f)?;
         Ok(())
     }
 }
@@ -328,6 +652,27 @@ mod tests {
         assert_eq!(lsdjtitle_from(invalid_title2), Err(err::BAD_TITLE_FMT));
     }
 
+    #[test]
+    fn test_check_titles() {
+        let mut metadata = LsdjMetadata::empty();
+        metadata.title_table[0] = *b"SONG\0\0\0\0";
+        metadata.title_table[1] = [b'T', b'I', b'T', b'L', b'E', 0, b'C', b'R'];
+        metadata.title_table[2] = [b'O', b'~', b'k', 0, 0, 0, 0, 0xff];
+        assert_eq!(metadata.check_titles(), [
+            TitleProblem::Junk { song: 1, bytes: vec![b'C', b'R'] },
+            TitleProblem::BadChars { song: 2, bytes: vec![b'~', b'k'] },
+            TitleProblem::Junk { song: 2, bytes: vec![0, 0, 0, 0xff] },
+        ]);
+        assert_eq!(metadata.check_titles()[0].to_string(), "title 01: junk after the end of the title: 43 52");
+        assert_eq!(metadata.check_titles()[1].to_string(),
+                   "title 02: characters outside LSDj's character set: 7E 6B");
+
+        assert_eq!(metadata.fix_titles(), [1, 2]);
+        assert_eq!(metadata.title_table[1], [b'T', b'I', b'T', b'L', b'E', 0, 0, 0]);
+        assert_eq!(metadata.check_titles(), [TitleProblem::BadChars { song: 2, bytes: vec![b'~', b'k'] }]);
+        assert!(metadata.fix_titles().is_empty());
+    }
+
     #[test]
     fn test_check_sram_init() {
         let mut metadata = LsdjMetadata::empty();
@@ -394,6 +739,39 @@ mod tests {
         assert_eq!(metadata.next_block_for(3, 1), Some(67));
     }
 
+    #[test]
+    fn test_free() {
+        let mut metadata = LsdjMetadata::empty();
+        metadata.alloc_table[0] = 0;
+        metadata.alloc_table[1] = 1;
+        metadata.alloc_table[2] = 0;
+        metadata.title(0, [b'A', 0, 0, 0, 0, 0, 0, 0]);
+        metadata.version_table[0] = 3;
+        metadata.free(0);
+        assert_eq!(metadata.size_of(0), 0);
+        assert_eq!(metadata.size_of(1), 1);
+        assert_eq!(metadata.title_table[0], [0; TITLE_LENGTH]);
+        assert_eq!(metadata.version_table[0], 0);
+
+        metadata.alloc_table[3] = 0x40;
+        metadata.free(0x40);
+        assert_eq!(metadata.size_of(0x40), 0);
+        assert_eq!(metadata.block_region(4), "block 04 (free)");
+    }
+
+    #[test]
+    fn test_prune_orphans() {
+        let mut metadata = LsdjMetadata::empty();
+        metadata.title(0, [b'A', 0, 0, 0, 0, 0, 0, 0]);
+        metadata.alloc_table[0] = 0;
+        metadata.alloc_table[1] = 1;    // slot has no title
+        metadata.alloc_table[2] = 0x20; // past the last slot
+        metadata.alloc_table[3] = 0;
+        assert_eq!(metadata.prune_orphans(), 2);
+        assert_eq!(&metadata.alloc_table[0..5], &[0, 0xff, 0xff, 0, 0xff]);
+        assert_eq!(metadata.prune_orphans(), 0);
+    }
+
     #[test]
     fn test_size_of() {
         let mut metadata = LsdjMetadata::empty();
@@ -414,6 +792,108 @@ mod tests {
         assert_eq!(metadata.blocks_used(), 0);
     }
 
+    #[test]
+    fn test_songs() {
+        let mut metadata = LsdjMetadata::empty();
+        assert_eq!(metadata.songs(), Vec::<u8>::new());
+        metadata.alloc_table[0] = 3;
+        metadata.alloc_table[1] = 0;
+        metadata.alloc_table[2] = 3;
+        assert_eq!(metadata.songs(), vec![0, 3]);
+    }
+
+    #[test]
+    fn test_song_title() {
+        let mut metadata = LsdjMetadata::empty();
+        metadata.title(0, [b'T', b'I', b'T', b'L', b'E', 0, b'C', b'R']);
+        metadata.title(1, [b'S', b'O', b'N', b'G', b'N', b'A', b'M', b'E']);
+        assert_eq!(metadata.song_title(0), "TITLE");
+        assert_eq!(metadata.song_title(1), "SONGNAME");
+        assert_eq!(metadata.song_title(2), "");
+        assert_eq!(metadata.song_title(0x40), ""); // past the last slot, as corrupt allocation tables claim
+    }
+
+    #[test]
+    fn test_sort_songs() {
+        let mut metadata = LsdjMetadata::empty();
+        metadata.alloc_table[0] = 0;
+        metadata.alloc_table[1] = 3;
+        metadata.alloc_table[2] = 0;
+        metadata.alloc_table[3] = 5;
+        metadata.title(0, [b'C', 0, 0, 0, 0, 0, 0, 0]);
+        metadata.title(3, [b'A', 0, 0, 0, 0, 0, 0, 0]);
+        metadata.title(5, [b'B', 0, 0, 0, 0, 0, 0, 0]);
+        metadata.version_table[0] = 1;
+        metadata.version_table[3] = 2;
+        metadata.version_table[5] = 0;
+        metadata.set_working_song(5).unwrap();
+        metadata.sort_songs(SortKey::Title, false);
+        assert_eq!(metadata.songs(), vec![0, 1, 2]);
+        assert_eq!(&metadata.alloc_table[0..4], &[2, 0, 2, 1]);
+        assert_eq!(metadata.song_title(0), "A");
+        assert_eq!(metadata.song_title(1), "B");
+        assert_eq!(metadata.song_title(2), "C");
+        assert_eq!(metadata.song_title(3), "");
+        assert_eq!(&metadata.version_table[0..3], &[2, 0, 1]);
+        assert_eq!(metadata.working_song(), Some(1));
+        metadata.sort_songs(SortKey::Size, true);
+        assert_eq!(metadata.song_title(0), "C");
+        metadata.sort_songs(SortKey::Version, false);
+        assert_eq!(&metadata.version_table[0..3], &[0, 1, 2]);
+        assert_eq!("size".parse(), Ok(SortKey::Size));
+        assert_eq!("name".parse::<SortKey>(), Err(err::BAD_SORT_KEY));
+    }
+
+    #[test]
+    fn test_sort_songs_empty_working_slot() {
+        let mut metadata = LsdjMetadata::empty();
+        metadata.alloc_table[0] = 2;
+        metadata.alloc_table[1] = 4;
+        metadata.title(2, [b'B', 0, 0, 0, 0, 0, 0, 0]);
+        metadata.title(4, [b'A', 0, 0, 0, 0, 0, 0, 0]);
+        metadata.set_working_song(1).unwrap(); // a new song, not saved yet
+        metadata.sort_songs(SortKey::Title, false);
+        assert_eq!(metadata.songs(), vec![0, 1]);
+        assert_eq!(metadata.working_song(), Some(2));
+        assert_eq!(metadata.size_of(2), 0);
+    }
+
+    #[test]
+    fn test_unique_title() {
+        let mut metadata = LsdjMetadata::empty();
+        for (song, title) in ["SONG", "SONG2", "LONGNAME", "909"].iter().enumerate() {
+            metadata.title(song as u8, lsdjtitle_from(title).unwrap());
+            metadata.reserve(song + 1, song as u8).unwrap();
+        }
+        let title = |t| lsdjtitle_from(t).unwrap();
+        assert_eq!(metadata.find_title(title("SONG2")), Some(1));
+        assert_eq!(metadata.find_title(title("SONG3")), None);
+        assert_eq!(metadata.unique_title(title("SONG")), title("SONG3"));
+        assert_eq!(metadata.unique_title(title("SONG2")), title("SONG3"));
+        assert_eq!(metadata.unique_title(title("LONGNAME")), title("LONGNAM2"));
+        assert_eq!(metadata.unique_title(title("909")), title("9092"));
+        assert_eq!("rename".parse(), Ok(OnCollision::Rename));
+        assert_eq!("replace".parse::<OnCollision>(), Err(err::BAD_COLLISION));
+    }
+
+    #[test]
+    fn test_block_map() {
+        let mut metadata = LsdjMetadata::empty();
+        metadata.alloc_table[0] = 0;
+        metadata.alloc_table[0x11] = 1;
+        metadata.title(0, [b'A', 0, 0, 0, 0, 0, 0, 0]);
+        metadata.title(1, [b'B', 0, 0, 0, 0, 0, 0, 0]);
+        let map = metadata.block_map(false);
+        let lines: Vec<&str> = map.lines().collect();
+        assert_eq!(lines.len(), 1 + 12 + 1 + 2 + 1);
+        assert!(lines[1].starts_with("00  | 00 .. .."));
+        assert!(lines[2].starts_with("10  | .. 01 .."));
+        assert_eq!(lines[14], "00: A (1 blocks)");
+        assert_eq!(lines[16], "free: 189 blocks");
+        assert!(!map.contains('\x1b'));
+        assert!(metadata.block_map(true).contains("\x1b[32m01\x1b[0m"));
+    }
+
     #[test]
     fn test_next_available_song() {
         let mut metadata = LsdjMetadata::empty();
@@ -431,4 +911,17 @@ mod tests {
         metadata0.alloc_table = [0; ALLOC_TABLE_LENGTH];
         assert_eq!(metadata0.next_available_song(), None);
     }
+
+    #[test]
+    fn test_working_song() {
+        let mut metadata = LsdjMetadata::empty();
+        assert_eq!(metadata.working_song(), Some(0));
+        assert_eq!(metadata.set_working_song(0x1f), Ok(()));
+        assert_eq!(metadata.working_song(), Some(0x1f));
+        assert_eq!(metadata.set_working_song(0x20), Err(err::BAD_SONG_INDEX));
+        assert_eq!(metadata.working_song(), Some(0x1f));
+        metadata.working_song[0] = 0xff; // as found in a corrupted save
+        assert_eq!(metadata.working_song(), None);
+        assert!(format!("{:?}", metadata).contains("working song: FF [INVALID]"));
+    }
 }
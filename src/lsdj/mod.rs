@@ -1,111 +1,589 @@
-use std::io;
-use std::io::{Seek, SeekFrom::Start};
-use std::io::Read;
-use std::fs::File;
-use std::fmt;
+use core::convert::TryInto;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom::{Start, End}, Write};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+use log::debug;
+use serde::Serialize;
+
+use prelude::*;
 
-use compression::LsdjBlock;
 use metadata::*;
-use metadata::LsdjTitle;
+pub use metadata::LsdjTitle;
+
+use layout::{BLOCK_ADDRESS, BLOCK_SIZE, METADATA_LENGTH, SRAM_SIZE};
+pub use layout::LsdjLayout;
 
-const BLOCK_SIZE: usize = 0x200;
-const BLOCK_COUNT   : usize = 0xbe;
-const BANK_SIZE : usize = 0x2000;
-const BANK_COUNT: usize = 4;
-const SRAM_SIZE : usize = BANK_SIZE * BANK_COUNT;
-const BLOCK_ADDRESS : u64   = 0x8200;
-const SAVE_SIZE     : usize = 0x20000;
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME       : u64 = 0x100000001b3;
 
 mod compression;
 mod metadata;
+pub mod layout;
+mod error;
+#[cfg(feature = "std")]
+pub mod io;
+#[cfg(feature = "mmap")]
+pub mod mapped;
+pub mod pocket;
+pub mod goomba;
+pub mod rom;
+mod lzo;
+pub mod song;
+pub mod diff;
+pub mod stats;
+pub mod edit;
+pub mod clean;
+pub mod snippet;
+pub mod splice;
+pub mod synth;
+pub mod speech;
+pub mod preset;
+pub mod container;
+pub mod armor;
+pub mod builder;
+pub mod audit;
+pub mod recover;
+pub mod carve;
+pub mod patch;
+pub mod generate;
+mod slot;
+pub mod snapshot;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "python")]
+pub mod python;
 
+/// The parts of the standard prelude which come from `alloc`, imported by
+/// every module so that they build without `std` as well.
+mod prelude {
+    pub use alloc::format;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec;
+    pub use alloc::vec::Vec;
+}
+
+pub use compression::LsdjBlock;
 pub use compression::LsdjBlockExt;
+pub use compression::Compat;
+pub use compression::CompressionStats;
+use compression::DecompressError;
 pub use metadata::lsdjtitle_from;
+pub use metadata::title_string;
+pub use metadata::TitleProblem;
+pub use metadata::SortKey;
+pub use metadata::OnCollision;
+pub use error::{LsdjError, ErrorClass};
+pub use slot::SongSlot;
+pub use snapshot::LsdjSaveSnapshot;
+
+/// Declares the messages of `LsdjError::Invalid`, each under the `ErrorClass`
+/// of error it describes, along with `class_of()`, so that every message has a
+/// class.
+macro_rules! errors {
+    ($($class:ident { $($(#[$attr:meta])* $name:ident : $msg:expr;)* })*) => {
+        $($($(#[$attr])* pub const $name: &str = $msg;)*)*
+
+        /// Returns the class of errors described by `e`, or `None` if it
+        /// isn't one of the messages above.
+        pub fn class_of(e: &str) -> Option<super::ErrorClass> {
+            match e {
+                $($($(#[$attr])* $name => Some(super::ErrorClass::$class),)*)*
+                _ => None,
+            }
+        }
+    };
+}
 
 mod err {
-    pub const SONGS_FULL   : &str = "song slots full!";
-    pub const BAD_FMT      : &str = "blocks are incorrectly formatted!";
-    pub const NO_BLOCKS    : &str = "not enough free blocks left!";
-    pub const BLOCK_TAKEN  : &str = "block is already taken!";
-    pub const NO_SKIP      : &str = "block contains no skip instruction!";
-    pub const WTF          : &str = "something has gone terribly wrong";
-    pub const BAD_TITLE_FMT: &str = "title must be at most 8 characters, A-Z0-9x.";
+    errors! {
+        NoSpace {
+            SONGS_FULL   : "song slots full!";
+            NO_BLOCKS    : "not enough free blocks left!";
+            NO_ROOM      : "songs use blocks past the end of the new layout!";
+            GOOMBA_FULL  : "not enough room left in Goomba save!";
+            SONG_FULL    : "not enough free chains, phrases, instruments, or tables left in song!";
+        }
+        NotFound {
+            NO_SONG      : "no song exists at that index!";
+            NO_CHAIN     : "no chain exists at that index!";
+            NO_INSTRUMENT: "no instrument exists at that index!";
+            NO_DELETED   : "no deleted song starts at that block!";
+        }
+        Argument {
+            BLOCK_TAKEN  : "block is already taken!";
+            BAD_TITLE_FMT: "title must be at most 8 characters, A-Z0-9x.";
+            BAD_SORT_KEY : "sort key must be one of title, version, or size.";
+            BAD_COLLISION: "collision policy must be one of rename, skip, or force.";
+            BAD_BLOCK_COUNT: "a save file must hold between 1 and $bf blocks!";
+            BAD_SONG_INDEX: "song index is out of range!";
+            #[cfg(feature = "std")]
+            BAD_CODEC    : "compression must be one of gzip or zstd.";
+            BAD_COMPAT   : "compatibility mode must be one of lsdj, lsdpatch or legacy.";
+            #[cfg(feature = "std")]
+            BAD_BACKUP   : "backup policy must be a list of keep=N and dir=PATH.";
+            BAD_BLOCK    : "block number is out of range!";
+            BAD_INSTRUMENT_TYPE: "instrument type must be one of pulse, wave, kit, or noise.";
+            NOTE_RANGE   : "notes would be transposed out of range!";
+            BAD_INSTRUMENT_SLOT: "instrument slot is out of range!";
+            BAD_ALLOPHONE: "unknown allophone!";
+            WORD_TOO_LONG: "speech words hold at most 16 allophones!";
+            SONG_TOO_LONG: "songs have too many rows between them to splice!";
+            BAD_TEMPO    : "tempo must be between 40 and 295 BPM.";
+            BAD_PATCH_FORMAT: "patch format must be one of ips or bps.";
+            WRONG_PATCH_SOURCE: "patch was made from a different file!";
+            PATCH_TOO_BIG: "IPS patches can't address files larger than 16MB!";
+            BAD_CONTENT  : "song content must be one of empty, song, or noise.";
+            BAD_GEN_SIZE : "song can't be made to compress to that many blocks!";
+        }
+        Corrupt {
+            BAD_FMT      : "blocks are incorrectly formatted!";
+            NO_SKIP      : "block contains no skip instruction!";
+            WTF          : "something has gone terribly wrong";
+            BAD_SAVE_SIZE: "save file size does not match any known layout!";
+            BAD_LZO      : "LZO data is corrupt!";
+            NOT_GOOMBA   : "file is not a Goomba save!";
+            NO_GOOMBA_SRAM: "no matching SRAM found in Goomba save!";
+            GOOMBA_UNCLEAN: "Goomba save is unclean; load and exit the game in Goomba first!";
+            BAD_SONG     : "song data is corrupt or not decompressed!";
+            BAD_ROM      : "ROM header is missing or corrupt!";
+            NOT_LSDJ_ROM : "ROM is not an LSDj ROM with battery-backed RAM!";
+            BAD_ARMOR    : "armored song is missing a header line or corrupt!";
+            BAD_PARTS    : "song parts are missing, repeated, or unnumbered!";
+            BAD_CONTAINER: "song container header is missing or corrupt!";
+            BAD_PRESET   : "preset instrument type does not match its parameters!";
+            NO_SAVE_FOUND: "no LSDj save found in dump!";
+            NO_SRAM_INIT : "SRAM initialization check bytes are not 'jk'!";
+            BAD_ROUND_TRIP: "recompressed song doesn't decompress to the same data!";
+            BAD_BLOCK_REGION: "block region length does not match the save file's layout!";
+            BAD_PATCH    : "patch is corrupt or not an IPS or BPS patch!";
+            BLOCK_LOOP   : "blocks skip back to a block already decompressed!";
+        }
+    }
+}
+
+/// Summarizes one song in a save file, as listed by `LsdjSave::song_table()`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SongInfo {
+    pub index: u8,
+    pub title: String,
+    /// Version byte, counting how many times the song has been saved.
+    pub version: u8,
+    /// Number of blocks used.
+    pub blocks: usize,
+    /// Version of LSDj's song format in which the song is stored, or `None` if
+    /// the song couldn't be decompressed.
+    pub format_version: Option<u8>,
+    /// Kits played by the song's kit instruments, or `None` if the song
+    /// couldn't be decompressed.
+    pub kits: Option<Vec<u8>>,
+}
+
+/// Whether a song would fit into a save file, as reported by
+/// `LsdjSave::can_fit()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fit {
+    /// Number of blocks the song takes.
+    pub needed: usize,
+    /// Number of blocks free in the save file.
+    pub free: usize,
+    /// Whether a song slot is free.
+    pub slot_free: bool,
+}
+
+impl Fit {
+    /// Returns true if the song would fit: there are enough free blocks and a
+    /// free song slot.
+    pub fn fits(&self) -> bool {
+        self.slot_free && self.needed <= self.free
+    }
+}
+
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "needs {} block(s), {} free", self.needed, self.free)?;
+        if !self.slot_free {
+            write!(f, ", but no song slot is free")?;
+        }
+        write!(f, " ({})", if self.fits() { "fits" } else { "doesn't fit" })
+    }
 }
 
 /// Contains the contents of LSDj's save RAM ($8000 bytes long).
+///
+/// SRAMs compare equal if their data is the same, regardless of their
+/// compatibility modes.
+#[derive(Clone)]
 pub struct LsdjSram {
-    pub position: usize,
     pub data: [u8; SRAM_SIZE],
+    /// How default instruments and waves are compressed and decompressed.
+    pub compat: Compat,
+}
+
+/// Reads blocks of compressed song data from `reader` into a `Vec<u8>`,
+/// returning the number of blocks read.
+///
+/// Returns an `Err` if the data ends partway through a block, unless `pad` is
+/// true, in which case the last block is filled out with zeroes. Also returns
+/// an `Err` if any block doesn't end with a skip or end-of-file instruction.
+#[cfg(feature = "std")]
+pub fn read_blocks<R: Read>(mut reader: R, bytes: &mut Vec<u8>, pad: bool) -> Result<usize, LsdjError> {
+    let start = bytes.len();
+    reader.read_to_end(bytes)?;
+    check_blocks(bytes, start, pad)
+}
+
+/// Reads blocks of compressed song data from `data` into a `Vec<u8>`, as the
+/// `std` version of `read_blocks()` reads them from a reader.
+#[cfg(not(feature = "std"))]
+pub fn read_blocks(data: &[u8], bytes: &mut Vec<u8>, pad: bool) -> Result<usize, LsdjError> {
+    let start = bytes.len();
+    bytes.extend_from_slice(data);
+    check_blocks(bytes, start, pad)
+}
+
+/// Checks the blocks read into `bytes` from `start` on for `read_blocks()`,
+/// padding the last one if `pad` is true. Returns the number of blocks.
+fn check_blocks(bytes: &mut Vec<u8>, start: usize, pad: bool) -> Result<usize, LsdjError> {
+    let len = bytes.len() - start;
+    if !len.is_multiple_of(BLOCK_SIZE) {
+        if !pad {
+            return Err(LsdjError::TruncatedBlock { block: len / BLOCK_SIZE, len: len % BLOCK_SIZE });
+        }
+        bytes.resize(start + len.next_multiple_of(BLOCK_SIZE), 0);
+    }
+    let mut block = LsdjBlock::empty();
+    for (i, chunk) in bytes[start..].chunks(BLOCK_SIZE).enumerate() {
+        block.data.copy_from_slice(chunk);
+        if !block.is_terminated() {
+            return Err(LsdjError::UnterminatedBlock { block: i });
+        }
+    }
+    Ok((bytes.len() - start) / BLOCK_SIZE)
+}
+
+/// A repair made by `repair_blocks()` to a slightly malformed block stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockRepair {
+    /// This many bytes of zeroes after the last block were dropped.
+    DroppedPadding(usize),
+    /// The end-of-file instruction missing from the last block was added.
+    AddedTerminator,
+}
+
+impl fmt::Display for BlockRepair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockRepair::DroppedPadding(len) => write!(f, "dropped {} bytes of zeroes padding the last block", len),
+            BlockRepair::AddedTerminator => write!(f, "added the end-of-file instruction missing from the last block"),
+        }
+    }
+}
+
+/// Repairs blocks of compressed song data as written by some older export
+/// tools, so that `read_blocks()` accepts them: zeroes padding the data past
+/// its last block are dropped, and an end-of-file instruction ($e0, $ff)
+/// missing from the last block is added if nothing but zeroes follows the
+/// point at which the blocks fill SRAM. Returns the repairs made, leaving
+/// anything it can't repair for `read_blocks()` to reject.
+pub fn repair_blocks(bytes: &mut Vec<u8>, compat: Compat) -> Vec<BlockRepair> {
+    let mut repairs = Vec::new();
+    let end = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1).next_multiple_of(BLOCK_SIZE);
+    if end > 0 && end < bytes.len() {
+        repairs.push(BlockRepair::DroppedPadding(bytes.len() - end));
+        bytes.truncate(end);
+    }
+    let last = bytes.len().saturating_sub(1) / BLOCK_SIZE * BLOCK_SIZE;
+    let mut block = LsdjBlock::empty();
+    let mut position = 0;
+    for chunk in bytes[..last].chunks(BLOCK_SIZE) {
+        block.data.copy_from_slice(chunk);
+        position += block.decompressed_len(position, compat);
+    }
+    let mut block = LsdjBlock::empty();
+    block.data[..(bytes.len() - last)].copy_from_slice(&bytes[last..]);
+    if !bytes.is_empty() && !block.is_terminated() && block.add_terminator(position, compat) {
+        bytes.truncate(last);
+        bytes.extend_from_slice(&block.data);
+        repairs.push(BlockRepair::AddedTerminator);
+    }
+    repairs
+}
+
+/// Decompresses blocks of compressed song data exported from a save file (see
+/// `LsdjSave::export_song()`) into an SRAM image.
+pub fn sram_from_blocks(bytes: &[u8], compat: Compat) -> Result<[u8; SRAM_SIZE], LsdjError> {
+    let mut sram = LsdjSram::with_compat(compat);
+    compression::decompress_sequence(bytes, &mut sram).map_err(|e| exported_block_error(bytes, e))?;
+    Ok(sram.data)
+}
+
+/// Describes where `e` was found in blocks exported from a save file.
+fn exported_block_error(bytes: &[u8], e: DecompressError) -> LsdjError {
+    let base = e.block * BLOCK_SIZE;
+    let data = &bytes[base..(base + BLOCK_SIZE).min(bytes.len())];
+    LsdjError::corrupt(e.reason, data, base, e.index, format!("block {} of {}", e.block + 1, bytes.len().div_ceil(BLOCK_SIZE)))
+}
+
+/// Decompresses blocks of compressed song data exported from a save file (see
+/// `LsdjSave::export_song()`) and reads them as a `Song`.
+pub fn song_from_blocks(bytes: &[u8], compat: Compat) -> Result<song::Song, LsdjError> {
+    Ok(song::Song::from(&sram_from_blocks(bytes, compat)?)?)
+}
+
+/// Compresses a decompressed SRAM image ($8000 bytes) into blocks of
+/// compressed song data, as `LsdjSave::export_song()` returns them. Returns an
+/// `Err` if `bytes` is not exactly the size of SRAM.
+pub fn blocks_from_sram(bytes: &[u8], compat: Compat) -> Result<Vec<u8>, &'static str> {
+    if bytes.len() != SRAM_SIZE {
+        return Err(err::BAD_FMT);
+    }
+    let mut sram = LsdjSram::with_compat(compat);
+    sram.data.copy_from_slice(bytes);
+    Ok(sram.compress_into(1..)?.bytes())
+}
+
+/// Checks that `sram` survives a round trip through the compressor, as its
+/// compatibility mode compresses it: compresses it into blocks and
+/// decompresses them, comparing the data. Returns `err::BAD_ROUND_TRIP` if it
+/// differs, or the error which stopped either step.
+pub fn verify_roundtrip(sram: &LsdjSram) -> Result<(), LsdjError> {
+    let blocks = blocks_from_sram(&sram.data, sram.compat)?;
+    if sram_from_blocks(&blocks, sram.compat)? != sram.data {
+        return Err(err::BAD_ROUND_TRIP.into());
+    }
+    Ok(())
+}
+
+/// Returns a fingerprint of blocks of compressed song data exported from a
+/// save file, matching `LsdjSave::song_hash()` for the song they came from.
+pub fn blocks_hash(bytes: &[u8]) -> Result<u64, LsdjError> {
+    let mut sram = LsdjSram::empty();
+    compression::decompress_sequence(bytes, &mut sram).map_err(|e| exported_block_error(bytes, e))?;
+    Ok(fnv1a(&sram.data))
 }
 
-/// Reads blocks of compressed song data into a `Vec<u8>`, returns either an
-/// `Err` or the number of blocks read.
-pub fn read_blocks_from_file(mut blockfile: &mut File, mut bytes: &mut Vec<u8>) -> io::Result<usize> {
-    let read_size = BLOCK_SIZE; // read a block ($200 bytes) at a time
-    let mut blocks_read = 0;
-    loop {
-        let nread = Read::by_ref(&mut blockfile).take(read_size as u64).read_to_end(&mut bytes)?;
-        blocks_read += 1;
-        if nread == 0 || nread < read_size { break; }
-    }
-    Ok(blocks_read)
+/// Computes the 64-bit FNV-1a hash of `bytes`.
+///
+/// Unlike `std::hash`, the output of this function is guaranteed to be the same
+/// across platforms and Rust versions, so it can be stored and compared later.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for byte in bytes.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
 }
 
 impl LsdjSram {
     /// Returns an `LsdjSram` with all fields initalized to zero.
     pub fn empty() -> LsdjSram {
-        LsdjSram { position: 0, data: [0; SRAM_SIZE] }
+        LsdjSram { data: [0; SRAM_SIZE], compat: Compat::Lsdj }
+    }
+
+    /// Returns an empty `LsdjSram` which compresses and decompresses with
+    /// `compat`.
+    pub fn with_compat(compat: Compat) -> LsdjSram {
+        LsdjSram { compat, ..LsdjSram::empty() }
     }
 
     /// Loads SRAM from the LSDj save file pointed to by `savefile`.
-    fn load(&mut self, savefile: &mut File) -> io::Result<()> {
+    #[cfg(feature = "std")]
+    fn load<R: Read + Seek>(&mut self, savefile: &mut R) -> std::io::Result<()> {
         savefile.seek(Start(0))?;
-        let mut handle = Read::by_ref(savefile).take(SRAM_SIZE as u64);
-        handle.read(&mut self.data)?;
-        Ok(())
+        savefile.read_exact(&mut self.data)
     }
 
     /// Creates a new `LsdjSram` by reading its data from `savefile`.
-    pub fn from(mut savefile: &mut File) -> io::Result<LsdjSram> {
+    #[cfg(feature = "std")]
+    pub fn from<R: Read + Seek>(savefile: &mut R) -> std::io::Result<LsdjSram> {
         let mut sram = LsdjSram::empty();
-        sram.load(&mut savefile)?;
+        sram.load(savefile)?;
         Ok(sram)
     }
 }
 
 /// Contains a representation of all parts of an LSDj save file (the SRAM, the metadata, and the
 /// blocks.)
+#[derive(Clone, PartialEq)]
 pub struct LsdjSave {
     sram: LsdjSram,
     pub metadata: LsdjMetadata,
-    blocks: LsdjBlockTable
+    blocks: LsdjBlockTable,
+    layout: LsdjLayout,
 }
 
 impl LsdjSave {
-    /// Creates an empty `LsdjSave` (all fields initialized with `::empty()`.)
-    #[allow(dead_code)]
+    /// Creates an empty 128KB `LsdjSave` (all fields initialized with `::empty()`.)
     pub fn empty() -> LsdjSave {
+        LsdjSave::empty_with_layout(LsdjLayout::SAVE_128KB)
+    }
+
+    /// Creates an empty `LsdjSave` with the given layout.
+    pub fn empty_with_layout(layout: LsdjLayout) -> LsdjSave {
         LsdjSave {
             sram: LsdjSram::empty(),
             metadata: LsdjMetadata::empty(),
-            blocks: LsdjBlockTable([LsdjBlock::empty(); BLOCK_COUNT])
+            blocks: LsdjBlockTable(vec![LsdjBlock::empty(); layout.block_count()]),
+            layout,
+        }
+    }
+
+    /// Creates a new `LsdjSave` from the bytes of a save file. The layout of
+    /// the save is detected from the number of bytes.
+    ///
+    /// Returns `LsdjError::TruncatedSave` if there are fewer bytes than the
+    /// 128KB layout needs and they don't make a 64KB save, or an `Err` if
+    /// there are too many to match any layout.
+    pub fn from_bytes(bytes: &[u8]) -> Result<LsdjSave, LsdjError> {
+        let layout = LsdjSave::layout_for_len(bytes.len() as u64)?;
+        LsdjSave::from_bytes_with_layout(bytes, layout)
+    }
+
+    /// Returns the layout of a save file `len` bytes long. A length short of
+    /// one of the standard layouts is taken to be a save of the smallest one
+    /// it could have been cut from, even if it ends on a block boundary.
+    fn layout_for_len(len: u64) -> Result<LsdjLayout, LsdjError> {
+        LsdjLayout::detect(len).map_err(|e| {
+            match [LsdjLayout::SAVE_64KB, LsdjLayout::SAVE_128KB].iter().find(|layout| len < layout.save_size() as u64) {
+                Some(layout) => LsdjError::TruncatedSave { expected: layout.save_size(), got: len as usize },
+                None => LsdjError::Invalid(e),
+            }
+        })
+    }
+
+    /// Creates a new `LsdjSave` from the bytes of a save file with the given
+    /// layout, for saves whose layout can't be told from their size, such as
+    /// those written by hacked ROMs which keep fewer blocks than fit in the
+    /// file. Any bytes after the last block of the layout are ignored.
+    ///
+    /// Returns `LsdjError::TruncatedSave` if there are fewer bytes than the
+    /// layout needs.
+    pub fn from_bytes_with_layout(bytes: &[u8], layout: LsdjLayout) -> Result<LsdjSave, LsdjError> {
+        let len = BLOCK_ADDRESS as usize + layout.block_count() * BLOCK_SIZE;
+        let bytes = bytes.get(..len).ok_or(LsdjError::TruncatedSave { expected: len, got: bytes.len() })?;
+        let (sram_bytes, rest) = bytes.split_at(SRAM_SIZE);
+        let (metadata_bytes, block_bytes) = rest.split_at(METADATA_LENGTH);
+        let mut sram = LsdjSram::empty();
+        sram.data.copy_from_slice(sram_bytes);
+        let metadata = LsdjMetadata::from_bytes(metadata_bytes.try_into().expect("split at METADATA_LENGTH"));
+        let blocks = LsdjBlockTable::from_bytes(block_bytes);
+        Ok(LsdjSave { sram, metadata, blocks, layout })
+    }
+
+    /// Creates a new `LsdjSave`, reading all data from `savefile`. The layout of
+    /// the save is detected from the length of the file.
+    ///
+    /// Returns `LsdjError::TruncatedSave` if the file is shorter than the
+    /// layout it was cut from (or if fewer bytes than its length can be
+    /// read), or an `Err` if it is too long to match any layout.
+    #[cfg(feature = "std")]
+    pub fn from<R: Read + Seek>(savefile: &mut R) -> Result<LsdjSave, LsdjError> {
+        let len = savefile.seek(End(0))?;
+        LsdjSave::check_len(len)?;
+        let mut bytes = Vec::with_capacity(len as usize);
+        savefile.seek(Start(0))?;
+        savefile.read_to_end(&mut bytes)?;
+        LsdjSave::from_bytes(&bytes)
+    }
+
+    /// Creates a new `LsdjSave` like `from()`, reading from `savefile`
+    /// without blocking, as a server handling uploaded saves would want.
+    #[cfg(feature = "tokio")]
+    pub async fn from_async<R: AsyncRead + AsyncSeek + Unpin>(savefile: &mut R) -> Result<LsdjSave, LsdjError> {
+        let len = savefile.seek(End(0)).await?;
+        LsdjSave::check_len(len)?;
+        let mut bytes = Vec::with_capacity(len as usize);
+        savefile.seek(Start(0)).await?;
+        savefile.read_to_end(&mut bytes).await?;
+        LsdjSave::from_bytes(&bytes)
+    }
+
+    /// Returns an `Err` without reading a save file `len` bytes long if it's
+    /// too long to match any layout, so that huge files (or readers claiming
+    /// to be huge) aren't read into memory only to be rejected.
+    #[cfg(feature = "std")]
+    fn check_len(len: u64) -> Result<(), LsdjError> {
+        if len > LsdjLayout::SAVE_128KB.save_size() as u64 {
+            return Err(LsdjError::Invalid(err::BAD_SAVE_SIZE));
+        }
+        Ok(())
+    }
+
+    /// Returns the layout of this save file.
+    pub fn layout(&self) -> LsdjLayout {
+        self.layout
+    }
+
+    /// Returns the blocks of compressed song data in this save file.
+    pub fn blocks(&self) -> &LsdjBlockTable {
+        &self.blocks
+    }
+
+    /// Returns the blocks of compressed song data in this save file mutably,
+    /// for allocating blocks directly. The allocation table in `metadata` must
+    /// be kept in step with any blocks replaced.
+    pub fn blocks_mut(&mut self) -> &mut LsdjBlockTable {
+        &mut self.blocks
+    }
+
+    /// Sets how songs in this save file are compressed and decompressed.
+    pub fn set_compat(&mut self, compat: Compat) {
+        self.sram.compat = compat;
+    }
+
+    /// Returns the mode in which songs in this save are compressed and
+    /// decompressed.
+    pub fn compat(&self) -> Compat {
+        self.sram.compat
+    }
+
+    /// Changes the layout of this save file, adding empty blocks to or removing
+    /// blocks from the end of the block table. Returns an `Err` (leaving the
+    /// save unchanged) if any block which would be removed is allocated.
+    pub fn set_layout(&mut self, layout: LsdjLayout) -> Result<(), &'static str> {
+        if self.metadata.alloc_table.iter().skip(layout.block_count()).any(|&b| b != 0xff) {
+            return Err(err::NO_ROOM);
         }
+        self.blocks.0.resize(layout.block_count(), LsdjBlock::empty());
+        self.layout = layout;
+        Ok(())
+    }
+
+    /// Compresses the SRAM contained in this instance into blocks to be
+    /// stored at the block numbers in `positions`, from which skip
+    /// instructions (`$e0 xx`) are calculated (see `LsdjSram::compress_into()`).
+    pub fn compress_sram_into<I>(&self, positions: I) -> Result<Vec<LsdjBlock>, &'static str>
+        where I: IntoIterator<Item = usize> {
+        self.sram.compress_into(positions)
+    }
+
+    /// Returns a handle on the song at the given index, or `None` if no song
+    /// exists there (see `SongSlot`).
+    pub fn slot(&self, song: u8) -> Option<SongSlot<'_>> {
+        SongSlot::new(self, song)
     }
 
-    /// Creates a new `LsdjSave`, reading all data from `savefile`.
-    pub fn from(mut savefile: &mut File) -> io::Result<LsdjSave> {
-        let sram     = LsdjSram::from(&mut savefile)?;
-        let metadata = LsdjMetadata::from(&mut savefile)?;
-        let blocks   = LsdjBlockTable::from(&mut savefile)?;
-        Ok(LsdjSave { sram: sram, metadata: metadata, blocks: blocks })
+    /// Turns this save into a snapshot which can be cheaply cloned and shared
+    /// between threads (see `LsdjSaveSnapshot`).
+    pub fn snapshot(self) -> LsdjSaveSnapshot {
+        LsdjSaveSnapshot::from(self)
     }
 
-    /// Compresses the SRAM contained in this instance, storing the compressed
-    /// blocks in a `Vec<LsdjBlock>`. `first_block` is the index from which
-    /// skip instructions (`$e0 xx`) are calculated.
-    pub fn compress_sram_into(&mut self, mut blocks: &mut Vec<LsdjBlock>, first_block: usize) -> Result<u8, &'static str> {
-        let block = self.sram.compress_into(&mut blocks, first_block)?;
-        Ok(block)
+    /// Returns the blocks allocated to `song`, in the order of the allocation
+    /// table.
+    fn song_blocks(&self, song: u8) -> impl Iterator<Item = &LsdjBlock> {
+        self.metadata.alloc_table.iter().enumerate()
+            .filter(move |&(_, &owner)| owner == song)
+            .map_while(move |(i, _)| {
+                debug!("exporting block {} of song {:02X}", i + 1, song);
+                self.blocks.get(i + 1) // blocks are one-indexed
+            })
     }
 
     /// Extracts the song at the given index to a `Vec<u8>`.
@@ -113,115 +591,522 @@ impl LsdjSave {
     /// # Notes
     ///
     /// Note that this function does not check whether there is actually a song
-    /// at index `song`, and thus may return a `Vec` of zeroes if given a
+    /// at index `song`, and thus may return an empty `Vec` if given a
     /// nonexistent song.
     pub fn export_song(&self, song: u8) -> Vec<u8> {
-        let num_blocks = self.metadata.size_of(song);
-        let mut bytes  = Vec::with_capacity(num_blocks * BLOCK_SIZE); // raw bytes from blocks
-        let mut blocks = Vec::with_capacity(num_blocks); // contains LsdjBlocks
-        for i in 0..blocks.capacity() {
-            let next_block = match self.metadata.next_block_for(song, i) {
-                Some(b) => b - 1, // blocks are one-indexed
-                None => break
-            };
-            blocks.push(self.blocks.0[next_block]);
+        let mut bytes = Vec::with_capacity(self.metadata.size_of(song) * BLOCK_SIZE);
+        for block in self.song_blocks(song) {
+            bytes.extend_from_slice(&block.data);
+        }
+        bytes
+    }
+
+    /// Writes the song at the given index to `w`, as `export_song()` returns
+    /// it, straight from the blocks it is stored in. Returns the number of
+    /// blocks written.
+    #[cfg(feature = "std")]
+    pub fn export_song_to<W: Write + ?Sized>(&self, song: u8, w: &mut W) -> std::io::Result<usize> {
+        let mut written = 0;
+        for block in self.song_blocks(song) {
+            w.write_all(&block.data)?;
+            written += 1;
         }
-        for block in blocks {
-            for byte in block.data.iter() {
-                bytes.push(*byte); // copy byte from blocks to bytes
+        Ok(written)
+    }
+
+    /// Decompresses the song at the given index into a full SRAM-sized image
+    /// ($8000 bytes), following the skip instructions in its blocks starting
+    /// from the first block allocated to it.
+    ///
+    /// Returns an `Err` if no blocks are allocated to `song` or if its blocks
+    /// are incorrectly formatted, giving the offset and region of the
+    /// instruction which couldn't be decompressed.
+    pub fn decompress_song(&self, song: u8) -> Result<[u8; SRAM_SIZE], LsdjError> {
+        let first_block = match self.metadata.next_block_for(song, 0) {
+            Some(b) => b,
+            None => return Err(err::NO_SONG.into()),
+        };
+        let mut sram = LsdjSram::with_compat(self.sram.compat);
+        let block = |i: usize| self.blocks.0.get(i).map(|b| &b.data[..]);
+        compression::decompress_chain(block, &mut sram, first_block - 1) // blocks are one-indexed
+            .map_err(|e| self.block_error(e))?;
+        Ok(sram.data)
+    }
+
+    /// Describes where `e` was found in this save file.
+    fn block_error(&self, e: DecompressError) -> LsdjError {
+        let base = BLOCK_ADDRESS as usize + e.block * BLOCK_SIZE;
+        let region = self.region(base).unwrap_or_default();
+        LsdjError::corrupt(e.reason, &self.blocks.0[e.block].data, base, e.index, region)
+    }
+
+    /// Follows the skip instructions in the blocks of the song at the given
+    /// index as `decompress_song()` does, breaking down how they decompress
+    /// (see `LsdjBlock::tally()`).
+    ///
+    /// Returns an `Err` if no blocks are allocated to `song` or its chain of
+    /// blocks is broken.
+    pub fn compression_stats(&self, song: u8) -> Result<CompressionStats, &'static str> {
+        let mut current = self.metadata.next_block_for(song, 0).ok_or(err::NO_SONG)?;
+        let mut stats = CompressionStats::default();
+        let mut position = 0;
+        for _ in 0..self.blocks.len() {
+            let block = self.blocks.get(current).ok_or(err::BAD_FMT)?;
+            position = block.tally(position, self.sram.compat, &mut stats);
+            match block.next_block(self.sram.compat) {
+                Some(0) => return Ok(stats),
+                Some(next) => current = next as usize,
+                None => break,
             }
         }
-        bytes
+        Err(err::BAD_FMT)
+    }
+
+    /// Decompresses the song at the given index and reads it as a `Song`.
+    pub fn song(&self, song: u8) -> Result<song::Song, LsdjError> {
+        Ok(song::Song::from(&self.decompress_song(song)?)?)
+    }
+
+    /// Returns a fingerprint of the song at the given index, computed from its
+    /// decompressed data.
+    ///
+    /// The title and version byte are not part of the song data, so two copies
+    /// of the same song hash identically regardless of what they are named,
+    /// as do songs whose blocks compress differently but decompress the same.
+    pub fn song_hash(&self, song: u8) -> Result<u64, LsdjError> {
+        let sram = self.decompress_song(song)?;
+        Ok(fnv1a(&sram))
+    }
+
+    /// Returns a summary of each song in the save file. Every song is
+    /// decompressed, to find its format version and the kits it plays.
+    pub fn song_info(&self) -> Vec<SongInfo> {
+        self.metadata.songs().into_iter().map(|s| {
+            let song = self.song(s).ok();
+            SongInfo {
+                index: s,
+                title: self.metadata.song_title(s),
+                version: self.metadata.version_table[s as usize],
+                blocks: self.metadata.size_of(s),
+                format_version: song.as_ref().map(|song| song.format_version()),
+                kits: song.map(|song| song.kits().into_iter().collect()),
+            }
+        }).collect()
+    }
+
+    /// Returns a `std::String` containing an aligned table of all songs in the
+    /// save file, listing each song's index, title, version byte, format
+    /// version, the number of blocks it uses, the percentage of the save's
+    /// blocks that makes up, and the kits it plays (the format version and kits
+    /// are shown as `?` if it couldn't be decompressed), followed by the
+    /// number of free blocks. If `color` is true, the header is bolded and each
+    /// song's title is colored to match `LsdjMetadata::block_map()`.
+    pub fn song_table(&self, color: bool) -> String {
+        let block_count = self.layout.block_count();
+        let percent = |blocks: usize| if block_count == 0 { 0.0 } else { blocks as f64 * 100.0 / block_count as f64 };
+        let header = "IDX  TITLE     VER  FMT  BLOCKS   SAVE  KITS";
+        let mut out = String::new();
+        if color {
+            out.push_str(format!("\x1b[1m{}\x1b[0m\n", header).as_str());
+        } else {
+            out.push_str(header);
+            out.push('\n');
+        }
+        for info in self.song_info() {
+            let title = format!("{:<8}", info.title);
+            let title = if color {
+                format!("\x1b[{}m{}\x1b[0m", metadata::song_color(info.index), title)
+            } else {
+                title
+            };
+            let kits = match &info.kits {
+                Some(kits) if kits.is_empty() => "-".to_string(),
+                Some(kits) => kits.iter().map(|k| format!("{:02X}", k)).collect::<Vec<String>>().join(" "),
+                None => "?".to_string(),
+            };
+            let format_version = match info.format_version {
+                Some(v) => format!("{:02X}", v),
+                None => "?".to_string(),
+            };
+            out.push_str(format!("{:02X}   {}  {:>3X}  {:>3}  {:>6}  {:>5.1}%  {}\n", info.index, title, info.version,
+                                 format_version, info.blocks, percent(info.blocks), kits).as_str());
+        }
+        let free = block_count.saturating_sub(self.metadata.blocks_used());
+        out.push_str(format!("free: {} blocks ({:.1}%)\n", free, percent(free)).as_str());
+        out
     }
 
     /// Adds a new song to the save file, reading from a slice of `u8`s and
     /// giving it the title specified by `title`. This function adds the song
     /// at the next available index (next unused song), or returns an `Err` if
-    /// all songs are taken or there are not enough bytes left in the save file
-    /// to store the blocks of song data.
+    /// all songs are taken, there are not enough bytes left in the save file
+    /// to store the blocks of song data, or a block doesn't end with a skip or
+    /// end-of-file instruction.
+    ///
+    /// The song's version byte starts at 0, as it does for a song LSDj saves
+    /// for the first time, rather than whatever the slot last held. Its blocks
+    /// are the lowest-numbered free ones, in order, so importing the same
+    /// song into the same save file always gives byte-identical results.
     pub fn import_song(&mut self, bytes: &[u8], title: LsdjTitle) -> Result<u8, &'static str> {
         let song = match self.metadata.next_available_song() {
             Some(s) => s,
             None => return Err(err::SONGS_FULL)
         };
-        if bytes.len() % BLOCK_SIZE != 0 {
+        self.import_song_at(bytes, title, song)
+    }
+
+    /// Imports blocks of compressed song data as `song`, which must have no
+    /// blocks allocated to it (see `import_song()`).
+    fn import_song_at(&mut self, bytes: &[u8], title: LsdjTitle, song: u8) -> Result<u8, &'static str> {
+        if !bytes.len().is_multiple_of(BLOCK_SIZE) {
             return Err(err::BAD_FMT); // make sure correct number of bytes are passed in
         }
-        let num_blocks  = bytes.len() / BLOCK_SIZE;
-        let free_blocks = BLOCK_COUNT - self.metadata.blocks_used();
-        if num_blocks > free_blocks {
+        let num_blocks = bytes.len() / BLOCK_SIZE;
+        let positions: Vec<usize> = (1..=self.layout.block_count())
+            .filter(|&b| !self.metadata.is_allocated(b))
+            .take(num_blocks)
+            .collect();
+        if positions.len() < num_blocks {
             return Err(err::NO_BLOCKS);
         }
+        debug!("importing {} blocks as song {:02X}", num_blocks, song);
+        // every block is checked, and pointed at the block after it, before
+        // anything in the save is changed
         let mut blocks_vec = Vec::with_capacity(num_blocks);
-        for i in 0..blocks_vec.capacity() {
-            let start = i * BLOCK_SIZE; // index to begin copying bytes from
-            let end   = start + BLOCK_SIZE; // where to stop fetching blocks
-            let mut bytes_array = [0; BLOCK_SIZE];
-            for (index, byte) in bytes[start..end].iter().enumerate() {
-                bytes_array[index] = *byte;
-            } // copy bytes from slice into array to allow using in an LsdjBlock
-            blocks_vec.push(LsdjBlock {
+        for (i, chunk) in bytes.chunks_exact(BLOCK_SIZE).enumerate() {
+            let mut block = LsdjBlock {
                 position: 0,
-                data: bytes_array
-            });
-        }
-        let mut block_positions = Vec::with_capacity(num_blocks);
-        for _block in blocks_vec.iter() {
-            if let Some(next_block) = self.metadata.next_empty_block() {
-                self.metadata.reserve(next_block, song)?;
-                block_positions.push(next_block); // keep track of reserved blocks so that we know where to insert song data
+                data: chunk.try_into().expect("chunks are BLOCK_SIZE long"),
+            };
+            if !block.is_terminated() {
+                return Err(err::BAD_FMT); // every block must end with a skip or end-of-file instruction
+            }
+            if let Some(&next_pos) = positions.get(i + 1) {
+                block.skip_to_block(next_pos, self.sram.compat)?; // every block but the last must skip to the next
             }
+            blocks_vec.push(block);
         }
-        let mut positions_iter = block_positions.iter().peekable();
-        let mut blocks_iter    = blocks_vec.iter_mut().enumerate();
-        while let (Some(pos), Some((num_copied, block))) =
-                  (positions_iter.next(), blocks_iter.next()) {
-            if num_copied < num_blocks - 1 {
-                let next_pos = match positions_iter.peek() {
-                    Some(&&n) => n, // peek into next block index to find value of skip instruction
-                    None => return Err(err::WTF),
-                };
-                block.skip_to_block(next_pos)?; // modifies the block so that the index of the next block is sorrect
-            } // modify every block except the last
-            self.blocks.0[*pos - 1] = *block; // insert block into the correct position in block array
+        for (&pos, block) in positions.iter().zip(blocks_vec) {
+            self.metadata.reserve(pos, song)?;
+            self.blocks.replace(pos, block)?; // insert block into the correct position in block array
         }
         self.metadata.title(song, title); // set title
+        self.metadata.version_table[song as usize] = 0;
         Ok(song)
     }
 
-    /// Returns all bytes in this save file as a `Vec<u8>`.
-    pub fn bytes(&self) -> Vec<u8> {
-        let mut out = Vec::with_capacity(SAVE_SIZE);
-        for b in self.sram.data.iter() {
-            out.push(*b);
-        }
-        for b in self.metadata.bytes().iter() {
-            out.push(*b);
+    /// Reports whether blocks of compressed song data (as exported by
+    /// `export_song()`) would fit into this save file if imported, without
+    /// changing anything.
+    pub fn can_fit(&self, bytes: &[u8]) -> Fit {
+        Fit {
+            needed: bytes.len().div_ceil(BLOCK_SIZE),
+            free: self.layout.block_count().saturating_sub(self.metadata.blocks_used()),
+            slot_free: self.metadata.next_available_song().is_some(),
         }
-        for block in self.blocks.0.iter() {
-            for b in block.data.iter() {
-                out.push(*b);
+    }
+
+    /// Estimates whether blocks of compressed song data would fit into this
+    /// save file (see `can_fit()`) once the chains, phrases, instruments, and
+    /// tables which go unplayed (see `clean::clean()`) are freed in the song
+    /// and in every song already in the save file, without changing anything.
+    /// Returns an `Err` if any of the songs can't be decompressed.
+    pub fn can_fit_cleaned(&self, bytes: &[u8]) -> Result<Fit, LsdjError> {
+        let mut cleaned = self.clone();
+        for s in cleaned.metadata.songs() {
+            let mut song = cleaned.song(s)?;
+            if !clean::clean(&mut song).is_empty() {
+                cleaned.replace_song(s, &song.data)?;
             }
         }
+        let mut song = song_from_blocks(bytes, self.sram.compat)?;
+        clean::clean(&mut song);
+        Ok(cleaned.can_fit(&blocks_from_sram(&song.data, self.sram.compat)?))
+    }
+
+    /// Adds a new song to the save file from a decompressed SRAM image
+    /// ($8000 bytes), compressing it into blocks before importing it with
+    /// `import_song()`. Returns an `Err` if `bytes` is not exactly the size of
+    /// SRAM, or under the same conditions as `import_song()`.
+    pub fn import_decompressed_song(&mut self, bytes: &[u8], title: LsdjTitle) -> Result<u8, &'static str> {
+        let blocks = blocks_from_sram(bytes, self.sram.compat)?;
+        self.import_song(&blocks, title)
+    }
+
+    /// Replaces the contents of the song at the given index with a decompressed
+    /// SRAM image ($8000 bytes), keeping its index and title. Its version byte
+    /// is incremented (wrapping around from $ff), as LSDj does whenever it
+    /// saves a song. Returns an `Err` (leaving the save unchanged) if no song
+    /// exists at that index, `bytes` is not exactly the size of SRAM, or there
+    /// are not enough free blocks to hold the new contents.
+    pub fn replace_song(&mut self, song: u8, bytes: &[u8]) -> Result<(), &'static str> {
+        if self.metadata.size_of(song) == 0 {
+            return Err(err::NO_SONG);
+        }
+        let blocks = blocks_from_sram(bytes, self.sram.compat)?;
+        let metadata = self.metadata.clone();
+        self.metadata.free(song);
+        match self.import_song_at(&blocks, metadata.title_table[song as usize], song) {
+            Ok(_) => {
+                self.metadata.version_table[song as usize] = metadata.version_table[song as usize].wrapping_add(1);
+                Ok(())
+            },
+            Err(e) => {
+                self.metadata = metadata;
+                Err(e)
+            },
+        }
+    }
+
+    /// Recompresses the song at the given index from a decompressed SRAM image
+    /// ($8000 bytes), such as its own data or a cleaned-up copy of it, keeping
+    /// its index, title, and version, but only if that takes fewer blocks.
+    /// Returns the number of blocks reclaimed.
+    ///
+    /// Returns an `Err` (leaving the save unchanged) if no song exists at that
+    /// index, `bytes` is not exactly the size of SRAM, or the recompressed
+    /// song doesn't decompress to `bytes`.
+    pub fn recompress_song(&mut self, song: u8, bytes: &[u8]) -> Result<usize, LsdjError> {
+        if self.metadata.size_of(song) == 0 {
+            return Err(err::NO_SONG.into());
+        }
+        let blocks = blocks_from_sram(bytes, self.sram.compat)?;
+        self.recompress_song_from(song, bytes, &blocks)
+    }
+
+    /// Recompresses the song at the given index as `recompress_song()` does,
+    /// with `bytes` already compressed into `blocks` by `blocks_from_sram()`
+    /// (in the mode returned by `compat()`), so that several songs can be
+    /// compressed at once before being put back one at a time.
+    pub fn recompress_song_from(&mut self, song: u8, bytes: &[u8], blocks: &[u8]) -> Result<usize, LsdjError> {
+        let before = self.metadata.size_of(song);
+        if before == 0 {
+            return Err(err::NO_SONG.into());
+        }
+        if blocks.len() / BLOCK_SIZE >= before {
+            return Ok(0);
+        }
+        let (metadata, table) = (self.metadata.clone(), self.blocks.clone());
+        self.metadata.free(song);
+        let imported = self.import_song_at(blocks, metadata.title_table[song as usize], song);
+        self.metadata.version_table[song as usize] = metadata.version_table[song as usize];
+        let result = match imported.map_err(LsdjError::from).and_then(|_| self.decompress_song(song)) {
+            Ok(sram) if sram[..] == *bytes => return Ok(before - self.metadata.size_of(song)),
+            Ok(_) => Err(err::BAD_ROUND_TRIP.into()),
+            Err(e) => Err(e),
+        };
+        self.metadata = metadata;
+        self.blocks = table;
+        result
+    }
+
+    /// Checks that the song at the given index survives a round trip through
+    /// the compressor: decompresses it, recompresses it, and decompresses the
+    /// result, comparing the data. Returns the number of blocks the
+    /// recompressed song takes, or `err::BAD_ROUND_TRIP` if the data differs.
+    pub fn round_trip(&self, song: u8) -> Result<usize, LsdjError> {
+        let sram = self.decompress_song(song)?;
+        let blocks = blocks_from_sram(&sram, self.sram.compat)?;
+        if sram_from_blocks(&blocks, self.sram.compat)? != sram {
+            return Err(err::BAD_ROUND_TRIP.into());
+        }
+        Ok(blocks.len() / BLOCK_SIZE)
+    }
+
+    /// Deletes the song at the given index, freeing its blocks and clearing its
+    /// title and version. Returns an `Err` if no song exists at that index.
+    pub fn delete_song(&mut self, song: u8) -> Result<(), &'static str> {
+        if self.metadata.size_of(song) == 0 {
+            return Err(err::NO_SONG);
+        }
+        self.metadata.free(song);
+        Ok(())
+    }
+
+    /// Cross-checks the skip instructions chaining each song's blocks against
+    /// the allocation table (see `audit::audit()`), returning the problems
+    /// found.
+    pub fn audit_blocks(&self) -> Vec<audit::BlockProblem> {
+        audit::audit(&self.metadata, &self.blocks, self.sram.compat)
+    }
+
+    /// Repairs the chain of skip instructions of the song at the given index
+    /// from the order of its blocks in the allocation table (see
+    /// `audit::repair_chain()`), salvaging a song whose skip instructions are
+    /// broken. Returns the number of blocks freed past the end of the song.
+    ///
+    /// Returns an `Err` (leaving the save unchanged) if no song exists at that
+    /// index or it still can't be decompressed once repaired.
+    pub fn repair_chain(&mut self, song: u8) -> Result<usize, LsdjError> {
+        if self.metadata.size_of(song) == 0 {
+            return Err(err::NO_SONG.into());
+        }
+        let (metadata, blocks) = (self.metadata.clone(), self.blocks.clone());
+        let freed = audit::repair_chain(&mut self.metadata, &mut self.blocks, song, self.sram.compat);
+        if let Err(e) = self.decompress_song(song) {
+            self.metadata = metadata;
+            self.blocks = blocks;
+            return Err(e);
+        }
+        Ok(freed)
+    }
+
+    /// Scans every block for complete songs, ignoring the metadata (see
+    /// `recover::find_chains()`), salvaging songs from a save whose title or
+    /// allocation tables are lost or corrupted. Returns the first block of
+    /// each song found, along with its blocks as `export_song()` returns them.
+    pub fn recover_songs(&self) -> Vec<(usize, Vec<u8>)> {
+        recover::find_chains(&self.blocks, self.sram.compat).into_iter().map(|chain| {
+            let bytes = chain.iter().filter_map(|&b| self.blocks.get(b)).flat_map(|b| b.data).collect();
+            (chain[0], bytes)
+        }).collect()
+    }
+
+    /// Finds songs which were deleted but whose blocks haven't been
+    /// overwritten since (see `recover::deleted_chains()`), returning the
+    /// blocks of each, in order.
+    pub fn deleted_songs(&self) -> Vec<Vec<usize>> {
+        recover::deleted_chains(&self.metadata, &self.blocks, self.sram.compat)
+    }
+
+    /// Restores the deleted song starting at block `first` (see
+    /// `deleted_songs()`) into the first free song slot with the given title,
+    /// allocating its blocks to it where they are. Returns the song's new
+    /// index.
+    ///
+    /// A song whose blocks LSDj would load in the wrong order, as its first
+    /// block isn't its lowest, is imported into free blocks instead (see
+    /// `import_song()`).
+    ///
+    /// Returns an `Err` if no deleted song starts at block `first`, or if the
+    /// song slots are full.
+    pub fn undelete_song(&mut self, first: usize, title: LsdjTitle) -> Result<u8, &'static str> {
+        let chain = self.deleted_songs().into_iter().find(|c| c[0] == first).ok_or(err::NO_DELETED)?;
+        if chain.iter().min() != Some(&first) {
+            let bytes: Vec<u8> = chain.iter().filter_map(|&b| self.blocks.get(b)).flat_map(|b| b.data).collect();
+            return self.import_song(&bytes, title);
+        }
+        let song = self.metadata.next_available_song().ok_or(err::SONGS_FULL)?;
+        for block in chain {
+            self.metadata.alloc_table[block - 1] = song;
+        }
+        self.metadata.title_table[song as usize] = title;
+        Ok(song)
+    }
+
+    /// Checks that the working song in SRAM is intact: that the SRAM
+    /// initialization check bytes are 'jk', and that SRAM holds the check
+    /// bytes LSDj writes into every song. Returns an `Err` describing the
+    /// first check to fail, as after LSDj crashes or loses power mid-save.
+    pub fn check_sram(&self) -> Result<(), &'static str> {
+        if !self.metadata.check_sram_init() {
+            return Err(err::NO_SRAM_INIT);
+        }
+        song::Song::from(&self.sram.data).map(|_| ())
+    }
+
+    /// Returns a description of the byte at `offset` in this save file: the
+    /// region of the working song or metadata holding it, or the block holding
+    /// it and the song that block is allocated to. Returns `None` if `offset`
+    /// is past the end of the save file.
+    pub fn region(&self, offset: usize) -> Option<String> {
+        if offset < SRAM_SIZE {
+            return Some(format!("working song: {}", song::region(offset)));
+        }
+        if let Some(region) = self.metadata.region(offset as u64) {
+            return Some(region);
+        }
+        self.layout.block_at(offset).map(|block| self.metadata.block_region(block))
+    }
+
+    /// Returns the bytes of the block region (from $8200 to the end of the
+    /// save file), without SRAM or metadata.
+    pub fn block_region(&self) -> Vec<u8> {
+        self.blocks.iter().flat_map(|(_, block)| block.data).collect()
+    }
+
+    /// Overwrites the block region with `bytes` (as returned by
+    /// `block_region()`), leaving SRAM and metadata untouched. Returns an
+    /// `Err` if `bytes` isn't exactly as long as the block region.
+    ///
+    /// The allocation table isn't updated, so it only describes the restored
+    /// blocks if they came from a save with the same allocation table.
+    pub fn restore_block_region(&mut self, bytes: &[u8]) -> Result<(), &'static str> {
+        if bytes.len() != self.layout.block_count() * BLOCK_SIZE {
+            return Err(err::BAD_BLOCK_REGION);
+        }
+        for (block, data) in self.blocks.0.iter_mut().zip(bytes.chunks_exact(BLOCK_SIZE)) {
+            block.data.copy_from_slice(data);
+        }
+        Ok(())
+    }
+
+    /// Returns all bytes in this save file as a `Vec<u8>`.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.layout.save_size());
+        out.extend_from_slice(&self.sram.data);
+        out.extend_from_slice(&self.metadata.bytes());
+        for (_, block) in self.blocks.iter() {
+            out.extend_from_slice(&block.data);
+        }
         out
     }
 }
 
-struct LsdjBlockTable([LsdjBlock; BLOCK_COUNT]); // must be wrapped in a struct to allow implementation
+/// The blocks of compressed song data in a save file, numbered from 1 as in
+/// the allocation table and skip instructions.
+#[derive(Clone, PartialEq)]
+pub struct LsdjBlockTable(Vec<LsdjBlock>); // must be wrapped in a struct to allow implementation
 
 impl LsdjBlockTable {
-    fn fill(&mut self, savefile: &mut File) -> io::Result<()> {
-        savefile.seek(Start(BLOCK_ADDRESS))?;
-        for block in self.0.iter_mut() {
-            savefile.take(BLOCK_SIZE as u64).read(&mut block.data)?;
-        }
-        Ok(())
+    /// Returns the number of blocks in the table.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns true if the table holds no blocks.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 
-    fn from(mut savefile: &mut File) -> io::Result<LsdjBlockTable> {
-        let mut table = LsdjBlockTable([LsdjBlock::empty(); BLOCK_COUNT]);
-        table.fill(&mut savefile)?;
-        Ok(table)
+    /// Iterates over the blocks in the table along with their (one-indexed)
+    /// block numbers.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &LsdjBlock)> {
+        self.0.iter().enumerate().map(|(i, block)| (i + 1, block))
+    }
+
+    /// Returns the block with the given (one-indexed) number, or `None` if
+    /// there is no such block.
+    pub fn get(&self, block: usize) -> Option<&LsdjBlock> {
+        self.0.get(block.checked_sub(1)?)
+    }
+
+    /// Returns the block with the given (one-indexed) number mutably, or
+    /// `None` if there is no such block.
+    pub fn get_mut(&mut self, block: usize) -> Option<&mut LsdjBlock> {
+        self.0.get_mut(block.checked_sub(1)?)
+    }
+
+    /// Replaces the block with the given (one-indexed) number, setting the
+    /// new block's `position` to that number, and returns the block it
+    /// replaced. Returns an `Err` if there is no such block.
+    ///
+    /// The allocation table is left unchanged, so callers allocating blocks
+    /// themselves must keep it in step (see `LsdjMetadata::reserve()`).
+    pub fn replace(&mut self, block: usize, mut new: LsdjBlock) -> Result<LsdjBlock, &'static str> {
+        let old = self.0.get_mut(block.wrapping_sub(1)).ok_or(err::BAD_BLOCK)?;
+        new.position = block;
+        Ok(core::mem::replace(old, new))
+    }
+
+    /// Returns a table of the whole blocks in `bytes`, which start at the
+    /// first block ($8200).
+    fn from_bytes(bytes: &[u8]) -> LsdjBlockTable {
+        LsdjBlockTable(bytes.chunks_exact(BLOCK_SIZE).map(|data| {
+            let mut block = LsdjBlock::empty();
+            block.data.copy_from_slice(data);
+            block
+        }).collect())
     }
 }
 
@@ -231,13 +1116,13 @@ impl fmt::Debug for LsdjSram {
         for i in 0..0x10 {
             write!(f, "{:X} | ", i)?;
         }
-        write!(f, "\n")?;
+        writeln!(f)?;
         for disp in 0..(SRAM_SIZE / 0x10) {
             write!(f, "{:04X}  | ", disp * 0x10)?;
             for offset in 0..0x10 {
                 write!(f, "{:02X}| ", self.data[disp * 0x10 + offset])?;
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
         Ok(())
     }
@@ -247,9 +1132,9 @@ impl fmt::Debug for LsdjSave {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "SRAM: {:?}", self.sram)?;
         write!(f, "metadata: {:?}", self.metadata)?;
-        write!(f, "blocks:\n")?;
-        for (i, block) in self.blocks.0.iter().enumerate() {
-            write!(f, "block {:X}: {:?}", i + 1, block)?;
+        writeln!(f, "blocks:")?;
+        for (i, block) in self.blocks.iter() {
+            write!(f, "block {:X}: {:?}", i, block)?;
         }
         Ok(())
     }
@@ -257,42 +1142,218 @@ impl fmt::Debug for LsdjSave {
 
 impl PartialEq for LsdjSram {
     fn eq(&self, rhs: &Self) -> bool {
-        self.data.iter().zip(rhs.data.iter()).all(|(a, b)| a == b)
+        self.data == rhs.data
+    }
+}
+
+impl Default for LsdjSram {
+    fn default() -> LsdjSram {
+        LsdjSram::empty()
+    }
+}
+
+impl Default for LsdjSave {
+    fn default() -> LsdjSave {
+        LsdjSave::empty()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::io;
-    use std::fs::File;
-    use std::path::PathBuf;
+
+    use proptest::prelude::*;
 
     use super::*;
 
     #[test]
     fn test_lsdjsave_load() -> io::Result<()> {
-        let savepath = PathBuf::from("saves/test.sav");
-        let mut savefile = File::open(savepath)?;
-        let save = LsdjSave::from(&mut savefile)?;
+        let bytes = generate::test_save().bytes();
+        let save = LsdjSave::from(&mut io::Cursor::new(bytes))?;
         println!("{:?}", save);
         Ok(())
     }
 
     #[test]
     fn print_export_song() -> io::Result<()> {
-        let savepath = PathBuf::from("saves/test.sav");
-        let mut savefile = File::open(savepath)?;
-        let save = LsdjSave::from(&mut savefile)?;
+        let save = generate::test_save();
         let bytes = save.export_song(0);
         println!("{:02X?}", bytes);
         Ok(())
     }
 
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_from_async() -> io::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let save = runtime.block_on(async {
+            LsdjSave::from_async(&mut io::Cursor::new(generate::test_save().bytes())).await
+        })?;
+        assert_eq!(save, generate::test_save());
+
+        let truncated = runtime.block_on(LsdjSave::from_async(&mut io::Cursor::new(vec![0; 0x8100])));
+        assert!(matches!(truncated, Err(LsdjError::TruncatedSave { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_sram() -> io::Result<()> {
+        let mut save = generate::test_save();
+        assert_eq!(save.check_sram(), Ok(()));
+        save.sram.data[0x3e80] ^= 0xff; // clobber a song check byte
+        assert_eq!(save.check_sram(), Err(err::BAD_SONG));
+        save.metadata.sram_init_chk = [0; 2];
+        assert_eq!(save.check_sram(), Err(err::NO_SRAM_INIT));
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> io::Result<()> {
+        let save = generate::test_save();
+        assert_eq!(save.round_trip(0).ok(), Some(save.metadata.size_of(0)));
+        assert!(matches!(save.round_trip(1), Err(LsdjError::Invalid(e)) if e == err::NO_SONG));
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_region() -> io::Result<()> {
+        let save = generate::test_save();
+        let region = save.block_region();
+        assert_eq!(region.len(), save.layout().block_count() * BLOCK_SIZE);
+        assert_eq!(region[..], save.bytes()[(BLOCK_ADDRESS as usize)..]);
+
+        let mut fresh = LsdjSave::empty();
+        fresh.restore_block_region(&region).unwrap();
+        assert_eq!(fresh.block_region(), region);
+        assert_eq!(fresh.metadata, LsdjSave::empty().metadata);
+        assert_eq!(fresh.restore_block_region(&region[BLOCK_SIZE..]), Err(err::BAD_BLOCK_REGION));
+        Ok(())
+    }
+
+    #[test]
+    fn test_region() -> io::Result<()> {
+        let save = generate::test_save();
+        assert_eq!(save.region(0x7fff).unwrap(), "working song: format version");
+        assert_eq!(save.region(0x8009).unwrap(), "title of song 01");
+        assert_eq!(save.region(0x8000).unwrap(), "title of song 00 (TEST)");
+        assert_eq!(save.region(0x813f).unwrap(), "SRAM initialization check bytes ('jk')");
+        assert_eq!(save.region(0x8141).unwrap(), "allocation table entry of block 01");
+        assert_eq!(save.region(0x8200).unwrap(), "block 01 (song 00, TEST)");
+        assert_eq!(save.region(0x1ffff).unwrap(), "block BF (free)");
+        assert_eq!(save.region(0x20000), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_can_fit() -> io::Result<()> {
+        let mut save = LsdjSave::empty_with_layout(LsdjLayout::new(8).unwrap());
+        let mut sram = LsdjSram::empty();
+        for (i, byte) in sram.data.iter_mut().enumerate() {
+            *byte = (i % 0x61) as u8;
+        }
+        let blocks = sram.compress_into(1..).unwrap().bytes();
+        let fit = save.can_fit(&blocks);
+        assert_eq!(fit, Fit { needed: blocks.len() / BLOCK_SIZE, free: 8, slot_free: true });
+        assert!(!fit.fits());
+        assert_eq!(fit.to_string(), format!("needs {} block(s), 8 free (doesn't fit)", fit.needed));
+        assert!(save.can_fit(&blocks[..BLOCK_SIZE * 8]).fits());
+
+        save.import_decompressed_song(&[0; SRAM_SIZE], [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(save.can_fit(&[0; BLOCK_SIZE]).free, 7);
+        let mut full = LsdjSave::empty();
+        for s in 0..metadata::SONG_SLOTS {
+            full.metadata.reserve(s + 1, s as u8).unwrap(); // every slot owns a block
+        }
+        assert!(!full.can_fit(&[0; BLOCK_SIZE]).slot_free);
+
+        let save = generate::test_save();
+        let blocks = save.export_song(0);
+        let (fit, cleaned) = (save.can_fit(&blocks), save.can_fit_cleaned(&blocks).unwrap());
+        assert!(cleaned.needed <= fit.needed && cleaned.free >= fit.free);
+        assert!(matches!(save.can_fit_cleaned(&[0; BLOCK_SIZE]), Err(LsdjError::Corrupt { reason, .. }) if reason == err::BAD_FMT));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression_stats() -> io::Result<()> {
+        let save = generate::test_save();
+        let stats = save.compression_stats(0).unwrap();
+        assert_eq!(stats.blocks, save.metadata.size_of(0));
+        assert_eq!(stats.decompressed(), SRAM_SIZE);
+        assert!(stats.compressed <= stats.blocks * BLOCK_SIZE);
+        assert_eq!(save.compression_stats(1), Err(err::NO_SONG));
+        Ok(())
+    }
+
     #[test]
     fn test_export_song() {
         let save = LsdjSave::empty();
         let bytes = save.export_song(0);
-        assert_eq!(bytes, vec![]); // should be empty, as song 0 does not exist
+        assert_eq!(bytes, Vec::<u8>::new()); // should be empty, as song 0 does not exist
+    }
+
+    #[test]
+    fn test_export_song_to() -> io::Result<()> {
+        let save = generate::test_save();
+        let mut out = Vec::new();
+        assert_eq!(save.export_song_to(0, &mut out)?, 6);
+        assert_eq!(out, save.export_song(0));
+        assert_eq!(save.export_song_to(1, &mut out)?, 0);
+        assert_eq!(out.len(), 6 * BLOCK_SIZE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_song() {
+        let save = LsdjSave::empty();
+        assert!(matches!(save.decompress_song(0), Err(LsdjError::Invalid(e)) if e == err::NO_SONG));
+        let mut sram = LsdjSram::empty();
+        sram.data[0x10] = 0x41;
+        sram.data[0x7fff] = 0xc0;
+        let blocks = sram.compress_into(1..).unwrap();
+        let mut save = LsdjSave::empty();
+        let title = [b'T', b'E', b'S', b'T', 0, 0, 0, 0];
+        assert_eq!(save.import_song(&blocks.bytes(), title), Ok(0));
+        let decompressed = save.decompress_song(0).unwrap();
+        assert_eq!(&decompressed[..], &sram.data[..]);
+
+        let block = save.blocks_mut().get_mut(1).unwrap();
+        block.data = [0x01; BLOCK_SIZE];
+        block.data[BLOCK_SIZE - 1] = 0xe0; // an instruction cut off by the end of the block
+        let e = save.decompress_song(0).unwrap_err();
+        assert!(matches!(e, LsdjError::Corrupt { offset: 0x83ff, at: 8, .. }));
+        assert_eq!(e.to_string(), "blocks are incorrectly formatted! (at 0x83ff, in block 01 (song 00, TEST): \
+                                   01 01 01 01 01 01 01 01 [e0])");
+        let e = sram_from_blocks(&[0x01; BLOCK_SIZE], Compat::Lsdj).unwrap_err();
+        assert_eq!(e.to_string(), "blocks are incorrectly formatted! (at 0x200, in block 1 of 1: \
+                                   01 01 01 01 01 01 01 01 [])");
+
+        let block = save.blocks_mut().get_mut(1).unwrap();
+        block.data[..2].copy_from_slice(&[0xe0, 0x01]); // skips back to itself, forever
+        let e = save.decompress_song(0).unwrap_err();
+        assert!(matches!(e, LsdjError::Corrupt { reason, offset: 0x8200, .. } if reason == err::BLOCK_LOOP));
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(&[]), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn test_song_hash() {
+        let mut save = LsdjSave::empty();
+        assert!(matches!(save.song_hash(0), Err(LsdjError::Invalid(e)) if e == err::NO_SONG));
+        let mut sram = [0; SRAM_SIZE];
+        sram[0x100] = 0x42;
+        save.import_decompressed_song(&sram, [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        save.import_decompressed_song(&sram, [b'B', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        sram[0x100] = 0x43;
+        save.import_decompressed_song(&sram, [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(save.song_hash(0).ok(), save.song_hash(1).ok()); // titles are ignored
+        assert_ne!(save.song_hash(0).ok(), save.song_hash(2).ok());
+        assert_eq!(blocks_hash(&save.export_song(2)).ok(), save.song_hash(2).ok());
     }
 
     #[test]
@@ -317,20 +1378,370 @@ mod tests {
         println!("{:?}", empty_save);
     }
 
+    #[test]
+    fn test_import_reproducible() -> io::Result<()> {
+        let original = generate::test_save();
+        let song = original.export_song(0);
+        let imported: Vec<Vec<u8>> = (0..2).map(|_| {
+            let mut save = original.clone();
+            save.delete_song(0).unwrap();
+            save.import_song(&song, [b'B', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+            save.import_song(&song, [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+            save.bytes()
+        }).collect();
+        assert_eq!(imported[0], imported[1]);
+        let save = LsdjSave::from_bytes(&imported[0]).unwrap();
+        assert_eq!(save.metadata.alloc_table[..12], [0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1]);
+        assert_eq!(save.export_song(0), save.export_song(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_unchained_blocks() {
+        let mut save = generate::test_save();
+        let before = save.bytes();
+        let mut block = vec![5; BLOCK_SIZE];
+        block[(BLOCK_SIZE - 2)..].copy_from_slice(&[0xe0, 0xff]); // ends the song after the first block
+        let title = [b'E', b'O', b'F', 0, 0, 0, 0, 0];
+        assert_eq!(save.import_song(&block.repeat(2), title), Err(err::NO_SKIP));
+        assert_eq!(save.bytes(), before);
+    }
+
+    #[test]
+    fn test_read_blocks() {
+        let mut block_bytes = vec![5; BLOCK_SIZE * 2];
+        block_bytes[BLOCK_SIZE - 2] = 0xe0;
+        block_bytes[BLOCK_SIZE - 1] = b'x';
+        block_bytes[BLOCK_SIZE * 2 - 2] = 0xe0;
+        block_bytes[BLOCK_SIZE * 2 - 1] = 0xff;
+        let mut bytes = Vec::new();
+        assert_eq!(read_blocks(&block_bytes[..], &mut bytes, false).unwrap(), 2);
+        assert_eq!(bytes, block_bytes);
+        let mut bytes = Vec::new();
+        let reader = Read::chain(&block_bytes[..0x100], &block_bytes[0x100..]); // arriving in pieces
+        assert_eq!(read_blocks(reader, &mut bytes, false).unwrap(), 2);
+        assert_eq!(bytes, block_bytes);
+
+        let truncated = &block_bytes[..(BLOCK_SIZE + 0x10)];
+        match read_blocks(truncated, &mut Vec::new(), false) {
+            Err(LsdjError::TruncatedBlock { block: 1, len: 0x10 }) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match read_blocks(truncated, &mut Vec::new(), true) {
+            Err(LsdjError::UnterminatedBlock { block: 1 }) => (), // padding doesn't add an instruction
+            r => panic!("unexpected result {:?}", r),
+        }
+        let mut padded = block_bytes[..(BLOCK_SIZE + 0x10)].to_vec();
+        padded[(BLOCK_SIZE + 0x0e)..].copy_from_slice(&[0xe0, 0xff]);
+        let mut bytes = Vec::new();
+        assert_eq!(read_blocks(&padded[..], &mut bytes, true).unwrap(), 2);
+        assert_eq!(bytes.len(), BLOCK_SIZE * 2);
+
+        let mut save = LsdjSave::empty();
+        let title = [b'T', b'E', b'S', b'T', 0, 0, 0, 0];
+        assert_eq!(save.import_song(&[5; BLOCK_SIZE], title), Err(err::BAD_FMT));
+        assert_eq!(save.metadata.blocks_used(), 0);
+    }
+
+    #[test]
+    fn test_block_table() {
+        let mut save = LsdjSave::empty_with_layout(LsdjLayout::SAVE_64KB);
+        assert_eq!(save.blocks().len(), 0x3f);
+        assert!(save.blocks().get(0).is_none());
+        assert!(save.blocks().get(0x40).is_none());
+        assert_eq!(save.blocks().iter().next().map(|(i, _)| i), Some(1));
+
+        let mut block = LsdjBlock::empty();
+        block.data[..2].copy_from_slice(&[0xe0, 0xff]);
+        assert!(save.blocks_mut().replace(3, block).is_ok());
+        assert_eq!(save.blocks().get(3).map(|b| (b.position, b.data[1])), Some((3, 0xff)));
+        save.blocks_mut().get_mut(3).unwrap().data[2] = 0x11;
+        assert_eq!(save.bytes()[BLOCK_ADDRESS as usize + BLOCK_SIZE * 2 + 2], 0x11);
+        assert_eq!(save.blocks_mut().replace(0, block).err(), Some(err::BAD_BLOCK));
+        assert_eq!(save.blocks_mut().replace(0x40, block).err(), Some(err::BAD_BLOCK));
+    }
+
+    #[test]
+    fn test_standard_traits() {
+        let save = LsdjSave::default();
+        assert!(save == LsdjSave::empty());
+        assert!(LsdjMetadata::default() == LsdjMetadata::empty());
+        assert_eq!(LsdjBlock::default(), LsdjBlock::empty());
+        assert!(LsdjSram::default() == LsdjSram::empty());
+
+        let mut copy = save.clone();
+        assert!(copy == save);
+        let title = [b'T', b'E', b'S', b'T', 0, 0, 0, 0];
+        copy.import_decompressed_song(&[0; SRAM_SIZE], title).unwrap();
+        assert!(copy != save);
+        assert_eq!(save.metadata.blocks_used(), 0); // the original is untouched
+
+        let mut moved = *copy.blocks().get(1).unwrap();
+        moved.position = 5; // blocks compare by contents only
+        assert_eq!(&moved, copy.blocks().get(1).unwrap());
+        assert!(copy.blocks().clone() == *copy.blocks());
+    }
+
+    #[test]
+    fn test_repair_blocks() {
+        let mut sram = LsdjSram::empty();
+        for (i, byte) in sram.data.iter_mut().enumerate() {
+            *byte = (i % 0x61) as u8; // no runs, so the song spans several blocks
+        }
+        let blocks = sram.compress_into(1..).unwrap().bytes();
+        let mut repaired = blocks.clone();
+        assert_eq!(repair_blocks(&mut repaired, Compat::Lsdj), vec![]);
+        assert_eq!(repaired, blocks);
+
+        let eof = blocks.windows(2).rposition(|w| w == [0xe0, 0xff]).unwrap();
+        let mut malformed = blocks[..eof].to_vec(); // no terminator, ending partway through the last block
+        malformed.resize(blocks.len() + BLOCK_SIZE * 3 + 0x10, 0);
+        assert_eq!(repair_blocks(&mut malformed, Compat::Lsdj),
+                   vec![BlockRepair::DroppedPadding(BLOCK_SIZE * 3 + 0x10), BlockRepair::AddedTerminator]);
+        assert_eq!(malformed, blocks);
+        assert!(read_blocks(&malformed[..], &mut Vec::new(), false).is_ok());
+
+        let mut truncated = blocks[..(eof - 0x10)].to_vec(); // doesn't fill SRAM, so can't be repaired
+        assert_eq!(repair_blocks(&mut truncated, Compat::Lsdj), vec![]);
+        assert_eq!(truncated.len(), eof - 0x10);
+        assert_eq!(repair_blocks(&mut vec![0; BLOCK_SIZE], Compat::Lsdj), vec![]);
+    }
+
+    #[test]
+    fn test_import_decompressed_song() {
+        let mut save = LsdjSave::empty();
+        let title = [b'T', b'E', b'S', b'T', 0, 0, 0, 0];
+        assert_eq!(save.import_decompressed_song(&[0; BLOCK_SIZE], title), Err(err::BAD_FMT));
+        let mut sram = [0; SRAM_SIZE];
+        for (i, byte) in sram.iter_mut().enumerate() {
+            *byte = (i % 0x61) as u8; // no runs, so the song spans several blocks
+        }
+        assert_eq!(save.import_decompressed_song(&sram, title), Ok(0));
+        assert!(save.metadata.size_of(0) > 1);
+        assert_eq!(&save.decompress_song(0).unwrap()[..], &sram[..]);
+    }
+
+    #[test]
+    fn test_delete_song() {
+        let mut save = LsdjSave::empty();
+        assert_eq!(save.delete_song(0), Err(err::NO_SONG));
+        let sram = [0; SRAM_SIZE];
+        save.import_decompressed_song(&sram, [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        save.import_decompressed_song(&sram, [b'B', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(save.delete_song(0), Ok(()));
+        assert_eq!(save.metadata.songs(), vec![1]);
+        assert_eq!(save.metadata.blocks_used(), save.metadata.size_of(1));
+        assert_eq!(save.metadata.next_available_song(), Some(0));
+    }
+
+    #[test]
+    fn test_truncated_save() {
+        let bytes = LsdjSave::empty().bytes();
+        match LsdjSave::from(&mut io::Cursor::new(&bytes[..0x4000])) {
+            Err(LsdjError::TruncatedSave { expected: 0x10000, got: 0x4000 }) => (),
+            r => panic!("unexpected result {:?}", r.err()),
+        }
+        match LsdjSave::from(&mut io::Cursor::new(&bytes[..0x1ff00])) {
+            Err(LsdjError::TruncatedSave { expected: 0x20000, got: 0x1ff00 }) => (),
+            r => panic!("unexpected result {:?}", r.err()),
+        }
+        match LsdjSave::from_bytes(&bytes[..0x1fe00]) { // missing only the last block
+            Err(LsdjError::TruncatedSave { expected: 0x20000, got: 0x1fe00 }) => (),
+            r => panic!("unexpected result {:?}", r.err()),
+        }
+        match LsdjSave::from_bytes(&bytes[..0x8400]) {
+            Err(LsdjError::TruncatedSave { expected: 0x10000, got: 0x8400 }) => (),
+            r => panic!("unexpected result {:?}", r.err()),
+        }
+        let mut long = bytes.clone();
+        long.resize(0x20200, 0);
+        match LsdjSave::from(&mut io::Cursor::new(&long)) {
+            Err(LsdjError::Invalid(e)) => assert_eq!(e, err::BAD_SAVE_SIZE),
+            r => panic!("unexpected result {:?}", r.err()),
+        }
+        assert!(LsdjSave::from(&mut io::Cursor::new(&bytes)).is_ok());
+    }
+
+    #[test]
+    fn test_metadata_init_check() {
+        let mut bytes = LsdjSave::empty().bytes();
+        bytes[0x813e..0x8140].copy_from_slice(b"jk");
+        let save = LsdjSave::from(&mut io::Cursor::new(&bytes)).unwrap();
+        assert!(save.metadata.check_sram_init());
+        assert_eq!(save.metadata.empty_bytes, [0; 0x1e]);
+    }
+
+    #[test]
+    fn test_save_layouts() {
+        assert_eq!(LsdjSave::empty().bytes().len(), 0x20000);
+        let mut save = LsdjSave::empty_with_layout(LsdjLayout::SAVE_64KB);
+        assert_eq!(save.bytes().len(), 0x10000);
+        let mut block_bytes = vec![5; BLOCK_SIZE * 0x40];
+        for i in 1..0x40 {
+            block_bytes[BLOCK_SIZE * i - 2] = 0xe0;
+            block_bytes[BLOCK_SIZE * i - 1] = b'x';
+        }
+        block_bytes[BLOCK_SIZE * 0x40 - 2] = 0xe0;
+        block_bytes[BLOCK_SIZE * 0x40 - 1] = 0xff;
+        let title = [b'T', b'E', b'S', b'T', 0, 0, 0, 0];
+        assert_eq!(save.import_song(&block_bytes, title), Err(err::NO_BLOCKS));
+        assert_eq!(save.import_song(&block_bytes[BLOCK_SIZE..], title), Ok(0));
+        assert_eq!(save.metadata.size_of(0), 0x3f);
+        assert_eq!(save.metadata.next_empty_block(), Some(0x40)); // past the end of the save
+        assert_eq!(save.layout(), LsdjLayout::SAVE_64KB);
+        assert_eq!(save.import_song(&block_bytes[(BLOCK_SIZE * 0x3f)..], title), Err(err::NO_BLOCKS));
+    }
+
+    #[test]
+    fn test_from_bytes_with_layout() {
+        let mut save = LsdjSave::empty_with_layout(LsdjLayout::SAVE_64KB);
+        save.import_decompressed_song(&[0; SRAM_SIZE], [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        let mut bytes = save.bytes();
+        bytes.resize(LsdjLayout::SAVE_128KB.save_size(), 0x55); // e.g. a hack keeping other data after the blocks
+        let read = LsdjSave::from_bytes_with_layout(&bytes, LsdjLayout::SAVE_64KB).unwrap();
+        assert_eq!(read.layout(), LsdjLayout::SAVE_64KB);
+        assert_eq!(read.bytes(), save.bytes());
+        assert_eq!(read.decompress_song(0).ok(), Some([0; SRAM_SIZE]));
+
+        let layout = LsdjLayout::new(0x10).unwrap();
+        assert!(matches!(LsdjSave::from_bytes_with_layout(&bytes[..0xa000], layout),
+                         Err(LsdjError::TruncatedSave { expected: 0xa200, got: 0xa000 })));
+        assert_eq!(LsdjSave::from_bytes_with_layout(&bytes, layout).unwrap().region(0xa200), None);
+
+        // layouts are checked when they're made, so the largest one there is
+        // still has an allocation table entry for every block
+        assert_eq!(LsdjLayout::new(0x100), Err(err::BAD_BLOCK_COUNT));
+        let largest = LsdjLayout::new(layout::MAX_BLOCK_COUNT).unwrap();
+        let read = LsdjSave::from_bytes_with_layout(&bytes, largest).unwrap();
+        assert!(read.audit_blocks().is_empty());
+    }
+
+    #[test]
+    fn test_set_layout() {
+        let mut save = LsdjSave::empty();
+        save.set_layout(LsdjLayout::SAVE_64KB).unwrap();
+        assert_eq!(save.bytes().len(), 0x10000);
+        save.set_layout(LsdjLayout::SAVE_128KB).unwrap();
+        assert_eq!(save.bytes().len(), 0x20000);
+        save.metadata.alloc_table[0x3f] = 0; // block $40 is only present in a 128KB save
+        assert_eq!(save.set_layout(LsdjLayout::SAVE_64KB), Err(err::NO_ROOM));
+        assert_eq!(save.layout(), LsdjLayout::SAVE_128KB);
+    }
+
+    #[test]
+    fn test_song_table() {
+        let mut save = LsdjSave::empty();
+        let mut song = song::tests::empty_song();
+        song::tests::set_instrument(&mut song, 0, "KICK", [2, 0, 0x12, 0, 0, 0, 0, 0, 0, 0x05, 0, 0, 0, 0, 0, 0]);
+        song.set_format_version(0x16);
+        save.import_decompressed_song(&song.data, [b'T', b'E', b'S', b'T', 0, 0, 0, 0]).unwrap();
+        save.metadata.alloc_table[1] = 3; // a song whose block doesn't decompress
+        save.metadata.title(3, [b'L', b'O', b'N', b'G', b'N', b'A', b'M', b'E']);
+        save.metadata.version_table[3] = 0x1a;
+        assert_eq!(save.song_info()[0].kits, Some(vec![0x05, 0x12]));
+        assert_eq!(save.song_info()[0].format_version, Some(0x16));
+        assert_eq!(save.song_info()[1].kits, None);
+        let table = save.song_table(false);
+        assert_eq!(table, "IDX  TITLE     VER  FMT  BLOCKS   SAVE  KITS\n\
+                           00   TEST        0   16       1    0.5%  05 12\n\
+                           03   LONGNAME   1A    ?       1    0.5%  ?\n\
+                           free: 189 blocks (99.0%)\n");
+        let colored = save.song_table(true);
+        assert!(colored.starts_with("\x1b[1mIDX"));
+        assert!(colored.contains("\x1b[34mLONGNAME\x1b[0m"));
+    }
+
+    #[test]
+    fn test_replace_song() {
+        let mut save = LsdjSave::empty();
+        let mut sram = [0; SRAM_SIZE];
+        save.metadata.version_table[0] = 0x33; // left over in the free slot
+        save.import_decompressed_song(&sram, [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        save.import_decompressed_song(&sram, [b'B', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(save.metadata.version_table[0], 0x00);
+        save.metadata.version_table[0] = 0x05;
+        save.metadata.version_table[1] = 0xff;
+        for (i, byte) in sram.iter_mut().enumerate() {
+            *byte = (i % 0x61) as u8; // takes more blocks than before
+        }
+        save.replace_song(0, &sram).unwrap();
+        assert_eq!(&save.decompress_song(0).unwrap()[..], &sram[..]);
+        assert_eq!(save.metadata.song_title(0), "A");
+        assert_eq!(save.metadata.version_table[0], 0x06);
+        save.replace_song(1, &sram).unwrap();
+        assert_eq!(save.metadata.version_table[1], 0x00);
+        assert_eq!(save.metadata.songs(), vec![0, 1]);
+        assert_eq!(save.replace_song(2, &sram), Err(err::NO_SONG));
+        assert_eq!(save.replace_song(0, &sram[..0x100]), Err(err::BAD_FMT));
+    }
+
+    #[test]
+    fn test_recompress_song() {
+        let mut save = LsdjSave::empty();
+        let mut sram = [0; SRAM_SIZE];
+        for (i, byte) in sram.iter_mut().enumerate() {
+            *byte = (i % 0x61) as u8;
+        }
+        save.import_decompressed_song(&sram, [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        save.metadata.version_table[0] = 0x07;
+        let before = save.metadata.size_of(0);
+        assert_eq!(save.recompress_song(0, &sram).ok(), Some(0)); // already as small as it gets
+        let cleared = [0; SRAM_SIZE];
+        let blocks = blocks_from_sram(&cleared, save.compat()).unwrap();
+        assert!(save.recompress_song_from(0, &[1; SRAM_SIZE], &blocks).is_err()); // not those bytes compressed
+        assert_eq!(save.decompress_song(0).unwrap(), sram);
+        assert_eq!(save.recompress_song(0, &cleared).ok(), Some(before - 1));
+        assert_eq!(save.decompress_song(0).unwrap(), cleared);
+        assert_eq!(save.metadata.version_table[0], 0x07);
+        assert_eq!(save.metadata.song_title(0), "A");
+        assert!(matches!(save.recompress_song(1, &cleared), Err(LsdjError::Invalid(e)) if e == err::NO_SONG));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        /// Saves with corrupt metadata and blocks (and cut short) are read
+        /// and inspected without panicking or looping.
+        #[test]
+        fn prop_corrupt_saves_dont_panic(
+            edits in proptest::collection::vec((SRAM_SIZE..0x9000usize, any::<u8>()), 1..64),
+            len in 0..0x20000usize,
+        ) {
+            let mut bytes = generate::generate(&generate::GenOptions { songs: 3, ..Default::default() }).unwrap().bytes();
+            for (offset, byte) in edits {
+                bytes[offset] = byte;
+            }
+            if len < SRAM_SIZE {
+                bytes.truncate(len);
+            }
+            let _ = sram_from_blocks(&bytes[SRAM_SIZE.min(bytes.len())..], Compat::Lsdj);
+            let save = match LsdjSave::from(&mut io::Cursor::new(bytes)) {
+                Ok(save) => save,
+                Err(_) => return Ok(()),
+            };
+            let _ = (save.song_table(false), save.song_info(), save.audit_blocks(), save.recover_songs());
+            let _ = save.metadata.block_map(false);
+            for song in 0..=0xff {
+                let _ = (save.slot(song).map(|s| s.title()), save.export_song(song), save.song(song));
+                let _ = (save.compression_stats(song), save.round_trip(song));
+            }
+        }
+    }
+
     #[test]
     fn test_lsdjsram_partialeq() {
         let sram = LsdjSram::empty();
         let eq_sram0 = LsdjSram {
-            position: 0,
-            data: [0; SRAM_SIZE]
+            data: [0; SRAM_SIZE],
+            compat: Compat::Lsdj,
         };
         let neq_sram = LsdjSram {
-            position: 0,
-            data: [1; SRAM_SIZE]
+            data: [1; SRAM_SIZE],
+            compat: Compat::Lsdj,
         };
         let eq_sram1 = LsdjSram {
-            position: 1234,
-            data: [0; SRAM_SIZE]
+            data: [0; SRAM_SIZE],
+            compat: Compat::Legacy,
         };
         assert!(sram == eq_sram0);
         assert!(sram != neq_sram);
@@ -0,0 +1,377 @@
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::{err, LsdjError};
+use crate::lsdj::container::crc32;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+/// Offset which IPS records can't start at, as it reads as `IPS_EOF`.
+const IPS_EOF_OFFSET: usize = 0x454f46;
+/// Largest offset IPS records can start at (three bytes).
+const IPS_MAX_OFFSET: usize = 0xffffff;
+/// Longest IPS record (two bytes of length).
+const IPS_MAX_RECORD: usize = 0xffff;
+
+const BPS_MAGIC: &[u8] = b"BPS1";
+/// Bytes in a BPS footer: CRC32s of the source, target, and patch.
+const BPS_FOOTER_LENGTH: usize = 12;
+const BPS_SOURCE_READ: usize = 0;
+const BPS_TARGET_READ: usize = 1;
+const BPS_SOURCE_COPY: usize = 2;
+const BPS_TARGET_COPY: usize = 3;
+
+/// Binary patch formats which emulator users already have tools to apply.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PatchFormat {
+    /// IPS: records of bytes to write at offsets in the source. Small and
+    /// widely supported, but with no way to check it's applied to the right
+    /// file.
+    Ips,
+    /// BPS: the target described as reads and copies from the source and
+    /// itself, along with CRC32s of the source, target, and patch.
+    Bps,
+}
+
+impl FromStr for PatchFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<PatchFormat, &'static str> {
+        match s {
+            "ips" => Ok(PatchFormat::Ips),
+            "bps" => Ok(PatchFormat::Bps),
+            _ => Err(err::BAD_PATCH_FORMAT),
+        }
+    }
+}
+
+impl PatchFormat {
+    /// Returns the format named by the extension of `path` (`.ips` or
+    /// `.bps`), if any.
+    #[cfg(feature = "std")]
+    pub fn from_path(path: &Path) -> Option<PatchFormat> {
+        path.extension()?.to_str()?.to_lowercase().parse().ok()
+    }
+
+    /// Returns the format of `patch`, if it starts like an IPS or BPS patch.
+    pub fn detect(patch: &[u8]) -> Option<PatchFormat> {
+        if patch.starts_with(IPS_MAGIC) {
+            Some(PatchFormat::Ips)
+        } else if patch.starts_with(BPS_MAGIC) {
+            Some(PatchFormat::Bps)
+        } else {
+            None
+        }
+    }
+
+    /// Returns a patch in this format which turns `source` into `target`.
+    pub fn create(self, source: &[u8], target: &[u8]) -> Result<Vec<u8>, &'static str> {
+        match self {
+            PatchFormat::Ips => ips(source, target),
+            PatchFormat::Bps => Ok(bps(source, target)),
+        }
+    }
+}
+
+/// Applies `patch` (in either format) to `source`, returning the patched
+/// bytes.
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, LsdjError> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(source, patch).ok_or(LsdjError::Invalid(err::BAD_PATCH)),
+        Some(PatchFormat::Bps) => apply_bps(source, patch),
+        None => Err(LsdjError::Invalid(err::BAD_PATCH)),
+    }
+}
+
+/// Returns an IPS patch which turns `source` into `target`: a record for each
+/// run of bytes which differ (a run-length record if they're all the same
+/// byte), with `target`'s length after the end-of-file marker if it's shorter
+/// than `source`.
+fn ips(source: &[u8], target: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if target.len() > IPS_MAX_OFFSET {
+        return Err(err::PATCH_TOO_BIG);
+    }
+    let differs = |i: usize| source.get(i) != Some(&target[i]);
+    let mut patch = IPS_MAGIC.to_vec();
+    let mut i = 0;
+    while i < target.len() {
+        if !differs(i) {
+            i += 1;
+            continue;
+        }
+        // a record can't start at the offset which reads as "EOF", so start it a byte early
+        let start = if i == IPS_EOF_OFFSET { i - 1 } else { i };
+        let mut end = i;
+        while end < target.len() && end - start < IPS_MAX_RECORD && differs(end) {
+            end += 1;
+        }
+        let data = &target[start..end];
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        if data.len() > 3 && data.iter().all(|&b| b == data[0]) {
+            patch.extend_from_slice(&[0, 0]);
+            patch.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            patch.push(data[0]);
+        } else {
+            patch.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            patch.extend_from_slice(data);
+        }
+        i = end;
+    }
+    patch.extend_from_slice(IPS_EOF);
+    if target.len() < source.len() {
+        patch.extend_from_slice(&(target.len() as u32).to_be_bytes()[1..]);
+    }
+    Ok(patch)
+}
+
+/// Applies an IPS patch to `source`, returning `None` if the patch is
+/// malformed.
+fn apply_ips(source: &[u8], patch: &[u8]) -> Option<Vec<u8>> {
+    let mut target = source.to_vec();
+    let mut rest = patch.strip_prefix(IPS_MAGIC)?;
+    loop {
+        let (offset, tail) = rest.split_at_checked(3)?;
+        rest = tail;
+        if offset == IPS_EOF {
+            match rest.len() {
+                0 => return Some(target),
+                3 => {
+                    target.truncate(read_be(rest));
+                    return Some(target);
+                },
+                _ => return None,
+            }
+        }
+        let offset = read_be(offset);
+        let (len, tail) = rest.split_at_checked(2)?;
+        rest = tail;
+        let data = match read_be(len) {
+            0 => { // a run of one byte
+                let (run, tail) = rest.split_at_checked(3)?;
+                rest = tail;
+                vec![run[2]; read_be(&run[..2])]
+            },
+            len => {
+                let (data, tail) = rest.split_at_checked(len)?;
+                rest = tail;
+                data.to_vec()
+            },
+        };
+        if target.len() < offset + data.len() {
+            target.resize(offset + data.len(), 0);
+        }
+        target[offset..(offset + data.len())].copy_from_slice(&data);
+    }
+}
+
+/// Returns the big-endian number in `bytes`.
+fn read_be(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |n, &b| (n << 8) | b as usize)
+}
+
+/// Returns a BPS patch which turns `source` into `target`, reading the bytes
+/// of `target` which match `source` at the same offset from the source and
+/// storing the rest in the patch.
+fn bps(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = BPS_MAGIC.to_vec();
+    for n in [source.len(), target.len(), 0] { // no metadata
+        write_varint(&mut patch, n);
+    }
+    let same = |i: usize| source.get(i) == Some(&target[i]);
+    let mut i = 0;
+    while i < target.len() {
+        let matching = same(i);
+        let mut end = i;
+        while end < target.len() && same(end) == matching {
+            end += 1;
+        }
+        let action = if matching { BPS_SOURCE_READ } else { BPS_TARGET_READ };
+        write_varint(&mut patch, ((end - i - 1) << 2) | action);
+        if !matching {
+            patch.extend_from_slice(&target[i..end]);
+        }
+        i = end;
+    }
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    let checksum = crc32(&patch);
+    patch.extend_from_slice(&checksum.to_le_bytes());
+    patch
+}
+
+/// Applies a BPS patch to `source`, checking the CRC32s of the patch, the
+/// source, and the result.
+fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, LsdjError> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_LENGTH {
+        return Err(LsdjError::Invalid(err::BAD_PATCH));
+    }
+    let (body, footer) = patch.split_at(patch.len() - BPS_FOOTER_LENGTH);
+    let footer_crc = |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+    let (expected, got) = (footer_crc(8), crc32(&patch[..(patch.len() - 4)]));
+    if expected != got {
+        return Err(LsdjError::BadChecksum { expected, got });
+    }
+
+    let mut rest = &body[BPS_MAGIC.len()..];
+    let source_size = read_varint(&mut rest).ok_or(err::BAD_PATCH)?;
+    let target_size = read_varint(&mut rest).ok_or(err::BAD_PATCH)?;
+    let metadata_size = read_varint(&mut rest).ok_or(err::BAD_PATCH)?;
+    rest = rest.get(metadata_size..).ok_or(err::BAD_PATCH)?;
+    if source.len() != source_size || crc32(source) != footer_crc(0) {
+        return Err(LsdjError::Invalid(err::WRONG_PATCH_SOURCE));
+    }
+
+    let mut target: Vec<u8> = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0, 0);
+    while !rest.is_empty() {
+        let data = read_varint(&mut rest).ok_or(err::BAD_PATCH)?;
+        let len = (data >> 2) + 1;
+        match data & 3 {
+            BPS_SOURCE_READ => {
+                let bytes = source.get(target.len()..(target.len() + len)).ok_or(err::BAD_PATCH)?;
+                target.extend_from_slice(bytes);
+            },
+            BPS_TARGET_READ => {
+                let (bytes, tail) = rest.split_at_checked(len).ok_or(err::BAD_PATCH)?;
+                target.extend_from_slice(bytes);
+                rest = tail;
+            },
+            BPS_SOURCE_COPY => {
+                source_offset = read_relative(&mut rest, source_offset).ok_or(err::BAD_PATCH)?;
+                let bytes = source.get(source_offset..(source_offset + len)).ok_or(err::BAD_PATCH)?;
+                target.extend_from_slice(bytes);
+                source_offset += len;
+            },
+            BPS_TARGET_COPY => {
+                // copied a byte at a time, as the copy may overlap the bytes it writes
+                target_offset = read_relative(&mut rest, target_offset).ok_or(err::BAD_PATCH)?;
+                for _ in 0..len {
+                    let byte = *target.get(target_offset).ok_or(err::BAD_PATCH)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            },
+            _ => unreachable!(), // the action is two bits
+        }
+        if target.len() > target_size {
+            return Err(LsdjError::Invalid(err::BAD_PATCH));
+        }
+    }
+    if target.len() != target_size {
+        return Err(LsdjError::Invalid(err::BAD_PATCH));
+    }
+    let (expected, got) = (footer_crc(4), crc32(&target));
+    if expected != got {
+        return Err(LsdjError::BadChecksum { expected, got });
+    }
+    Ok(target)
+}
+
+/// Appends `n` to `patch` as a BPS variable-length number.
+fn write_varint(patch: &mut Vec<u8>, mut n: usize) {
+    loop {
+        let low = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            patch.push(0x80 | low);
+            return;
+        }
+        patch.push(low);
+        n -= 1;
+    }
+}
+
+/// Reads a BPS variable-length number from the start of `bytes`, advancing
+/// past it.
+fn read_varint(bytes: &mut &[u8]) -> Option<usize> {
+    let (mut n, mut shift) = (0usize, 1usize);
+    loop {
+        let (&byte, tail) = bytes.split_first()?;
+        *bytes = tail;
+        n = n.checked_add(((byte & 0x7f) as usize).checked_mul(shift)?)?;
+        if byte & 0x80 != 0 {
+            return Some(n);
+        }
+        shift = shift.checked_shl(7)?;
+        n = n.checked_add(shift)?;
+    }
+}
+
+/// Reads a BPS signed offset relative to `offset` from the start of `bytes`,
+/// returning the offset it points to.
+fn read_relative(bytes: &mut &[u8], offset: usize) -> Option<usize> {
+    let n = read_varint(bytes)?;
+    if n & 1 != 0 {
+        offset.checked_sub(n >> 1)
+    } else {
+        offset.checked_add(n >> 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> Vec<u8> {
+        (0..0x1000).map(|i| (i % 0x61) as u8).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let source = source();
+        let mut target = source.clone();
+        target[0x10] = 0xff;
+        target[0x200..0x400].fill(0); // a run-length record in IPS
+        let mut longer = target.clone();
+        longer.extend_from_slice(b"tail");
+        let shorter = target[..0x800].to_vec();
+        for format in [PatchFormat::Ips, PatchFormat::Bps] {
+            for target in [&target, &longer, &shorter, &source] {
+                let patch = format.create(&source, target).unwrap();
+                assert_eq!(PatchFormat::detect(&patch), Some(format));
+                assert_eq!(&apply(&source, &patch).unwrap(), target);
+            }
+        }
+        assert!(PatchFormat::Ips.create(&source, &target).unwrap().len() < 0x40);
+        assert_eq!(PatchFormat::from_path(Path::new("song.BPS")), Some(PatchFormat::Bps));
+    }
+
+    #[test]
+    fn test_ips_eof_offset() {
+        let source = vec![0; IPS_EOF_OFFSET + 2];
+        let mut target = source.clone();
+        target[IPS_EOF_OFFSET] = 1;
+        let patch = ips(&source, &target).unwrap();
+        assert!(!patch[IPS_MAGIC.len()..].starts_with(IPS_EOF));
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn test_bps_checks() {
+        let source = source();
+        let mut target = source.clone();
+        target[0x20] = 0xff;
+        let mut patch = bps(&source, &target);
+        assert!(matches!(apply(&target, &patch), Err(LsdjError::Invalid(e)) if e == err::WRONG_PATCH_SOURCE));
+        let len = patch.len();
+        patch[len - BPS_FOOTER_LENGTH - 1] ^= 1;
+        assert!(matches!(apply(&source, &patch), Err(LsdjError::BadChecksum { .. })));
+        assert!(matches!(apply(&source, b"nonsense"), Err(LsdjError::Invalid(e)) if e == err::BAD_PATCH));
+        assert!(matches!(apply(&source, b"PATCH\0\0"), Err(LsdjError::Invalid(e)) if e == err::BAD_PATCH));
+    }
+
+    #[test]
+    fn test_varint() {
+        for n in [0, 1, 0x7f, 0x80, 0x407f, 0x4080, 0x12345678] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, n);
+            let mut slice = &bytes[..];
+            assert_eq!(read_varint(&mut slice), Some(n));
+            assert!(slice.is_empty());
+        }
+        assert_eq!(read_relative(&mut &[0x83][..], 5), Some(4));
+        assert_eq!(read_relative(&mut &[0x82][..], 5), Some(6));
+    }
+}
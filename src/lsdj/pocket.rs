@@ -0,0 +1,72 @@
+use crate::lsdj::prelude::*;
+use crate::lsdj::err;
+use crate::lsdj::LsdjLayout;
+use crate::lsdj::LsdjSave;
+
+/// The Analogue Pocket sizes save files from the RAM size in the cartridge
+/// header, which is 128KB for LSDj, and won't load a save of any other size.
+pub const POCKET_SAVE_SIZE: usize = 0x20000;
+
+/// Returns true if every byte in `bytes` is the same and is either $00 or
+/// $ff (as written when padding out a save file).
+fn is_padding(bytes: &[u8]) -> bool {
+    match bytes.first() {
+        Some(&first) => (first == 0x00 || first == 0xff) && bytes.iter().all(|&b| b == first),
+        None => true,
+    }
+}
+
+/// Converts `save` into the bytes of a save file the Analogue Pocket can load,
+/// expanding smaller saves (such as 64KB saves) with empty blocks.
+pub fn to_pocket(mut save: LsdjSave) -> Result<Vec<u8>, &'static str> {
+    save.set_layout(LsdjLayout::detect(POCKET_SAVE_SIZE as u64)?)?;
+    Ok(save.bytes())
+}
+
+/// Reads a save file copied off the Analogue Pocket, converting it to the
+/// given layout.
+///
+/// Save files copied off the Pocket may be padded past the end of cartridge
+/// RAM; the padding is discarded as long as it is uniform. Returns an `Err` if
+/// the save is too short, contains anything other than padding after
+/// cartridge RAM, or has songs which don't fit into `layout`.
+pub fn from_pocket(bytes: &[u8], layout: LsdjLayout) -> Result<LsdjSave, &'static str> {
+    if bytes.len() < POCKET_SAVE_SIZE || !is_padding(&bytes[POCKET_SAVE_SIZE..]) {
+        return Err(err::BAD_SAVE_SIZE);
+    }
+    let mut save = match LsdjSave::from_bytes(&bytes[..POCKET_SAVE_SIZE]) {
+        Ok(s) => s,
+        Err(_) => return Err(err::BAD_SAVE_SIZE),
+    };
+    save.set_layout(layout)?;
+    Ok(save)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_padding() {
+        assert!(is_padding(&[]));
+        assert!(is_padding(&[0xff; 0x10]));
+        assert!(is_padding(&[0x00; 0x10]));
+        assert!(!is_padding(&[0x00, 0xff]));
+        assert!(!is_padding(&[0x12; 0x10]));
+    }
+
+    #[test]
+    fn test_pocket_round_trip() {
+        let mut save = LsdjSave::empty_with_layout(LsdjLayout::SAVE_64KB);
+        save.metadata.title(0, [b'A', 0, 0, 0, 0, 0, 0, 0]);
+        let mut bytes = to_pocket(save).unwrap();
+        assert_eq!(bytes.len(), POCKET_SAVE_SIZE);
+        bytes.extend_from_slice(&[0xff; 0x100]); // padding added past the end of cartridge RAM
+        let converted = from_pocket(&bytes, LsdjLayout::SAVE_64KB).unwrap();
+        assert_eq!(converted.layout(), LsdjLayout::SAVE_64KB);
+        assert_eq!(converted.metadata.title_table[0], [b'A', 0, 0, 0, 0, 0, 0, 0]);
+        bytes.push(0x12);
+        assert_eq!(from_pocket(&bytes, LsdjLayout::SAVE_64KB).err(), Some(err::BAD_SAVE_SIZE));
+        assert_eq!(from_pocket(&bytes[..0x10000], LsdjLayout::SAVE_64KB).err(), Some(err::BAD_SAVE_SIZE));
+    }
+}
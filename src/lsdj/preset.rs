@@ -0,0 +1,111 @@
+use alloc::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::err;
+use crate::lsdj::clean::tables_run;
+use crate::lsdj::snippet::{free_slots, import_tables, remap};
+use crate::lsdj::song::*;
+
+/// A single instrument which can be saved to a file (an `.lsdinst`, as JSON)
+/// and shared between songs: its type, name, and raw parameter bytes, along
+/// with the table it runs and every table that one runs in turn.
+///
+/// Tables are keyed by their index in the song the preset was exported from;
+/// importing the preset moves them into free slots. As with snippets, the wave
+/// frames and synth settings played by wave instruments are not included.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    /// Format version of the song the preset was exported from.
+    pub format_version: u8,
+    /// Type of the instrument (e.g. `pulse`), which must agree with the first
+    /// parameter byte.
+    pub kind: String,
+    pub name: String,
+    pub params: [u8; INSTRUMENT_PARAMS_LENGTH],
+    pub tables: BTreeMap<u8, [[u8; STEP_COUNT]; 6]>,
+}
+
+impl Preset {
+    /// Copies instrument `instrument` out of `song`, along with the tables it
+    /// runs. Returns an `Err` if the instrument isn't allocated.
+    pub fn export(song: &Song, instrument: u8) -> Result<Preset, &'static str> {
+        let instrument = song.instrument(instrument as usize).ok_or(err::NO_INSTRUMENT)?;
+        let tables = tables_run(song, instrument.table()).into_iter()
+            .filter_map(|t| Some((t, song.table(t as usize)?)))
+            .collect();
+        Ok(Preset {
+            format_version: song.format_version(),
+            kind: instrument.kind.to_string(),
+            name: instrument.name,
+            params: instrument.params,
+            tables,
+        })
+    }
+
+    /// Copies this preset into instrument slot `slot` of `song` (replacing
+    /// whatever instrument is there), or the first free one if `slot` is
+    /// `None`, and its tables into free table slots. Returns the instrument's
+    /// index, or an `Err` (leaving the song unchanged) if the preset's type
+    /// disagrees with its parameters, `slot` is out of range, or there aren't
+    /// enough free slots.
+    pub fn import(&self, song: &mut Song, slot: Option<u8>) -> Result<u8, &'static str> {
+        if InstrumentType::from(self.params[0]).to_string() != self.kind {
+            return Err(err::BAD_PRESET);
+        }
+        let slot = match slot {
+            Some(slot) if slot as usize >= INSTRUMENT_COUNT => return Err(err::BAD_INSTRUMENT_SLOT),
+            Some(slot) => slot,
+            None => free_slots(INSTRUMENT_COUNT, 1, |i| song.instrument(i).is_none())?[0],
+        };
+        let tables = remap(&self.tables, free_slots(TABLE_COUNT, self.tables.len(), |t| song.table(t).is_none())?);
+
+        import_tables(song, &self.tables, &tables);
+        let mut instrument = Instrument { name: self.name.clone(), kind: InstrumentType::from(self.params[0]),
+                                          params: self.params };
+        instrument.set_table(instrument.table().map(|t| *tables.get(&t).unwrap_or(&t)));
+        song.set_instrument(slot as usize, &instrument.name, &instrument.params);
+        Ok(slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::song::tests::*;
+
+    #[test]
+    fn test_preset() {
+        let mut song = empty_song();
+        set_instrument(&mut song, 0x02, "LEAD", [1, 0, 0, 0, 0, 0x24, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        set_table(&mut song, 0x04, TABLE_COMMAND, 0x01);
+        set_table(&mut song, 0x01, 0, 0);
+        set_table(&mut song, 0x06, 0, 0); // not run
+        assert_eq!(Preset::export(&song, 0x03), Err(err::NO_INSTRUMENT));
+        let preset = Preset::export(&song, 0x02).unwrap();
+        assert_eq!((preset.kind.as_str(), preset.name.as_str()), ("wave", "LEAD"));
+        assert_eq!(preset.tables.keys().copied().collect::<Vec<u8>>(), vec![0x01, 0x04]);
+        let json = serde_json::to_string(&preset).unwrap();
+        assert_eq!(serde_json::from_str::<Preset>(&json).unwrap(), preset);
+
+        let mut other = empty_song();
+        set_instrument(&mut other, 0x00, "BASS", [0; 16]);
+        set_table(&mut other, 0x00, 0, 0);
+        assert_eq!(preset.import(&mut other, None), Ok(0x01));
+        let instrument = other.instrument(0x01).unwrap();
+        assert_eq!((instrument.name.as_str(), instrument.kind, instrument.table()), ("LEAD", InstrumentType::Wave, Some(0x02)));
+        assert_eq!(other.table(0x02).unwrap()[3][0], 0x01);
+
+        assert_eq!(preset.import(&mut other, Some(0x00)), Ok(0x00));
+        let instrument = other.instrument(0x00).unwrap();
+        assert_eq!((instrument.name.as_str(), instrument.table()), ("LEAD", Some(0x04)));
+        assert_eq!(other.table(0x04).unwrap()[3][0], 0x03);
+
+        let mismatched = Preset { kind: "kit".to_string(), ..preset.clone() };
+        let original = other.clone();
+        assert_eq!(mismatched.import(&mut other, None), Err(err::BAD_PRESET));
+        assert_eq!(preset.import(&mut other, Some(INSTRUMENT_COUNT as u8)), Err(err::BAD_INSTRUMENT_SLOT));
+        assert_eq!(&other.data[..], &original.data[..]);
+    }
+}
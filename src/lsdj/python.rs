@@ -0,0 +1,134 @@
+//! A Python module, `lsdjtool`, built with pyo3, so that batch operations on
+//! save files can be scripted without running the command-line tool.
+//!
+//! ```python
+//! import lsdjtool
+//! save = lsdjtool.LsdjSave.open("lsdj.sav")
+//! for song in save.list():
+//!     open(f"{song.title}.lsdsng", "wb").write(save.export_song(song.index))
+//! ```
+
+use std::fs;
+use std::path::PathBuf;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::lsdj::err;
+use crate::lsdj::{lsdjtitle_from, LsdjSave};
+
+/// Converts a library error into a Python `ValueError`.
+fn py_error<E: ToString>(e: E) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// A song in a save file, as listed by `LsdjSave.list()`.
+#[pyclass(name = "SongEntry", module = "lsdjtool", get_all, frozen)]
+pub struct SongEntry {
+    pub index: u8,
+    pub title: String,
+    /// Version byte, counting how many times the song has been saved.
+    pub version: u8,
+    /// Number of blocks used.
+    pub blocks: usize,
+}
+
+#[pymethods]
+impl SongEntry {
+    fn __repr__(&self) -> String {
+        format!("SongEntry(index={}, title={:?}, version={}, blocks={})", self.index, self.title, self.version, self.blocks)
+    }
+}
+
+/// A save file, exposed to Python as `LsdjSave`.
+#[pyclass(name = "LsdjSave", module = "lsdjtool")]
+pub struct PySave(LsdjSave);
+
+#[pymethods]
+impl PySave {
+    /// Parses the bytes of a save file of any supported size.
+    #[new]
+    fn new(data: &[u8]) -> PyResult<PySave> {
+        LsdjSave::from_bytes(data).map(PySave).map_err(py_error)
+    }
+
+    /// Reads and parses the save file at `path`.
+    #[staticmethod]
+    fn open(path: PathBuf) -> PyResult<PySave> {
+        PySave::new(&fs::read(path)?)
+    }
+
+    /// Lists the songs in the save file, without decompressing them.
+    fn list(&self) -> Vec<SongEntry> {
+        self.0.metadata.songs().into_iter().filter_map(|s| self.0.slot(s)).map(|slot| SongEntry {
+            index: slot.index(),
+            title: slot.title(),
+            version: slot.version(),
+            blocks: slot.block_indices().len(),
+        }).collect()
+    }
+
+    /// Returns the blocks of compressed song data making up a song, as saved
+    /// in an .lsdsng file.
+    fn export_song<'py>(&self, py: Python<'py>, song: u8) -> PyResult<Bound<'py, PyBytes>> {
+        let slot = self.0.slot(song).ok_or_else(|| py_error(err::NO_SONG))?;
+        Ok(PyBytes::new(py, &slot.compressed_bytes()))
+    }
+
+    /// Imports blocks of compressed song data into the first free song slot
+    /// under `title`, returning the slot's index.
+    fn import_song(&mut self, data: &[u8], title: &str) -> PyResult<u8> {
+        let title = lsdjtitle_from(title).map_err(py_error)?;
+        self.0.import_song(data, title).map_err(py_error)
+    }
+
+    /// Deletes a song, freeing its blocks.
+    fn delete_song(&mut self, song: u8) -> PyResult<()> {
+        self.0.delete_song(song).map_err(py_error)
+    }
+
+    /// Returns the bytes of the save file.
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.0.bytes())
+    }
+
+    /// Writes the save file to `path`.
+    fn write(&self, path: PathBuf) -> PyResult<()> {
+        Ok(fs::write(path, self.0.bytes())?)
+    }
+}
+
+/// The `lsdjtool` Python module.
+#[pymodule]
+fn lsdjtool(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySave>()?;
+    m.add_class::<SongEntry>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_python_save() {
+        Python::initialize();
+        Python::attach(|py| {
+            let mut save = PySave::new(&crate::lsdj::generate::test_save().bytes()).unwrap();
+            let songs = save.list();
+            assert_eq!(songs.len(), 1);
+            assert_eq!(songs[0].__repr__(), "SongEntry(index=0, title=\"TEST\", version=0, blocks=6)");
+
+            let song = save.export_song(py, 0).unwrap();
+            assert_eq!(song.as_bytes().len(), 6 * 0x200);
+            assert!(save.export_song(py, 1).unwrap_err().is_instance_of::<PyValueError>(py));
+            assert_eq!(save.import_song(song.as_bytes(), "COPY").unwrap(), 1);
+            assert!(save.import_song(song.as_bytes(), "copy!").is_err());
+            save.delete_song(0).unwrap();
+
+            let save = PySave::new(save.to_bytes(py).as_bytes()).unwrap();
+            assert_eq!(save.list().iter().map(|s| (s.index, s.title.as_str())).collect::<Vec<_>>(), [(1, "COPY")]);
+        });
+    }
+}
@@ -0,0 +1,103 @@
+use alloc::collections::BTreeSet;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::{Compat, LsdjBlockTable, LsdjSram, SRAM_SIZE};
+use crate::lsdj::compression::decompress_block;
+use crate::lsdj::metadata::LsdjMetadata;
+
+/// Follows the skip instructions from block `start`, decompressing each block
+/// reached. Returns the blocks of the chain, in order, if it decompresses to
+/// exactly a full SRAM image and ends with an end-of-file instruction, as
+/// every song LSDj saves does.
+fn chain_from(blocks: &LsdjBlockTable, start: usize, compat: Compat) -> Option<Vec<usize>> {
+    let mut sram = LsdjSram::with_compat(compat);
+    let mut chain = Vec::new();
+    let mut current = start;
+    let mut position = 0;
+    loop {
+        if chain.contains(&current) {
+            return None; // chain loops back on itself
+        }
+        let block = blocks.get(current)?;
+        chain.push(current);
+        match decompress_block(&block.data, &mut sram, &mut position).ok()? {
+            0 => break,
+            n => current = n as usize,
+        }
+    }
+    Some(chain).filter(|_| position == SRAM_SIZE)
+}
+
+/// Scans every block for chains of compressed song data, without looking at
+/// the allocation table, as a song whose metadata is lost or cleared can
+/// still be read from its blocks. Returns the blocks of each complete song
+/// found (one-indexed, in the order its skip instructions chain them),
+/// ordered by first block.
+///
+/// A chain which is the tail of a longer complete chain isn't itself counted
+/// as a song.
+pub fn find_chains(blocks: &LsdjBlockTable, compat: Compat) -> Vec<Vec<usize>> {
+    let chains: Vec<Vec<usize>> = (1..=blocks.len()).filter_map(|b| chain_from(blocks, b, compat)).collect();
+    let continued: BTreeSet<usize> = chains.iter().flat_map(|c| c[1..].iter().copied()).collect();
+    chains.into_iter().filter(|c| !continued.contains(&c[0])).collect()
+}
+
+/// Finds songs which were deleted but whose blocks haven't been overwritten
+/// since: complete chains (see `find_chains()`) all of whose blocks are free
+/// in the allocation table.
+pub fn deleted_chains(metadata: &LsdjMetadata, blocks: &LsdjBlockTable, compat: Compat) -> Vec<Vec<usize>> {
+    let mut chains = find_chains(blocks, compat);
+    chains.retain(|c| c.iter().all(|&b| metadata.alloc_table[b - 1] == 0xff));
+    chains
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::{LsdjBlock, LsdjSave};
+
+    #[test]
+    fn test_find_chains() {
+        let mut save = LsdjSave::empty();
+        let mut sram = LsdjSram::empty();
+        for (i, byte) in sram.data.iter_mut().enumerate() {
+            *byte = (i % 0x61) as u8;
+        }
+        save.import_decompressed_song(&[0; SRAM_SIZE], [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        save.import_decompressed_song(&sram.data[..], [b'B', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        let first = save.metadata.size_of(0) + 1;
+        let expected = vec![
+            vec![1],
+            (first..first + save.metadata.size_of(1)).collect::<Vec<_>>(),
+        ];
+        assert_eq!(find_chains(save.blocks(), Compat::Lsdj), expected);
+
+        // the metadata isn't consulted
+        let mut wiped = save.clone();
+        wiped.metadata = Default::default();
+        assert_eq!(find_chains(wiped.blocks(), Compat::Lsdj), expected);
+
+        // a song with a block missing isn't complete
+        wiped.blocks_mut().replace(first + 1, LsdjBlock::empty()).unwrap();
+        assert_eq!(find_chains(wiped.blocks(), Compat::Lsdj), vec![vec![1]]);
+    }
+
+    #[test]
+    fn test_undelete() {
+        let mut save = LsdjSave::empty();
+        for title in [b'A', b'B'] {
+            save.import_decompressed_song(&[title; SRAM_SIZE], [title, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        }
+        let original = save.clone();
+        assert_eq!(deleted_chains(&save.metadata, save.blocks(), Compat::Lsdj), Vec::<Vec<usize>>::new());
+
+        save.delete_song(0).unwrap();
+        let first = original.metadata.next_block_for(0, 0).unwrap();
+        let chain: Vec<usize> = (first..first + original.metadata.size_of(0)).collect();
+        assert_eq!(deleted_chains(&save.metadata, save.blocks(), Compat::Lsdj), vec![chain]);
+        assert_eq!(save.undelete_song(first + 1, [b'A', 0, 0, 0, 0, 0, 0, 0]), Err(crate::lsdj::err::NO_DELETED));
+        assert_eq!(save.undelete_song(first, [b'A', 0, 0, 0, 0, 0, 0, 0]), Ok(0));
+        assert_eq!(save, original);
+        assert_eq!(save.undelete_song(first, [b'A', 0, 0, 0, 0, 0, 0, 0]), Err(crate::lsdj::err::NO_DELETED));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::lsdj::prelude::*;
+use crate::lsdj::err;
+use crate::lsdj::LsdjLayout;
+use crate::lsdj::LsdjSave;
+
+const TITLE_ADDRESS    : usize = 0x134;
+const TITLE_LENGTH     : usize = 0x10;
+const CART_TYPE_ADDRESS: usize = 0x147;
+const RAM_SIZE_ADDRESS : usize = 0x149;
+const CHECKSUM_ADDRESS : usize = 0x14d;
+const HEADER_END       : usize = 0x150;
+
+/// Cartridge types with battery-backed RAM, whose contents are kept in a save
+/// file by flashcarts and emulators.
+const BATTERY_CART_TYPES: [u8; 7] = [0x03, 0x06, 0x09, 0x0f, 0x10, 0x13, 0x1b];
+
+/// The parts of a Game Boy ROM's cartridge header needed to pair it with a
+/// save file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomHeader {
+    /// Title of the ROM (LSDj's ROMs have titles starting with "LSDj").
+    pub title: String,
+    /// Type of cartridge (memory bank controller and extra hardware).
+    pub cart_type: u8,
+    /// Length in bytes of the cartridge's RAM.
+    pub ram_size: usize,
+}
+
+impl RomHeader {
+    /// Reads the cartridge header of `rom`. Returns an `Err` if `rom` is too
+    /// short or the header checksum doesn't match.
+    pub fn from(rom: &[u8]) -> Result<RomHeader, &'static str> {
+        if rom.len() < HEADER_END {
+            return Err(err::BAD_ROM);
+        }
+        let checksum = rom[TITLE_ADDRESS..CHECKSUM_ADDRESS].iter()
+                                                          .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+        if checksum != rom[CHECKSUM_ADDRESS] {
+            return Err(err::BAD_ROM);
+        }
+        let title = &rom[TITLE_ADDRESS..(TITLE_ADDRESS + TITLE_LENGTH)];
+        let end = title.iter().position(|&c| c == 0).unwrap_or(TITLE_LENGTH);
+        let ram_size = match rom[RAM_SIZE_ADDRESS] {
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        };
+        Ok(RomHeader {
+            title: String::from_utf8_lossy(&title[..end]).into_owned(),
+            cart_type: rom[CART_TYPE_ADDRESS],
+            ram_size,
+        })
+    }
+
+    /// Returns true if the cartridge keeps its RAM in a save file.
+    pub fn has_battery(&self) -> bool {
+        BATTERY_CART_TYPES.contains(&self.cart_type) && self.ram_size > 0
+    }
+}
+
+/// Prepares `save` to be loaded alongside the LSDj ROM `rom`, returning the
+/// bytes of the save file to be placed next to it.
+///
+/// Flashcarts and emulators which load a save file with the same name as the
+/// ROM generally expect it to be exactly as large as the cartridge RAM named in
+/// the ROM's header, so `save` is expanded or shrunk to that size. Returns an
+/// `Err` if `rom` isn't an LSDj ROM with battery-backed RAM, or if the songs
+/// in `save` don't fit.
+pub fn bundle(rom: &[u8], mut save: LsdjSave) -> Result<Vec<u8>, &'static str> {
+    let header = RomHeader::from(rom)?;
+    if !header.title.starts_with("LSDj") || !header.has_battery() {
+        return Err(err::NOT_LSDJ_ROM);
+    }
+    save.set_layout(LsdjLayout::detect(header.ram_size as u64)?)?;
+    Ok(save.bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a minimal ROM with the given header fields and a correct header
+    /// checksum.
+    fn rom(title: &str, cart_type: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[TITLE_ADDRESS..(TITLE_ADDRESS + title.len())].copy_from_slice(title.as_bytes());
+        rom[CART_TYPE_ADDRESS] = cart_type;
+        rom[RAM_SIZE_ADDRESS] = ram_size;
+        rom[CHECKSUM_ADDRESS] = rom[TITLE_ADDRESS..CHECKSUM_ADDRESS].iter()
+                                                                   .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+        rom
+    }
+
+    #[test]
+    fn test_rom_header() {
+        let header = RomHeader::from(&rom("LSDj-v9.2.6", 0x1b, 0x04)).unwrap();
+        assert_eq!(header.title, "LSDj-v9.2.6");
+        assert_eq!(header.ram_size, 0x20000);
+        assert!(header.has_battery());
+        assert!(!RomHeader::from(&rom("TETRIS", 0x00, 0x00)).unwrap().has_battery());
+        let mut bad = rom("LSDj", 0x1b, 0x04);
+        bad[CHECKSUM_ADDRESS] ^= 0xff;
+        assert_eq!(RomHeader::from(&bad), Err(err::BAD_ROM));
+        assert_eq!(RomHeader::from(&[0; 0x100]), Err(err::BAD_ROM));
+    }
+
+    #[test]
+    fn test_bundle() {
+        let save = LsdjSave::empty_with_layout(LsdjLayout::SAVE_64KB);
+        assert_eq!(bundle(&rom("LSDj-v9.2.6", 0x1b, 0x04), save).unwrap().len(), 0x20000);
+        let save = LsdjSave::empty();
+        assert_eq!(bundle(&rom("TETRIS", 0x03, 0x04), save), Err(err::NOT_LSDJ_ROM));
+        let save = LsdjSave::empty();
+        assert_eq!(bundle(&rom("LSDj", 0x1b, 0x03), save), Err(err::BAD_SAVE_SIZE));
+    }
+}
@@ -0,0 +1,89 @@
+use crate::lsdj::prelude::*;
+use crate::lsdj::{LsdjError, LsdjSave, SRAM_SIZE};
+use crate::lsdj::metadata::{LsdjTitle, SONG_SLOTS};
+
+/// A handle on a song slot of a save file which holds a song, as returned by
+/// `LsdjSave::slot()`, gathering what the metadata and blocks say about it.
+#[derive(Clone, Copy)]
+pub struct SongSlot<'a> {
+    save: &'a LsdjSave,
+    index: u8,
+}
+
+impl<'a> SongSlot<'a> {
+    /// Returns a handle on slot `index` of `save`, or `None` if the index is
+    /// out of range or no blocks are allocated to it.
+    pub(super) fn new(save: &'a LsdjSave, index: u8) -> Option<SongSlot<'a>> {
+        if index as usize >= SONG_SLOTS || save.metadata.size_of(index) == 0 {
+            return None;
+        }
+        Some(SongSlot { save, index })
+    }
+
+    /// Returns the index of the slot.
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Returns the title of the song, without trailing null bytes.
+    pub fn title(&self) -> String {
+        self.save.metadata.song_title(self.index)
+    }
+
+    /// Returns the title of the song as stored in the title table.
+    pub fn raw_title(&self) -> LsdjTitle {
+        self.save.metadata.title_table[self.index as usize]
+    }
+
+    /// Returns the version byte of the song, incremented by LSDj whenever
+    /// it's saved.
+    pub fn version(&self) -> u8 {
+        self.save.metadata.version_table[self.index as usize]
+    }
+
+    /// Returns the (one-indexed) blocks allocated to the song, in the order
+    /// of the allocation table.
+    pub fn block_indices(&self) -> Vec<usize> {
+        self.save.metadata.alloc_table.iter().enumerate()
+            .filter(|&(_, &owner)| owner == self.index)
+            .map(|(i, _)| i + 1)
+            .collect()
+    }
+
+    /// Returns the blocks of compressed song data allocated to the song, as
+    /// `LsdjSave::export_song()` does.
+    pub fn compressed_bytes(&self) -> Vec<u8> {
+        self.save.export_song(self.index)
+    }
+
+    /// Decompresses the song into an SRAM image, as
+    /// `LsdjSave::decompress_song()` does.
+    pub fn decompress(&self) -> Result<[u8; SRAM_SIZE], LsdjError> {
+        self.save.decompress_song(self.index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::lsdj::BLOCK_SIZE;
+    use super::*;
+
+    #[test]
+    fn test_slot() -> io::Result<()> {
+        let save = crate::lsdj::generate::test_save();
+        let slot = save.slot(0).unwrap();
+        assert_eq!(slot.index(), 0);
+        assert_eq!(slot.title(), "TEST");
+        assert_eq!(&slot.raw_title()[..4], b"TEST");
+        assert_eq!(slot.version(), save.metadata.version_table[0]);
+        assert_eq!(slot.block_indices(), (1..=save.metadata.size_of(0)).collect::<Vec<_>>());
+        assert_eq!(slot.compressed_bytes().len(), slot.block_indices().len() * BLOCK_SIZE);
+        assert_eq!(slot.decompress().ok(), save.decompress_song(0).ok());
+
+        assert!(save.slot(1).is_none());
+        assert!(save.slot(SONG_SLOTS as u8).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use alloc::sync::Arc;
+use core::ops::Deref;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::{LsdjSave, LsdjSram, LsdjBlock, LsdjBlockTable, LsdjLayout, LsdjError, SongSlot};
+use crate::lsdj::metadata::LsdjMetadata;
+
+/// A read-only save file which can be shared between threads, as a GUI or
+/// server would to let several consumers (e.g. one per request) read the same
+/// parsed save. Cloning a snapshot only counts another reference to the save,
+/// rather than copying its 128KB of data.
+///
+/// A snapshot derefs to `LsdjSave`, so every method which doesn't modify the
+/// save can be called on it directly. To make changes, take an owned copy
+/// with `to_save()` (or `into_save()`) and snapshot it again afterwards.
+#[derive(Clone, PartialEq)]
+pub struct LsdjSaveSnapshot(Arc<LsdjSave>);
+
+impl LsdjSaveSnapshot {
+    /// Returns an owned copy of the save, to be modified.
+    pub fn to_save(&self) -> LsdjSave {
+        LsdjSave::clone(&self.0)
+    }
+
+    /// Returns the save, copying it only if other snapshots of it remain.
+    pub fn into_save(self) -> LsdjSave {
+        Arc::unwrap_or_clone(self.0)
+    }
+
+    /// Returns true if `self` and `other` are snapshots of the same save,
+    /// rather than merely equal ones.
+    pub fn ptr_eq(&self, other: &LsdjSaveSnapshot) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Deref for LsdjSaveSnapshot {
+    type Target = LsdjSave;
+
+    fn deref(&self) -> &LsdjSave {
+        &self.0
+    }
+}
+
+impl From<LsdjSave> for LsdjSaveSnapshot {
+    fn from(save: LsdjSave) -> LsdjSaveSnapshot {
+        LsdjSaveSnapshot(Arc::new(save))
+    }
+}
+
+/// Fails to build if any of the types which frontends hand between threads
+/// stop being `Send` and `Sync` (e.g. by gaining an `Rc` or `Cell`).
+#[allow(dead_code)]
+fn assert_send_sync() {
+    fn check<T: Send + Sync>() {}
+    check::<LsdjSave>();
+    check::<LsdjSaveSnapshot>();
+    check::<LsdjSram>();
+    check::<LsdjMetadata>();
+    check::<LsdjBlock>();
+    check::<LsdjBlockTable>();
+    check::<LsdjLayout>();
+    check::<LsdjError>();
+    check::<SongSlot<'static>>();
+    check::<crate::lsdj::song::Song>();
+    check::<crate::lsdj::container::Container>();
+    check::<crate::lsdj::builder::LsdjSaveBuilder>();
+    #[cfg(feature = "mmap")]
+    check::<crate::lsdj::mapped::MappedSave>();
+    check::<Vec<u8>>();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot_threads() {
+        let save = crate::lsdj::generate::test_save();
+        let expected = save.export_song(0);
+        let snapshot = save.snapshot();
+        let handles: Vec<_> = (0..4).map(|_| {
+            let snapshot = snapshot.clone();
+            thread::spawn(move || (snapshot.export_song(0), snapshot.slot(0).map(|s| s.title())))
+        }).collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), (expected.clone(), Some("TEST".to_string())));
+        }
+    }
+
+    #[test]
+    fn test_snapshot_copies() {
+        let snapshot = LsdjSave::empty().snapshot();
+        let shared = snapshot.clone();
+        assert!(snapshot.ptr_eq(&shared));
+
+        let mut save = shared.to_save();
+        save.import_decompressed_song(&[0; crate::lsdj::SRAM_SIZE], [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert!(snapshot.metadata.songs().is_empty());
+        let modified = save.snapshot();
+        assert!(!modified.ptr_eq(&snapshot));
+        assert_eq!(modified.metadata.songs(), [0]);
+
+        drop(shared);
+        assert_eq!(snapshot.into_save().bytes(), LsdjSave::empty().bytes());
+    }
+}
@@ -0,0 +1,178 @@
+use alloc::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::err;
+use crate::lsdj::clean::tables_run;
+use crate::lsdj::song::*;
+
+/// An instrument as stored in a snippet: its name and raw parameter bytes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnippetInstrument {
+    pub name: String,
+    pub params: [u8; INSTRUMENT_PARAMS_LENGTH],
+}
+
+/// Chains along with the phrases, instruments, and tables they play, which can
+/// be saved to a file (as JSON) and imported into another song.
+///
+/// Everything is keyed by its index in the song the snippet was exported
+/// from; importing the snippet moves it all into free slots and points
+/// everything which refers to it at the new indices. The wave
+/// frames and synth settings played by wave instruments are not included.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snippet {
+    /// Format version of the song the snippet was exported from.
+    pub format_version: u8,
+    pub chains: BTreeMap<u8, [ChainStep; STEP_COUNT]>,
+    pub phrases: BTreeMap<u8, [Step; STEP_COUNT]>,
+    pub instruments: BTreeMap<u8, SnippetInstrument>,
+    pub tables: BTreeMap<u8, [[u8; STEP_COUNT]; 6]>,
+}
+
+impl Snippet {
+    /// Copies `chains` out of `song`, along with every phrase they play, every
+    /// instrument those phrases play, and every table those phrases and
+    /// instruments run. Returns an `Err` if any of the chains isn't allocated.
+    pub fn export(song: &Song, chains: &[u8]) -> Result<Snippet, &'static str> {
+        let chains = chains.iter().map(|&c| Ok((c, song.chain(c as usize).ok_or(err::NO_CHAIN)?)))
+                           .collect::<Result<BTreeMap<u8, [ChainStep; STEP_COUNT]>, &'static str>>()?;
+        let phrases: BTreeMap<u8, [Step; STEP_COUNT]> = chains.values().flatten().filter_map(|s| s.phrase)
+            .filter_map(|p| Some((p, song.phrase(p as usize)?)))
+            .collect();
+        let mut instruments = BTreeMap::new();
+        let mut run = Vec::new();
+        for step in phrases.values().flatten() {
+            if let Some((i, instrument)) = step.instrument.and_then(|i| Some((i, song.instrument(i as usize)?))) {
+                run.extend(instrument.table());
+                instruments.insert(i, SnippetInstrument { name: instrument.name, params: instrument.params });
+            }
+            if step.command == TABLE_COMMAND {
+                run.push(step.value);
+            }
+        }
+        let tables = tables_run(song, run).into_iter().filter_map(|t| Some((t, song.table(t as usize)?))).collect();
+        Ok(Snippet { format_version: song.format_version(), chains, phrases, instruments, tables })
+    }
+
+    /// Copies this snippet into free slots of `song`, returning the index
+    /// each chain was copied into by its index in the snippet. Returns an
+    /// `Err` (leaving the song unchanged) if there aren't enough free chains,
+    /// phrases, instruments, or tables.
+    pub fn import(&self, song: &mut Song) -> Result<BTreeMap<u8, u8>, &'static str> {
+        let chains = remap(&self.chains, free_slots(CHAIN_COUNT, self.chains.len(), |c| song.chain(c).is_none())?);
+        let phrases = remap(&self.phrases, free_slots(PHRASE_COUNT, self.phrases.len(), |p| song.phrase(p).is_none())?);
+        let instruments = remap(&self.instruments,
+                                free_slots(INSTRUMENT_COUNT, self.instruments.len(), |i| song.instrument(i).is_none())?);
+        let tables = remap(&self.tables, free_slots(TABLE_COUNT, self.tables.len(), |t| song.table(t).is_none())?);
+
+        import_tables(song, &self.tables, &tables);
+        for (old, instrument) in self.instruments.iter() {
+            let mut copy = Instrument { name: instrument.name.clone(), kind: InstrumentType::from(instrument.params[0]),
+                                        params: instrument.params };
+            copy.set_table(copy.table().map(|t| *tables.get(&t).unwrap_or(&t)));
+            song.set_instrument(instruments[old] as usize, &copy.name, &copy.params);
+        }
+        for (old, steps) in self.phrases.iter() {
+            let mut steps = *steps;
+            for step in steps.iter_mut() {
+                step.instrument = step.instrument.map(|i| *instruments.get(&i).unwrap_or(&i));
+                retarget(&tables, step.command, &mut step.value);
+            }
+            song.set_phrase(phrases[old] as usize, &steps);
+        }
+        for (old, steps) in self.chains.iter() {
+            let mut steps = *steps;
+            for step in steps.iter_mut() {
+                step.phrase = step.phrase.map(|p| *phrases.get(&p).unwrap_or(&p));
+            }
+            song.set_chain(chains[old] as usize, &steps);
+        }
+        Ok(chains)
+    }
+}
+
+/// Copies each of `tables` into `song` at the index `remapped` gives it,
+/// pointing the table commands in them at the new indices.
+pub(super) fn import_tables(song: &mut Song, tables: &BTreeMap<u8, [[u8; STEP_COUNT]; 6]>,
+                            remapped: &BTreeMap<u8, u8>) {
+    for (old, columns) in tables.iter() {
+        let mut columns = *columns;
+        let [_, _, commands_1, values_1, commands_2, values_2] = &mut columns;
+        for (commands, values) in [(commands_1, values_1), (commands_2, values_2)] {
+            for (&command, value) in commands.iter().zip(values.iter_mut()) {
+                retarget(remapped, command, value);
+            }
+        }
+        song.set_table(remapped[old] as usize, &columns);
+    }
+}
+
+/// Points `value` at the new index `remapped` gives the table it names, if
+/// `command` is the table command.
+fn retarget(remapped: &BTreeMap<u8, u8>, command: u8, value: &mut u8) {
+    if let Some(&table) = remapped.get(value).filter(|_| command == TABLE_COMMAND) {
+        *value = table;
+    }
+}
+
+/// Returns the first `n` of the `count` slots for which `is_free` is true, or an
+/// `Err` if there aren't that many.
+pub(super) fn free_slots(count: usize, n: usize, is_free: impl Fn(usize) -> bool) -> Result<Vec<u8>, &'static str> {
+    let free: Vec<u8> = (0..count).filter(|&i| is_free(i)).take(n).map(|i| i as u8).collect();
+    if free.len() < n { Err(err::SONG_FULL) } else { Ok(free) }
+}
+
+/// Pairs each key of `items` with a new index from `slots`.
+pub(super) fn remap<T>(items: &BTreeMap<u8, T>, slots: Vec<u8>) -> BTreeMap<u8, u8> {
+    items.keys().copied().zip(slots).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::song::tests::*;
+
+    #[test]
+    fn test_snippet() {
+        let mut song = empty_song();
+        set_chain(&mut song, 0x05, 0x03, 0x0c);
+        set_phrase(&mut song, 0x03, 0x19, 0x02, TABLE_COMMAND, 0x01);
+        set_instrument(&mut song, 0x02, "LEAD", [0, 0, 0, 0, 0, 0x24, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        set_table(&mut song, 0x01, 0, 0);
+        set_table(&mut song, 0x04, TABLE_COMMAND, 0x01);
+        set_table(&mut song, 0x06, 0, 0); // not run
+        assert_eq!(Snippet::export(&song, &[0x05, 0x06]), Err(err::NO_CHAIN));
+        let snippet = Snippet::export(&song, &[0x05]).unwrap();
+        assert_eq!(snippet.phrases.keys().copied().collect::<Vec<u8>>(), vec![0x03]);
+        assert_eq!(snippet.instruments.keys().copied().collect::<Vec<u8>>(), vec![0x02]);
+        assert_eq!(snippet.tables.keys().copied().collect::<Vec<u8>>(), vec![0x01, 0x04]);
+        let json = serde_json::to_string(&snippet).unwrap();
+        assert_eq!(serde_json::from_str::<Snippet>(&json).unwrap(), snippet);
+
+        let mut other = empty_song();
+        set_chain(&mut other, 0x00, 0x00, 0);
+        set_phrase(&mut other, 0x00, 0x01, 0x00, 0, 0);
+        set_instrument(&mut other, 0x00, "BASS", [0; 16]);
+        set_table(&mut other, 0x00, 0, 0);
+        set_table(&mut other, 0x01, 0, 0);
+        assert_eq!(snippet.import(&mut other), Ok(BTreeMap::from([(0x05, 0x01)])));
+        let chain = other.chain(0x01).unwrap();
+        assert_eq!(chain[0], ChainStep { phrase: Some(0x01), transpose: 0x0c });
+        let step = other.phrase(0x01).unwrap()[0];
+        assert_eq!((step.note, step.instrument, step.command, step.value), (0x19, Some(0x01), TABLE_COMMAND, 0x02));
+        let instrument = other.instrument(0x01).unwrap();
+        assert_eq!((instrument.name.as_str(), instrument.table()), ("LEAD", Some(0x03)));
+        assert_eq!(other.table(0x03).unwrap()[3][0], 0x02);
+        assert_eq!(other.phrase(0x00).unwrap()[0].note, 0x01);
+
+        let mut full = empty_song();
+        for t in 0..TABLE_COUNT - 1 {
+            set_table(&mut full, t, 0, 0);
+        }
+        let original = full.clone();
+        assert_eq!(snippet.import(&mut full), Err(err::SONG_FULL));
+        assert_eq!(&full.data[..], &original.data[..]);
+    }
+}
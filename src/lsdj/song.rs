@@ -0,0 +1,818 @@
+use alloc::collections::BTreeSet;
+use core::fmt;
+use core::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::{err, SRAM_SIZE};
+use crate::lsdj::compression::DEF_INST_VALUES;
+
+pub const PHRASE_COUNT    : usize = 0xff;
+pub const CHAIN_COUNT     : usize = 0x80;
+pub const INSTRUMENT_COUNT: usize = 0x40;
+pub const TABLE_COUNT     : usize = 0x20;
+pub const GROOVE_COUNT    : usize = 0x20;
+pub const ROW_COUNT       : usize = 0x100;
+pub const CHANNEL_COUNT   : usize = 4;
+pub const STEP_COUNT      : usize = 0x10;
+pub const SYNTH_COUNT     : usize = 0x10;
+pub const WORD_COUNT      : usize = 0x2a;
+/// Allophones in each speech word.
+pub const WORD_LENGTH     : usize = 0x10;
+/// Characters in the name of a speech word.
+pub const WORD_NAME_LENGTH: usize = 4;
+/// Bytes in a wave frame, which holds 32 4-bit samples (high nibble first).
+pub const WAVE_LENGTH     : usize = 0x10;
+/// Bytes of parameters for each soft synth.
+pub const SYNTH_PARAMS_LENGTH: usize = 0x10;
+/// Range of tempos LSDj can play, in BPM. Tempos above 255 BPM are stored
+/// wrapped around into the bytes below `MIN_TEMPO`.
+pub const MIN_TEMPO       : u16   = 40;
+pub const MAX_TEMPO       : u16   = 295;
+/// The table command, whose value is the table to run.
+pub const TABLE_COMMAND   : u8    = 1;
+/// Highest note LSDj can play (B-B); notes count up in semitones from 1 (C-3).
+pub const HIGHEST_NOTE    : u8    = 0x6c;
+
+pub(crate) const PHRASE_NOTES_ADDRESS      : usize = 0x0000;
+const GROOVES_ADDRESS           : usize = 0x1090;
+const SONG_CHAINS_ADDRESS       : usize = 0x1290;
+const TABLE_ENVELOPES_ADDRESS   : usize = 0x1690;
+const WORDS_ADDRESS             : usize = 0x1890; // pairs of allophone and duration
+const WORD_NAMES_ADDRESS        : usize = 0x1dd0;
+const CHECK_1_ADDRESS           : usize = 0x1e78;
+const INSTRUMENT_NAMES_ADDRESS  : usize = 0x1e7a;
+const INSTRUMENT_NAME_LENGTH    : usize = 5;
+const TABLE_ALLOC_ADDRESS       : usize = 0x2020;
+const INSTRUMENT_ALLOC_ADDRESS  : usize = 0x2040;
+pub(crate) const CHAIN_PHRASES_ADDRESS     : usize = 0x2080;
+pub(crate) const CHAIN_TRANSPOSES_ADDRESS  : usize = 0x2880;
+const INSTRUMENT_PARAMS_ADDRESS : usize = 0x3080;
+pub const INSTRUMENT_PARAMS_LENGTH  : usize = 0x10;
+const TABLE_TRANSPOSES_ADDRESS  : usize = 0x3480;
+const TABLE_COMMANDS_1_ADDRESS  : usize = 0x3680;
+const TABLE_VALUES_1_ADDRESS    : usize = 0x3880;
+const TABLE_COMMANDS_2_ADDRESS  : usize = 0x3a80;
+const TABLE_VALUES_2_ADDRESS    : usize = 0x3c80;
+const CHECK_2_ADDRESS           : usize = 0x3e80;
+const PHRASE_ALLOC_ADDRESS      : usize = 0x3e82;
+const CHAIN_ALLOC_ADDRESS       : usize = 0x3ea2;
+const SYNTH_PARAMS_ADDRESS      : usize = 0x3eb2;
+const TEMPO_ADDRESS             : usize = 0x3fb4;
+pub(crate) const PHRASE_COMMANDS_ADDRESS   : usize = 0x4000;
+pub(crate) const PHRASE_VALUES_ADDRESS     : usize = 0x4ff0;
+pub(crate) const WAVES_ADDRESS             : usize = 0x6000;
+pub(crate) const PHRASE_INSTRUMENTS_ADDRESS: usize = 0x7000;
+const CHECK_3_ADDRESS           : usize = 0x7ff0;
+const FORMAT_VERSION_ADDRESS    : usize = 0x7fff;
+
+const CHECK_BYTES: [u8; 2] = [b'r', b'b'];
+
+/// Address, length, and name of each region of song data, in order.
+const REGIONS: [(usize, usize, &str); 28] = [
+    (PHRASE_NOTES_ADDRESS, PHRASE_COUNT * STEP_COUNT, "phrase notes"),
+    (GROOVES_ADDRESS, GROOVE_COUNT * STEP_COUNT, "grooves"),
+    (SONG_CHAINS_ADDRESS, ROW_COUNT * CHANNEL_COUNT, "song rows"),
+    (TABLE_ENVELOPES_ADDRESS, TABLE_COUNT * STEP_COUNT, "table envelopes"),
+    (WORDS_ADDRESS, WORD_COUNT * WORD_LENGTH * 2, "speech words"),
+    (WORD_NAMES_ADDRESS, WORD_COUNT * WORD_NAME_LENGTH, "speech word names"),
+    (CHECK_1_ADDRESS, 2, "first 'rb' check bytes"),
+    (INSTRUMENT_NAMES_ADDRESS, INSTRUMENT_COUNT * INSTRUMENT_NAME_LENGTH, "instrument names"),
+    (TABLE_ALLOC_ADDRESS, TABLE_COUNT, "table allocation table"),
+    (INSTRUMENT_ALLOC_ADDRESS, INSTRUMENT_COUNT, "instrument allocation table"),
+    (CHAIN_PHRASES_ADDRESS, CHAIN_COUNT * STEP_COUNT, "chain phrases"),
+    (CHAIN_TRANSPOSES_ADDRESS, CHAIN_COUNT * STEP_COUNT, "chain transposes"),
+    (INSTRUMENT_PARAMS_ADDRESS, INSTRUMENT_COUNT * INSTRUMENT_PARAMS_LENGTH, "instrument parameters"),
+    (TABLE_TRANSPOSES_ADDRESS, TABLE_COUNT * STEP_COUNT, "table transposes"),
+    (TABLE_COMMANDS_1_ADDRESS, TABLE_COUNT * STEP_COUNT, "table commands (first column)"),
+    (TABLE_VALUES_1_ADDRESS, TABLE_COUNT * STEP_COUNT, "table command values (first column)"),
+    (TABLE_COMMANDS_2_ADDRESS, TABLE_COUNT * STEP_COUNT, "table commands (second column)"),
+    (TABLE_VALUES_2_ADDRESS, TABLE_COUNT * STEP_COUNT, "table command values (second column)"),
+    (CHECK_2_ADDRESS, 2, "second 'rb' check bytes"),
+    (PHRASE_ALLOC_ADDRESS, 0x20, "phrase allocation table"),
+    (CHAIN_ALLOC_ADDRESS, CHAIN_COUNT / 8, "chain allocation table"),
+    (SYNTH_PARAMS_ADDRESS, SYNTH_COUNT * SYNTH_PARAMS_LENGTH, "synth parameters"),
+    (TEMPO_ADDRESS, 1, "tempo"),
+    (PHRASE_COMMANDS_ADDRESS, PHRASE_COUNT * STEP_COUNT, "phrase commands"),
+    (PHRASE_VALUES_ADDRESS, PHRASE_COUNT * STEP_COUNT, "phrase command values"),
+    (WAVES_ADDRESS, PHRASE_INSTRUMENTS_ADDRESS - WAVES_ADDRESS, "wave frames"),
+    (PHRASE_INSTRUMENTS_ADDRESS, PHRASE_COUNT * STEP_COUNT, "phrase instruments"),
+    (CHECK_3_ADDRESS, 2, "third 'rb' check bytes"),
+];
+
+// Columns of each table, in the order returned by `Song::table()`
+const TABLE_COLUMN_ADDRESSES: [usize; 6] = [TABLE_ENVELOPES_ADDRESS, TABLE_TRANSPOSES_ADDRESS,
+                                            TABLE_COMMANDS_1_ADDRESS, TABLE_VALUES_1_ADDRESS,
+                                            TABLE_COMMANDS_2_ADDRESS, TABLE_VALUES_2_ADDRESS];
+
+/// Marks an empty song row, chain step, or phrase instrument.
+const EMPTY: u8 = 0xff;
+
+// Instrument parameter offsets of the two kits used by a kit instrument
+const KIT_1_OFFSET: usize = 2;
+const KIT_2_OFFSET: usize = 9;
+const KIT_MASK    : u8    = 0x3f;
+// Instrument parameter holding the table an instrument runs, if enabled
+const TABLE_OFFSET : usize = 5;
+const TABLE_ENABLED: u8    = 0x20;
+const TABLE_MASK   : u8    = 0x1f;
+
+const NOTE_NAMES: [&str; 12] = ["C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-"];
+const COMMAND_LETTERS: &[u8] = b"-ACDEFGHKLMOPRSTVWZ";
+
+/// The kind of sound an instrument makes, which decides how its parameters
+/// are interpreted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InstrumentType {
+    Pulse,
+    Wave,
+    Kit,
+    Noise,
+    /// A type byte which no known version of LSDj writes.
+    Unknown(u8),
+}
+
+impl From<u8> for InstrumentType {
+    fn from(byte: u8) -> InstrumentType {
+        match byte {
+            0 => InstrumentType::Pulse,
+            1 => InstrumentType::Wave,
+            2 => InstrumentType::Kit,
+            3 => InstrumentType::Noise,
+            b => InstrumentType::Unknown(b),
+        }
+    }
+}
+
+impl FromStr for InstrumentType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<InstrumentType, &'static str> {
+        match s {
+            "pulse" => Ok(InstrumentType::Pulse),
+            "wave" => Ok(InstrumentType::Wave),
+            "kit" => Ok(InstrumentType::Kit),
+            "noise" => Ok(InstrumentType::Noise),
+            _ => Err(err::BAD_INSTRUMENT_TYPE),
+        }
+    }
+}
+
+impl fmt::Display for InstrumentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstrumentType::Pulse => write!(f, "pulse"),
+            InstrumentType::Wave => write!(f, "wave"),
+            InstrumentType::Kit => write!(f, "kit"),
+            InstrumentType::Noise => write!(f, "noise"),
+            InstrumentType::Unknown(b) => write!(f, "?{:02X}", b),
+        }
+    }
+}
+
+/// One step of a phrase.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+    /// Note played (0 if none; 1 is C-3).
+    pub note: u8,
+    /// Instrument played, if any.
+    pub instrument: Option<u8>,
+    /// Effect command (0 if none).
+    pub command: u8,
+    /// Value of the effect command.
+    pub value: u8,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ", note_name(self.note))?;
+        match self.instrument {
+            Some(i) => write!(f, "I{:02X} ", i)?,
+            None => write!(f, "--- ")?,
+        }
+        if self.command == 0 {
+            write!(f, "---")
+        } else {
+            write!(f, "{}{:02X}", command_name(self.command), self.value)
+        }
+    }
+}
+
+/// A step of a chain: the phrase it plays, transposed by some number of
+/// semitones.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChainStep {
+    pub phrase: Option<u8>,
+    pub transpose: u8,
+}
+
+impl fmt::Display for ChainStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.phrase {
+            Some(p) => write!(f, "{:02X} {:02X}", p, self.transpose),
+            None => write!(f, "-- {:02X}", self.transpose),
+        }
+    }
+}
+
+/// An instrument: its name, type, and raw parameter bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instrument {
+    pub name: String,
+    pub kind: InstrumentType,
+    pub params: [u8; INSTRUMENT_PARAMS_LENGTH],
+}
+
+impl Instrument {
+    /// Returns the kits played by this instrument, if it is a kit instrument.
+    pub fn kits(&self) -> Option<[u8; 2]> {
+        match self.kind {
+            InstrumentType::Kit => Some([self.params[KIT_1_OFFSET] & KIT_MASK, self.params[KIT_2_OFFSET] & KIT_MASK]),
+            _ => None,
+        }
+    }
+
+    /// Returns the table this instrument runs on every note, if any.
+    pub fn table(&self) -> Option<u8> {
+        let byte = self.params[TABLE_OFFSET];
+        if byte & TABLE_ENABLED != 0 { Some(byte & TABLE_MASK) } else { None }
+    }
+
+    /// Sets the table this instrument runs on every note, if any.
+    pub fn set_table(&mut self, table: Option<u8>) {
+        let byte = &mut self.params[TABLE_OFFSET];
+        *byte = match table {
+            Some(t) => (*byte & !TABLE_MASK) | TABLE_ENABLED | (t & TABLE_MASK),
+            None => *byte & !TABLE_ENABLED,
+        };
+    }
+}
+
+/// A word spoken by speech instruments: a name, and the allophones making up
+/// the word (numbered from 1; see `speech::allophone_name()`), each with how
+/// long it lasts.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Word {
+    pub name: String,
+    pub allophones: Vec<(u8, u8)>,
+}
+
+/// Returns the name of `note` as shown by LSDj (e.g. `C-3`), or `---` if no
+/// note is played.
+pub fn note_name(note: u8) -> String {
+    match note {
+        0 => "---".to_string(),
+        n => format!("{}{:X}", NOTE_NAMES[(n as usize - 1) % 12], 3 + (n as usize - 1) / 12),
+    }
+}
+
+/// Returns the letter of effect `command` as shown by LSDj, or `?` for
+/// commands not known to this tool.
+pub fn command_name(command: u8) -> char {
+    COMMAND_LETTERS.get(command as usize).map(|&c| c as char).unwrap_or('?')
+}
+
+/// Returns the name of the region of song data holding `address` (e.g.
+/// `phrase notes`), or `other song data` for bytes between the regions known
+/// to this tool.
+pub fn region(address: usize) -> &'static str {
+    if address == FORMAT_VERSION_ADDRESS {
+        return "format version";
+    }
+    REGIONS.iter().find(|&&(start, len, _)| (start..(start + len)).contains(&address))
+           .map_or("other song data", |&(_, _, name)| name)
+}
+
+/// The contents of a decompressed song ($8000 bytes of SRAM), with accessors
+/// for the parts of it which make up the song: the song rows, chains, phrases,
+/// instruments, and tables.
+///
+/// Chains, phrases, instruments, and tables which LSDj hasn't allocated are
+/// reported as `None`, whatever bytes happen to be left in them. So are those
+/// whose index is out of range, and the methods setting or clearing them
+/// return `None` for such an index, leaving the song unchanged.
+#[derive(Clone)]
+pub struct Song {
+    pub data: [u8; SRAM_SIZE],
+}
+
+impl Song {
+    /// Wraps decompressed song data. Returns an `Err` if it is too short or
+    /// lacks the check bytes LSDj writes into every song.
+    pub fn from(bytes: &[u8]) -> Result<Song, &'static str> {
+        if bytes.len() < SRAM_SIZE {
+            return Err(err::BAD_SONG);
+        }
+        let mut data = [0; SRAM_SIZE];
+        data.copy_from_slice(&bytes[..SRAM_SIZE]);
+        for &address in [CHECK_1_ADDRESS, CHECK_2_ADDRESS, CHECK_3_ADDRESS].iter() {
+            if data[address..(address + 2)] != CHECK_BYTES {
+                return Err(err::BAD_SONG);
+            }
+        }
+        Ok(Song { data })
+    }
+
+    /// Returns an empty song: one with the check bytes LSDj needs to load it,
+    /// and no chains or phrases placed anywhere. Its format version is 0.
+    pub fn empty() -> Song {
+        let mut data = [0; SRAM_SIZE];
+        for &address in [CHECK_1_ADDRESS, CHECK_2_ADDRESS, CHECK_3_ADDRESS].iter() {
+            data[address..(address + 2)].copy_from_slice(&CHECK_BYTES);
+        }
+        data[SONG_CHAINS_ADDRESS..(SONG_CHAINS_ADDRESS + ROW_COUNT * CHANNEL_COUNT)].fill(EMPTY);
+        data[CHAIN_PHRASES_ADDRESS..CHAIN_TRANSPOSES_ADDRESS].fill(EMPTY);
+        data[PHRASE_INSTRUMENTS_ADDRESS..(PHRASE_INSTRUMENTS_ADDRESS + PHRASE_COUNT * STEP_COUNT)].fill(EMPTY);
+        Song { data }
+    }
+
+    /// Returns the version of LSDj's song format in which this song is stored.
+    pub fn format_version(&self) -> u8 {
+        self.data[FORMAT_VERSION_ADDRESS]
+    }
+
+    /// Sets the version of LSDj's song format in which this song is stored.
+    pub fn set_format_version(&mut self, version: u8) {
+        self.data[FORMAT_VERSION_ADDRESS] = version;
+    }
+
+    /// Returns the tempo of this song in BPM.
+    pub fn tempo(&self) -> u16 {
+        match self.data[TEMPO_ADDRESS] {
+            b if (b as u16) < MIN_TEMPO => b as u16 + 0x100,
+            b => b as u16,
+        }
+    }
+
+    /// Sets the tempo of this song in BPM, which must be between `MIN_TEMPO`
+    /// and `MAX_TEMPO`.
+    pub fn set_tempo(&mut self, bpm: u16) {
+        self.data[TEMPO_ADDRESS] = bpm as u8;
+    }
+
+    /// Returns the chain played by each channel at `row` of the song.
+    pub fn row(&self, row: usize) -> [Option<u8>; CHANNEL_COUNT] {
+        let mut chains = [None; CHANNEL_COUNT];
+        for (channel, chain) in chains.iter_mut().enumerate() {
+            *chain = non_empty(self.data[SONG_CHAINS_ADDRESS + row * CHANNEL_COUNT + channel]);
+        }
+        chains
+    }
+
+    /// Sets the chain played by each channel at `row` of the song.
+    pub fn set_row(&mut self, row: usize, chains: [Option<u8>; CHANNEL_COUNT]) {
+        for (channel, chain) in chains.iter().enumerate() {
+            self.data[SONG_CHAINS_ADDRESS + row * CHANNEL_COUNT + channel] = chain.unwrap_or(EMPTY);
+        }
+    }
+
+    /// Returns the number of song rows up to and including the last one
+    /// which plays a chain on any channel.
+    pub fn length(&self) -> usize {
+        (0..ROW_COUNT).rev().find(|&r| self.row(r).iter().any(Option::is_some)).map_or(0, |r| r + 1)
+    }
+
+    /// Returns the steps of `chain`, or `None` if it isn't allocated.
+    pub fn chain(&self, chain: usize) -> Option<[ChainStep; STEP_COUNT]> {
+        if chain >= CHAIN_COUNT || !bit_set(&self.data[CHAIN_ALLOC_ADDRESS..], chain) {
+            return None;
+        }
+        let mut steps = [ChainStep { phrase: None, transpose: 0 }; STEP_COUNT];
+        for (i, step) in steps.iter_mut().enumerate() {
+            step.phrase = non_empty(self.data[CHAIN_PHRASES_ADDRESS + chain * STEP_COUNT + i]);
+            step.transpose = self.data[CHAIN_TRANSPOSES_ADDRESS + chain * STEP_COUNT + i];
+        }
+        Some(steps)
+    }
+
+    /// Returns the steps of `phrase`, or `None` if it isn't allocated.
+    pub fn phrase(&self, phrase: usize) -> Option<[Step; STEP_COUNT]> {
+        if phrase >= PHRASE_COUNT || !bit_set(&self.data[PHRASE_ALLOC_ADDRESS..], phrase) {
+            return None;
+        }
+        let mut steps = [Step { note: 0, instrument: None, command: 0, value: 0 }; STEP_COUNT];
+        for (i, step) in steps.iter_mut().enumerate() {
+            let offset = phrase * STEP_COUNT + i;
+            step.note = self.data[PHRASE_NOTES_ADDRESS + offset];
+            step.instrument = non_empty(self.data[PHRASE_INSTRUMENTS_ADDRESS + offset]);
+            step.command = self.data[PHRASE_COMMANDS_ADDRESS + offset];
+            step.value = self.data[PHRASE_VALUES_ADDRESS + offset];
+        }
+        Some(steps)
+    }
+
+    /// Sets the note played at `step` of `phrase` (0 for none).
+    pub fn set_note(&mut self, phrase: usize, step: usize, note: u8) -> Option<()> {
+        if phrase >= PHRASE_COUNT || step >= STEP_COUNT {
+            return None;
+        }
+        self.data[PHRASE_NOTES_ADDRESS + phrase * STEP_COUNT + step] = note;
+        Some(())
+    }
+
+    /// Returns `instrument`, or `None` if it isn't allocated.
+    pub fn instrument(&self, instrument: usize) -> Option<Instrument> {
+        if instrument >= INSTRUMENT_COUNT || self.data[INSTRUMENT_ALLOC_ADDRESS + instrument] == 0 {
+            return None;
+        }
+        let name = &self.data[(INSTRUMENT_NAMES_ADDRESS + instrument * INSTRUMENT_NAME_LENGTH)..]
+                             [..INSTRUMENT_NAME_LENGTH];
+        let end = name.iter().position(|&c| c == 0).unwrap_or(INSTRUMENT_NAME_LENGTH);
+        let mut params = [0; INSTRUMENT_PARAMS_LENGTH];
+        params.copy_from_slice(&self.data[(INSTRUMENT_PARAMS_ADDRESS + instrument * INSTRUMENT_PARAMS_LENGTH)..]
+                                         [..INSTRUMENT_PARAMS_LENGTH]);
+        Some(Instrument {
+            name: String::from_utf8_lossy(&name[..end]).into_owned(),
+            kind: InstrumentType::from(params[0]),
+            params,
+        })
+    }
+
+    /// Returns the raw bytes of `table` (envelopes, transposes, and both
+    /// columns of commands and values, in that order), or `None` if it isn't
+    /// allocated.
+    pub fn table(&self, table: usize) -> Option<[[u8; STEP_COUNT]; 6]> {
+        if table >= TABLE_COUNT || self.data[TABLE_ALLOC_ADDRESS + table] == 0 {
+            return None;
+        }
+        let mut columns = [[0; STEP_COUNT]; 6];
+        for (column, address) in columns.iter_mut().zip(TABLE_COLUMN_ADDRESSES.iter()) {
+            column.copy_from_slice(&self.data[(address + table * STEP_COUNT)..][..STEP_COUNT]);
+        }
+        Some(columns)
+    }
+
+    /// Returns the step lengths (in ticks) of `groove`.
+    pub fn groove(&self, groove: usize) -> [u8; STEP_COUNT] {
+        let mut steps = [0; STEP_COUNT];
+        steps.copy_from_slice(&self.data[(GROOVES_ADDRESS + groove * STEP_COUNT)..][..STEP_COUNT]);
+        steps
+    }
+
+    /// Sets the step lengths (in ticks) of `groove`.
+    pub fn set_groove(&mut self, groove: usize, steps: [u8; STEP_COUNT]) {
+        self.data[(GROOVES_ADDRESS + groove * STEP_COUNT)..][..STEP_COUNT].copy_from_slice(&steps);
+    }
+
+    /// Allocates `chain`, setting its steps.
+    pub fn set_chain(&mut self, chain: usize, steps: &[ChainStep; STEP_COUNT]) -> Option<()> {
+        if chain >= CHAIN_COUNT {
+            return None;
+        }
+        set_bit(&mut self.data[CHAIN_ALLOC_ADDRESS..], chain);
+        for (i, step) in steps.iter().enumerate() {
+            self.data[CHAIN_PHRASES_ADDRESS + chain * STEP_COUNT + i] = step.phrase.unwrap_or(EMPTY);
+            self.data[CHAIN_TRANSPOSES_ADDRESS + chain * STEP_COUNT + i] = step.transpose;
+        }
+        Some(())
+    }
+
+    /// Allocates `phrase`, setting its steps.
+    pub fn set_phrase(&mut self, phrase: usize, steps: &[Step; STEP_COUNT]) -> Option<()> {
+        if phrase >= PHRASE_COUNT {
+            return None;
+        }
+        set_bit(&mut self.data[PHRASE_ALLOC_ADDRESS..], phrase);
+        for (i, step) in steps.iter().enumerate() {
+            let offset = phrase * STEP_COUNT + i;
+            self.data[PHRASE_NOTES_ADDRESS + offset] = step.note;
+            self.data[PHRASE_INSTRUMENTS_ADDRESS + offset] = step.instrument.unwrap_or(EMPTY);
+            self.data[PHRASE_COMMANDS_ADDRESS + offset] = step.command;
+            self.data[PHRASE_VALUES_ADDRESS + offset] = step.value;
+        }
+        Some(())
+    }
+
+    /// Allocates `instrument`, setting its name (truncated to five bytes) and
+    /// parameters. Its type is taken from its parameters.
+    pub fn set_instrument(&mut self, instrument: usize, name: &str, params: &[u8; INSTRUMENT_PARAMS_LENGTH]) -> Option<()> {
+        if instrument >= INSTRUMENT_COUNT {
+            return None;
+        }
+        self.data[INSTRUMENT_ALLOC_ADDRESS + instrument] = 1;
+        let address = INSTRUMENT_NAMES_ADDRESS + instrument * INSTRUMENT_NAME_LENGTH;
+        let name = &name.as_bytes()[..name.len().min(INSTRUMENT_NAME_LENGTH)];
+        self.data[address..(address + INSTRUMENT_NAME_LENGTH)].fill(0);
+        self.data[address..(address + name.len())].copy_from_slice(name);
+        self.data[(INSTRUMENT_PARAMS_ADDRESS + instrument * INSTRUMENT_PARAMS_LENGTH)..][..INSTRUMENT_PARAMS_LENGTH]
+            .copy_from_slice(params);
+        Some(())
+    }
+
+    /// Allocates `table`, setting its columns (in the order returned by
+    /// `table()`).
+    pub fn set_table(&mut self, table: usize, columns: &[[u8; STEP_COUNT]; 6]) -> Option<()> {
+        if table >= TABLE_COUNT {
+            return None;
+        }
+        self.data[TABLE_ALLOC_ADDRESS + table] = 1;
+        for (column, address) in columns.iter().zip(TABLE_COLUMN_ADDRESSES.iter()) {
+            self.data[(address + table * STEP_COUNT)..][..STEP_COUNT].copy_from_slice(column);
+        }
+        Some(())
+    }
+
+    /// Sets the phrase played at `step` of `chain`.
+    pub fn set_chain_phrase(&mut self, chain: usize, step: usize, phrase: Option<u8>) -> Option<()> {
+        if chain >= CHAIN_COUNT || step >= STEP_COUNT {
+            return None;
+        }
+        self.data[CHAIN_PHRASES_ADDRESS + chain * STEP_COUNT + step] = phrase.unwrap_or(EMPTY);
+        Some(())
+    }
+
+    /// Frees `chain`, clearing its steps.
+    pub fn clear_chain(&mut self, chain: usize) -> Option<()> {
+        if chain >= CHAIN_COUNT {
+            return None;
+        }
+        clear_bit(&mut self.data[CHAIN_ALLOC_ADDRESS..], chain);
+        self.data[(CHAIN_PHRASES_ADDRESS + chain * STEP_COUNT)..][..STEP_COUNT].fill(EMPTY);
+        self.data[(CHAIN_TRANSPOSES_ADDRESS + chain * STEP_COUNT)..][..STEP_COUNT].fill(0);
+        Some(())
+    }
+
+    /// Frees `phrase`, clearing its steps.
+    pub fn clear_phrase(&mut self, phrase: usize) -> Option<()> {
+        if phrase >= PHRASE_COUNT {
+            return None;
+        }
+        clear_bit(&mut self.data[PHRASE_ALLOC_ADDRESS..], phrase);
+        for (address, byte) in [(PHRASE_NOTES_ADDRESS, 0), (PHRASE_INSTRUMENTS_ADDRESS, EMPTY),
+                                (PHRASE_COMMANDS_ADDRESS, 0), (PHRASE_VALUES_ADDRESS, 0)] {
+            self.data[(address + phrase * STEP_COUNT)..][..STEP_COUNT].fill(byte);
+        }
+        Some(())
+    }
+
+    /// Frees `instrument`, clearing its name and resetting its parameters to
+    /// those of LSDj's default instrument.
+    pub fn clear_instrument(&mut self, instrument: usize) -> Option<()> {
+        if instrument >= INSTRUMENT_COUNT {
+            return None;
+        }
+        self.data[INSTRUMENT_ALLOC_ADDRESS + instrument] = 0;
+        self.data[(INSTRUMENT_NAMES_ADDRESS + instrument * INSTRUMENT_NAME_LENGTH)..][..INSTRUMENT_NAME_LENGTH].fill(0);
+        self.data[(INSTRUMENT_PARAMS_ADDRESS + instrument * INSTRUMENT_PARAMS_LENGTH)..][..INSTRUMENT_PARAMS_LENGTH]
+            .copy_from_slice(&DEF_INST_VALUES);
+        Some(())
+    }
+
+    /// Frees `table`, clearing all of its columns.
+    pub fn clear_table(&mut self, table: usize) -> Option<()> {
+        if table >= TABLE_COUNT {
+            return None;
+        }
+        self.data[TABLE_ALLOC_ADDRESS + table] = 0;
+        for address in TABLE_COLUMN_ADDRESSES {
+            self.data[(address + table * STEP_COUNT)..][..STEP_COUNT].fill(0);
+        }
+        Some(())
+    }
+
+    /// Returns the raw parameter bytes of soft synth `synth` (see
+    /// `synth::SynthParams`).
+    pub fn synth_params(&self, synth: usize) -> [u8; SYNTH_PARAMS_LENGTH] {
+        let mut params = [0; SYNTH_PARAMS_LENGTH];
+        params.copy_from_slice(&self.data[(SYNTH_PARAMS_ADDRESS + synth * SYNTH_PARAMS_LENGTH)..][..SYNTH_PARAMS_LENGTH]);
+        params
+    }
+
+    /// Sets the raw parameter bytes of soft synth `synth`.
+    pub fn set_synth_params(&mut self, synth: usize, params: &[u8; SYNTH_PARAMS_LENGTH]) {
+        self.data[(SYNTH_PARAMS_ADDRESS + synth * SYNTH_PARAMS_LENGTH)..][..SYNTH_PARAMS_LENGTH].copy_from_slice(params);
+    }
+
+    /// Returns wave frame `wave`. Soft synth `n` plays frames `n * 0x10` to
+    /// `n * 0x10 + 0xF`.
+    pub fn wave(&self, wave: usize) -> [u8; WAVE_LENGTH] {
+        let mut frame = [0; WAVE_LENGTH];
+        frame.copy_from_slice(&self.data[(WAVES_ADDRESS + wave * WAVE_LENGTH)..][..WAVE_LENGTH]);
+        frame
+    }
+
+    /// Sets wave frame `wave`.
+    pub fn set_wave(&mut self, wave: usize, frame: &[u8; WAVE_LENGTH]) {
+        self.data[(WAVES_ADDRESS + wave * WAVE_LENGTH)..][..WAVE_LENGTH].copy_from_slice(frame);
+    }
+
+    /// Returns speech word `word`: its name and its allophones, each with its
+    /// duration, up to the first empty one.
+    pub fn word(&self, word: usize) -> Word {
+        let name = &self.data[(WORD_NAMES_ADDRESS + word * WORD_NAME_LENGTH)..][..WORD_NAME_LENGTH];
+        let end = name.iter().position(|&c| c == 0).unwrap_or(WORD_NAME_LENGTH);
+        let bytes = &self.data[(WORDS_ADDRESS + word * WORD_LENGTH * 2)..][..(WORD_LENGTH * 2)];
+        Word {
+            name: String::from_utf8_lossy(&name[..end]).into_owned(),
+            allophones: bytes.chunks(2).take_while(|pair| pair[0] != 0).map(|pair| (pair[0], pair[1])).collect(),
+        }
+    }
+
+    /// Sets speech word `word`, truncating its name to four bytes and its
+    /// allophones to `WORD_LENGTH`.
+    pub fn set_word(&mut self, word: usize, contents: &Word) {
+        let name = &contents.name.as_bytes()[..contents.name.len().min(WORD_NAME_LENGTH)];
+        let address = WORD_NAMES_ADDRESS + word * WORD_NAME_LENGTH;
+        self.data[address..(address + WORD_NAME_LENGTH)].fill(0);
+        self.data[address..(address + name.len())].copy_from_slice(name);
+        let bytes = &mut self.data[(WORDS_ADDRESS + word * WORD_LENGTH * 2)..][..(WORD_LENGTH * 2)];
+        bytes.fill(0);
+        for (pair, &(allophone, duration)) in bytes.chunks_mut(2).zip(contents.allophones.iter()) {
+            pair.copy_from_slice(&[allophone, duration]);
+        }
+    }
+
+    /// Returns the kits played by the song's kit instruments.
+    pub fn kits(&self) -> BTreeSet<u8> {
+        (0..INSTRUMENT_COUNT).filter_map(|i| self.instrument(i)?.kits())
+                             .flatten()
+                             .collect()
+    }
+
+    /// Returns every phrase step played by `channel`, following the song rows
+    /// from the top to the bottom (skipping empty rows) through each chain into
+    /// its phrases. Notes are transposed by their chain step's transpose.
+    pub fn played_steps(&self, channel: usize) -> Vec<Step> {
+        let mut played = Vec::new();
+        for row in 0..ROW_COUNT {
+            let chain = match self.row(row)[channel].and_then(|c| self.chain(c as usize)) {
+                Some(chain) => chain,
+                None => continue,
+            };
+            for chain_step in chain.iter() {
+                let steps = match chain_step.phrase.and_then(|p| self.phrase(p as usize)) {
+                    Some(steps) => steps,
+                    None => continue,
+                };
+                played.extend(steps.iter().map(|&step| Step {
+                    note: if step.note == 0 { 0 } else { step.note.wrapping_add(chain_step.transpose) },
+                    ..step
+                }));
+            }
+        }
+        played
+    }
+}
+
+/// Returns `None` for bytes marking an empty slot.
+fn non_empty(byte: u8) -> Option<u8> {
+    if byte == EMPTY { None } else { Some(byte) }
+}
+
+/// Returns true if bit `n` of the little-endian bitfield `bits` is set.
+fn bit_set(bits: &[u8], n: usize) -> bool {
+    bits[n / 8] & (1 << (n % 8)) != 0
+}
+
+/// Sets bit `n` of the little-endian bitfield `bits`.
+fn set_bit(bits: &mut [u8], n: usize) {
+    bits[n / 8] |= 1 << (n % 8);
+}
+
+/// Clears bit `n` of the little-endian bitfield `bits`.
+fn clear_bit(bits: &mut [u8], n: usize) {
+    bits[n / 8] &= !(1 << (n % 8));
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Returns an otherwise empty song with the check bytes in place.
+    pub fn empty_song() -> Song {
+        Song::empty()
+    }
+
+    /// Allocates `phrase` in `song`, setting its first step.
+    pub fn set_phrase(song: &mut Song, phrase: usize, note: u8, instrument: u8, command: u8, value: u8) {
+        let mut steps = song.phrase(phrase).unwrap_or([Step { note: 0, instrument: None, command: 0, value: 0 }; STEP_COUNT]);
+        steps[0] = Step { note, instrument: non_empty(instrument), command, value };
+        song.set_phrase(phrase, &steps).unwrap();
+    }
+
+    /// Allocates `chain` in `song`, setting its first step.
+    pub fn set_chain(song: &mut Song, chain: usize, phrase: u8, transpose: u8) {
+        let mut steps = song.chain(chain).unwrap_or([ChainStep { phrase: None, transpose: 0 }; STEP_COUNT]);
+        steps[0] = ChainStep { phrase: non_empty(phrase), transpose };
+        song.set_chain(chain, &steps).unwrap();
+    }
+
+    /// Allocates `instrument` in `song`, setting its name and parameters.
+    pub fn set_instrument(song: &mut Song, instrument: usize, name: &str, params: [u8; 16]) {
+        song.set_instrument(instrument, name, &params).unwrap();
+    }
+
+    /// Allocates `table` in `song`, setting the first command of its first step.
+    pub fn set_table(song: &mut Song, table: usize, command: u8, value: u8) {
+        let mut columns = song.table(table).unwrap_or([[0; STEP_COUNT]; 6]);
+        columns[2][0] = command;
+        columns[3][0] = value;
+        song.set_table(table, &columns).unwrap();
+    }
+
+    /// Sets the chain played by `channel` at `row` of `song`.
+    pub fn set_row(song: &mut Song, row: usize, channel: usize, chain: u8) {
+        let mut chains = song.row(row);
+        chains[channel] = non_empty(chain);
+        song.set_row(row, chains);
+    }
+
+    #[test]
+    fn test_from() {
+        let song = empty_song();
+        assert!(Song::from(&song.data).is_ok());
+        assert_eq!(Song::from(&song.data[..0x4000]).err(), Some(err::BAD_SONG));
+        assert_eq!(Song::from(&[0; SRAM_SIZE]).err(), Some(err::BAD_SONG));
+    }
+
+    #[test]
+    fn test_accessors() {
+        let mut song = empty_song();
+        set_row(&mut song, 1, 2, 0x05);
+        set_chain(&mut song, 0x05, 0x0a, 0x0c);
+        set_phrase(&mut song, 0x0a, 0x19, 0x01, 0x10, 0x42);
+        set_instrument(&mut song, 0x01, "KICK", [2, 0xa0, 0x83, 0, 0, 0, 0, 0, 0, 0x45, 0, 0, 0, 0, 0, 0]);
+        song.set_format_version(0x16);
+
+        assert_eq!(song.row(1), [None, None, Some(0x05), None]);
+        assert_eq!(song.chain(0x05).unwrap()[0], ChainStep { phrase: Some(0x0a), transpose: 0x0c });
+        assert_eq!(song.chain(0x04), None);
+        let step = song.phrase(0x0a).unwrap()[0];
+        assert_eq!(step.to_string(), "C-5 I01 V42");
+        assert_eq!(song.phrase(0x0a).unwrap()[1].to_string(), "--- --- ---");
+        assert_eq!(song.phrase(0x0b), None);
+        assert_eq!(song.phrase(0xff), None);
+        let instrument = song.instrument(0x01).unwrap();
+        assert_eq!(instrument.name, "KICK");
+        assert_eq!(instrument.kind, InstrumentType::Kit);
+        assert_eq!(instrument.kits(), Some([0x03, 0x05]));
+        assert_eq!(song.instrument(0x02), None);
+        assert_eq!(song.table(0), None);
+        set_instrument(&mut song, 0x02, "SNARE", [2, 0, 0x43, 0, 0, 0, 0, 0, 0, 0x03, 0, 0, 0, 0, 0, 0]);
+        set_instrument(&mut song, 0x03, "LEAD", [0, 0, 0x43, 0, 0, 0, 0, 0, 0, 0x07, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(song.kits().into_iter().collect::<Vec<u8>>(), vec![0x03, 0x05]);
+        assert_eq!(song.format_version(), 0x16);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let mut song = empty_song();
+        let original = song.clone();
+        assert_eq!(song.set_chain(CHAIN_COUNT, &[ChainStep { phrase: Some(0), transpose: 0 }; STEP_COUNT]), None);
+        assert_eq!(song.set_phrase(PHRASE_COUNT, &[Step { note: 1, instrument: None, command: 0, value: 0 }; STEP_COUNT]), None);
+        assert_eq!(song.set_instrument(INSTRUMENT_COUNT, "KICK", &[2; INSTRUMENT_PARAMS_LENGTH]), None);
+        assert_eq!(song.set_table(TABLE_COUNT, &[[1; STEP_COUNT]; 6]), None);
+        assert_eq!(song.set_chain_phrase(0, STEP_COUNT, Some(0)), None);
+        assert_eq!(song.set_note(0, STEP_COUNT, 1), None);
+        assert_eq!(song.clear_chain(CHAIN_COUNT), None);
+        assert_eq!(song.clear_phrase(PHRASE_COUNT), None);
+        assert_eq!(song.clear_instrument(INSTRUMENT_COUNT), None);
+        assert_eq!(song.clear_table(TABLE_COUNT), None);
+        assert_eq!(&song.data[..], &original.data[..]);
+
+        song.data.fill(0xff); // every allocation bit set
+        assert!(song.chain(CHAIN_COUNT - 1).is_some());
+        assert_eq!(song.chain(CHAIN_COUNT), None);
+        assert!(song.instrument(INSTRUMENT_COUNT - 1).is_some());
+        assert_eq!(song.instrument(INSTRUMENT_COUNT), None);
+        assert!(song.table(TABLE_COUNT - 1).is_some());
+        assert_eq!(song.table(TABLE_COUNT), None);
+        assert_eq!(song.clear_table(TABLE_COUNT - 1), Some(()));
+    }
+
+    #[test]
+    fn test_played_steps() {
+        let mut song = empty_song();
+        set_row(&mut song, 0, 0, 0x00);
+        set_row(&mut song, 2, 0, 0x00);
+        set_row(&mut song, 3, 0, 0x01); // not allocated
+        set_chain(&mut song, 0x00, 0x00, 0x02);
+        set_phrase(&mut song, 0x00, 0x01, 0x00, 0, 0);
+        let played = song.played_steps(0);
+        assert_eq!(played.len(), 2 * STEP_COUNT);
+        assert_eq!(played[0].note, 0x03);
+        assert_eq!(played[1].note, 0);
+        assert_eq!(played[STEP_COUNT].note, 0x03);
+        assert!(song.played_steps(1).is_empty());
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(note_name(0), "---");
+        assert_eq!(note_name(1), "C-3");
+        assert_eq!(note_name(0x0e), "C#4");
+        assert_eq!(command_name(1), 'A');
+        assert_eq!(command_name(0x12), 'Z');
+        assert_eq!(command_name(0x40), '?');
+        assert_eq!(region(0), "phrase notes");
+        assert_eq!(region(CHECK_1_ADDRESS + 1), "first 'rb' check bytes");
+        assert_eq!(region(TEMPO_ADDRESS + 1), "other song data");
+        assert_eq!(region(FORMAT_VERSION_ADDRESS), "format version");
+        for pair in REGIONS.windows(2) {
+            assert!(pair[0].0 + pair[0].1 <= pair[1].0, "{} overlaps {}", pair[0].2, pair[1].2);
+        }
+        assert_eq!("kit".parse(), Ok(InstrumentType::Kit));
+        assert_eq!("drum".parse::<InstrumentType>(), Err(err::BAD_INSTRUMENT_TYPE));
+    }
+}
@@ -0,0 +1,136 @@
+use core::fmt;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::err;
+use crate::lsdj::song::{Word, WORD_LENGTH};
+
+/// Names of the allophones speech instruments can say (those of the SP0256
+/// speech chip), in order; allophone 1 is `PA1`.
+const ALLOPHONES: [&str; 64] = [
+    "PA1", "PA2", "PA3", "PA4", "PA5", "OY",  "AY",  "EH",  "KK3", "PP",  "JH",  "NN1", "IH",  "TT2", "RR1", "AX",
+    "MM",  "TT1", "DH1", "IY",  "EY",  "DD1", "UW1", "AO",  "AA",  "YY2", "AE",  "HH1", "BB1", "TH",  "UH",  "UW2",
+    "AW",  "DD2", "GG3", "VV",  "GG1", "SH",  "ZH",  "RR2", "FF",  "KK2", "KK1", "ZZ",  "NG",  "LL",  "WW",  "XR",
+    "WH",  "YY1", "CH",  "ER1", "ER2", "OW",  "DH2", "SS",  "NN2", "HH2", "OR",  "AR",  "YR",  "GG2", "EL",  "BB2",
+];
+
+/// Duration given to allophones for which none is written.
+pub const DEFAULT_DURATION: u8 = 0x08;
+
+/// Spellings and the allophones they roughly stand for, longest first so that
+/// they take priority; used by `from_text()`.
+const SPELLINGS: &[(&str, &[&str])] = &[
+    ("tch", &["CH"]), ("igh", &["AY"]),
+    ("sh", &["SH"]), ("ch", &["CH"]), ("th", &["TH"]), ("ng", &["NG"]), ("ph", &["FF"]), ("wh", &["WH"]),
+    ("ck", &["KK2"]), ("qu", &["KK1", "WW"]), ("ee", &["IY"]), ("ea", &["IY"]), ("oo", &["UW2"]),
+    ("ou", &["AW"]), ("ow", &["OW"]), ("oi", &["OY"]), ("oy", &["OY"]), ("ai", &["EY"]), ("ay", &["EY"]),
+    ("er", &["ER1"]), ("ar", &["AR"]), ("or", &["OR"]), ("ll", &["LL"]), ("ss", &["SS"]),
+    ("a", &["AE"]), ("b", &["BB1"]), ("c", &["KK1"]), ("d", &["DD1"]), ("e", &["EH"]), ("f", &["FF"]),
+    ("g", &["GG1"]), ("h", &["HH1"]), ("i", &["IH"]), ("j", &["JH"]), ("k", &["KK1"]), ("l", &["LL"]),
+    ("m", &["MM"]), ("n", &["NN1"]), ("o", &["AA"]), ("p", &["PP"]), ("q", &["KK1"]), ("r", &["RR1"]),
+    ("s", &["SS"]), ("t", &["TT1"]), ("u", &["AX"]), ("v", &["VV"]), ("w", &["WW"]), ("x", &["KK2", "SS"]),
+    ("y", &["YY1"]), ("z", &["ZZ"]),
+];
+
+/// Pause said between the words of a text.
+const WORD_PAUSE: &str = "PA2";
+
+/// Returns the name of `allophone` (numbered from 1), or `?` if there is no
+/// such allophone.
+pub fn allophone_name(allophone: u8) -> &'static str {
+    (allophone as usize).checked_sub(1).and_then(|a| ALLOPHONES.get(a)).copied().unwrap_or("?")
+}
+
+/// Returns the number of the allophone named `name` (ignoring case).
+fn allophone(name: &str) -> Result<u8, &'static str> {
+    ALLOPHONES.iter().position(|a| a.eq_ignore_ascii_case(name)).map(|a| a as u8 + 1).ok_or(err::BAD_ALLOPHONE)
+}
+
+/// Parses a list of allophone names separated by whitespace, each optionally
+/// followed by a colon and its duration in hexadecimal (e.g. `HH1 EH:0C LL
+/// OW`). Allophones without a duration get `DEFAULT_DURATION`.
+pub fn parse_allophones(s: &str) -> Result<Vec<(u8, u8)>, &'static str> {
+    let allophones = s.split_whitespace().map(|token| {
+        let (name, duration) = match token.split_once(':') {
+            Some((name, d)) => (name, u8::from_str_radix(d, 16).map_err(|_| err::BAD_ALLOPHONE)?),
+            None => (token, DEFAULT_DURATION),
+        };
+        Ok((allophone(name)?, duration))
+    }).collect::<Result<Vec<(u8, u8)>, &'static str>>()?;
+    if allophones.len() > WORD_LENGTH { Err(err::WORD_TOO_LONG) } else { Ok(allophones) }
+}
+
+/// Spells out `text` (English, more or less) as allophones, using simple
+/// spelling rules rather than a dictionary, so the result is a starting point
+/// to be tuned by ear. Words are separated by a short pause, and a silent `e`
+/// ending a word is skipped. Characters other than letters and spaces are
+/// ignored.
+pub fn from_text(text: &str) -> Result<Vec<(u8, u8)>, &'static str> {
+    let mut names = Vec::new();
+    for word in text.split_whitespace() {
+        let word: String = word.chars().filter(char::is_ascii_alphabetic).collect::<String>().to_lowercase();
+        let word = match word.strip_suffix('e') {
+            Some(stem) if stem.len() >= 2 => stem,
+            _ => &word,
+        };
+        if word.is_empty() {
+            continue;
+        }
+        if !names.is_empty() {
+            names.push(WORD_PAUSE);
+        }
+        let mut rest = word;
+        while let Some((spelling, sounds)) = SPELLINGS.iter().find(|(spelling, _)| rest.starts_with(spelling)) {
+            names.extend(sounds.iter());
+            rest = &rest[spelling.len()..];
+        }
+    }
+    parse_allophones(&names.join(" "))
+}
+
+impl fmt::Display for Word {
+    /// Formats the word as its name followed by its allophones, in the form
+    /// accepted by `parse_allophones()`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<4}", self.name)?;
+        for &(allophone, duration) in self.allophones.iter() {
+            write!(f, " {}:{:02X}", allophone_name(allophone), duration)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::song::tests::empty_song;
+
+    #[test]
+    fn test_parse_allophones() {
+        assert_eq!(parse_allophones("HH1 eh:0c LL OW"), Ok(vec![(28, 8), (8, 0x0c), (46, 8), (54, 8)]));
+        assert_eq!(allophone_name(1), "PA1");
+        assert_eq!(allophone_name(64), "BB2");
+        assert_eq!(allophone_name(0), "?");
+        assert_eq!(parse_allophones("HH1 XX"), Err(err::BAD_ALLOPHONE));
+        assert_eq!(parse_allophones("HH1:zz"), Err(err::BAD_ALLOPHONE));
+        assert_eq!(parse_allophones(&["PA1"; 17].join(" ")), Err(err::WORD_TOO_LONG));
+    }
+
+    #[test]
+    fn test_from_text() {
+        let names = |text| from_text(text).unwrap().iter().map(|&(a, _)| allophone_name(a)).collect::<Vec<_>>().join(" ");
+        assert_eq!(names("hello"), "HH1 EH LL AA");
+        assert_eq!(names("the ship!"), "TH PA2 SH IH PP");
+        assert_eq!(names("make"), "MM AE KK1");
+        assert_eq!(from_text("this is far too long a text"), Err(err::WORD_TOO_LONG));
+    }
+
+    #[test]
+    fn test_words() {
+        let mut song = empty_song();
+        let word = Word { name: "HELO".to_string(), allophones: parse_allophones("HH1 EH LL OW").unwrap() };
+        song.set_word(0x29, &word);
+        assert_eq!(song.word(0x29), word);
+        assert_eq!(song.word(0x29).to_string(), "HELO HH1:08 EH:08 LL:08 OW:08");
+        assert_eq!(song.word(0x28), Word::default());
+    }
+}
@@ -0,0 +1,70 @@
+use alloc::collections::BTreeSet;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::err;
+use crate::lsdj::snippet::Snippet;
+use crate::lsdj::song::*;
+
+/// Returns a copy of `first` with the arrangement of `second` appended after
+/// its last row. The chains played by `second`, with their phrases,
+/// instruments, and tables, are copied into free slots of the copy (see
+/// `Snippet::import()`); everything else, such as the tempo and grooves, is
+/// kept from `first`. Song rows of `second` naming chains it hasn't allocated
+/// are left empty.
+///
+/// Returns an `Err` if the two songs have more rows between them than a song
+/// can hold, or `first` hasn't enough free slots to copy `second` into.
+pub fn splice(first: &Song, second: &Song) -> Result<Song, &'static str> {
+    let (start, length) = (first.length(), second.length());
+    if start + length > ROW_COUNT {
+        return Err(err::SONG_TOO_LONG);
+    }
+    let chains: BTreeSet<u8> = (0..length).flat_map(|r| second.row(r))
+                                          .flatten()
+                                          .filter(|&c| (c as usize) < CHAIN_COUNT && second.chain(c as usize).is_some())
+                                          .collect();
+    let mut song = first.clone();
+    let copied = Snippet::export(second, &chains.into_iter().collect::<Vec<u8>>())?.import(&mut song)?;
+    for r in 0..length {
+        song.set_row(start + r, second.row(r).map(|c| c.and_then(|c| copied.get(&c).copied())));
+    }
+    Ok(song)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::song::tests::*;
+
+    #[test]
+    fn test_splice() {
+        let mut first = empty_song();
+        set_row(&mut first, 0, 0, 0x00);
+        set_row(&mut first, 1, 2, 0x00);
+        set_chain(&mut first, 0x00, 0x00, 0);
+        set_phrase(&mut first, 0x00, 0x01, 0x00, 0, 0);
+        set_instrument(&mut first, 0x00, "LEAD", [0; 16]);
+        let mut second = empty_song();
+        set_row(&mut second, 0, 1, 0x02);
+        set_row(&mut second, 2, 0, 0x02);
+        set_row(&mut second, 2, 3, 0x03); // not allocated
+        set_chain(&mut second, 0x02, 0x04, 0x0c);
+        set_phrase(&mut second, 0x04, 0x0d, 0x01, 0, 0);
+        set_instrument(&mut second, 0x01, "BASS", [0; 16]);
+
+        let song = splice(&first, &second).unwrap();
+        assert_eq!(song.length(), 5);
+        assert_eq!(song.row(1), [None, None, Some(0x00), None]);
+        assert_eq!(song.row(2), [None, Some(0x01), None, None]);
+        assert_eq!(song.row(3), [None; CHANNEL_COUNT]);
+        assert_eq!(song.row(4), [Some(0x01), None, None, None]);
+        assert_eq!(song.chain(0x01).unwrap()[0], ChainStep { phrase: Some(0x01), transpose: 0x0c });
+        assert_eq!(song.phrase(0x01).unwrap()[0].instrument, Some(0x01));
+        assert_eq!(song.instrument(0x01).unwrap().name, "BASS");
+        assert_eq!(song.phrase(0x00).unwrap()[0].instrument, Some(0x00));
+
+        let mut long = empty_song();
+        set_row(&mut long, ROW_COUNT - 1, 0, 0x00);
+        assert_eq!(splice(&first, &long).err(), Some(err::SONG_TOO_LONG));
+    }
+}
@@ -0,0 +1,111 @@
+use alloc::collections::BTreeMap;
+use core::fmt;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::song::*;
+
+const CHANNEL_NAMES: [&str; CHANNEL_COUNT] = ["PU1", "PU2", "WAV", "NOI"];
+
+/// Notes played by one channel of a song.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelStats {
+    /// Number of notes played.
+    pub notes: usize,
+    /// Lowest note played, if any.
+    pub lowest: Option<u8>,
+    /// Highest note played, if any.
+    pub highest: Option<u8>,
+}
+
+/// Counts of what a song plays, following its song rows through chains into
+/// phrases (see `Song::played_steps()`), so a phrase played twice is counted
+/// twice.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SongStats {
+    pub channels: [ChannelStats; CHANNEL_COUNT],
+    /// Number of times each effect command is played.
+    pub commands: BTreeMap<u8, usize>,
+    /// Number of times each instrument is played, with its name.
+    pub instruments: BTreeMap<u8, (usize, String)>,
+    /// Number of times each table is started by a table command.
+    pub tables: BTreeMap<u8, usize>,
+}
+
+/// Counts the notes, commands, instruments, and tables played by `song`.
+pub fn stats(song: &Song) -> SongStats {
+    let mut stats = SongStats::default();
+    for (channel, channel_stats) in stats.channels.iter_mut().enumerate() {
+        for step in song.played_steps(channel) {
+            if step.note != 0 {
+                channel_stats.notes += 1;
+                channel_stats.lowest = Some(channel_stats.lowest.map_or(step.note, |n| n.min(step.note)));
+                channel_stats.highest = Some(channel_stats.highest.map_or(step.note, |n| n.max(step.note)));
+            }
+            if let Some(i) = step.instrument {
+                let name = song.instrument(i as usize).map(|i| i.name).unwrap_or_default();
+                stats.instruments.entry(i).or_insert((0, name)).0 += 1;
+            }
+            if step.command != 0 {
+                *stats.commands.entry(step.command).or_insert(0) += 1;
+            }
+            if step.command == TABLE_COMMAND {
+                *stats.tables.entry(step.value).or_insert(0) += 1;
+            }
+        }
+    }
+    stats
+}
+
+impl fmt::Display for SongStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "CHAN  NOTES  LOWEST  HIGHEST")?;
+        for (name, channel) in CHANNEL_NAMES.iter().zip(self.channels.iter()) {
+            let lowest = channel.lowest.map_or("---".to_string(), note_name);
+            let highest = channel.highest.map_or("---".to_string(), note_name);
+            writeln!(f, "{:<4}  {:>5}  {:<6}  {}", name, channel.notes, lowest, highest)?;
+        }
+        writeln!(f, "\ncommands:")?;
+        for (command, count) in self.commands.iter() {
+            writeln!(f, "  {}  {:>5}", command_name(*command), count)?;
+        }
+        writeln!(f, "\ninstruments:")?;
+        for (instrument, (count, name)) in self.instruments.iter() {
+            writeln!(f, "  {:02X} {:<5}  {:>5}", instrument, name, count)?;
+        }
+        writeln!(f, "\ntables:")?;
+        for (table, count) in self.tables.iter() {
+            writeln!(f, "  {:02X}  {:>5}", table, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::song::tests::*;
+
+    #[test]
+    fn test_stats() {
+        let mut song = empty_song();
+        set_row(&mut song, 0, 0, 0x00);
+        set_row(&mut song, 1, 0, 0x01);
+        set_row(&mut song, 0, 2, 0x01);
+        set_chain(&mut song, 0x00, 0x00, 0x00);
+        set_chain(&mut song, 0x01, 0x01, 0x0c);
+        set_phrase(&mut song, 0x00, 0x0d, 0x00, TABLE_COMMAND, 0x03);
+        set_phrase(&mut song, 0x01, 0x0f, 0x01, 0x10, 0x42);
+        set_instrument(&mut song, 0x00, "BASS", [0; 16]);
+
+        let stats = stats(&song);
+        assert_eq!(stats.channels[0], ChannelStats { notes: 2, lowest: Some(0x0d), highest: Some(0x1b) });
+        assert_eq!(stats.channels[1], ChannelStats::default());
+        assert_eq!(stats.channels[2].notes, 1);
+        assert_eq!(stats.commands.get(&TABLE_COMMAND), Some(&1));
+        assert_eq!(stats.commands.get(&0x10), Some(&2));
+        assert_eq!(stats.instruments.get(&0x00), Some(&(1, "BASS".to_string())));
+        assert_eq!(stats.instruments.get(&0x01), Some(&(2, String::new())));
+        assert_eq!(stats.tables.get(&0x03), Some(&1));
+        assert!(stats.to_string().starts_with("CHAN  NOTES  LOWEST  HIGHEST\nPU1       2  C-4     D-5\n"));
+    }
+}
@@ -0,0 +1,289 @@
+use core::fmt;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::song::{Song, STEP_COUNT, SYNTH_PARAMS_LENGTH, WAVE_LENGTH};
+
+/// Samples in each wave frame.
+#[cfg(feature = "std")]
+const SAMPLE_COUNT: usize = WAVE_LENGTH * 2;
+/// Highest value of a 4-bit sample.
+#[cfg(feature = "std")]
+const SAMPLE_MAX: f64 = 15.0;
+/// Volume at which the waveform fills the whole range of a sample.
+#[cfg(feature = "std")]
+const FULL_VOLUME: f64 = 16.0;
+/// Cycles run through the filter before a frame is sampled, so that its
+/// output settles.
+#[cfg(feature = "std")]
+const FILTER_CYCLES: usize = 8;
+
+const WAVEFORM_OFFSET  : usize = 0;
+const FILTER_OFFSET    : usize = 1;
+const RESONANCE_OFFSET : usize = 2;
+const DISTORTION_OFFSET: usize = 3;
+const PHASE_TYPE_OFFSET: usize = 4;
+const START_OFFSET     : usize = 5;  // volume, cutoff, phase, and vertical shift
+const END_OFFSET       : usize = 9;  // the same again
+const LIMIT_OFFSET     : usize = 13; // start, then end
+
+/// Settings of a soft synth which change from its first frame to its last.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SynthFrame {
+    pub volume: u8,
+    pub cutoff: u8,
+    /// How far the waveform is squeezed (0 to 0x1F).
+    pub phase: u8,
+    /// How far the waveform is shifted upwards, wrapping around.
+    pub vshift: u8,
+    /// Level at which the waveform is clipped or wrapped (0 to 0xF).
+    pub limit: u8,
+    /// Filter resonance (0 to 0xF).
+    pub resonance: u8,
+}
+
+/// The parameters of one of a song's soft synths, from which LSDj generates
+/// the sixteen wave frames the synth plays.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SynthParams {
+    /// 0 for sawtooth, 1 for square, or 2 for triangle.
+    pub waveform: u8,
+    /// 0 for low-pass, 1 for high-pass, 2 for band-pass, or 3 for all-pass.
+    pub filter: u8,
+    /// 0 to clip the waveform at the limit, or 1 to wrap it around.
+    pub distortion: u8,
+    /// 0 to squeeze the waveform into the start of the frame, 1 to repeat
+    /// it, or 2 to repeat it mirrored.
+    pub phase_type: u8,
+    pub start: SynthFrame,
+    pub end: SynthFrame,
+    /// Last byte, which this tool doesn't interpret, kept as it was.
+    pub reserved: u8,
+}
+
+impl SynthParams {
+    /// Parses the raw parameter bytes of a soft synth.
+    pub fn from(bytes: &[u8; SYNTH_PARAMS_LENGTH]) -> SynthParams {
+        let frame = |offset: usize, limit: u8, resonance: u8| SynthFrame {
+            volume: bytes[offset],
+            cutoff: bytes[offset + 1],
+            phase: bytes[offset + 2],
+            vshift: bytes[offset + 3],
+            limit,
+            resonance,
+        };
+        SynthParams {
+            waveform: bytes[WAVEFORM_OFFSET],
+            filter: bytes[FILTER_OFFSET],
+            distortion: bytes[DISTORTION_OFFSET],
+            phase_type: bytes[PHASE_TYPE_OFFSET],
+            start: frame(START_OFFSET, bytes[LIMIT_OFFSET], bytes[RESONANCE_OFFSET] >> 4),
+            end: frame(END_OFFSET, bytes[LIMIT_OFFSET + 1], bytes[RESONANCE_OFFSET] & 0xf),
+            reserved: bytes[SYNTH_PARAMS_LENGTH - 1],
+        }
+    }
+
+    /// Returns the raw parameter bytes of this soft synth.
+    pub fn bytes(&self) -> [u8; SYNTH_PARAMS_LENGTH] {
+        let mut bytes = [0; SYNTH_PARAMS_LENGTH];
+        bytes[WAVEFORM_OFFSET] = self.waveform;
+        bytes[FILTER_OFFSET] = self.filter;
+        bytes[RESONANCE_OFFSET] = (self.start.resonance << 4) | (self.end.resonance & 0xf);
+        bytes[DISTORTION_OFFSET] = self.distortion;
+        bytes[PHASE_TYPE_OFFSET] = self.phase_type;
+        for (offset, frame) in [(START_OFFSET, &self.start), (END_OFFSET, &self.end)] {
+            bytes[offset..(offset + 4)].copy_from_slice(&[frame.volume, frame.cutoff, frame.phase, frame.vshift]);
+        }
+        bytes[LIMIT_OFFSET] = self.start.limit;
+        bytes[LIMIT_OFFSET + 1] = self.end.limit;
+        bytes[SYNTH_PARAMS_LENGTH - 1] = self.reserved;
+        bytes
+    }
+
+    /// Generates the sixteen wave frames of this soft synth, moving each
+    /// setting in even steps from its start value in the first frame to its
+    /// end value in the last.
+    ///
+    /// This follows the stages of LSDj's synth (waveform, phase, filter,
+    /// volume, vertical shift, then distortion) but isn't a bit-exact copy of
+    /// it, so the frames are close to, rather than the same as, the ones LSDj
+    /// would generate.
+    ///
+    /// Needs the `std` feature, for floating-point math.
+    #[cfg(feature = "std")]
+    pub fn synthesize(&self) -> [[u8; WAVE_LENGTH]; STEP_COUNT] {
+        let mut frames = [[0; WAVE_LENGTH]; STEP_COUNT];
+        for (i, frame) in frames.iter_mut().enumerate() {
+            let t = i as f64 / (STEP_COUNT - 1) as f64;
+            let lerp = |a: u8, b: u8| a as f64 + (b as f64 - a as f64) * t;
+            let samples = self.samples(lerp(self.start.volume, self.end.volume),
+                                       lerp(self.start.cutoff, self.end.cutoff),
+                                       lerp(self.start.phase, self.end.phase),
+                                       lerp(self.start.vshift, self.end.vshift),
+                                       lerp(self.start.limit, self.end.limit),
+                                       lerp(self.start.resonance, self.end.resonance));
+            for (byte, pair) in frame.iter_mut().zip(samples.chunks(2)) {
+                *byte = (pair[0] << 4) | pair[1];
+            }
+        }
+        frames
+    }
+
+    /// Returns the 4-bit samples of one frame with the given settings.
+    #[cfg(feature = "std")]
+    fn samples(&self, volume: f64, cutoff: f64, phase: f64, vshift: f64, limit: f64, resonance: f64)
+               -> [u8; SAMPLE_COUNT] {
+        // the waveform from -1 to 1, squeezed or repeated according to phase
+        let squeeze = 1.0 + phase / 8.0;
+        let wave: Vec<f64> = (0..SAMPLE_COUNT).map(|n| {
+            let x = n as f64 / SAMPLE_COUNT as f64 * squeeze;
+            let x = match self.phase_type {
+                1 => x.fract(),
+                2 if x.floor() as u64 % 2 == 1 => 1.0 - x.fract(),
+                2 => x.fract(),
+                _ if x >= 1.0 => return 0.0,
+                _ => x,
+            };
+            match self.waveform {
+                1 => if x < 0.5 { 1.0 } else { -1.0 },
+                2 => 1.0 - 4.0 * (x - 0.5).abs(),
+                _ => 2.0 * x - 1.0,
+            }
+        }).collect();
+
+        // a (trapezoidal) state-variable filter, run over several cycles until
+        // it settles; the cutoff is a fraction of the sample rate up to just
+        // under half of it
+        let g = (core::f64::consts::PI * (0.01 + 0.48 * cutoff / 255.0)).tan();
+        let damping = 2.0 - 1.9 * resonance / 15.0;
+        let (mut s1, mut s2) = (0.0, 0.0);
+        let mut filtered = [0.0; SAMPLE_COUNT];
+        for cycle in 0..FILTER_CYCLES {
+            for (n, &x) in wave.iter().enumerate() {
+                let high = (x - (g + damping) * s1 - s2) / (1.0 + g * (g + damping));
+                let band = g * high + s1;
+                s1 = g * high + band;
+                let low = g * band + s2;
+                s2 = g * band + low;
+                if cycle == FILTER_CYCLES - 1 {
+                    filtered[n] = match self.filter {
+                        1 => high,
+                        2 => band,
+                        3 => x - 2.0 * damping * band, // all-pass: shifts phase, keeps level
+                        _ => low,
+                    };
+                }
+            }
+        }
+
+        let level = (limit + 1.0) / 16.0;
+        let mut samples = [0; SAMPLE_COUNT];
+        for (sample, &y) in samples.iter_mut().zip(filtered.iter()) {
+            let y = y * volume / FULL_VOLUME + vshift / 128.0;
+            let y = match self.distortion {
+                1 => (y + level).rem_euclid(2.0 * level) - level,
+                _ => y.clamp(-level, level),
+            };
+            *sample = ((y + 1.0) / 2.0 * SAMPLE_MAX).round().clamp(0.0, SAMPLE_MAX) as u8;
+        }
+        samples
+    }
+}
+
+impl fmt::Display for SynthParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let waveform = ["sawtooth", "square", "triangle"].get(self.waveform as usize).unwrap_or(&"?");
+        let filter = ["low-pass", "high-pass", "band-pass", "all-pass"].get(self.filter as usize).unwrap_or(&"?");
+        let distortion = ["clip", "wrap"].get(self.distortion as usize).unwrap_or(&"?");
+        let phase_type = ["normal", "resync", "resync2"].get(self.phase_type as usize).unwrap_or(&"?");
+        writeln!(f, "waveform   {}", waveform)?;
+        writeln!(f, "filter     {}", filter)?;
+        writeln!(f, "distortion {}", distortion)?;
+        writeln!(f, "phase      {}", phase_type)?;
+        writeln!(f, "           START  END")?;
+        type Field = fn(&SynthFrame) -> u8;
+        let rows: [(&str, Field); 6] = [
+            ("volume", |s| s.volume), ("cutoff", |s| s.cutoff), ("resonance", |s| s.resonance),
+            ("phase", |s| s.phase), ("vshift", |s| s.vshift), ("limit", |s| s.limit),
+        ];
+        for (name, value) in rows.iter() {
+            writeln!(f, "{:<10}    {:02X}   {:02X}", name, value(&self.start), value(&self.end))?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a wave frame as its 32 samples in hexadecimal.
+pub fn frame_string(frame: &[u8; WAVE_LENGTH]) -> String {
+    frame.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+impl Song {
+    /// Returns the parameters of soft synth `synth`.
+    pub fn synth(&self, synth: usize) -> SynthParams {
+        SynthParams::from(&self.synth_params(synth))
+    }
+
+    /// Returns the sixteen wave frames played by soft synth `synth`.
+    pub fn synth_waves(&self, synth: usize) -> [[u8; WAVE_LENGTH]; STEP_COUNT] {
+        let mut frames = [[0; WAVE_LENGTH]; STEP_COUNT];
+        for (i, frame) in frames.iter_mut().enumerate() {
+            *frame = self.wave(synth * STEP_COUNT + i);
+        }
+        frames
+    }
+
+    /// Regenerates the wave frames played by soft synth `synth` from its
+    /// parameters (see `SynthParams::synthesize()`).
+    #[cfg(feature = "std")]
+    pub fn resynthesize(&mut self, synth: usize) {
+        for (i, frame) in self.synth(synth).synthesize().iter().enumerate() {
+            self.set_wave(synth * STEP_COUNT + i, frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::song::tests::empty_song;
+
+    fn params(waveform: u8, distortion: u8, phase_type: u8) -> SynthParams {
+        let frame = SynthFrame { volume: 0x10, cutoff: 0xff, phase: 0, vshift: 0, limit: 0xf, resonance: 0 };
+        SynthParams { waveform, filter: 0, distortion, phase_type, start: frame, end: frame, reserved: 0 }
+    }
+
+    #[test]
+    fn test_params() {
+        let bytes = [1, 2, 0x3c, 1, 2, 0x10, 0xff, 0x04, 0x20, 0x08, 0x80, 0x1f, 0x00, 0x0f, 0x07, 0x55];
+        let params = SynthParams::from(&bytes);
+        assert_eq!(params.start.resonance, 0x3);
+        assert_eq!(params.end.resonance, 0xc);
+        assert_eq!(params.end.limit, 0x7);
+        assert_eq!(params.end.phase, 0x1f);
+        assert_eq!(params.bytes(), bytes);
+        assert!(params.to_string().starts_with("waveform   square\nfilter     band-pass\n"));
+    }
+
+    #[test]
+    fn test_synthesize() {
+        let frames = params(1, 0, 0).synthesize();
+        assert_eq!(frames[0], frames[15]);
+        let samples: Vec<u8> = frames[0].iter().flat_map(|b| [b >> 4, b & 0xf]).collect();
+        assert!(samples[2..14].iter().all(|&s| s >= 12));
+        assert!(samples[18..30].iter().all(|&s| s <= 3));
+
+        let saw: Vec<u8> = params(0, 0, 0).synthesize()[0].iter().flat_map(|b| [b >> 4, b & 0xf]).collect();
+        assert!(saw[4..28].windows(2).all(|w| w[0] <= w[1]));
+
+        let mut quiet = params(1, 0, 0);
+        quiet.end.volume = 0;
+        assert_eq!(frame_string(&quiet.synthesize()[15]), "88888888888888888888888888888888");
+
+        let mut song = empty_song();
+        song.set_synth_params(2, &params(1, 0, 0).bytes());
+        song.resynthesize(2);
+        assert_eq!(song.synth_waves(2), frames);
+        assert_eq!(song.wave(0x20), frames[0]);
+        assert_eq!(song.synth_waves(1), [[0; WAVE_LENGTH]; STEP_COUNT]);
+    }
+}
@@ -0,0 +1,110 @@
+//! Bindings for using save files from JavaScript, built with wasm-bindgen, so
+//! that a web page can list, export, import, and delete songs without a
+//! server. Save files and songs are passed in and out as `Uint8Array`s.
+
+use wasm_bindgen::prelude::*;
+
+use crate::lsdj::prelude::*;
+use crate::lsdj::err;
+use crate::lsdj::{lsdjtitle_from, LsdjSave};
+
+/// Converts a library error into a JavaScript `Error`.
+fn js_error<E: ToString>(e: E) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+/// A song in a save file, as listed by `LsdjSave.list()`.
+#[wasm_bindgen(getter_with_clone)]
+pub struct SongEntry {
+    pub index: u8,
+    pub title: String,
+    /// Version byte, counting how many times the song has been saved.
+    pub version: u8,
+    /// Number of blocks used.
+    pub blocks: usize,
+}
+
+/// A save file, exposed to JavaScript as `LsdjSave`.
+#[wasm_bindgen(js_name = LsdjSave)]
+pub struct WasmSave(LsdjSave);
+
+#[wasm_bindgen(js_class = LsdjSave)]
+impl WasmSave {
+    /// Parses the bytes of a save file of any supported size.
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: &[u8]) -> Result<WasmSave, JsError> {
+        LsdjSave::from_bytes(bytes).map(WasmSave).map_err(js_error)
+    }
+
+    /// Returns an empty 128KB save file.
+    pub fn empty() -> WasmSave {
+        WasmSave(LsdjSave::empty())
+    }
+
+    /// Lists the songs in the save file. Unlike the command-line tool's list,
+    /// songs aren't decompressed, so this is cheap.
+    pub fn list(&self) -> Vec<SongEntry> {
+        self.0.metadata.songs().into_iter().filter_map(|s| self.0.slot(s)).map(|slot| SongEntry {
+            index: slot.index(),
+            title: slot.title(),
+            version: slot.version(),
+            blocks: slot.block_indices().len(),
+        }).collect()
+    }
+
+    /// Returns the blocks of compressed song data making up a song, as saved
+    /// in an .lsdsng file.
+    #[wasm_bindgen(js_name = exportSong)]
+    pub fn export_song(&self, song: u8) -> Result<Vec<u8>, JsError> {
+        self.0.slot(song).map(|slot| slot.compressed_bytes()).ok_or_else(|| js_error(err::NO_SONG))
+    }
+
+    /// Imports blocks of compressed song data into the first free song slot
+    /// under `title`, returning the slot's index.
+    #[wasm_bindgen(js_name = importSong)]
+    pub fn import_song(&mut self, bytes: &[u8], title: &str) -> Result<u8, JsError> {
+        let title = lsdjtitle_from(title).map_err(js_error)?;
+        self.0.import_song(bytes, title).map_err(js_error)
+    }
+
+    /// Deletes a song, freeing its blocks.
+    #[wasm_bindgen(js_name = deleteSong)]
+    pub fn delete_song(&mut self, song: u8) -> Result<(), JsError> {
+        self.0.delete_song(song).map_err(js_error)
+    }
+
+    /// Returns the bytes of the save file, ready to be downloaded.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // Only the paths which succeed are tested: building a `JsError` needs a
+    // JavaScript host.
+    #[test]
+    fn test_wasm_save() {
+        let mut save = WasmSave::new(&crate::lsdj::generate::test_save().bytes()).ok().unwrap();
+        let songs = save.list();
+        assert_eq!(songs.len(), 1);
+        assert_eq!((songs[0].index, songs[0].title.as_str()), (0, "TEST"));
+
+        let song = save.export_song(0).ok().unwrap();
+        assert_eq!(song.len(), songs[0].blocks * 0x200);
+        let sram = save.0.decompress_song(0);
+        assert_eq!(save.import_song(&song, "COPY").ok(), Some(1));
+        save.delete_song(0).ok().unwrap();
+
+        let save = WasmSave::new(&save.bytes()).ok().unwrap();
+        let songs = save.list();
+        assert_eq!(songs.len(), 1);
+        assert_eq!((songs[0].index, songs[0].title.as_str()), (1, "COPY"));
+        // the skip instructions are renumbered for the blocks the copy was
+        // imported into, so compare the decompressed songs
+        assert!(save.0.decompress_song(1).ok() == sram.ok());
+    }
+}
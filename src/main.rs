@@ -1,93 +1,2558 @@
+use std::convert::TryFrom;
 use std::io;
+use std::io::{IsTerminal, Read, Write};
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
+use log::info;
 use structopt::StructOpt;
+use structopt::clap::{AppSettings, Error, ErrorKind};
 
 use lsdj::LsdjSave;
+use lsdj::LsdjError;
+use lsdj::Compat;
 use lsdj::LsdjBlockExt;
+use lsdj::LsdjLayout;
+use lsdj::SortKey;
+use lsdj::OnCollision;
+use lsdj::io::{BackupPolicy, Codec};
+use lsdj::patch::PatchFormat;
+use lsdj::song::InstrumentType;
+use config::{ColorChoice, Config};
+use hooks::Event;
+use exit::{fail, ErrorFormat, Status};
 
-mod lsdj;
+use lsdjtool::lsdj;
 
-const ERR_COMPRESSION: &str = "SRAM compression failed";
-const ERR_TITLE_FMT: &str   = "Title incorrectly formatted";
+/// Prints a status message (such as what a command did) to stderr, unless
+/// `-q` was given. Data always goes to stdout, and errors and warnings are
+/// always printed.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !crate::logger::quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+mod watch;
+mod dedupe;
+mod parallel;
+mod config;
+mod hooks;
+mod fetch;
+mod crypt;
+mod logger;
+mod grep;
+mod library;
+mod manifest;
+mod sidecar;
+mod cart;
+mod qr;
+mod confirm;
+mod exit;
+mod script;
+
+const ERR_JSON: &str = "JSON serialization failed";
+
+/// Backup policy applied whenever an existing file is overwritten.
+static BACKUP_POLICY: OnceLock<BackupPolicy> = OnceLock::new();
+/// Compressor whose choices are followed when songs are compressed and
+/// decompressed.
+static COMPAT: OnceLock<Compat> = OnceLock::new();
+/// Whether `--yes` was given, so that nothing is asked before overwriting
+/// songs.
+static ASSUME_YES: OnceLock<bool> = OnceLock::new();
+/// Defaults read from the config file.
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Returns the defaults read from the config file.
+fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
 
 #[derive(StructOpt, Debug)]
-#[structopt(name = "lsdjtool")]
+#[structopt(name = "lsdjtool", setting(AppSettings::SubcommandsNegateReqs),
+            after_help("Set $LSDJTOOL_LOG to debug or trace (or pass -vv or -vvv) to log, on stderr, how songs \
+                        are compressed and decompressed and which blocks they are given."))]
 struct Opt {
-    /// List indices, titles, and versions of songs present in save file
+    /// List indices, titles, versions, and sizes of songs present in save file, along with the
+    /// tags, BPM, and author from any sidecar files (SAVEFILE-STEM.TITLE.toml)
     #[structopt(short, long, conflicts_with_all(&["export", "import-from"]))]
     list_songs: bool,
 
-    /// Index of song to be exported from save file
+    /// Never color the song list
+    #[structopt(long, requires("list-songs"))]
+    no_color: bool,
+
+    /// Print the song list as JSON
+    #[structopt(long, requires("list-songs"), conflicts_with("no-color"))]
+    json: bool,
+
+    /// Index of song to be exported from save file (written to the configured output_dir if
+    /// no OUTFILE is given), or a list of indices and ranges (e.g. 0,3,5-7) of songs to be
+    /// written into the directory OUTFILE (or the configured output_dir), named from the export
+    /// template
     #[structopt(short, long, value_name("INDEX"), conflicts_with("import-from"))]
-    export: Option<u8>,
+    export: Option<SongList>,
+
+    /// Wrap the exported song in a container holding its title, versions, and a checksum, which
+    /// is verified when the song is imported
+    #[structopt(long, requires("export"))]
+    container: bool,
+
+    /// Export the song as ASCII-armored text (base64 with header and checksum lines) for pasting
+    /// into forums and chats; armored songs are recognized when imported, even amid other text
+    #[structopt(long, requires("export"), conflicts_with("container"))]
+    armor: bool,
+
+    /// Compress the exported song or SRAM, or the songs exported by export-all and watch, with
+    /// gzip or zstd (also chosen by giving OUTFILE a .gz or .zst extension); compressed files are
+    /// decompressed when imported
+    #[structopt(long, value_name("CODEC"), possible_values(&["gzip", "zstd"]))]
+    compress: Option<Codec>,
+
+    /// Record the time of export ($SOURCE_DATE_EPOCH, if set) in the header of a gzipped song or
+    /// SRAM; without this, exports hold no timestamps, so exporting the same song twice gives
+    /// byte-identical files
+    #[structopt(long)]
+    timestamp: bool,
+
+    /// Encrypt the exported song or SRAM, or the songs exported by export-all and watch (after any
+    /// compression), with a passphrase, read from $LSDJTOOL_PASSPHRASE or prompted for, in age's
+    /// format (also chosen by giving OUTFILE a .age extension); encrypted files are decrypted when
+    /// imported. Needs the encrypt feature
+    #[structopt(long)]
+    encrypt: bool,
+
+    /// Index of song to be decompressed and exported from save file as a
+    /// $8000-byte SRAM image
+    #[structopt(short = "d", long, value_name("INDEX"), conflicts_with_all(&["export", "import-from"]))]
+    export_decompressed: Option<u8>,
 
     /// Export working song (SRAM)
     #[structopt(short = "x", long = "export-sram", conflicts_with_all(&["export", "import-from"]))]
     export_sram: bool,
 
-    /// File from which to import blocks of compressed song data
+    /// File from which to import blocks of compressed song data (raw, in a container written by
+    /// --container, or armored by --armor). Raw blocks padded with zeroes or missing their final
+    /// end-of-file instruction are repaired, with a warning. When built with the http feature, may
+    /// be an http(s) URL, checked against its SHA-256 digest if it ends in #sha256=DIGEST
     #[structopt(short, long, value_name("SONGFILE"), parse(from_os_str))]
     import_from: Option<PathBuf>,
 
+    /// Treat SONGFILE as a decompressed $8000-byte SRAM image rather than
+    /// blocks of compressed song data
+    #[structopt(short = "D", long, requires("import-from"))]
+    decompressed: bool,
+
+    /// Fill out a truncated last block of SONGFILE with zeroes rather than
+    /// rejecting it
+    #[structopt(long, requires("import-from"), conflicts_with("decompressed"))]
+    pad: bool,
+
+    /// Format version the imported song must have (e.g. 0x16), rather than that of the songs
+    /// already in the save file
+    #[structopt(long, value_name("VERSION"), parse(try_from_str = parse_byte), requires("import-from"))]
+    target_version: Option<u8>,
+
+    /// Report how many blocks the imported song needs and how many are free, without importing
+    /// it (exits with status 1 if it doesn't fit)
+    #[structopt(long, requires("import-from"))]
+    check_fit: bool,
+
+    /// With --check-fit, also estimate the fit once unplayed chains, phrases, instruments, and
+    /// tables are freed (as the clean command does) in the imported song and every song in the
+    /// save file
+    #[structopt(long, requires("check-fit"))]
+    clean: bool,
+
+    /// Import the song even if its format version doesn't match, or export the working song even
+    /// if SRAM isn't initialized or the song in it is corrupt (compressing whatever is in SRAM)
+    #[structopt(long)]
+    force: bool,
+
     /// Title for imported song (at most eight characters, uppercase alphanumeric ASCII plus space
     /// (0x20),
-    /// lowercase 'x' represents the lightning bolt character). Defaults to
-    /// SONGNAME.
+    /// lowercase 'x' represents the lightning bolt character). Defaults to the title stored in a
+    /// container or armored SONGFILE, the configured default_title, or SONGNAME.
     #[structopt(short, long, value_name("TITLE"), requires("import-from"))]
     title: Option<String>,
 
+    /// What to do when a song in the save file already has the imported song's title: force
+    /// (the default) imports it anyway, with a warning, rename appends a number to the title
+    /// (SONG becomes SONG2), and skip refuses to import it
+    #[structopt(long, value_name("POLICY"), possible_values(&["force", "rename", "skip"]), requires("import-from"))]
+    on_collision: Option<OnCollision>,
+
     /// Output file (defaults to stdout)
     #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
     output: Option<PathBuf>,
 
-    /// Save file to read from
+    /// Save file to read from (or, when built with the http feature, an http(s) URL, checked
+    /// against its SHA-256 digest if it ends in #sha256=DIGEST)
     #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
-    savefile: PathBuf,
+    savefile: Option<PathBuf>,
+
+    /// Back up files before overwriting them (e.g. with -o pointing at the save file being
+    /// read): keep=N keeps the N newest backups (FILE.1, FILE.2, ...), and dir=PATH keeps them
+    /// in PATH rather than next to the file
+    #[structopt(long, value_name("POLICY"), global(true), number_of_values(1))]
+    backup: Vec<String>,
+
+    /// How default instruments and waves are written in compressed songs: lsdj (the default) and
+    /// lsdpatch count runs of them as those tools do, and legacy reads (and writes) them uncounted,
+    /// as earlier versions of lsdjtool wrote them
+    #[structopt(long, value_name("MODE"), global(true), default_value("lsdj"),
+                possible_values(&["lsdj", "lsdpatch", "legacy"]))]
+    compat: Compat,
+
+    /// Template for the names of exported songs, overriding the configured export_template:
+    /// {index}, {title}, and {version} are replaced, optionally with a format spec (e.g.
+    /// "{index:02}_{title}_{version:02x}.lsdsng"), and spaces and lightning bolts in titles
+    /// become underscores
+    #[structopt(long, value_name("TEMPLATE"), global(true))]
+    name_template: Option<String>,
+
+    /// Don't ask before overwriting, deleting, or replacing songs in an existing save file (which
+    /// is otherwise asked, listing the changes, whenever stdin is a terminal)
+    #[structopt(short, long, global(true))]
+    yes: bool,
+
+    /// Write errors to stderr as FORMAT: text, or json for a line holding an object with the
+    /// error's status (e.g. no_space or parse), its exit code, and its message. Exit codes are
+    /// 1 if a check failed, 2 for bad arguments, 3 if a file couldn't be parsed, 4 if there's
+    /// no space left, 5 if a song wasn't found, and 6 if reading or writing failed
+    #[structopt(long, value_name("FORMAT"), global(true), default_value("text"), possible_values(&["text", "json"]))]
+    error_format: ErrorFormat,
+
+    /// Don't print status messages (such as what was exported or written) to stderr; data
+    /// written to stdout, warnings, and errors are still printed
+    #[structopt(short, long, global(true))]
+    quiet: bool,
+
+    /// Print more about what's being done to stderr: -v for the files read and written, -vv for
+    /// the blocks given to songs, and -vvv for every run compressed
+    #[structopt(short, long, global(true), parse(from_occurrences), conflicts_with("quiet"))]
+    verbose: u8,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
 }
 
-fn main() -> io::Result<()> {
-    let opt = Opt::from_args();
-    let mut savefile = File::open(opt.savefile)?;
-    let mut outfile: Box<dyn io::Write> = match opt.output {
-        Some(path) => Box::new(File::create(path)?),
-        None => Box::new(io::stdout()),
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Export all songs, then re-export changed songs whenever the save file is rewritten
+    Watch {
+        /// Directory into which songs are exported (defaults to the configured output_dir)
+        #[structopt(long, value_name("DIR"), parse(from_os_str))]
+        export_all: Option<PathBuf>,
+
+        /// Save file to watch
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Export every song, along with a manifest of the exported songs' hashes
+    ExportAll {
+        /// Only export songs which have changed since the manifest was written
+        #[structopt(long)]
+        incremental: bool,
+
+        /// Directory into which songs are exported (defaults to the configured output_dir)
+        #[structopt(short, long, value_name("DIR"), parse(from_os_str))]
+        out_dir: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Print a fingerprint of each song's decompressed data
+    Hash {
+        /// Index of the only song to be hashed
+        #[structopt(short, long, value_name("INDEX"))]
+        song: Option<u8>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Find songs with identical contents across save files, keeping the copy with the
+    /// highest version
+    Dedupe {
+        /// Delete duplicate songs, rewriting the affected save files
+        #[structopt(long)]
+        remove: bool,
+
+        /// Save files, or directories to search for .sav files
+        #[structopt(value_name("PATH"), parse(from_os_str), required(true))]
+        paths: Vec<PathBuf>,
+    },
+    /// Reorder songs into consecutive slots by title, version, or size
+    Sort {
+        /// Key by which to sort songs (title, version, or size)
+        #[structopt(short, long, value_name("KEY"), default_value("title"),
+                    possible_values(&["title", "version", "size"]))]
+        by: SortKey,
+
+        /// Sort in descending order
+        #[structopt(short, long)]
+        reverse: bool,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Free blocks allocated to songs which have no title or don't exist
+    Prune {
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Mark a song as the one currently loaded into SRAM
+    SetWorking {
+        /// Index of the song to mark as the working song
+        #[structopt(value_name("INDEX"))]
+        index: u8,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Check that the skip instructions chaining each song's blocks agree with the allocation
+    /// table, listing any problems found (exits with status 1 if there are any)
+    Audit {
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Check the title table for junk bytes after the end of titles and for characters outside
+    /// LSDj's character set, listing any found (exits with status 1 if there are any)
+    Titles {
+        /// Zero the junk after each title instead, writing the fixed save (characters outside the
+        /// character set are only warned about)
+        #[structopt(long)]
+        fix: bool,
+
+        /// Output file for --fix (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str), requires("fix"))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Rebuild the skip instructions of songs whose chains are broken (as found by audit) from
+    /// the order of their blocks in the allocation table, salvaging songs which can't otherwise
+    /// be loaded or exported
+    Repair {
+        /// Index of the only song to be repaired, even if audit finds no problems with it
+        #[structopt(short, long, value_name("INDEX"))]
+        song: Option<u8>,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Export every complete song found by scanning the save file's blocks, ignoring its title
+    /// and allocation tables, for salvaging songs after the metadata is lost or corrupted (e.g.
+    /// by a cartridge battery glitch). Songs are named after the block they start at
+    Recover {
+        /// Directory into which songs are exported (defaults to the configured output_dir)
+        #[structopt(short, long, value_name("DIR"), parse(from_os_str))]
+        out_dir: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// List deleted songs whose blocks haven't been overwritten since, or restore one of them
+    /// into a free song slot
+    Undelete {
+        /// First block of the deleted song to restore (as listed without this option)
+        #[structopt(short, long, value_name("BLOCK"), parse(try_from_str = parse_byte))]
+        block: Option<u8>,
+
+        /// Title of the restored song, as deleting a song clears its title
+        #[structopt(short, long, value_name("TITLE"), default_value("RESTORED"))]
+        title: String,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Carve a save out of an off-spec binary dump (e.g. from a flashcart dumping tool) with extra
+    /// headers, the wrong size, or the save in every other byte, by locating its 'jk'
+    /// initialization bytes
+    Carve {
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Dump to carve the save out of
+        #[structopt(value_name("DUMPFILE"), parse(from_os_str))]
+        dumpfile: PathBuf,
+    },
+    /// Print a grid showing which song owns each block
+    Map {
+        /// Never color the output
+        #[structopt(long)]
+        no_color: bool,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Generate a save full of valid songs, for testing tools which read saves
+    Gen {
+        /// Number of songs to generate, titled SONG00, SONG01, ...
+        #[structopt(long, value_name("N"), default_value("1"))]
+        songs: usize,
+
+        /// Number of blocks each song compresses to (defaults to however many its content takes)
+        #[structopt(long, value_name("N"))]
+        blocks: Option<usize>,
+
+        /// What songs are filled with: nothing but what LSDj needs to load them, chains and
+        /// phrases of notes, or random bytes
+        #[structopt(long, value_name("CONTENT"), default_value("song"), possible_values(&["empty", "song", "noise"]))]
+        content: lsdj::generate::Content,
+
+        /// Seed for the songs' random bytes; the same options always generate the same save
+        #[structopt(long, value_name("SEED"), default_value("0"))]
+        seed: u64,
+
+        /// Size of the save file in KB
+        #[structopt(long, value_name("KB"), default_value("128"), possible_values(&["64", "128"]))]
+        size: u32,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Run the commands in a script against a save file, writing the save (and any songs exported)
+    /// only once every command has succeeded. Each line of the script is one of import PATH
+    /// [TITLE], rename SONG TITLE, delete SONG, or export SONG PATH, with paths relative to the
+    /// script and # starting a comment
+    Run {
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Script to run
+        #[structopt(value_name("SCRIPT"), parse(from_os_str))]
+        script: PathBuf,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Convert a save for use on the Analogue Pocket (or, with --from, a save taken from it)
+    Pocket {
+        /// Convert a save copied off the Pocket instead
+        #[structopt(long)]
+        from: bool,
+
+        /// Size in KB of the save produced by --from
+        #[structopt(long, value_name("KB"), default_value("128"), possible_values(&["64", "128"]))]
+        size: u32,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Pair an LSDj ROM with each save, for flashcarts and emulators which load a save named
+    /// after the ROM
+    Bundle {
+        /// Directory into which a copy of the ROM and a matching save are written for each
+        /// save file
+        #[structopt(short, long, value_name("DIR"), parse(from_os_str))]
+        out_dir: PathBuf,
+
+        /// LSDj ROM to bundle
+        #[structopt(value_name("ROM"), parse(from_os_str))]
+        rom: PathBuf,
+
+        /// Save files to bundle with the ROM
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str), required(true))]
+        savefiles: Vec<PathBuf>,
+    },
+    /// Show what changed between two songs: song rows, chains, phrases, instruments, tables,
+    /// and grooves
+    Diff {
+        /// Save file containing the old song
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+
+        /// Index of the old song
+        #[structopt(value_name("INDEX"))]
+        index: u8,
+
+        /// Index of the new song
+        #[structopt(value_name("OTHERINDEX"))]
+        other_index: u8,
+
+        /// Save file containing the new song (defaults to SAVEFILE)
+        #[structopt(value_name("OTHERSAVE"), parse(from_os_str))]
+        other_savefile: Option<PathBuf>,
+    },
+    /// Copy a song from another save file into this one, keeping its title and version
+    Copy {
+        /// Save file to copy the song from
+        #[structopt(long, value_name("OTHERSAVE"), parse(from_os_str))]
+        from: PathBuf,
+
+        /// Index of the song to copy
+        #[structopt(short, long, value_name("INDEX"), parse(try_from_str = parse_byte))]
+        song: u8,
+
+        /// Title for the copied song (defaults to its title in OTHERSAVE)
+        #[structopt(short, long, value_name("TITLE"))]
+        title: Option<String>,
+
+        /// What to do when a song in SAVEFILE already has the copied song's title: force,
+        /// rename, or skip (as with --on-collision when importing)
+        #[structopt(long, value_name("POLICY"), possible_values(&["force", "rename", "skip"]))]
+        on_collision: Option<OnCollision>,
+
+        /// Copy the song even if its format version doesn't match the songs in SAVEFILE
+        #[structopt(long)]
+        force: bool,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to copy the song into
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Write an IPS or BPS patch which turns one save file into another, for sharing changes
+    /// without the whole save
+    Patch {
+        /// Patch format: ips, or bps (the default), which checks that it's applied to the right
+        /// save (defaults to the extension of OUTFILE, if .ips or .bps)
+        #[structopt(long, value_name("FORMAT"), possible_values(&["ips", "bps"]))]
+        format: Option<PatchFormat>,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Original save file
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+
+        /// Modified save file
+        #[structopt(value_name("OTHERSAVE"), parse(from_os_str))]
+        other_savefile: PathBuf,
+    },
+    /// Apply an IPS or BPS patch to a save file
+    Apply {
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to patch
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+
+        /// IPS or BPS patch to apply
+        #[structopt(value_name("PATCHFILE"), parse(from_os_str))]
+        patch: PathBuf,
+    },
+    /// Write bytes at an offset in a save file, as a hex editor would (e.g. to fix the SRAM
+    /// initialization bytes with `poke 0x813e 6a6b`), listing the regions they land in
+    #[structopt(alias = "patch-bytes")]
+    Poke {
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+
+        /// Offset in the save file to write at (decimal, or hex prefixed with 0x)
+        #[structopt(value_name("OFFSET"), parse(try_from_str = parse_offset))]
+        offset: usize,
+
+        /// Bytes to write, as pairs of hex digits (e.g. 6a6b)
+        #[structopt(value_name("HEX"))]
+        bytes: HexBytes,
+    },
+    /// Dump or restore only the block region of a save file
+    Blocks {
+        #[structopt(subcommand)]
+        cmd: BlocksCommand,
+    },
+    /// Append the arrangement of one song after another's, as a new song
+    Splice {
+        /// Title of the new song (defaults to the first song's title)
+        #[structopt(short, long, value_name("TITLE"))]
+        title: Option<String>,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+
+        /// Index of the song which plays first
+        #[structopt(value_name("INDEX"))]
+        index: u8,
+
+        /// Index of the song appended after it
+        #[structopt(value_name("OTHERINDEX"))]
+        other_index: u8,
+    },
+    /// Summarize the notes, effect commands, instruments, and tables a song plays
+    Stats {
+        /// Index of the song to summarize
+        #[structopt(short, long, value_name("INDEX"))]
+        song: u8,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Show how each song compresses: its size in blocks and bytes, the ratio to its $8000
+    /// decompressed bytes, the bytes written as literals, and the bytes saved by runs and by
+    /// default instruments and waves
+    Compression {
+        /// Index of the only song to report on
+        #[structopt(short, long, value_name("INDEX"))]
+        song: Option<u8>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Find instruments which play a given kit or are of a given type, in every song of the
+    /// given save files
+    Grep {
+        /// Only list kit instruments which play KIT (e.g. 0x12)
+        #[structopt(long, value_name("KIT"), parse(try_from_str = parse_byte),
+                    required_unless("instrument-type"))]
+        kit: Option<u8>,
+
+        /// Only list instruments of type TYPE (pulse, wave, kit, or noise)
+        #[structopt(long, value_name("TYPE"), possible_values(&["pulse", "wave", "kit", "noise"]))]
+        instrument_type: Option<InstrumentType>,
+
+        /// Save files, or directories to search for .sav files
+        #[structopt(value_name("PATH"), parse(from_os_str), required(true))]
+        paths: Vec<PathBuf>,
+    },
+    /// Find songs whose titles (or, with --tags, sidecar tags) match a pattern, in save files,
+    /// exported songs, or directories of them, printing the file, slot, and version of each
+    Find {
+        /// Treat PATTERN as a regular expression, matching any part of a title
+        #[structopt(long)]
+        regex: bool,
+
+        /// Also match PATTERN against the tags in songs' sidecar files
+        #[structopt(long)]
+        tags: bool,
+
+        /// Glob matching whole titles, ignoring case, in which * matches anything and ? any one
+        /// character (e.g. 'OLD*')
+        #[structopt(value_name("PATTERN"))]
+        pattern: String,
+
+        /// Save files and exported songs, or directories to search for them
+        #[structopt(value_name("PATH"), parse(from_os_str), required(true))]
+        paths: Vec<PathBuf>,
+    },
+    /// Free the chains, phrases, instruments, and tables which songs never play
+    Clean {
+        /// Only list what would be freed, without writing anything
+        #[structopt(long, conflicts_with("output"))]
+        dry_run: bool,
+
+        /// Also merge identical phrases, pointing chains at the first copy of each
+        #[structopt(long)]
+        merge_phrases: bool,
+
+        /// Index of the only song to be cleaned (defaults to all songs)
+        #[structopt(short, long, value_name("INDEX"))]
+        song: Option<u8>,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Check that an exported song decompresses to exactly the same data as the song it was
+    /// exported from (exits with status 1 if it doesn't)
+    Verify {
+        /// Save file holding the original song
+        #[structopt(long, value_name("SAVEFILE"), parse(from_os_str))]
+        against: PathBuf,
+
+        /// Index of the original song
+        #[structopt(short, long, value_name("INDEX"), parse(try_from_str = parse_byte))]
+        song: u8,
+
+        /// Treat SONGFILE as a decompressed $8000-byte SRAM image (as exported with -d)
+        #[structopt(short = "D", long)]
+        decompressed: bool,
+
+        /// Exported song file (raw, in a container, or armored)
+        #[structopt(value_name("SONGFILE"), parse(from_os_str))]
+        songfile: PathBuf,
+    },
+    /// Check that every song survives being decompressed, recompressed, and decompressed again
+    /// with identical data (exits with status 1 if any doesn't)
+    Selftest {
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Decompress and recompress every song, reclaiming blocks from songs which take fewer once
+    /// recompressed (as songs saved by older LSDj versions often do); the save is only written
+    /// if every recompressed song decompresses to the same data
+    Optimize {
+        /// Also free the chains, phrases, instruments, and tables which songs never play (as the
+        /// clean command does) before recompressing them
+        #[structopt(long)]
+        clean: bool,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Edit the contents of a song
+    Edit {
+        #[structopt(subcommand)]
+        cmd: EditCommand,
+    },
+    /// Copy a chain, with the phrases, instruments, and tables it plays, between songs
+    Snippet {
+        #[structopt(subcommand)]
+        cmd: SnippetCommand,
+    },
+    /// Export an instrument of a song, with the tables it runs, as a preset file
+    ExportInstrument {
+        /// Index of the song to export from
+        #[structopt(short, long, value_name("INDEX"))]
+        song: u8,
+
+        /// Instrument to export (e.g. 0x1A)
+        #[structopt(long, value_name("INSTRUMENT"), parse(try_from_str = parse_byte))]
+        instrument: u8,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Import an instrument preset file into an instrument slot of a song
+    ImportInstrument {
+        /// Index of the song to import into
+        #[structopt(short, long, value_name("INDEX"))]
+        song: u8,
+
+        /// Instrument slot to import into, replacing its instrument (defaults to the first free slot)
+        #[structopt(long, value_name("SLOT"), parse(try_from_str = parse_byte))]
+        slot: Option<u8>,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Preset file to import
+        #[structopt(value_name("PRESET"), parse(from_os_str))]
+        preset: PathBuf,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Show the soft synths of a song, or regenerate their wave frames from their parameters
+    Synth {
+        #[structopt(subcommand)]
+        cmd: SynthCommand,
+    },
+    /// Show or edit the words said by the speech instrument of a song
+    Speech {
+        #[structopt(subcommand)]
+        cmd: SpeechCommand,
+    },
+    /// Read or write the save on a flash cart plugged in over USB (needs the usb feature)
+    Cart {
+        #[structopt(subcommand)]
+        cmd: CartCommand,
+    },
+    /// Print a song as QR codes of its armored text, or join scanned codes back together
+    Qr {
+        #[structopt(subcommand)]
+        cmd: QrCommand,
+    },
+    /// Index and search the save files and exported songs under a directory
+    Lib {
+        #[structopt(subcommand)]
+        cmd: LibCommand,
+    },
+    /// Extract a save from a Goomba GBA save (or, with --inject, replace the save inside it)
+    Goomba {
+        /// Save file to be placed into GBASAVE
+        #[structopt(long, value_name("SAVEFILE"), parse(from_os_str))]
+        inject: Option<PathBuf>,
+
+        /// Only use the SRAM of a ROM whose title contains TITLE (defaults to the first)
+        #[structopt(short, long, value_name("TITLE"))]
+        title: Option<String>,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Goomba save file to read from
+        #[structopt(value_name("GBASAVE"), parse(from_os_str))]
+        gbasave: PathBuf,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum EditCommand {
+    /// Transpose every note in a song, except on the noise channel and in kit instruments
+    Transpose {
+        /// Index of the song to transpose
+        #[structopt(short, long, value_name("INDEX"))]
+        song: u8,
+
+        /// Number of semitones to transpose by (negative to transpose down)
+        #[structopt(long, value_name("K"), allow_hyphen_values(true))]
+        semitones: i8,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Set the tempo of a song (or of every song)
+    Tempo {
+        /// Index of the only song to be edited (defaults to all songs)
+        #[structopt(short, long, value_name("INDEX"))]
+        song: Option<u8>,
+
+        /// Tempo to set, from 40 to 295
+        #[structopt(long, value_name("BPM"))]
+        bpm: u16,
+
+        /// Scale the step lengths of every groove along with the tempo, so that the song plays at
+        /// about the same speed as before
+        #[structopt(long)]
+        rescale_grooves: bool,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum BlocksCommand {
+    /// Write the block region ($8200 to the end of the save file) without SRAM or metadata
+    Dump {
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Overwrite the block region of a save file with a dumped one, leaving SRAM and metadata
+    /// untouched
+    Restore {
+        /// Block region dumped by blocks dump
+        #[structopt(long, value_name("BLOCKFILE"), parse(from_os_str))]
+        from: PathBuf,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum SnippetCommand {
+    /// Export a chain of a song as a snippet file
+    Export {
+        /// Index of the song to export from
+        #[structopt(short, long, value_name("INDEX"))]
+        song: u8,
+
+        /// Chain to export (e.g. 0x1A)
+        #[structopt(long, value_name("CHAIN"), parse(try_from_str = parse_byte))]
+        chain: u8,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Import a snippet file into free chain, phrase, instrument, and table slots of a song
+    Import {
+        /// Index of the song to import into
+        #[structopt(short, long, value_name("INDEX"))]
+        song: u8,
+
+        /// Snippet file to import
+        #[structopt(long, value_name("SNIPPET"), parse(from_os_str))]
+        from: PathBuf,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum QrCommand {
+    /// Render a song as numbered QR codes, TITLE-1.png, TITLE-2.png, ... (needs the qr feature)
+    Encode {
+        /// Index of the song
+        #[structopt(short, long, value_name("INDEX"))]
+        song: u8,
+
+        /// Write SVGs rather than PNGs
+        #[structopt(long)]
+        svg: bool,
+
+        /// Directory into which the codes are written
+        #[structopt(short, long, value_name("DIR"), parse(from_os_str))]
+        out_dir: PathBuf,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Join the text scanned from each QR code of a song (one file per code, in any order) into
+    /// an armored song, which can be imported with -i
+    Decode {
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Files holding the text of each code
+        #[structopt(value_name("PART"), parse(from_os_str), required(true))]
+        parts: Vec<PathBuf>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum CartCommand {
+    /// Read the save from the cart
+    Pull {
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Write a save file to the cart, replacing the save on it
+    Push {
+        /// Save file to write
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum SpeechCommand {
+    /// Print the name and allophones of every word
+    List {
+        /// Index of the song
+        #[structopt(short, long, value_name("INDEX"))]
+        song: u8,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Set the allophones (and optionally the name) of a word
+    Set {
+        /// Index of the song
+        #[structopt(short, long, value_name("INDEX"))]
+        song: u8,
+
+        /// Word to set (e.g. 0x1A)
+        #[structopt(long, value_name("WORD"), parse(try_from_str = parse_byte))]
+        word: u8,
+
+        /// Name of the word, up to 4 characters (defaults to its current name)
+        #[structopt(long, value_name("NAME"))]
+        name: Option<String>,
+
+        /// Allophones to say, e.g. "HH1 EH:0C LL OW" (durations in hexadecimal)
+        #[structopt(long, value_name("ALLOPHONES"), conflicts_with("text"))]
+        allophones: Option<String>,
+
+        /// English text to spell out as allophones, roughly (tune the result by ear)
+        #[structopt(long, value_name("TEXT"))]
+        text: Option<String>,
+
+        /// Output file (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum SynthCommand {
+    /// Print the parameters and wave frames of a soft synth
+    Show {
+        /// Index of the song
+        #[structopt(short, long, value_name("INDEX"))]
+        song: u8,
+
+        /// Soft synth to show (0 to F)
+        #[structopt(long, value_name("SYNTH"), parse(try_from_str = parse_byte))]
+        synth: u8,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+    /// Print the wave frames of a soft synth regenerated from its parameters. They only
+    /// approximate LSDj's, so the save is never changed
+    Render {
+        /// Index of the song
+        #[structopt(short, long, value_name("INDEX"))]
+        song: u8,
+
+        /// Soft synth to regenerate (0 to F)
+        #[structopt(long, value_name("SYNTH"), parse(try_from_str = parse_byte))]
+        synth: u8,
+
+        /// Save file to read from
+        #[structopt(value_name("SAVEFILE"), parse(from_os_str))]
+        savefile: PathBuf,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum LibCommand {
+    /// List every song in the save files (.sav) and exported songs (.lsdsng) under DIR
+    Scan {
+        /// Only list songs tagged TAG in their sidecar files
+        #[structopt(long, value_name("TAG"))]
+        tag: Option<String>,
+
+        /// Directory to search
+        #[structopt(value_name("DIR"), parse(from_os_str))]
+        dir: PathBuf,
+    },
+    /// List every copy of the songs whose titles contain TITLE
+    Find {
+        /// Title (or part of one) to search for
+        #[structopt(value_name("TITLE"))]
+        title: String,
+
+        /// Only list songs tagged TAG in their sidecar files
+        #[structopt(long, value_name("TAG"))]
+        tag: Option<String>,
+
+        /// Directory to search
+        #[structopt(value_name("DIR"), parse(from_os_str))]
+        dir: PathBuf,
+    },
+    /// List the newest copy of each song, by title
+    Latest {
+        /// Only list songs tagged TAG in their sidecar files
+        #[structopt(long, value_name("TAG"))]
+        tag: Option<String>,
+
+        /// Directory to search
+        #[structopt(value_name("DIR"), parse(from_os_str))]
+        dir: PathBuf,
+    },
+    /// List each distinct version of the song titled TITLE, oldest first (or, with --export,
+    /// export one of them)
+    History {
+        /// Export the version whose hash starts with HASH instead
+        #[structopt(long, value_name("HASH"))]
+        export: Option<String>,
+
+        /// Output file for --export (defaults to stdout)
+        #[structopt(short, long, value_name("OUTFILE"), parse(from_os_str), requires("export"))]
+        output: Option<PathBuf>,
+
+        /// Title of the song
+        #[structopt(value_name("TITLE"))]
+        title: String,
+
+        /// Directory to search
+        #[structopt(value_name("DIR"), parse(from_os_str))]
+        dir: PathBuf,
+    },
+}
+
+/// Indexes the songs under `dir`.
+#[cfg(not(feature = "sqlite"))]
+fn scan_library(dir: &Path) -> io::Result<library::Library> {
+    library::Library::scan(dir)
+}
+
+/// Indexes the songs under `dir`, keeping the index in a SQLite database so
+/// that only changed files are scanned again.
+#[cfg(feature = "sqlite")]
+fn scan_library(dir: &Path) -> io::Result<library::Library> {
+    match library::index_path() {
+        Some(index) => library::Library::scan_indexed(dir, &index),
+        None => library::Library::scan(dir),
+    }
+}
+
+/// Runs a `lib` subcommand, printing the songs it lists.
+fn lib(cmd: LibCommand) -> io::Result<()> {
+    let library = match &cmd {
+        LibCommand::Scan { dir, .. } | LibCommand::Find { dir, .. } | LibCommand::Latest { dir, .. }
+        | LibCommand::History { dir, .. } => scan_library(dir)?,
     };
-    let save = LsdjSave::from(&mut savefile)?;
-    if opt.list_songs {
-        let songlist = save.metadata.list_songs();
-        outfile.write_all(songlist.as_bytes())?;
+    let (listed, tag): (Vec<&library::Entry>, _) = match cmd {
+        LibCommand::Scan { tag, .. } => (library.entries.iter().collect(), tag),
+        LibCommand::Find { title, tag, .. } => (library.find(&title), tag),
+        LibCommand::Latest { tag, .. } => (library.latest(), tag),
+        LibCommand::History { export: Some(hash), output, title, .. } => {
+            let history = library.history(&title);
+            let mut matches = history.iter().filter(|(e, _)| format!("{:016x}", e.hash).starts_with(&hash));
+            let entry = match (matches.next(), matches.next()) {
+                (Some((entry, _)), None) => entry,
+                (None, _) => return Err(fail(Status::NotFound, format!("no version of {} has hash {}", title, hash))),
+                (Some(_), Some(_)) => return Err(fail(Status::Usage, format!("hash {} is ambiguous", hash))),
+            };
+            return write_output(output, &entry.export()?);
+        },
+        LibCommand::History { title, .. } => {
+            for (entry, copies) in library.history(&title) {
+                println!("{}  {}  ({} {})", library::format_time(entry.modified), entry, copies,
+                         if copies == 1 { "copy" } else { "copies" });
+            }
+            return Ok(());
+        },
+    };
+    for entry in listed.into_iter().filter(|e| tag.as_ref().is_none_or(|t| e.has_tag(t))) {
+        println!("{}", entry);
+    }
+    Ok(())
+}
+
+/// Prints every song in `paths` (save files and exported songs, or
+/// directories searched recursively for them) whose title matches `pattern`,
+/// or with `tags`, which has a tag matching it in its sidecar file.
+///
+/// Each line names the file, slot (`--` for exported songs), title, and
+/// version, along with the song's sidecar details if it has any.
+fn find_songs(pattern: &library::Pattern, paths: &[PathBuf], tags: bool) -> io::Result<()> {
+    for path in paths {
+        let entries = if path.is_dir() { scan_library(path)?.entries } else { library::scan_file(path)? };
+        for entry in entries.iter().filter(|e| e.matches(pattern, tags)) {
+            let slot = entry.index.map_or("--".to_string(), |s| format!("{:02X}", s));
+            let version = entry.version.map_or("--".to_string(), |v| format!("{:>2X}", v));
+            print!("{}: {} {:<8} {}", entry.path.display(), slot, entry.title, version);
+            match &entry.sidecar {
+                Some(sidecar) => println!("  {}", sidecar),
+                None => println!(),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses a byte written in hexadecimal with a leading `0x`, or in decimal.
+fn parse_byte(s: &str) -> Result<u8, std::num::ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Parses an offset given in decimal or, prefixed with `0x`, in hexadecimal.
+fn parse_offset(s: &str) -> Result<usize, std::num::ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Bytes given on the command line as pairs of hex digits, optionally
+/// separated by spaces (e.g. `6a6b` or `6a 6b`).
+#[derive(Debug)]
+struct HexBytes(Vec<u8>);
+
+impl std::str::FromStr for HexBytes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HexBytes, String> {
+        let digits: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return Err(format!("{}: bytes must be given as pairs of hex digits", s));
+        }
+        digits.chunks(2)
+              .map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16).map_err(|e| format!("{}: {}", s, e)))
+              .collect::<Result<_, _>>()
+              .map(HexBytes)
+    }
+}
+
+/// Song indices given on the command line as a comma-separated list of
+/// indices and inclusive ranges (e.g. `0,3,5-7`), in the order given, without
+/// repeats.
+#[derive(Debug)]
+struct SongList(Vec<u8>);
+
+impl std::str::FromStr for SongList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SongList, String> {
+        let mut songs = Vec::new();
+        for part in s.split(',') {
+            let (first, last) = part.split_once('-').unwrap_or((part, part));
+            let first = parse_byte(first.trim()).map_err(|e| format!("{}: {}", part, e))?;
+            let last = parse_byte(last.trim()).map_err(|e| format!("{}: {}", part, e))?;
+            if first > last {
+                return Err(format!("{}: range ends before it starts", part));
+            }
+            for song in first..=last {
+                if !songs.contains(&song) {
+                    songs.push(song);
+                }
+            }
+        }
+        Ok(SongList(songs))
+    }
+}
+
+/// Converts the save file at `savepath` to (or, if `from` is true, from) the
+/// Analogue Pocket's conventions, writing the converted save to `output`.
+fn convert_pocket(savepath: &Path, from: bool, size: u32, output: Option<PathBuf>) -> io::Result<()> {
+    let bytes = if from {
+        let layout = if size == 64 { LsdjLayout::SAVE_64KB } else { LsdjLayout::SAVE_128KB };
+        let save = lsdj::pocket::from_pocket(&std::fs::read(savepath)?, layout).map_err(LsdjError::from)?;
+        save.bytes()
+    } else {
+        let save = open_save(savepath)?;
+        lsdj::pocket::to_pocket(save).map_err(LsdjError::from)?
+    };
+    write_output(output, &bytes)
+}
+
+/// Writes a copy of the ROM at `rompath` to `out_dir` for each save file in
+/// `savepaths`, along with that save prepared for the ROM. Both files are
+/// named after the save file, so no two save files may share a name.
+fn bundle(rompath: &Path, savepaths: &[PathBuf], out_dir: &Path) -> io::Result<()> {
+    let names: Vec<String> = savepaths.iter()
+        .map(|path| path.file_stem().unwrap_or_default().to_string_lossy().into_owned())
+        .collect();
+    // compared without case, as the output directory may be on a case-insensitive filesystem
+    for (i, name) in names.iter().enumerate() {
+        if let Some(j) = names[..i].iter().position(|other| other.to_lowercase() == name.to_lowercase()) {
+            return Err(fail(Status::Usage, format!("{} and {} would both be bundled as {}.gb",
+                                                    savepaths[j].display(), savepaths[i].display(), name)));
+        }
+    }
+    let rom = std::fs::read(rompath)?;
+    std::fs::create_dir_all(out_dir)?;
+    for (savepath, name) in savepaths.iter().zip(&names) {
+        let save = open_save(savepath)?;
+        let bytes = lsdj::rom::bundle(&rom, save).map_err(LsdjError::from)?;
+        let rompath = out_dir.join(format!("{}.gb", name));
+        let outpath = out_dir.join(format!("{}.sav", name));
+        if outpath.exists() && outpath.canonicalize()? == savepath.canonicalize()? {
+            return Err(fail(Status::Usage, format!("{} would be overwritten", savepath.display())));
+        }
+        lsdj::io::write_atomic(&rompath, &rom)?;
+        lsdj::io::write_atomic(&outpath, &bytes)?;
+        status!("{}", rompath.display());
+    }
+    Ok(())
+}
+
+/// Extracts the save inside the Goomba save file at `gbapath` (or, if
+/// `inject` is given, replaces it with that save), writing the result to
+/// `output`.
+fn convert_goomba(gbapath: &Path, inject: Option<PathBuf>, title: Option<String>,
+                  output: Option<PathBuf>) -> io::Result<()> {
+    let gbasave = std::fs::read(gbapath)?;
+    let bytes = match inject {
+        Some(savepath) => {
+            let save = open_save(savepath)?;
+            lsdj::goomba::inject(&gbasave, &save.bytes(), title.as_deref()).map_err(LsdjError::from)?
+        },
+        None => lsdj::goomba::extract(&gbasave, title.as_deref()).map_err(LsdjError::from)?,
+    };
+    write_output(output, &bytes)
+}
+
+/// Returns true if output to stdout should be colored. Unless the config
+/// file says otherwise, stdout must be a terminal and the `NO_COLOR`
+/// environment variable must be unset.
+fn use_color(no_color: bool) -> bool {
+    match config().color {
+        _ if no_color => false,
+        Some(ColorChoice::Never) => false,
+        Some(ColorChoice::Always) => true,
+        Some(ColorChoice::Auto) | None =>
+            std::env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal(),
+    }
+}
+
+/// Returns the name of the file to which `song` in `save` is exported, named
+/// from the export template with extension `ext`.
+fn export_file_name(save: &LsdjSave, song: u8, ext: &str) -> String {
+    let version = save.metadata.version_table[song as usize];
+    format!("{}.{}", config().export_name(song, &save.metadata.song_title(song), version), ext)
+}
+
+/// Returns the file to which an exported song should be written: `output`
+/// if given, otherwise a file named from the export template inside the
+/// configured output directory (if any), with extension `ext`.
+fn export_output(output: Option<PathBuf>, save: &LsdjSave, song: u8, ext: &str) -> io::Result<Option<PathBuf>> {
+    if output.is_some() {
+        return Ok(output);
+    }
+    match &config().output_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            Ok(Some(dir.join(export_file_name(save, song, ext))))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns the extension of songs exported by `-e`: `lsdsng.txt` if armored
+/// (`armor`), `lsdc` in a container (`container`), or otherwise `lsdsng`.
+fn song_extension(container: bool, armor: bool) -> &'static str {
+    if armor {
+        "lsdsng.txt"
+    } else if container {
+        "lsdc"
+    } else {
+        "lsdsng"
+    }
+}
+
+/// Writes `song` of `save` to `output` as exported by `-e` (see
+/// `write_export()`): in a container with `--container`, armored with
+/// `--armor`, or otherwise as raw blocks, which are written straight from the
+/// save file unless they are to be compressed or encrypted.
+fn export_song(output: Option<PathBuf>, save: &LsdjSave, song: u8, opt: &Opt) -> io::Result<()> {
+    let bytes = if opt.armor {
+        lsdj::armor::armor(&song_container(save, song)?).into_bytes()
+    } else if opt.container {
+        song_container(save, song)?.bytes()
+    } else if export_filters(output.as_deref(), opt.compress, opt.encrypt) == (None, false) {
+        return write_output_with(output, |w| save.export_song_to(song, w).map(drop));
+    } else {
+        save.export_song(song)
+    };
+    write_export(output, opt.compress, opt.encrypt, opt.timestamp, &bytes)
+}
+
+/// Returns the blocks of `song` in `save` in a container, along with its title
+/// and versions.
+fn song_container(save: &LsdjSave, song: u8) -> io::Result<lsdj::container::Container> {
+    Ok(lsdj::container::Container {
+        title: save.metadata.title_table[song as usize],
+        version: save.metadata.version_table[song as usize],
+        format_version: read_song(save, song)?.format_version(),
+        blocks: save.export_song(song),
+    })
+}
+
+/// Returns the extension of exported files of type `ext` once compressed with
+/// `codec` and, if `encrypt` is true, encrypted (e.g. `lsdsng.gz.age`).
+fn export_extension(ext: &str, codec: Option<Codec>, encrypt: bool) -> String {
+    let mut ext = match codec {
+        Some(codec) => format!("{}.{}", ext, codec.extension()),
+        None => ext.to_string(),
+    };
+    if encrypt {
+        ext = format!("{}.{}", ext, lsdj::io::ENCRYPTED_EXTENSION);
+    }
+    ext
+}
+
+/// Returns the time recorded by `--timestamp`: `$SOURCE_DATE_EPOCH` if set,
+/// as with other tools making reproducible files, or else the current time.
+fn export_time() -> io::Result<u32> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let seconds = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(s) => s.parse::<u64>().map_err(|e| invalid(format!("SOURCE_DATE_EPOCH: {}", e)))?,
+        Err(_) => std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+                      .map_err(|e| invalid(e.to_string()))?.as_secs(),
+    };
+    u32::try_from(seconds).map_err(|_| invalid("time can't be recorded in a gzip header".to_string()))
+}
+
+/// Returns the codec an exported file written to `output` is compressed
+/// with (`codec`, or else the one named by the extension of `output`), and
+/// whether it is encrypted (if `encrypt` is true or `output` ends in `.age`).
+fn export_filters(output: Option<&Path>, codec: Option<Codec>, encrypt: bool) -> (Option<Codec>, bool) {
+    let encrypted_output = output.filter(|p| p.extension().is_some_and(|e| e == lsdj::io::ENCRYPTED_EXTENSION));
+    let unencrypted_name = encrypted_output.map(|p| p.with_extension(""));
+    let codec = codec.or_else(|| unencrypted_name.as_deref().or(output).and_then(Codec::from_path));
+    (codec, encrypt || encrypted_output.is_some())
+}
+
+/// Writes an exported file to `output` (see `write_output()`), compressing and
+/// encrypting it as `export_filters()` decides. The time of export is recorded
+/// if `timestamp` is true (see `Codec::compress_stamped()`).
+///
+/// Unless encrypted (age's format is randomized) or timestamped, the file
+/// written depends only on `bytes`.
+fn write_export(output: Option<PathBuf>, codec: Option<Codec>, encrypt: bool, timestamp: bool,
+                bytes: &[u8]) -> io::Result<()> {
+    let (codec, encrypt) = export_filters(output.as_deref(), codec, encrypt);
+    let mtime = if timestamp { Some(export_time()?) } else { None };
+    write_output(output, &filter_export(bytes, codec, encrypt, mtime)?)
+}
+
+/// Returns `bytes` compressed with `codec`, if given, recording `mtime` if
+/// given (see `Codec::compress_stamped()`), and then encrypted if `encrypt`
+/// is true.
+fn filter_export(bytes: &[u8], codec: Option<Codec>, encrypt: bool, mtime: Option<u32>) -> io::Result<Vec<u8>> {
+    let mut bytes = match (codec, mtime) {
+        (Some(codec), Some(mtime)) => codec.compress_stamped(bytes, mtime)?,
+        (Some(codec), None) => codec.compress(bytes)?,
+        (None, _) => bytes.to_vec(),
+    };
+    if encrypt {
+        bytes = crypt::encrypt(&bytes)?;
+    }
+    Ok(bytes)
+}
+
+/// Runs the song_exported hooks for `song` of `save`, read from `savepath`
+/// and exported to `output`.
+fn song_exported(savepath: &Path, output: Option<PathBuf>, save: &LsdjSave, song: u8) {
+    config().hooks.run(Event::SongExported, serde_json::json!({
+        "path": output,
+        "source": savepath,
+        "song": hooks::song(save, song),
+    }));
+}
+
+/// Returns what `result` holds, or an error giving the reason `song` couldn't
+/// be read (such as where its blocks are corrupt).
+fn song_or_err<T>(song: u8, result: Result<T, LsdjError>) -> io::Result<T> {
+    result.map_err(|e| exit::song_failure(song, e))
+}
+
+/// Decompresses and reads `song` of `save`.
+fn read_song(save: &LsdjSave, song: u8) -> io::Result<lsdj::song::Song> {
+    song_or_err(song, save.song(song))
+}
+
+/// Reads the save file at `path`, compressing and decompressing its songs in
+/// the mode given by `--compat`.
+fn open_save<P: AsRef<Path>>(path: P) -> io::Result<LsdjSave> {
+    let mut save = LsdjSave::from(&mut File::open(&path)?)?;
+    save.set_compat(*COMPAT.get_or_init(Compat::default));
+    info!("read {}: {} song(s) in {} of {} blocks", path.as_ref().display(), save.metadata.songs().len(),
+          save.metadata.blocks_used(), save.layout().block_count());
+    Ok(save)
+}
+
+/// Writes `bytes` to the file at `output` (atomically, so that a failed write
+/// never leaves a partial file, and backing up any file being overwritten), or
+/// to stdout if no path is given.
+///
+/// If a save file would be written over another, the songs it changes are
+/// listed and the user is asked whether to go ahead (see `confirm`), exiting
+/// without writing anything if not.
+fn write_output(output: Option<PathBuf>, bytes: &[u8]) -> io::Result<()> {
+    if let Some(path) = output.as_ref() {
+        if !confirm::confirm_overwrite(path, bytes, assume_yes())? {
+            abort(&path.display().to_string());
+        }
+    }
+    write_output_with(output, |w| w.write_all(bytes))
+}
+
+/// Returns `dir`, or the configured output_dir if it's not given.
+fn export_dir(dir: Option<PathBuf>) -> io::Result<PathBuf> {
+    dir.or_else(|| config().output_dir.clone())
+       .ok_or_else(|| fail(Status::Usage, "no export directory given or configured"))
+}
+
+/// Returns true if `--yes` was given.
+fn assume_yes() -> bool {
+    *ASSUME_YES.get_or_init(|| false)
+}
+
+/// Exits after the user declines to overwrite `target`.
+fn abort(target: &str) -> ! {
+    eprintln!("aborted; {} was not written", target);
+    std::process::exit(Status::Failed as i32);
+}
+
+/// Writes to the file at `output`, or to stdout, as `write_output()` does,
+/// with `write` writing the contents.
+fn write_output_with<F>(output: Option<PathBuf>, write: F) -> io::Result<()>
+    where F: FnOnce(&mut dyn Write) -> io::Result<()> {
+    match output {
+        Some(path) => {
+            if let Some(backup) = lsdj::io::backup(&path, BACKUP_POLICY.get_or_init(BackupPolicy::default))? {
+                info!("backed up {} to {}", path.display(), backup.display());
+            }
+            lsdj::io::write_atomic_with(&path, |file| write(file))?;
+            info!("wrote {}", path.display());
+            Ok(())
+        },
+        None => write(&mut io::stdout().lock()),
+    }
+}
+
+/// Writes `save` to `output` (see `write_output()`), then runs the
+/// save_written hooks.
+fn write_save(output: Option<PathBuf>, save: &LsdjSave) -> io::Result<()> {
+    write_output(output.clone(), &save.bytes())?;
+    config().hooks.run(Event::SaveWritten, serde_json::json!({ "path": output, "songs": hooks::songs(save) }));
+    Ok(())
+}
+
+/// Sorts the songs in the save file at `savepath`, writing the modified save
+/// to `output`.
+fn sort_songs(savepath: &Path, by: SortKey, reverse: bool, output: Option<PathBuf>) -> io::Result<()> {
+    let mut save = open_save(savepath)?;
+    save.metadata.sort_songs(by, reverse);
+    write_save(output, &save)
+}
+
+/// Runs the script at `scriptpath` against the save file at `savepath`,
+/// writing the modified save to `output` and then the songs it exports once
+/// every step has succeeded; if any fails, nothing is written. Overwriting
+/// each export is confirmed before the save is written, so that declining
+/// one doesn't leave a modified save without the exports.
+fn run_script(scriptpath: &Path, savepath: &Path, output: Option<PathBuf>) -> io::Result<()> {
+    let mut save = open_save(savepath)?;
+    let exports = script::run(scriptpath, &mut save)?;
+    for (path, bytes) in exports.iter() {
+        if !confirm::confirm_overwrite(path, bytes, assume_yes())? {
+            abort(&path.display().to_string());
+        }
+    }
+    write_save(output, &save)?;
+    for (path, bytes) in exports {
+        write_output_with(Some(path.clone()), |w| w.write_all(&bytes))?;
+        status!("exported {}", path.display());
+    }
+    Ok(())
+}
+
+/// Frees orphaned blocks in the save file at `savepath`, writing the modified
+/// save to `output`.
+fn prune(savepath: &Path, output: Option<PathBuf>) -> io::Result<()> {
+    let mut save = open_save(savepath)?;
+    let freed = save.metadata.prune_orphans();
+    status!("freed {} orphaned block(s)", freed);
+    write_save(output, &save)
+}
+
+/// Marks `song` as the working song in the save file at `savepath`, writing
+/// the modified save to `output`.
+fn set_working(savepath: &Path, song: u8, output: Option<PathBuf>) -> io::Result<()> {
+    let mut save = open_save(savepath)?;
+    save.metadata.set_working_song(song).map_err(LsdjError::from)?;
+    write_save(output, &save)
+}
+
+/// Repairs the skip chains of the songs in the save file at `savepath` which
+/// audit finds problems with (or only `song`, if given), writing the modified
+/// save to `output`.
+fn repair_chains(savepath: &Path, song: Option<u8>, output: Option<PathBuf>) -> io::Result<()> {
+    let mut save = open_save(savepath)?;
+    let mut songs: Vec<u8> = match song {
+        Some(s) => vec![s],
+        None => save.audit_blocks().iter().map(|p| p.song()).collect(),
+    };
+    songs.dedup();
+    for song in songs {
+        match save.repair_chain(song) {
+            Ok(0) => status!("repaired song {:02X} ({})", song, save.metadata.song_title(song)),
+            Ok(freed) => status!("repaired song {:02X} ({}), freeing {} block(s) past its end",
+                                   song, save.metadata.song_title(song), freed),
+            Err(e) => eprintln!("could not repair song {:02X} ({}): {}", song, save.metadata.song_title(song), e),
+        }
+    }
+    write_save(output, &save)
+}
+
+/// Lists the problems with the title table of the save file at `savepath`,
+/// exiting with status 1 if there are any or, if `fix` is true, zeroes the
+/// junk after each title and writes the fixed save to `output`.
+fn check_titles(savepath: &Path, fix: bool, output: Option<PathBuf>) -> io::Result<()> {
+    let mut save = open_save(savepath)?;
+    let problems = save.metadata.check_titles();
+    if !fix {
+        for problem in problems.iter() {
+            println!("{}", problem);
+        }
+        if !problems.is_empty() {
+            std::process::exit(Status::Failed as i32);
+        }
+        status!("no problems found");
+        return Ok(());
+    }
+    for problem in problems.iter().filter(|p| matches!(p, lsdj::TitleProblem::BadChars { .. })) {
+        eprintln!("warning: {}", problem);
+    }
+    for song in save.metadata.fix_titles() {
+        status!("fixed title {:02X} ({})", song, save.metadata.song_title(song));
+    }
+    write_save(output, &save)
+}
+
+/// Exports each song found by scanning the blocks of the save file at
+/// `savepath` into `dir`, exiting with status 1 if none are found.
+fn recover_songs(savepath: &Path, dir: &Path) -> io::Result<()> {
+    let save = open_save(savepath)?;
+    let songs = save.recover_songs();
+    if songs.is_empty() {
+        eprintln!("no complete songs found");
+        std::process::exit(Status::Failed as i32);
+    }
+    std::fs::create_dir_all(dir)?;
+    for (first, bytes) in songs {
+        let path = dir.join(format!("RECOVERED_{:02X}.lsdsng", first));
+        lsdj::io::write_atomic(&path, &bytes)?;
+        status!("recovered song starting at block {:02X} into {}", first, path.display());
+    }
+    Ok(())
+}
+
+/// Restores the deleted song starting at `block` in the save file at
+/// `savepath` as `title`, writing the modified save to `output`, or lists the
+/// deleted songs found if no block is given.
+fn undelete(savepath: &Path, block: Option<u8>, title: &str, output: Option<PathBuf>) -> io::Result<()> {
+    let mut save = open_save(savepath)?;
+    let block = match block {
+        Some(b) => b,
+        None => {
+            let deleted = save.deleted_songs();
+            if deleted.is_empty() {
+                status!("no deleted songs found");
+            }
+            for chain in deleted {
+                println!("deleted song at block {:02X} ({} blocks)", chain[0], chain.len());
+            }
+            return Ok(());
+        },
+    };
+    let title = lsdj::lsdjtitle_from(title).map_err(LsdjError::from)?;
+    let song = save.undelete_song(block as usize, title).map_err(LsdjError::from)?;
+    status!("restored into {:02X}", song);
+    write_save(output, &save)
+}
+
+/// Reads the song file at `path`: blocks of compressed song data (raw, in a
+/// container, or armored, and possibly compressed with gzip or zstd), or an
+/// SRAM image if `decompressed` is true. Raw blocks are repaired (with a
+/// warning) and, if `pad` is true, a truncated last block is padded. Returns
+/// the song data along with the container it was read from, if any.
+fn read_song_file(path: &Path, decompressed: bool, pad: bool, compat: Compat)
+                  -> io::Result<(Vec<u8>, Option<lsdj::container::Container>)> {
+    let mut raw = Vec::new();
+    File::open(path)?.read_to_end(&mut raw)?;
+    if lsdj::io::is_encrypted(&raw) {
+        raw = crypt::decrypt(&raw)?;
+    }
+    let mut raw = lsdj::io::decompress(raw)?; // in case the song was exported with --compress
+
+    if decompressed {
+        return Ok((raw, None));
+    }
+    let container = if lsdj::armor::is_armored(&raw) {
+        lsdj::armor::dearmor(&String::from_utf8_lossy(&raw))?
+    } else if lsdj::container::Container::is_container(&raw) {
+        lsdj::container::Container::parse(&raw)?
+    } else {
+        for repair in lsdj::repair_blocks(&mut raw, compat) {
+            eprintln!("warning: {}", repair);
+        }
+        let mut bytes = Vec::new();
+        lsdj::read_blocks(&raw[..], &mut bytes, pad)?;
+        return Ok((bytes, None));
+    };
+    Ok((container.blocks.clone(), Some(container)))
+}
+
+/// Checks that the song file at `songpath` (an SRAM image if `decompressed`
+/// is true) decompresses to the same data as `song` in the save file at
+/// `savepath`, exiting with status 1 if it doesn't.
+fn verify_export(songpath: &Path, savepath: &Path, song: u8, decompressed: bool) -> io::Result<()> {
+    let save = open_save(savepath)?;
+    let compat = *COMPAT.get_or_init(Compat::default);
+    let (bytes, _) = read_song_file(songpath, decompressed, false, compat)?;
+    let exported = if decompressed {
+        bytes
+    } else {
+        lsdj::sram_from_blocks(&bytes, compat)?.to_vec()
+    };
+    let original = save.decompress_song(song)?;
+    let title = save.metadata.song_title(song);
+    let differences: Vec<usize> = (0..original.len()).filter(|&i| exported.get(i) != Some(&original[i])).collect();
+    if differences.is_empty() && exported.len() == original.len() {
+        println!("{} matches song {:02X} ({}) of {}", songpath.display(), song, title, savepath.display());
         return Ok(());
+    }
+    match differences.first() {
+        Some(&first) => println!("{} differs from song {:02X} ({}) in {} byte(s), first at {:#06x} ({})",
+                                 songpath.display(), song, title, differences.len(), first, lsdj::song::region(first)),
+        None => println!("{} is longer than song {:02X} ({})", songpath.display(), song, title),
+    }
+    std::process::exit(Status::Failed as i32);
+}
+
+/// Returns the title under which a song titled `title` should be imported
+/// into `save`, as decided by `policy` if a song in `save` already has that
+/// title, or an `Err` if `policy` is to skip it.
+fn resolve_collision(save: &LsdjSave, title: lsdj::LsdjTitle, policy: OnCollision) -> io::Result<lsdj::LsdjTitle> {
+    let existing = match save.metadata.find_title(title) {
+        Some(s) => s,
+        None => return Ok(title),
+    };
+    let name = save.metadata.song_title(existing);
+    match policy {
+        OnCollision::Force => {
+            eprintln!("warning: song {:02X} is already titled {}", existing, name);
+            Ok(title)
+        },
+        OnCollision::Rename => {
+            let title = save.metadata.unique_title(title);
+            status!("song {:02X} is already titled {}; importing as {}", existing, name, lsdj::title_string(title));
+            Ok(title)
+        },
+        OnCollision::Skip => Err(fail(Status::Usage, format!(
+            "song {:02X} is already titled {}; use --on-collision rename or force to import it anyway", existing, name))),
+    }
+}
+
+/// Copies `song` from the save file at `frompath` into the save file at
+/// `savepath`, keeping its title (unless `title` is given) and version, and
+/// writes the modified save to `output`.
+fn copy_song(savepath: &Path, frompath: &Path, song: u8, title: Option<String>, on_collision: OnCollision,
+             force: bool, output: Option<PathBuf>) -> io::Result<()> {
+    let from = open_save(frompath)?;
+    let slot = match from.slot(song) {
+        Some(slot) => slot,
+        None => return Err(fail(Status::NotFound,
+                                format!("{:02X}: no song exists at that index in {}", song, frompath.display()))),
+    };
+    let mut save = open_save(savepath)?;
+    if !force {
+        let version = slot.decompress().and_then(|sram| Ok(lsdj::song::Song::from(&sram)?)).map(|s| s.format_version());
+        if let Some(warning) = version_mismatch(&save, version, None) {
+            return Err(fail(Status::Usage, format!("{}; use --force to copy it anyway", warning)));
+        }
+    }
+    let title = match title {
+        Some(t) => lsdj::lsdjtitle_from(t.as_str()).map_err(LsdjError::from)?,
+        None => slot.raw_title(),
+    };
+    let title = resolve_collision(&save, title, on_collision)?;
+    let index = save.import_song(&slot.compressed_bytes(), title).map_err(LsdjError::from)?;
+    save.metadata.version_table[index as usize] = slot.version();
+    status!("copied {:02X} {} into {:02X}", song, slot.title(), index);
+    write_save(output.clone(), &save)?;
+    config().hooks.run(Event::SongImported, serde_json::json!({
+        "path": output,
+        "source": frompath,
+        "song": hooks::song(&save, index),
+    }));
+    Ok(())
+}
+
+/// Returns a description of why a song with format version `version` (or
+/// whose format version couldn't be read) shouldn't be imported into `save`:
+/// its version differs from `target`, if given, or otherwise from that of
+/// any song already in `save`.
+fn version_mismatch(save: &LsdjSave, version: Result<u8, LsdjError>, target: Option<u8>) -> Option<String> {
+    let version = match version {
+        Ok(v) => v,
+        Err(e) => return Some(format!("the song's format version could not be read ({})", e)),
+    };
+    match target {
+        Some(t) if t != version => Some(format!("the song has format version {:02X}, not {:02X}", version, t)),
+        Some(_) => None,
+        None => save.song_info().into_iter()
+                    .find(|info| info.format_version.is_some_and(|v| v != version))
+                    .map(|info| format!("the song has format version {:02X}, but song {:02X} ({}) has {:02X}",
+                                        version, info.index, info.title, info.format_version.unwrap_or_default())),
+    }
+}
+
+/// Frees what each song in the save file at `savepath` (or only `song`, if
+/// given) never plays, and merges its identical phrases if `merge_phrases` is
+/// true, listing what was freed and the blocks saved. Writes the modified save
+/// to `output` unless `dry_run` is true.
+fn clean_songs(savepath: &Path, dry_run: bool, merge_phrases: bool, song: Option<u8>,
+               output: Option<PathBuf>) -> io::Result<()> {
+    let mut save = open_save(savepath)?;
+    let songs = match song {
+        Some(s) => vec![s],
+        None => save.metadata.songs(),
+    };
+    for s in songs {
+        let mut song = read_song(&save, s)?;
+        let unused = lsdj::clean::clean(&mut song);
+        let merged = if merge_phrases { lsdj::clean::merge_phrases(&mut song) } else { Vec::new() };
+        if unused.is_empty() && merged.is_empty() {
+            continue;
+        }
+        let before = save.metadata.size_of(s);
+        save.replace_song(s, &song.data).map_err(|e| exit::song_failure(s, e.into()))?;
+        status!("{:02X} {}: {} blocks -> {} blocks", s, save.metadata.song_title(s), before, save.metadata.size_of(s));
+        if !logger::quiet() {
+            eprint!("{}", unused); // one line per kind of thing freed
+        }
+        for (phrase, original) in merged {
+            status!("merged phrase {:02X} into {:02X}", phrase, original);
+        }
+    }
+    if dry_run {
+        return Ok(());
+    }
+    write_save(output, &save)
+}
+
+/// Prints how each song in the save file at `savepath` (or only `song`, if
+/// given) compresses, with the share of the bytes saved by runs and by default
+/// instruments and waves.
+fn compression_report(savepath: &Path, song: Option<u8>) -> io::Result<()> {
+    let save = open_save(savepath)?;
+    let songs = match song {
+        Some(s) => vec![s],
+        None => save.metadata.songs(),
+    };
+    println!("IDX  TITLE     BLOCKS  BYTES   RATIO  LITERALS  RUNS SAVED      DEFAULTS SAVED");
+    for s in songs {
+        let title = save.metadata.song_title(s);
+        let stats = match save.compression_stats(s) {
+            Ok(stats) => stats,
+            Err(e) => {
+                println!("{:02X}   {:<8}  {}", s, title, e);
+                continue;
+            },
+        };
+        let saved = (stats.run_savings() + stats.default_savings()).max(1) as f64;
+        let share = |bytes: usize| format!("{} ({:.0}%)", bytes, bytes as f64 * 100.0 / saved);
+        println!("{:02X}   {:<8}  {:>6}  {:>5}  {:>5.1}%  {:>8}  {:<14}  {}", s, title, stats.blocks,
+                 stats.compressed, stats.ratio() * 100.0, stats.literal_bytes,
+                 share(stats.run_savings()), share(stats.default_savings()));
+    }
+    Ok(())
+}
+
+/// Round-trips every song in the save file at `savepath` through the
+/// compressor, listing whether each survives, and exits with status 1 if any
+/// doesn't.
+fn selftest(savepath: &Path) -> io::Result<()> {
+    let save = open_save(savepath)?;
+    let songs = save.metadata.songs();
+    let results = parallel::map(&songs, |&s| save.round_trip(s));
+    let mut failed = 0;
+    for (&s, result) in songs.iter().zip(results) {
+        let title = save.metadata.song_title(s);
+        match result {
+            Ok(blocks) => println!("{:02X} {:<8} ok ({} blocks, {} recompressed)", s, title, save.metadata.size_of(s), blocks),
+            Err(e) => {
+                println!("{:02X} {:<8} FAILED: {}", s, title, e);
+                failed += 1;
+            },
+        }
+    }
+    if failed > 0 {
+        eprintln!("{} of {} songs failed the round trip", failed, songs.len());
+        std::process::exit(Status::Failed as i32);
+    }
+    status!("all {} songs survived the round trip", songs.len());
+    Ok(())
+}
+
+/// Recompresses every song in the save file at `savepath` (cleaning each up
+/// first if `clean` is true), writing the modified save to `output` unless any
+/// song fails to recompress.
+fn optimize(savepath: &Path, clean: bool, output: Option<PathBuf>) -> io::Result<()> {
+    let mut save = open_save(savepath)?;
+    let mut reclaimed = 0;
+    let mut failed = false;
+    let songs = save.metadata.songs();
+    // decompress and recompress every song at once, then put them back one at a time
+    let recompressed = parallel::map(&songs, |&s| -> Result<_, LsdjError> {
+        let mut sram = save.decompress_song(s)?;
+        if clean {
+            let mut song = lsdj::song::Song::from(&sram)?;
+            lsdj::clean::clean(&mut song);
+            sram = song.data;
+        }
+        let blocks = lsdj::blocks_from_sram(&sram, save.compat())?;
+        Ok((sram, blocks))
+    });
+    for (s, recompressed) in songs.into_iter().zip(recompressed) {
+        let title = save.metadata.song_title(s);
+        let before = save.metadata.size_of(s);
+        let result = recompressed.and_then(|(sram, blocks)| save.recompress_song_from(s, &sram, &blocks));
+        match result {
+            Ok(0) => (),
+            Ok(freed) => {
+                status!("{:02X} {}: {} blocks -> {} blocks", s, title, before, before - freed);
+                reclaimed += freed;
+            },
+            Err(e) => {
+                eprintln!("{:02X} {}: {}", s, title, e);
+                failed = true;
+            },
+        }
+    }
+    if failed {
+        eprintln!("not writing the save, as some songs could not be recompressed");
+        std::process::exit(Status::Failed as i32);
+    }
+    status!("reclaimed {} block(s)", reclaimed);
+    write_save(output, &save)
+}
+
+/// Runs an `edit` subcommand, writing the save file with the edited song
+/// to `output`.
+fn edit(cmd: EditCommand) -> io::Result<()> {
+    match cmd {
+        EditCommand::Transpose { song: s, semitones, output, savefile } => {
+            let mut save = open_save(savefile)?;
+            let mut song = read_song(&save, s)?;
+            let shared = lsdj::edit::transpose(&mut song, semitones).map_err(LsdjError::from)?;
+            for phrase in shared {
+                status!("phrase {:02X} is played on the noise channel and another channel; left alone", phrase);
+            }
+            save.replace_song(s, &song.data).map_err(|e| exit::song_failure(s, e.into()))?;
+            write_save(output, &save)
+        },
+        EditCommand::Tempo { song, bpm, rescale_grooves, output, savefile } => {
+            let mut save = open_save(savefile)?;
+            let songs = match song {
+                Some(s) => vec![s],
+                None => save.metadata.songs(),
+            };
+            for s in songs {
+                let mut song = read_song(&save, s)?;
+                let from = song.tempo();
+                lsdj::edit::set_tempo(&mut song, bpm, rescale_grooves).map_err(LsdjError::from)?;
+                save.replace_song(s, &song.data).map_err(|e| exit::song_failure(s, e.into()))?;
+                status!("{:02X} {}: {} -> {} BPM", s, save.metadata.song_title(s), from, bpm);
+            }
+            write_save(output, &save)
+        },
+    }
+}
+
+/// Runs a `qr` subcommand.
+fn qr(cmd: QrCommand) -> io::Result<()> {
+    match cmd {
+        QrCommand::Encode { song, svg, out_dir, savefile } => {
+            let save = open_save(savefile)?;
+            let text = lsdj::armor::armor(&song_container(&save, song)?);
+            std::fs::create_dir_all(&out_dir)?;
+            let parts = lsdj::armor::split(&text, qr::PART_LENGTH);
+            for (i, part) in parts.iter().enumerate() {
+                let name = format!("{}-{}.{}", save.metadata.song_title(song), i + 1, if svg { "svg" } else { "png" });
+                qr::render(part, svg, &out_dir.join(&name))?;
+                status!("{}", name);
+            }
+            Ok(())
+        },
+        QrCommand::Decode { output, parts } => {
+            let parts = parts.iter().map(std::fs::read_to_string).collect::<io::Result<Vec<String>>>()?;
+            let text = lsdj::armor::join(&parts).map_err(LsdjError::from)?;
+            lsdj::armor::dearmor(&text)?; // check that the song is intact
+            write_output(output, text.as_bytes())
+        },
+    }
+}
+
+/// Runs a `cart` subcommand. Saves are checked to be LSDj saves before
+/// they're written anywhere.
+fn cart(cmd: CartCommand) -> io::Result<()> {
+    match cmd {
+        CartCommand::Pull { output } => {
+            let sram = cart::pull()?;
+            let save = LsdjSave::from(&mut io::Cursor::new(&sram))?;
+            status!("pulled {} songs", save.metadata.songs().len());
+            write_output(output, &sram)
+        },
+        CartCommand::Push { savefile } => {
+            let bytes = std::fs::read(savefile)?;
+            let save = LsdjSave::from(&mut io::Cursor::new(&bytes))?;
+            if bytes.len() != cart::SRAM_SIZE {
+                return Err(fail(Status::Usage, format!("cart SRAM holds {} bytes, but the save is {}",
+                                                    cart::SRAM_SIZE, bytes.len())));
+            }
+            if !confirm::confirm_changes("the cart", || cart::pull().map(Some), &bytes, assume_yes())? {
+                abort("the cart");
+            }
+            cart::push(&bytes)?;
+            status!("pushed {} songs", save.metadata.songs().len());
+            Ok(())
+        },
+    }
+}
+
+/// Runs a `speech` subcommand.
+fn speech(cmd: SpeechCommand) -> io::Result<()> {
+    match cmd {
+        SpeechCommand::List { song, savefile } => {
+            let song = read_song(&open_save(savefile)?, song)?;
+            for w in 0..lsdj::song::WORD_COUNT {
+                println!("{:02X} {}", w, song.word(w));
+            }
+            Ok(())
+        },
+        SpeechCommand::Set { song: s, word, name, allophones, text, output, savefile } => {
+            if word as usize >= lsdj::song::WORD_COUNT {
+                return Err(fail(Status::NotFound, format!("no speech word {:02X}", word)));
+            }
+            let mut save = open_save(savefile)?;
+            let mut song = read_song(&save, s)?;
+            let allophones = match (allophones, text) {
+                (Some(allophones), None) => lsdj::speech::parse_allophones(&allophones),
+                (None, Some(text)) => lsdj::speech::from_text(&text),
+                _ => return Err(fail(Status::Usage, "give either --allophones or --text")),
+            }.map_err(LsdjError::from)?;
+            let name = name.unwrap_or_else(|| song.word(word as usize).name);
+            song.set_word(word as usize, &lsdj::song::Word { name, allophones });
+            save.replace_song(s, &song.data).map_err(|e| exit::song_failure(s, e.into()))?;
+            status!("{:02X} {}", word, song.word(word as usize));
+            write_save(output, &save)
+        },
+    }
+}
+
+/// Runs a `synth` subcommand.
+fn synth(cmd: SynthCommand) -> io::Result<()> {
+    match cmd {
+        SynthCommand::Show { song, synth, savefile } => {
+            let song = read_song(&open_save(savefile)?, song)?;
+            if synth as usize >= lsdj::song::SYNTH_COUNT {
+                return Err(fail(Status::NotFound, format!("no soft synth {:X}", synth)));
+            }
+            print!("{}", song.synth(synth as usize));
+            for (i, frame) in song.synth_waves(synth as usize).iter().enumerate() {
+                println!("frame {:X}    {}", i, lsdj::synth::frame_string(frame));
+            }
+            Ok(())
+        },
+        SynthCommand::Render { song, synth, savefile } => {
+            let mut song = read_song(&open_save(savefile)?, song)?;
+            if synth as usize >= lsdj::song::SYNTH_COUNT {
+                return Err(fail(Status::NotFound, format!("no soft synth {:X}", synth)));
+            }
+            song.resynthesize(synth as usize);
+            for (i, frame) in song.synth_waves(synth as usize).iter().enumerate() {
+                println!("frame {:X}    {}", i, lsdj::synth::frame_string(frame));
+            }
+            Ok(())
+        },
+    }
+}
+
+/// Runs a `snippet` subcommand.
+fn snippet(cmd: SnippetCommand) -> io::Result<()> {
+    match cmd {
+        SnippetCommand::Export { song, chain, output, savefile } => {
+            let save = open_save(savefile)?;
+            let snippet = lsdj::snippet::Snippet::export(&read_song(&save, song)?, &[chain])
+                .map_err(LsdjError::from)?;
+            let mut json = serde_json::to_string_pretty(&snippet).expect(ERR_JSON);
+            json.push('\n');
+            write_output(output, json.as_bytes())
+        },
+        SnippetCommand::Import { song: s, from, output, savefile } => {
+            let snippet: lsdj::snippet::Snippet = serde_json::from_slice(&std::fs::read(from)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let mut save = open_save(savefile)?;
+            let mut song = read_song(&save, s)?;
+            if snippet.format_version != song.format_version() {
+                eprintln!("warning: snippet is from format version {:02X}, but song is in {:02X}",
+                          snippet.format_version, song.format_version());
+            }
+            let chains = snippet.import(&mut song).map_err(LsdjError::from)?;
+            save.replace_song(s, &song.data).map_err(|e| exit::song_failure(s, e.into()))?;
+            for (old, new) in chains {
+                status!("imported chain {:02X} as chain {:02X}", old, new);
+            }
+            write_save(output, &save)
+        },
+    }
+}
+
+/// Exports instrument `instrument` of `song` in the save file at `savepath` as
+/// a preset, writing it to `output`.
+fn export_instrument(savepath: &Path, song: u8, instrument: u8, output: Option<PathBuf>) -> io::Result<()> {
+    let save = open_save(savepath)?;
+    let preset = lsdj::preset::Preset::export(&read_song(&save, song)?, instrument)
+        .map_err(LsdjError::from)?;
+    let mut json = serde_json::to_string_pretty(&preset).expect(ERR_JSON);
+    json.push('\n');
+    write_output(output, json.as_bytes())
+}
+
+/// Imports the preset at `presetpath` into `song` in the save file at
+/// `savepath`, at instrument `slot` (or the first free one), writing the
+/// modified save to `output`.
+fn import_instrument(savepath: &Path, song: u8, slot: Option<u8>, presetpath: &Path,
+                     output: Option<PathBuf>) -> io::Result<()> {
+    let preset: lsdj::preset::Preset = serde_json::from_slice(&std::fs::read(presetpath)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut save = open_save(savepath)?;
+    let mut s = read_song(&save, song)?;
+    if preset.format_version != s.format_version() {
+        eprintln!("warning: preset is from format version {:02X}, but song is in {:02X}",
+                  preset.format_version, s.format_version());
+    }
+    if let Some(replaced) = slot.and_then(|i| s.instrument(i as usize)) {
+        status!("replacing instrument {:02X} {}", slot.unwrap(), replaced.name);
+    }
+    let instrument = preset.import(&mut s, slot).map_err(LsdjError::from)?;
+    save.replace_song(song, &s.data).map_err(|e| exit::song_failure(song, e.into()))?;
+    status!("imported {} as instrument {:02X}", preset.name, instrument);
+    write_save(output, &save)
+}
+
+/// Writes a patch in `format` (or the format named by the extension of
+/// `output`, or BPS) which turns the save file at `savepath` into the one at
+/// `other_savepath` to `output`.
+fn create_patch(savepath: &Path, other_savepath: &Path, format: Option<PatchFormat>,
+                output: Option<PathBuf>) -> io::Result<()> {
+    let format = format.or_else(|| output.as_deref().and_then(PatchFormat::from_path)).unwrap_or(PatchFormat::Bps);
+    let patch = format.create(&std::fs::read(savepath)?, &std::fs::read(other_savepath)?).map_err(LsdjError::from)?;
+    status!("patch is {} bytes", patch.len());
+    write_output(output, &patch)
+}
+
+/// Applies the patch at `patchpath` to the save file at `savepath`, writing
+/// the patched save to `output` if it's still a save file.
+fn apply_patch(savepath: &Path, patchpath: &Path, output: Option<PathBuf>) -> io::Result<()> {
+    let patched = lsdj::patch::apply(&std::fs::read(savepath)?, &std::fs::read(patchpath)?)?;
+    if let Err(e) = LsdjSave::from(&mut io::Cursor::new(&patched)) {
+        return Err(fail(Status::Parse, format!("the patched file is not a save file ({})", e)));
+    }
+    write_output(output, &patched)
+}
+
+/// Writes `bytes` at `offset` in the save file at `savepath`, listing the
+/// old and new bytes in each region they land in, and writes the modified
+/// save to `output`.
+fn poke(savepath: &Path, offset: usize, bytes: &[u8], output: Option<PathBuf>) -> io::Result<()> {
+    let save = open_save(savepath)?;
+    let mut raw = std::fs::read(savepath)?;
+    let end = offset.checked_add(bytes.len()).filter(|&end| end <= raw.len());
+    let end = match end {
+        Some(end) => end,
+        None => return Err(fail(Status::Usage, format!(
+            "{} byte(s) at {:#x} would run past the end of the save file ({:#x} bytes)", bytes.len(), offset, raw.len()))),
+    };
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+    let mut start = offset;
+    while start < end {
+        let region = save.region(start);
+        let mut stop = start + 1;
+        while stop < end && save.region(stop) == region {
+            stop += 1;
+        }
+        status!("{:#06x}-{:#06x} {}: {} -> {}", start, stop - 1, region.unwrap_or_default(),
+                  hex(&raw[start..stop]), hex(&bytes[(start - offset)..(stop - offset)]));
+        start = stop;
+    }
+    raw[offset..end].copy_from_slice(bytes);
+    write_output(output, &raw)
+}
+
+/// Runs a `blocks` subcommand.
+fn blocks(cmd: BlocksCommand) -> io::Result<()> {
+    match cmd {
+        BlocksCommand::Dump { output, savefile } => write_output(output, &open_save(savefile)?.block_region()),
+        BlocksCommand::Restore { from, output, savefile } => {
+            let mut save = open_save(savefile)?;
+            save.restore_block_region(&std::fs::read(from)?).map_err(LsdjError::from)?;
+            let problems = save.audit_blocks();
+            if !problems.is_empty() {
+                eprintln!("warning: the allocation table doesn't match the restored blocks ({} problem(s); see the audit command)",
+                          problems.len());
+            }
+            write_save(output, &save)
+        },
+    }
+}
+
+/// Splices `other_song` after `song` in the save file at `savepath`, adding the
+/// result as a new song titled `title` (or `song`'s title) and writing the
+/// modified save to `output`.
+fn splice_songs(savepath: &Path, song: u8, other_song: u8, title: Option<String>,
+                output: Option<PathBuf>) -> io::Result<()> {
+    let mut save = open_save(savepath)?;
+    let title = match title {
+        Some(t) => lsdj::lsdjtitle_from(t.as_str()).map_err(LsdjError::from)?,
+        None => lsdj::lsdjtitle_from(save.metadata.song_title(song).as_str()).map_err(LsdjError::from)?,
+    };
+    let spliced = lsdj::splice::splice(&read_song(&save, song)?, &read_song(&save, other_song)?)
+        .map_err(LsdjError::from)?;
+    let index = save.import_decompressed_song(&spliced.data, title).map_err(LsdjError::from)?;
+    status!("spliced into {:02X}", index);
+    write_save(output, &save)
+}
+
+/// Prints the differences between `song` in the save file at `savepath` and
+/// `other_song` in the save file at `other_savepath` (or `savepath`).
+fn diff_songs(savepath: &Path, song: u8, other_savepath: Option<PathBuf>, other_song: u8) -> io::Result<()> {
+    let save = open_save(savepath)?;
+    let old = read_song(&save, song)?;
+    let new = match other_savepath {
+        Some(path) => read_song(&open_save(path)?, other_song)?,
+        None => read_song(&save, other_song)?,
+    };
+    let differences = lsdj::diff::diff(&old, &new);
+    if differences.is_empty() {
+        status!("songs are identical");
+    }
+    for difference in differences {
+        print!("{}", difference);
+    }
+    Ok(())
+}
+
+/// Prints the index, title, and content hash of each song in the save file at
+/// `savepath` (or only of `song`, if given).
+fn hash_songs(savepath: &Path, song: Option<u8>) -> io::Result<()> {
+    let save = open_save(savepath)?;
+    let songs = match song {
+        Some(s) => vec![s],
+        None => save.metadata.songs(),
+    };
+    let hashes = parallel::map(&songs, |&s| save.song_hash(s));
+    for (s, hash) in songs.into_iter().zip(hashes) {
+        let hash = song_or_err(s, hash)?;
+        println!("{:02X}: {:016x} {}", s, hash, save.metadata.song_title(s));
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<_> = std::env::args_os().collect();
+    let format = ErrorFormat::from_args(&args);
+    let opt = Opt::from_iter_safe(args).unwrap_or_else(|e| exit::usage(e, format));
+    if let Err(e) = run(opt) {
+        exit::report(&e, format);
+    }
+}
+
+/// Runs the command given by `opt`.
+fn run(opt: Opt) -> io::Result<()> {
+    logger::init(opt.quiet, opt.verbose)?;
+    let mut config = Config::load()?;
+    if opt.name_template.is_some() {
+        config.export_template = opt.name_template.clone();
+    }
+    let config = CONFIG.get_or_init(|| config);
+    let backup_policy = match (opt.backup.is_empty(), &config.backup) {
+        (true, Some(policy)) => policy.parse::<BackupPolicy>(),
+        _ => opt.backup.join(",").parse::<BackupPolicy>(),
+    };
+    let backup_policy = backup_policy.map_err(LsdjError::from)?;
+    BACKUP_POLICY.get_or_init(|| backup_policy);
+    COMPAT.get_or_init(|| opt.compat);
+    ASSUME_YES.get_or_init(|| opt.yes);
+    if let Some(cmd) = opt.cmd {
+        return match cmd {
+            Command::Watch { export_all, savefile } => {
+                let dir = export_dir(export_all)?;
+                watch::watch(&savefile, &dir, opt.compress, opt.encrypt, config)
+            },
+            Command::ExportAll { incremental, out_dir, savefile } => {
+                let dir = export_dir(out_dir)?;
+                let written = manifest::export_all(&savefile, &dir, incremental, opt.compress, opt.encrypt, config)?;
+                status!("exported {} song(s)", written);
+                Ok(())
+            },
+            Command::Hash { song, savefile } => hash_songs(&savefile, song),
+            Command::Dedupe { remove, paths } => 
+                dedupe::dedupe(&paths, remove, BACKUP_POLICY.get_or_init(BackupPolicy::default), assume_yes()),
+            Command::Sort { by, reverse, output, savefile } => sort_songs(&savefile, by, reverse, output),
+            Command::Prune { output, savefile } => prune(&savefile, output),
+            Command::SetWorking { index, output, savefile } => set_working(&savefile, index, output),
+            Command::Pocket { from, size, output, savefile } => convert_pocket(&savefile, from, size, output),
+            Command::Bundle { out_dir, rom, savefiles } => bundle(&rom, &savefiles, &out_dir),
+            Command::Diff { savefile, index, other_index, other_savefile } =>
+                diff_songs(&savefile, index, other_savefile, other_index),
+            Command::Copy { from, song, title, on_collision, force, output, savefile } =>
+                copy_song(&savefile, &from, song, title, on_collision.unwrap_or_default(), force, output),
+            Command::Patch { format, output, savefile, other_savefile } =>
+                create_patch(&savefile, &other_savefile, format, output),
+            Command::Apply { output, savefile, patch } => apply_patch(&savefile, &patch, output),
+            Command::Poke { output, savefile, offset, bytes: HexBytes(bytes) } => poke(&savefile, offset, &bytes, output),
+            Command::Blocks { cmd } => blocks(cmd),
+            Command::Splice { title, output, savefile, index, other_index } =>
+                splice_songs(&savefile, index, other_index, title, output),
+            Command::Stats { song, savefile } => {
+                let save = open_save(savefile)?;
+                print!("{}", lsdj::stats::stats(&read_song(&save, song)?));
+                Ok(())
+            },
+            Command::Compression { song, savefile } => compression_report(&savefile, song),
+            Command::Grep { kit, instrument_type, paths } => grep::grep(&paths, kit, instrument_type),
+            Command::Find { regex, tags, pattern, paths } => {
+                let compiled = if regex {
+                    library::Pattern::regex(&pattern).map_err(|e| fail(Status::Usage, format!("{}: {}", pattern, e)))?
+                } else {
+                    library::Pattern::glob(&pattern)
+                };
+                find_songs(&compiled, &paths, tags)
+            },
+            Command::Clean { dry_run, merge_phrases, song, output, savefile } =>
+                clean_songs(&savefile, dry_run, merge_phrases, song, output),
+            Command::Verify { against, song, decompressed, songfile } => verify_export(&songfile, &against, song, decompressed),
+            Command::Selftest { savefile } => selftest(&savefile),
+            Command::Optimize { clean, output, savefile } => optimize(&savefile, clean, output),
+            Command::Edit { cmd } => edit(cmd),
+            Command::Snippet { cmd } => snippet(cmd),
+            Command::ExportInstrument { song, instrument, output, savefile } =>
+                export_instrument(&savefile, song, instrument, output),
+            Command::ImportInstrument { song, slot, output, preset, savefile } =>
+                import_instrument(&savefile, song, slot, &preset, output),
+            Command::Synth { cmd } => synth(cmd),
+            Command::Speech { cmd } => speech(cmd),
+            Command::Cart { cmd } => cart(cmd),
+            Command::Qr { cmd } => qr(cmd),
+            Command::Lib { cmd } => lib(cmd),
+            Command::Goomba { inject, title, output, gbasave } => convert_goomba(&gbasave, inject, title, output),
+            Command::Audit { savefile } => {
+                let problems = open_save(savefile)?.audit_blocks();
+                for problem in problems.iter() {
+                    println!("{}", problem);
+                }
+                if !problems.is_empty() {
+                    std::process::exit(Status::Failed as i32);
+                }
+                status!("no problems found");
+                Ok(())
+            },
+            Command::Titles { fix, output, savefile } => check_titles(&savefile, fix, output),
+            Command::Repair { song, output, savefile } => repair_chains(&savefile, song, output),
+            Command::Recover { out_dir, savefile } => {
+                let dir = export_dir(out_dir)?;
+                recover_songs(&savefile, &dir)
+            },
+            Command::Undelete { block, title, output, savefile } => undelete(&savefile, block, &title, output),
+            Command::Carve { output, dumpfile } => {
+                let (save, carving) = lsdj::carve::carve(&std::fs::read(dumpfile)?).map_err(LsdjError::from)?;
+                status!("{}", carving);
+                if carving.blocks < save.layout().block_count() {
+                    eprintln!("warning: dump ends early; the {} block(s) past it are empty",
+                              save.layout().block_count() - carving.blocks);
+                }
+                write_save(output, &save)
+            },
+            Command::Map { no_color, savefile } => {
+                let save = open_save(savefile)?;
+                print!("{}", save.metadata.block_map(use_color(no_color)));
+                Ok(())
+            },
+            Command::Gen { songs, blocks, content, seed, size, output } => {
+                let layout = if size == 64 { LsdjLayout::SAVE_64KB } else { LsdjLayout::SAVE_128KB };
+                let options = lsdj::generate::GenOptions { songs, blocks, content, seed, layout };
+                let save = lsdj::generate::generate(&options).map_err(LsdjError::from)?;
+                write_save(output, &save)
+            },
+            Command::Run { output, script, savefile } => run_script(&script, &savefile, output),
+        };
+    }
+    let savepath = match opt.savefile.clone() {
+        Some(path) => path,
+        None => exit::usage(Error::with_description("SAVEFILE was not provided", ErrorKind::MissingRequiredArgument),
+                            opt.error_format),
+    };
+    let mut save = {
+        let download = fetch::resolve(&savepath)?;
+        LsdjSave::from(&mut File::open(download.as_ref().map_or(savepath.as_path(), |d| d.path()))?)?
+    };
+    save.set_compat(opt.compat);
+    if opt.list_songs && opt.json {
+        let sidecars = sidecar::load_all(&savepath, &save);
+        let mut songs = serde_json::to_value(save.song_info()).expect(ERR_JSON);
+        for song in songs.as_array_mut().into_iter().flatten() {
+            let sidecar = song["index"].as_u64().and_then(|s| sidecars.get(&(s as u8)));
+            song["sidecar"] = serde_json::to_value(sidecar).expect(ERR_JSON);
+        }
+        let mut songlist = serde_json::to_string_pretty(&songs).expect(ERR_JSON);
+        songlist.push('\n');
+        write_output(opt.output, songlist.as_bytes())
+    } else if opt.list_songs {
+        let color = opt.output.is_none() && use_color(opt.no_color);
+        let mut songlist = save.song_table(color);
+        let sidecars = sidecar::load_all(&savepath, &save);
+        if !sidecars.is_empty() {
+            songlist.push('\n');
+        }
+        for (s, sidecar) in sidecars {
+            songlist.push_str(&format!("{:02X}   {:<8}  {}\n", s, save.metadata.song_title(s), sidecar));
+        }
+        write_output(opt.output, songlist.as_bytes())
     } else if opt.export_sram {
-        let mut save_copy = save;
-        let mut blocks = Vec::new();
-        save_copy.compress_sram_into(&mut blocks, 1).expect(ERR_COMPRESSION);
-        let bytes = blocks.bytes();
-        outfile.write_all(&bytes)?;
-        return Ok(())
-    } else if opt.export != None {
-        let index = opt.export.unwrap();
-        let song_bytes = save.export_song(index);
-        outfile.write_all(&song_bytes)?;
-        return Ok(())
-    } else if opt.import_from != None {
-        let blockpath = opt.import_from.unwrap();
-        let mut blockfile = File::open(blockpath)?;
-
-        let mut bytes = Vec::new(); // bytes of compressed song data
-        lsdj::read_blocks_from_file(&mut blockfile, &mut bytes)?;
+        if let Err(e) = save.check_sram() {
+            if !opt.force {
+                return Err(fail(Status::Parse, format!("{} use --force to export the working song anyway", e)));
+            }
+            eprintln!("warning: {} exporting whatever is in SRAM", e);
+        }
+        let blocks = save.compress_sram_into(1..).map_err(LsdjError::from)?;
+        write_export(opt.output, opt.compress, opt.encrypt, opt.timestamp, &blocks.bytes())
+    } else if let Some(SongList(songs)) = &opt.export {
+        if let [index] = songs[..] {
+            if save.slot(index).is_none() {
+                return Err(fail(Status::NotFound, format!("{:02X}: no song exists at that index", index)));
+            }
+            let ext = export_extension(song_extension(opt.container, opt.armor), opt.compress, opt.encrypt);
+            let output = export_output(opt.output.clone(), &save, index, &ext)?;
+            export_song(output.clone(), &save, index, &opt)?;
+            song_exported(&savepath, output, &save, index);
+            return Ok(());
+        }
+        let dir = export_dir(opt.output.clone())?;
+        std::fs::create_dir_all(&dir)?;
+        for &index in songs {
+            if save.slot(index).is_none() {
+                eprintln!("{:02X}: no song exists at that index", index);
+                continue;
+            }
+            let ext = export_extension(song_extension(opt.container, opt.armor), opt.compress, opt.encrypt);
+            let path = dir.join(export_file_name(&save, index, &ext));
+            export_song(Some(path.clone()), &save, index, &opt)?;
+            status!("exported {:02X}: {}", index, save.metadata.song_title(index));
+            song_exported(&savepath, Some(path), &save, index);
+        }
+        Ok(())
+    } else if let Some(index) = opt.export_decompressed {
+        let sram = song_or_err(index, save.decompress_song(index))?;
+        let ext = export_extension("sram", opt.compress, opt.encrypt);
+        let output = export_output(opt.output, &save, index, &ext)?;
+        write_export(output, opt.compress, opt.encrypt, opt.timestamp, &sram)
+    } else if let Some(blockpath) = opt.import_from {
+        let (bytes, container) = {
+            let download = fetch::resolve(&blockpath)?;
+            let path = download.as_ref().map_or(blockpath.as_path(), |d| d.path());
+            read_song_file(path, opt.decompressed, opt.pad, opt.compat)?
+        };
+        if opt.check_fit {
+            let blocks = if opt.decompressed {
+                lsdj::blocks_from_sram(&bytes, opt.compat).map_err(LsdjError::from)?
+            } else {
+                bytes
+            };
+            let fit = save.can_fit(&blocks);
+            println!("{}", fit);
+            if opt.clean {
+                let fit = save.can_fit_cleaned(&blocks)?;
+                println!("after cleaning: {}", fit);
+            }
+            std::process::exit(if fit.fits() { 0 } else { Status::Failed as i32 });
+        }
+        if !opt.force {
+            let song = if opt.decompressed {
+                lsdj::song::Song::from(&bytes).map_err(LsdjError::from)
+            } else {
+                lsdj::song_from_blocks(&bytes, opt.compat)
+            };
+            if let Some(warning) = version_mismatch(&save, song.map(|s| s.format_version()), opt.target_version) {
+                return Err(fail(Status::Usage, format!("{}; use --force to import it anyway", warning)));
+            }
+        }
         let mut outsave = save;
 
-        let title_result = match opt.title {
+        let title_result = match opt.title.as_ref() {
             Some(t) => lsdj::lsdjtitle_from(t.as_str()),
-            None => lsdj::lsdjtitle_from("SONGNAME"),
+            None => match (&container, config.default_title.as_ref()) {
+                (Some(c), _) => Ok(c.title),
+                (None, Some(t)) => lsdj::lsdjtitle_from(t.as_str()),
+                (None, None) => lsdj::lsdjtitle_from("SONGNAME"),
+            },
         };
-        let title = title_result.expect(ERR_TITLE_FMT);
-        outsave.import_song(&bytes, title).unwrap();
-        let save_bytes = outsave.bytes();
-        outfile.write_all(&save_bytes)?;
-        return Ok(());
+        let title = resolve_collision(&outsave, title_result.map_err(LsdjError::from)?, opt.on_collision.unwrap_or_default())?;
+        let index = if opt.decompressed {
+            outsave.import_decompressed_song(&bytes, title).map_err(LsdjError::from)?
+        } else {
+            let index = outsave.import_song(&bytes, title).map_err(LsdjError::from)?;
+            if let Some(c) = container {
+                outsave.metadata.version_table[index as usize] = c.version;
+            }
+            index
+        };
+        write_save(opt.output.clone(), &outsave)?;
+        config.hooks.run(Event::SongImported, serde_json::json!({
+            "path": opt.output,
+            "source": blockpath,
+            "song": hooks::song(&outsave, index),
+        }));
+        Ok(())
+    } else {
+        Ok(())
     }
-    Ok(())
 }
@@ -0,0 +1,151 @@
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::lsdj::LsdjSave;
+use crate::lsdj::io::Codec;
+use crate::parallel;
+use crate::watch::export_path;
+
+/// Name of the manifest file written alongside exported songs.
+pub const MANIFEST_NAME: &str = "manifest.txt";
+
+/// Records the songs exported from a save file into a directory: for each song
+/// slot, the hash of the song's decompressed data (see
+/// `LsdjSave::song_hash()`) and the name of the file it was exported to.
+///
+/// Manifests are stored as text, one song per line (`00 0123456789abcdef
+/// 00_TITLE.lsdsng`), so they can be diffed and synced like any other file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub songs: BTreeMap<u8, (u64, String)>,
+}
+
+impl Manifest {
+    /// Parses the contents of a manifest file, skipping lines which can't be
+    /// parsed.
+    pub fn parse(s: &str) -> Manifest {
+        let mut manifest = Manifest::default();
+        for line in s.lines() {
+            let mut fields = line.splitn(3, ' ');
+            if let (Some(index), Some(hash), Some(name)) = (fields.next(), fields.next(), fields.next()) {
+                if let (Ok(index), Ok(hash)) = (u8::from_str_radix(index, 16), u64::from_str_radix(hash, 16)) {
+                    manifest.songs.insert(index, (hash, name.to_string()));
+                }
+            }
+        }
+        manifest
+    }
+
+    /// Reads the manifest in `dir`, returning an empty manifest if there is
+    /// none.
+    pub fn load(dir: &Path) -> io::Result<Manifest> {
+        match fs::read_to_string(dir.join(MANIFEST_NAME)) {
+            Ok(s) => Ok(Manifest::parse(&s)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl std::fmt::Display for Manifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (index, (hash, name)) in self.songs.iter() {
+            writeln!(f, "{:02X} {:016x} {}", index, hash, name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Exports every song in the save file at `savepath` into `dir`, compressed
+/// with `codec` and encrypted if `encrypt` is true, then writes a manifest of
+/// the exported songs there. If `incremental` is true, songs recorded in the
+/// existing manifest with the same hash and file name (whose file still
+/// exists) are skipped. Returns the number of songs written.
+pub fn export_all(savepath: &Path, dir: &Path, incremental: bool, codec: Option<Codec>, encrypt: bool,
+                  config: &Config) -> io::Result<usize> {
+    fs::create_dir_all(dir)?;
+    let save = LsdjSave::from(&mut File::open(savepath)?)?;
+    let previous = if incremental { Manifest::load(dir)? } else { Manifest::default() };
+    let songs = save.metadata.songs();
+    let hashes = parallel::map(&songs, |&s| save.song_hash(s));
+    let mut manifest = Manifest::default();
+    let mut changed = Vec::new();
+    for (song, hash) in songs.into_iter().zip(hashes) {
+        let hash = match hash {
+            Ok(h) => h,
+            Err(e) => {
+                eprintln!("{:02X}: {}", song, e);
+                continue;
+            },
+        };
+        let title = save.metadata.song_title(song);
+        let path = export_path(dir, song, &title, save.metadata.version_table[song as usize], codec, encrypt, config);
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let unchanged = previous.songs.get(&song).is_some_and(|(h, n)| *h == hash && *n == name);
+        if !(unchanged && path.is_file()) {
+            changed.push((song, title, path));
+        }
+        manifest.songs.insert(song, (hash, name));
+    }
+    let exports = parallel::map(&changed, |(song, _, _)| {
+        crate::filter_export(&save.export_song(*song), codec, encrypt, None)
+    });
+    for ((song, title, path), bytes) in changed.iter().zip(exports) {
+        crate::lsdj::io::write_atomic(path, &bytes?)?;
+        status!("exported {:02X}: {}", song, title);
+    }
+    crate::lsdj::io::write_atomic(&dir.join(MANIFEST_NAME), manifest.to_string().as_bytes())?;
+    Ok(changed.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut manifest = Manifest::default();
+        manifest.songs.insert(0x00, (0x0123456789abcdef, "00_TEST.lsdsng".to_string()));
+        manifest.songs.insert(0x1f, (0xfedcba9876543210, "1F_MY SONG.lsdsng".to_string()));
+        let s = manifest.to_string();
+        assert_eq!(s, "00 0123456789abcdef 00_TEST.lsdsng\n1F fedcba9876543210 1F_MY SONG.lsdsng\n");
+        assert_eq!(Manifest::parse(&s), manifest);
+        assert_eq!(Manifest::parse("garbage\nzz 00 x\n"), Manifest::default());
+    }
+
+    #[test]
+    fn test_export_all() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("lsdjtool-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let savepath = dir.join("test.sav");
+        let out = dir.join("out");
+        let mut save = LsdjSave::empty();
+        save.import_decompressed_song(&[0; 0x8000], [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        save.import_decompressed_song(&[1; 0x8000], [b'B', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        fs::write(&savepath, save.bytes())?;
+        let config = Config::default();
+
+        assert_eq!(export_all(&savepath, &out, true, None, false, &config)?, 2);
+        assert_eq!(export_all(&savepath, &out, true, None, false, &config)?, 0);
+        assert_eq!(export_all(&savepath, &out, false, None, false, &config)?, 2);
+        fs::remove_file(out.join("01_B.lsdsng"))?;
+        assert_eq!(export_all(&savepath, &out, true, None, false, &config)?, 1);
+
+        save.delete_song(0).unwrap();
+        save.import_decompressed_song(&[2; 0x8000], [b'A', 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        fs::write(&savepath, save.bytes())?;
+        assert_eq!(export_all(&savepath, &out, true, None, false, &config)?, 1);
+        assert_eq!(fs::read(out.join("00_A.lsdsng"))?, save.export_song(0));
+        assert_eq!(Manifest::load(&out)?.songs.len(), 2);
+
+        assert_eq!(export_all(&savepath, &out, true, Some(Codec::Gzip), false, &config)?, 2); // renamed, so re-exported
+        let gzipped = fs::read(out.join("00_A.lsdsng.gz"))?;
+        assert_eq!(crate::lsdj::io::decompress(gzipped)?, save.export_song(0));
+        assert_eq!(Manifest::load(&out)?.songs[&0].1, "00_A.lsdsng.gz");
+        fs::remove_dir_all(&dir)
+    }
+}
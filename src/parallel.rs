@@ -0,0 +1,28 @@
+/// Applies `f` to every item in `items`, returning the results in the same
+/// order. With the `rayon` feature enabled, items are processed in parallel.
+#[cfg(feature = "rayon")]
+pub fn map<T, U, F>(items: &[T], f: F) -> Vec<U>
+    where T: Sync, U: Send, F: Fn(&T) -> U + Sync + Send {
+    use rayon::prelude::*;
+    items.par_iter().map(f).collect()
+}
+
+/// Applies `f` to every item in `items`, returning the results in the same
+/// order. With the `rayon` feature enabled, items are processed in parallel.
+#[cfg(not(feature = "rayon"))]
+pub fn map<T, U, F>(items: &[T], f: F) -> Vec<U>
+    where F: Fn(&T) -> U {
+    items.iter().map(f).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_keeps_order() {
+        let items: Vec<u32> = (0..1000).collect();
+        let doubled = map(&items, |&i| i * 2);
+        assert_eq!(doubled, (0..1000).map(|i| i * 2).collect::<Vec<u32>>());
+    }
+}
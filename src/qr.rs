@@ -0,0 +1,29 @@
+//! Rendering armored songs as QR codes. Needs the `qr` feature; without it,
+//! rendering fails with `ErrorKind::Unsupported`.
+
+use std::io;
+use std::path::Path;
+
+/// Characters of armored text in each QR code, which keeps the codes small
+/// enough to scan reliably from paper.
+pub const PART_LENGTH: usize = 1000;
+
+/// Renders `text` as a QR code, written to `path` as an SVG if `svg` is true
+/// and as a PNG otherwise.
+#[cfg(feature = "qr")]
+pub fn render(text: &str, svg: bool, path: &Path) -> io::Result<()> {
+    let code = qrcode::QrCode::with_error_correction_level(text, qrcode::EcLevel::M)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    if svg {
+        let image = code.render::<qrcode::render::svg::Color>().min_dimensions(400, 400).build();
+        crate::lsdj::io::write_atomic(path, image.as_bytes())
+    } else {
+        let image = code.render::<image::Luma<u8>>().min_dimensions(400, 400).build();
+        image.save_with_format(path, image::ImageFormat::Png).map_err(io::Error::other)
+    }
+}
+
+#[cfg(not(feature = "qr"))]
+pub fn render(_text: &str, _svg: bool, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "lsdjtool was built without QR code support (the qr feature)"))
+}
@@ -0,0 +1,207 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::exit::{fail, status_of, Status};
+use crate::lsdj::{self, LsdjError, LsdjSave, LsdjTitle, OnCollision};
+
+/// One command in a script run by `lsdjtool run`.
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    /// `import PATH [TITLE]`: imports the song file at `PATH`, titled `TITLE`
+    /// (or as the file's container says, or the configured default title).
+    Import { path: PathBuf, title: Option<LsdjTitle> },
+    /// `rename SONG TITLE`: retitles a song.
+    Rename { song: u8, title: LsdjTitle },
+    /// `delete SONG`: deletes a song.
+    Delete { song: u8 },
+    /// `export SONG PATH`: exports a song's blocks, as they are at that point
+    /// in the script, to `PATH`.
+    Export { song: u8, path: PathBuf },
+}
+
+/// Splits `line` into words at whitespace, keeping whitespace within double
+/// quotes (for paths with spaces in them) and dropping any `#` comment.
+fn words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let word: String = chars.by_ref().take_while(|&c| c != '"').collect();
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+    if !line.matches('"').count().is_multiple_of(2) {
+        return Err("unterminated quote".to_string());
+    }
+    Ok(words)
+}
+
+/// Parses the step given by `words`, resolving relative paths against `dir`.
+fn step(words: &[String], dir: &Path) -> Result<Step, String> {
+    let song = |s: &str| crate::parse_byte(s).map_err(|e| format!("{}: {}", s, e));
+    let title = |t: &str| lsdj::lsdjtitle_from(t).map_err(|e| format!("{}: {}", t, e));
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    match words[..] {
+        ["import", path] => Ok(Step::Import { path: dir.join(path), title: None }),
+        ["import", path, t] => Ok(Step::Import { path: dir.join(path), title: Some(title(t)?) }),
+        ["rename", s, t] => Ok(Step::Rename { song: song(s)?, title: title(t)? }),
+        ["delete", s] => Ok(Step::Delete { song: song(s)? }),
+        ["export", s, path] => Ok(Step::Export { song: song(s)?, path: dir.join(path) }),
+        [command, ..] if ["import", "rename", "delete", "export"].contains(&command) =>
+            Err(format!("wrong number of arguments to {}", command)),
+        [command, ..] => Err(format!("unknown command {}", command)),
+        [] => unreachable!("blank lines are skipped"),
+    }
+}
+
+/// Parses the script `text`, with relative paths in it resolved against
+/// `dir`, returning each step along with its line number. An `Err` names
+/// the first line which can't be parsed.
+pub fn parse(text: &str, dir: &Path) -> Result<Vec<(usize, Step)>, String> {
+    let mut steps = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let words = words(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        if !words.is_empty() {
+            steps.push((i + 1, step(&words, dir).map_err(|e| format!("line {}: {}", i + 1, e))?));
+        }
+    }
+    Ok(steps)
+}
+
+/// Returns an error if `save` has no song at `song`.
+fn check_song(save: &LsdjSave, song: u8) -> io::Result<()> {
+    match save.slot(song) {
+        Some(_) => Ok(()),
+        None => Err(fail(Status::NotFound, format!("{:02X}: no song exists at that index", song))),
+    }
+}
+
+/// Applies `step` to `save`, returning the bytes of any song it exports along
+/// with the path they're to be written to.
+fn apply(save: &mut LsdjSave, step: &Step) -> io::Result<Option<(PathBuf, Vec<u8>)>> {
+    match step {
+        Step::Import { path, title } => {
+            let compat = *crate::COMPAT.get_or_init(Default::default);
+            let (bytes, container) = crate::read_song_file(path, false, false, compat)?;
+            let title = match (title, &container, crate::config().default_title.as_ref()) {
+                (Some(t), _, _) => *t,
+                (None, Some(c), _) => c.title,
+                (None, None, Some(t)) => lsdj::lsdjtitle_from(t).map_err(LsdjError::from)?,
+                (None, None, None) => lsdj::lsdjtitle_from("SONGNAME").map_err(LsdjError::from)?,
+            };
+            let title = crate::resolve_collision(save, title, OnCollision::default())?;
+            let song = save.import_song(&bytes, title).map_err(LsdjError::from)?;
+            if let Some(c) = container {
+                save.metadata.version_table[song as usize] = c.version;
+            }
+            status!("imported {} as {:02X}: {}", path.display(), song, save.metadata.song_title(song));
+        },
+        Step::Rename { song, title } => {
+            check_song(save, *song)?;
+            let old = save.metadata.song_title(*song);
+            save.metadata.title(*song, *title);
+            status!("renamed {:02X}: {} to {}", song, old, save.metadata.song_title(*song));
+        },
+        Step::Delete { song } => {
+            check_song(save, *song)?;
+            let title = save.metadata.song_title(*song);
+            save.delete_song(*song).map_err(LsdjError::from)?;
+            status!("deleted {:02X}: {}", song, title);
+        },
+        Step::Export { song, path } => {
+            check_song(save, *song)?;
+            return Ok(Some((path.clone(), save.export_song(*song))));
+        },
+    }
+    Ok(None)
+}
+
+/// Runs the script at `path` against `save`, stopping at the first step
+/// which fails. Nothing is written: the songs exported are returned, with the
+/// paths they're to be written to, so that they (and `save`) can be written
+/// once the whole script has succeeded.
+pub fn run(path: &Path, save: &mut LsdjSave) -> io::Result<Vec<(PathBuf, Vec<u8>)>> {
+    let text = std::fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let steps = parse(&text, dir).map_err(|e| fail(Status::Parse, format!("{}: {}", path.display(), e)))?;
+    let mut exports = Vec::new();
+    for (line, step) in steps.iter() {
+        let export = apply(save, step).map_err(|e| {
+            fail(status_of(&e), format!("{}: line {}: {}", path.display(), line, e))
+        })?;
+        exports.extend(export);
+    }
+    Ok(exports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let script = r#"
+            # tidy up the live set
+            delete 3
+            rename 0x01 "LIVE1"   # quoted words work too
+            import "new songs/jam.lsdsng" JAM
+            import /tmp/b.lsdsng
+            export 0 out.lsdsng
+        "#;
+        let dir = Path::new("set");
+        assert_eq!(parse(script, dir), Ok(vec![
+            (3, Step::Delete { song: 3 }),
+            (4, Step::Rename { song: 1, title: *b"LIVE1\0\0\0" }),
+            (5, Step::Import { path: PathBuf::from("set/new songs/jam.lsdsng"), title: Some(*b"JAM\0\0\0\0\0") }),
+            (6, Step::Import { path: PathBuf::from("/tmp/b.lsdsng"), title: None }),
+            (7, Step::Export { song: 0, path: PathBuf::from("set/out.lsdsng") }),
+        ]));
+        assert_eq!(parse("delete 1\nplay 2", dir), Err("line 2: unknown command play".to_string()));
+        assert_eq!(parse("rename 1", dir), Err("line 1: wrong number of arguments to rename".to_string()));
+        assert_eq!(parse("export 1 \"a.lsdsng", dir), Err("line 1: unterminated quote".to_string()));
+        assert!(parse("delete 256", dir).unwrap_err().starts_with("line 1: 256: "));
+        assert!(parse("rename 0 lowercase", dir).unwrap_err().starts_with("line 1: lowercase: "));
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = std::env::temp_dir().join(format!("lsdjtool-script-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut save = lsdj::generate::test_save();
+        let song = save.export_song(0);
+        std::fs::write(dir.join("song.lsdsng"), &song).unwrap();
+        let script = dir.join("script.lsdj");
+
+        std::fs::write(&script, "import song.lsdsng COPY\nrename 0 ORIG\nexport 1 copy.lsdsng\ndelete 0\n").unwrap();
+        let exports = run(&script, &mut save).unwrap();
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports[0].0, dir.join("copy.lsdsng"));
+        let original = lsdj::sram_from_blocks(&song, Default::default()).unwrap();
+        assert!(lsdj::sram_from_blocks(&exports[0].1, Default::default()).unwrap() == original);
+        assert_eq!(save.metadata.songs(), [1]);
+        assert_eq!(save.metadata.song_title(1), "COPY");
+
+        std::fs::write(&script, "rename 1 AGAIN\ndelete 5\n").unwrap();
+        let e = run(&script, &mut save).unwrap_err();
+        assert_eq!(status_of(&e), Status::NotFound);
+        assert_eq!(e.to_string(), format!("{}: line 2: 05: no song exists at that index", script.display()));
+        std::fs::write(&script, "frobnicate\n").unwrap();
+        assert_eq!(status_of(&run(&script, &mut save).unwrap_err()), Status::Parse);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
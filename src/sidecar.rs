@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::lsdj::LsdjSave;
+
+/// Freeform metadata about a song, kept in a TOML file next to it since LSDj
+/// itself only stores an eight-character title.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sidecar {
+    pub tags: Vec<String>,
+    pub bpm: Option<u16>,
+    pub author: Option<String>,
+    pub notes: Option<String>,
+}
+
+/// Returns the path of the sidecar file for a song: `NAME.toml` next to an
+/// exported song `NAME.lsdsng`, or `SAVE.TITLE.toml` next to a save file
+/// `SAVE.sav` for the song titled `TITLE` in it.
+pub fn sidecar_path(path: &Path, title: Option<&str>) -> PathBuf {
+    match title {
+        Some(title) => {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            path.with_file_name(format!("{}.{}.toml", stem, title))
+        },
+        None => path.with_extension("toml"),
+    }
+}
+
+impl Sidecar {
+    /// Parses the contents of a sidecar file.
+    pub fn parse(s: &str) -> Result<Sidecar, toml::de::Error> {
+        toml::from_str(s)
+    }
+
+    /// Reads the sidecar file at `path`, returning `None` if there is none.
+    pub fn load(path: &Path) -> io::Result<Option<Sidecar>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Sidecar::parse(&contents).map(Some).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        })
+    }
+
+    /// Returns true if this sidecar has `tag` (ignoring case).
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+}
+
+/// Reads the sidecar file of each song in `save`, which was read from
+/// `savepath`, returning those found by song index. Sidecars which can't be
+/// read are reported and skipped.
+pub fn load_all(savepath: &Path, save: &LsdjSave) -> BTreeMap<u8, Sidecar> {
+    let mut sidecars = BTreeMap::new();
+    for s in save.metadata.songs() {
+        match Sidecar::load(&sidecar_path(savepath, Some(&save.metadata.song_title(s)))) {
+            Ok(Some(sidecar)) => { sidecars.insert(s, sidecar); },
+            Ok(None) => (),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    sidecars
+}
+
+impl fmt::Display for Sidecar {
+    /// Summarizes the tags, BPM, and author (but not the notes) on one line.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if !self.tags.is_empty() {
+            parts.push(format!("[{}]", self.tags.join(", ")));
+        }
+        if let Some(bpm) = self.bpm {
+            parts.push(format!("{} BPM", bpm));
+        }
+        if let Some(author) = &self.author {
+            parts.push(format!("by {}", author));
+        }
+        write!(f, "{}", parts.join("  "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Sidecar::parse("").unwrap(), Sidecar::default());
+        let sidecar = Sidecar::parse(r#"
+            tags = ["live", "WIP"]
+            bpm = 140
+            author = "someone"
+            notes = "needs a new bassline"
+        "#).unwrap();
+        assert_eq!(sidecar.bpm, Some(140));
+        assert!(sidecar.has_tag("wip"));
+        assert!(!sidecar.has_tag("done"));
+        assert_eq!(sidecar.to_string(), "[live, WIP]  140 BPM  by someone");
+        assert!(Sidecar::parse("tempo = 140").is_err());
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        assert_eq!(sidecar_path(Path::new("songs/00_TEST.lsdsng"), None), PathBuf::from("songs/00_TEST.toml"));
+        assert_eq!(sidecar_path(Path::new("saves/lsdj.sav"), Some("MY SONG")),
+                   PathBuf::from("saves/lsdj.MY SONG.toml"));
+    }
+}
@@ -0,0 +1,150 @@
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use notify::{RecursiveMode, Watcher};
+
+use crate::config::Config;
+use crate::lsdj::LsdjSave;
+use crate::lsdj::io::Codec;
+
+const SETTLE_TIME: Duration = Duration::from_millis(250); // time to let a writer finish before reloading
+
+/// Returns the path to which the song at `index` with title `title` is
+/// exported inside `dir`, named according to `config`'s export template and
+/// ending in the extension of a song compressed with `codec` and encrypted if
+/// `encrypt` is true (see `export_extension()`).
+pub fn export_path(dir: &Path, index: u8, title: &str, version: u8, codec: Option<Codec>, encrypt: bool,
+                   config: &Config) -> PathBuf {
+    let ext = crate::export_extension("lsdsng", codec, encrypt);
+    dir.join(format!("{}.{}", config.export_name(index, title, version), ext))
+}
+
+/// Exports every song in the save file at `savepath` into `dir`, compressed
+/// with `codec` and encrypted if `encrypt` is true, skipping songs whose
+/// compressed bytes and export path are unchanged since they were last
+/// recorded in `exported`. The earlier export of a song which was deleted, or
+/// whose export path changed (e.g. because it was renamed), is removed.
+/// Returns the number of songs written.
+fn export_changed(savepath: &Path, dir: &Path, exported: &mut HashMap<u8, (PathBuf, Vec<u8>)>,
+                  codec: Option<Codec>, encrypt: bool, config: &Config) -> io::Result<usize> {
+    let mut savefile = File::open(savepath)?;
+    let save = LsdjSave::from(&mut savefile)?;
+    let songs = save.metadata.songs();
+    let mut stale = Vec::new(); // paths of earlier exports which may no longer be current
+    let mut written = 0;
+    for &song in songs.iter() {
+        let bytes = save.export_song(song);
+        let title = save.metadata.song_title(song);
+        let version = save.metadata.version_table[song as usize];
+        let path = export_path(dir, song, &title, version, codec, encrypt, config);
+        if let Some((old_path, old_bytes)) = exported.get(&song) {
+            if *old_path == path && *old_bytes == bytes {
+                continue; // song hasn't changed since the last export
+            }
+        }
+        crate::lsdj::io::write_atomic(&path, &crate::filter_export(&bytes, codec, encrypt, None)?)?;
+        status!("exported {:02X}: {}", song, title);
+        if let Some((old_path, _)) = exported.insert(song, (path, bytes)) {
+            stale.push(old_path);
+        }
+        written += 1;
+    }
+    let deleted: Vec<u8> = exported.keys().copied().filter(|song| !songs.contains(song)).collect();
+    stale.extend(deleted.iter().filter_map(|song| exported.remove(song)).map(|(path, _)| path));
+    for path in stale {
+        if !exported.values().any(|(p, _)| *p == path) { // another song may have taken its name
+            remove_export(&path)?;
+        }
+    }
+    Ok(written)
+}
+
+/// Removes the stale export at `path`, if it's still there.
+fn remove_export(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => {
+            status!("removed {}", path.display());
+            Ok(())
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Exports all songs in the save file at `savepath` into `dir` (compressed
+/// and encrypted as `export_changed()` does), then watches the save file and
+/// re-exports any songs which change whenever it is rewritten. Only returns
+/// if the watch itself fails.
+pub fn watch(savepath: &Path, dir: &Path, codec: Option<Codec>, encrypt: bool, config: &Config) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut exported = HashMap::new();
+    export_changed(savepath, dir, &mut exported, codec, encrypt, config)?;
+
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(io::Error::other)?;
+    let parent = match savepath.parent() {
+        Some(p) if p != Path::new("") => p,
+        _ => Path::new("."),
+    };
+    // watch the parent directory, as many tools replace the save file instead of writing to it
+    watcher.watch(parent, RecursiveMode::NonRecursive).map_err(io::Error::other)?;
+    let filename = savepath.file_name();
+
+    while let Ok(event) = rx.recv() {
+        let event = event.map_err(io::Error::other)?;
+        if !(event.kind.is_create() || event.kind.is_modify()) ||
+           !event.paths.iter().any(|p| p.file_name() == filename) {
+            continue;
+        }
+        thread::sleep(SETTLE_TIME);
+        while rx.try_recv().is_ok() {} // discard events caused by the same write
+        if let Err(e) = export_changed(savepath, dir, &mut exported, codec, encrypt, config) {
+            eprintln!("{}: {}", savepath.display(), e); // keep watching, the next write may succeed
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsdj::generate::{generate, GenOptions};
+
+    #[test]
+    fn test_export_changed() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("lsdjtool-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let savepath = dir.join("x.sav");
+        let mut save = generate(&GenOptions { songs: 3, ..GenOptions::default() }).unwrap();
+        fs::write(&savepath, save.bytes())?;
+        let config = Config::default();
+        let path = |save: &LsdjSave, song: u8| {
+            let version = save.metadata.version_table[song as usize];
+            export_path(&dir, song, &save.metadata.song_title(song), version, None, false, &config)
+        };
+        let (first, second, third) = (path(&save, 0), path(&save, 1), path(&save, 2));
+
+        let mut exported = HashMap::new();
+        assert_eq!(export_changed(&savepath, &dir, &mut exported, None, false, &config)?, 3);
+        assert_eq!(fs::read(&first)?, save.export_song(0));
+        assert_eq!(export_changed(&savepath, &dir, &mut exported, None, false, &config)?, 0); // unchanged
+
+        save.metadata.title(1, [b'R', b'E', b'N', b'A', b'M', b'E', b'D', 0]);
+        save.delete_song(2).unwrap();
+        fs::write(&savepath, save.bytes())?;
+        assert_eq!(export_changed(&savepath, &dir, &mut exported, None, false, &config)?, 1);
+        assert_eq!(fs::read(path(&save, 1))?, save.export_song(1));
+        assert!(first.exists());
+        assert!(!second.exists()); // renamed
+        assert!(!third.exists()); // deleted
+        assert_eq!(exported.len(), 2);
+        fs::remove_dir_all(&dir)
+    }
+}
@@ -0,0 +1,90 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use lsdjtool::lsdj::generate::{generate, GenOptions};
+
+/// Returns a directory for the files of the test `name`, emptied.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lsdjtool-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs lsdjtool with `args`.
+fn lsdjtool(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lsdjtool")).args(args).output().unwrap()
+}
+
+#[test]
+fn test_import_without_space() {
+    let dir = test_dir("nospace");
+    let full = generate(&GenOptions { songs: 4, blocks: Some(47), ..GenOptions::default() }).unwrap();
+    assert!(full.metadata.blocks_used() + 10 > 0xbf);
+    fs::write(dir.join("full.sav"), full.bytes()).unwrap();
+    let song = generate(&GenOptions { blocks: Some(10), seed: 9, ..GenOptions::default() }).unwrap();
+    fs::write(dir.join("song.lsdsng"), song.export_song(0)).unwrap();
+
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let out = lsdjtool(&["-i", &path("song.lsdsng"), "-y", "-o", &path("out.sav"), &path("full.sav")]);
+    assert_eq!(out.status.code(), Some(4), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(String::from_utf8_lossy(&out.stderr).starts_with("error: "));
+    assert!(!dir.join("out.sav").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_synth_render_preview() {
+    let dir = test_dir("render");
+    let save = generate(&GenOptions::default()).unwrap();
+    fs::write(dir.join("in.sav"), save.bytes()).unwrap();
+
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let out = lsdjtool(&["synth", "render", "-s", "0", "--synth", "0", &path("in.sav")]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(String::from_utf8_lossy(&out.stdout).lines().filter(|l| l.starts_with("frame ")).count(), 16);
+    assert_eq!(fs::read(dir.join("in.sav")).unwrap(), save.bytes());
+
+    let out = lsdjtool(&["synth", "render", "-s", "0", "--synth", "0", "--write", &path("in.sav")]);
+    assert_eq!(out.status.code(), Some(2)); // there's no --write, since the frames are only approximate
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_run_writes_save_before_exports() {
+    let dir = test_dir("run");
+    let save = generate(&GenOptions::default()).unwrap();
+    fs::write(dir.join("in.sav"), save.bytes()).unwrap();
+    fs::write(dir.join("script.lsdj"), "export 0 song.lsdsng\n").unwrap();
+
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let out = lsdjtool(&["-y", "run", "-o", &path("missing/out.sav"), &path("script.lsdj"), &path("in.sav")]);
+    assert!(!out.status.success());
+    assert!(!dir.join("song.lsdsng").exists()); // the save couldn't be written, so neither was the export
+
+    let out = lsdjtool(&["-y", "run", "-o", &path("out.sav"), &path("script.lsdj"), &path("in.sav")]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(fs::read(dir.join("song.lsdsng")).unwrap(), save.export_song(0));
+    assert_eq!(fs::read(dir.join("out.sav")).unwrap(), save.bytes());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_bundle_name_clash() {
+    let dir = test_dir("bundle");
+    fs::create_dir_all(dir.join("a")).unwrap();
+    fs::create_dir_all(dir.join("b")).unwrap();
+    let save = generate(&GenOptions::default()).unwrap();
+    fs::write(dir.join("a/song.sav"), save.bytes()).unwrap();
+    fs::write(dir.join("b/Song.sav"), save.bytes()).unwrap();
+    fs::write(dir.join("lsdj.gb"), vec![0; 0x8000]).unwrap();
+
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let out = lsdjtool(&["bundle", "-o", &path("out"), &path("lsdj.gb"), &path("a/song.sav"), &path("b/Song.sav")]);
+    assert_eq!(out.status.code(), Some(2), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(!dir.join("out").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,55 @@
+#![cfg(feature = "std")]
+
+//! Golden tests against files written by LSDj itself and by lsdpatch. Those
+//! files can't be made without them, so they are collected by hand into `tests/fixtures` (see
+//! the README there) and these tests are ignored until they're run with
+//! `cargo test --test golden -- --ignored`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lsdjtool::lsdj::{blocks_from_sram, sram_from_blocks, Compat, LsdjSave};
+use lsdjtool::lsdj::layout::BLOCK_SIZE;
+
+/// Returns the files in `tests/fixtures/dir` ending in `.ext`, failing if there
+/// are none, so that a golden test never passes without checking anything.
+fn fixtures(dir: &str, ext: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(dir);
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir).map(|entries| {
+        entries.map(|e| e.unwrap().path())
+               .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case(ext)))
+               .collect()
+    }).unwrap_or_default();
+    paths.sort();
+    assert!(!paths.is_empty(), "no .{} files in {}", ext, dir.display());
+    paths
+}
+
+#[test]
+#[ignore = "needs saves written by LSDj in tests/fixtures/lsdj"]
+fn test_lsdj_saves_round_trip() {
+    for path in fixtures("lsdj", "sav") {
+        let save = LsdjSave::from(&mut fs::File::open(&path).unwrap()).unwrap();
+        for song in save.metadata.songs() {
+            let name = format!("{} {:02X}", path.display(), song);
+            let sram = save.decompress_song(song).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            let blocks = blocks_from_sram(&sram, Compat::Lsdj).unwrap();
+            assert_eq!(sram_from_blocks(&blocks, Compat::Lsdj).unwrap()[..], sram[..], "{}", name);
+            assert!(blocks.len() / BLOCK_SIZE <= save.metadata.size_of(song), "{} grew when recompressed", name);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs songs exported by lsdpatch in tests/fixtures/lsdpatch"]
+fn test_lsdpatch_exports_recompress_identically() {
+    let compat: Compat = "lsdpatch".parse().unwrap();
+    for path in fixtures("lsdpatch", "lsdsng") {
+        let file = fs::read(&path).unwrap();
+        // lsdpatch puts the title and version (9 bytes) before the blocks
+        assert_eq!(file.len() % BLOCK_SIZE, 9, "{} isn't an lsdpatch export", path.display());
+        let blocks = &file[9..];
+        let sram = sram_from_blocks(blocks, compat).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        assert!(blocks_from_sram(&sram, compat).unwrap() == blocks, "{} recompressed differently", path.display());
+    }
+}